@@ -0,0 +1,109 @@
+/// Embedded server for hosting LAN games from the client
+///
+/// The host runs the same network server and tick loop as a dedicated server,
+/// on background tasks of the client's runtime. It needs no window or
+/// renderer, so tests can run it headless.
+use crate::net::NetworkClient;
+use engine::config::Config;
+use engine::net_proto::DisconnectReason;
+use engine::spells::SpellRegistry;
+use server::net::{NetworkServer, SharedSessions};
+use server::TickLoop;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+/// How long `shutdown` gives connections to deliver the goodbye to players
+const SHUTDOWN_LINGER: Duration = Duration::from_millis(200);
+
+pub struct LanHost {
+    addr: SocketAddr,
+    sessions: SharedSessions,
+    server: JoinHandle<()>,
+    tick_loop: JoinHandle<()>,
+}
+
+impl LanHost {
+    /// Bind the configured server address and start serving on background tasks
+    ///
+    /// Fails if a spell definition is broken or the address cannot be bound,
+    /// e.g. because a server already runs there. Port 0 picks a free port,
+    /// see `addr`.
+    pub async fn start(config: &Config) -> anyhow::Result<Self> {
+        let spells = SpellRegistry::load_dir(Path::new(&config.server.spells_dir))?;
+        let mut server = NetworkServer::from_config(config);
+        let addr = server.bind().await?;
+        let sessions = server.sessions();
+        let mut tick_loop = TickLoop::new(config, server.inbound_events(), server.sessions())
+            .with_spells(Arc::new(spells));
+        let tick_loop = tokio::spawn(async move { tick_loop.run().await });
+        let server = tokio::spawn(async move {
+            if let Err(e) = server.start().await {
+                error!("LAN server stopped: {}", e);
+            }
+        });
+        info!("Hosting LAN game on {}", addr);
+        Ok(Self {
+            addr,
+            sessions,
+            server,
+            tick_loop,
+        })
+    }
+
+    /// Address the server is bound to
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Host and port for a client in this process to connect to
+    pub fn local_endpoint(&self) -> (String, u16) {
+        let ip = if self.addr.ip().is_unspecified() {
+            "127.0.0.1".to_string()
+        } else {
+            self.addr.ip().to_string()
+        };
+        (ip, self.addr.port())
+    }
+
+    pub fn sessions(&self) -> SharedSessions {
+        self.sessions.clone()
+    }
+
+    /// Players currently connected
+    pub fn player_count(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    /// Connect `client` to this host and join as `player_name`
+    pub async fn join(&self, client: &mut NetworkClient, player_name: &str) -> anyhow::Result<u32> {
+        let (host, port) = self.local_endpoint();
+        client.connect(&host, port).await?;
+        client.join(player_name).await
+    }
+
+    /// Tell every player the server is shutting down and stop it
+    pub async fn shutdown(mut self) {
+        self.sessions
+            .lock()
+            .unwrap()
+            .disconnect_all(DisconnectReason::ServerShutdown);
+        // Connection tasks end once their session is gone, flushing the goodbye
+        tokio::time::sleep(SHUTDOWN_LINGER).await;
+        self.server.abort();
+        self.tick_loop.abort();
+        let _ = (&mut self.server).await;
+        let _ = (&mut self.tick_loop).await;
+        info!("LAN server on {} shut down", self.addr);
+    }
+}
+
+impl Drop for LanHost {
+    fn drop(&mut self) {
+        self.server.abort();
+        self.tick_loop.abort();
+    }
+}
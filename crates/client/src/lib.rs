@@ -11,4 +11,7 @@ pub mod ui;
 #[cfg(feature = "audio")]
 pub mod audio;
 
+#[cfg(feature = "lan-host")]
+pub mod lan;
+
 pub use net::NetworkClient;
@@ -14,6 +14,9 @@ mod ui;
 #[cfg(feature = "audio")]
 mod audio;
 
+#[cfg(feature = "lan-host")]
+mod lan;
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     // Initialize logging
@@ -28,20 +31,32 @@ async fn main() -> anyhow::Result<()> {
         config.client.server_host, config.client.server_port
     );
 
+    // With `--host`, run the server in-process and join it as the first player
+    #[cfg(feature = "lan-host")]
+    let lan_host = if std::env::args().any(|arg| arg == "--host") {
+        Some(lan::LanHost::start(&config).await?)
+    } else {
+        None
+    };
+    #[cfg(feature = "lan-host")]
+    let (server_host, server_port) = match &lan_host {
+        Some(host) => host.local_endpoint(),
+        None => (config.client.server_host.clone(), config.client.server_port),
+    };
+    #[cfg(not(feature = "lan-host"))]
+    let (server_host, server_port) = (config.client.server_host.clone(), config.client.server_port);
+
     // Initialize network client
-    let mut client = net::NetworkClient::new();
+    let mut client = net::NetworkClient::from_config(&config);
 
     // Try to connect to server
-    match client
-        .connect(&config.client.server_host, config.client.server_port)
-        .await
-    {
+    match client.connect(&server_host, server_port).await {
         Ok(_) => {
             info!("Successfully connected to server");
 
-            // Send test message
-            if let Ok(response) = client.send_message("HELLO\n").await {
-                info!("Server responded: {}", response.trim());
+            match client.join("Player").await {
+                Ok(player_id) => info!("Joined as player {}", player_id),
+                Err(e) => info!("Could not join server: {}", e),
             }
 
             client.disconnect().await?;
@@ -58,15 +73,14 @@ async fn main() -> anyhow::Result<()> {
         // TODO: Initialize audio system
     }
 
-    #[cfg(feature = "lan-host")]
-    {
-        info!("LAN hosting capability enabled - can host local servers");
-        // TODO: Add logic to optionally start embedded server for LAN games
-    }
-
     info!("Client subsystems initialized");
 
     // TODO: Main game loop
 
+    #[cfg(feature = "lan-host")]
+    if let Some(host) = lan_host {
+        host.shutdown().await;
+    }
+
     Ok(())
 }
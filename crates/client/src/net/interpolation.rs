@@ -0,0 +1,200 @@
+/// Smooth rendering of remote entities between server snapshots
+///
+/// Snapshots are stamped with their server time (`tick / tick_rate`) and
+/// rendered `delay` behind the newest server time the client can estimate, so
+/// there is usually a snapshot on either side of the render time to blend
+/// between. When updates stop arriving, entities keep moving on their last
+/// velocity for at most `max_extrapolation` and then hold still.
+///
+/// The locally controlled player is predicted instead (see `ClientPhysics`)
+/// and should be skipped by the caller.
+use engine::config::ClientConfig;
+use engine::glam::{Quat, Vec3};
+use engine::net_proto::{EntityKind, EntityState, StateSnapshot};
+use engine::physics_core::EntityId;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Snapshots kept at most, about a second at 60 Hz
+const MAX_BUFFERED: usize = 64;
+
+/// How quickly the server clock estimate follows snapshots arriving late
+const CLOCK_LATE_RATE: f64 = 0.01;
+
+/// Transform of one remote entity at the render time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InterpolatedEntity {
+    pub id: EntityId,
+    pub kind: EntityKind,
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub velocity: Vec3,
+    /// Past the newest snapshot, moved along its velocity
+    pub extrapolated: bool,
+}
+
+impl InterpolatedEntity {
+    fn from_state(entity: &EntityState) -> Self {
+        Self {
+            id: entity.id,
+            kind: entity.kind,
+            position: entity.position(),
+            rotation: entity.rotation(),
+            velocity: entity.velocity(),
+            extrapolated: false,
+        }
+    }
+
+    fn blend(from: &EntityState, to: &EntityState, alpha: f32) -> Self {
+        Self {
+            id: to.id,
+            kind: to.kind,
+            position: from.position().lerp(to.position(), alpha),
+            rotation: from.rotation().slerp(to.rotation(), alpha),
+            velocity: from.velocity().lerp(to.velocity(), alpha),
+            extrapolated: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InterpolationBuffer {
+    tick_duration: f64,
+    delay: Duration,
+    max_extrapolation: Duration,
+    /// (server time in seconds, snapshot) in ascending tick order
+    snapshots: VecDeque<(f64, StateSnapshot)>,
+    /// Local reference point for `clock_offset`
+    epoch: Option<Instant>,
+    /// Estimated server time minus local time since `epoch`, in seconds
+    clock_offset: f64,
+}
+
+impl InterpolationBuffer {
+    pub fn new(tick_rate: u32, delay: Duration, max_extrapolation: Duration) -> Self {
+        Self {
+            tick_duration: 1.0 / tick_rate.max(1) as f64,
+            delay,
+            max_extrapolation,
+            snapshots: VecDeque::new(),
+            epoch: None,
+            clock_offset: 0.0,
+        }
+    }
+
+    /// Buffer for a server running at `tick_rate`, with the delays from `config`
+    pub fn from_config(config: &ClientConfig, tick_rate: u32) -> Self {
+        Self::new(
+            tick_rate,
+            Duration::from_millis(config.interpolation_delay_ms as u64),
+            Duration::from_millis(config.max_extrapolation_ms as u64),
+        )
+    }
+
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+
+    pub fn set_delay(&mut self, delay: Duration) {
+        self.delay = delay;
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    /// Add a snapshot received at `received_at`
+    ///
+    /// Snapshots for ticks already buffered, or older than the oldest one, are
+    /// ignored. Every arrival also refines the estimate of the server clock:
+    /// an early arrival moves it forward at once, late ones (jitter) only pull
+    /// it back slowly.
+    pub fn push(&mut self, snapshot: StateSnapshot, received_at: Instant) {
+        let server_time = snapshot.tick as f64 * self.tick_duration;
+        let epoch = *self.epoch.get_or_insert(received_at);
+        let local = received_at.saturating_duration_since(epoch).as_secs_f64();
+        let sample = server_time - local;
+        if self.snapshots.is_empty() || sample > self.clock_offset {
+            self.clock_offset = sample;
+        } else {
+            self.clock_offset += (sample - self.clock_offset) * CLOCK_LATE_RATE;
+        }
+
+        let index = self
+            .snapshots
+            .partition_point(|(_, buffered)| buffered.tick < snapshot.tick);
+        let duplicate = self
+            .snapshots
+            .get(index)
+            .is_some_and(|(_, buffered)| buffered.tick == snapshot.tick);
+        if duplicate || (index == 0 && !self.snapshots.is_empty()) {
+            return;
+        }
+        self.snapshots.insert(index, (server_time, snapshot));
+        if self.snapshots.len() > MAX_BUFFERED {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Server time, in seconds, that is rendered at `now`
+    pub fn render_time(&self, now: Instant) -> f64 {
+        let local = self.epoch.map_or(0.0, |epoch| {
+            now.saturating_duration_since(epoch).as_secs_f64()
+        });
+        local + self.clock_offset - self.delay.as_secs_f64()
+    }
+
+    /// Transforms of every buffered entity at `now`, in ascending ID order
+    ///
+    /// Snapshots older than the one just before the render time are dropped.
+    pub fn sample(&mut self, now: Instant) -> Vec<InterpolatedEntity> {
+        let time = self.render_time(now);
+        while self.snapshots.len() > 1 && self.snapshots[1].0 <= time {
+            self.snapshots.pop_front();
+        }
+
+        let Some((from_time, from)) = self.snapshots.front() else {
+            return Vec::new();
+        };
+        if time <= *from_time {
+            return from
+                .entities
+                .iter()
+                .map(InterpolatedEntity::from_state)
+                .collect();
+        }
+        match self.snapshots.get(1) {
+            Some((to_time, to)) => {
+                let alpha = ((time - from_time) / (to_time - from_time)) as f32;
+                blend(from, to, alpha)
+            }
+            None => {
+                let ahead = (time - from_time).min(self.max_extrapolation.as_secs_f64()) as f32;
+                from.entities
+                    .iter()
+                    .map(|entity| InterpolatedEntity {
+                        position: entity.position() + entity.velocity() * ahead,
+                        extrapolated: true,
+                        ..InterpolatedEntity::from_state(entity)
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Blend two snapshots; entities only in `from` are held until `to` removes them,
+/// entities only in `to` appear once it is reached
+fn blend(from: &StateSnapshot, to: &StateSnapshot, alpha: f32) -> Vec<InterpolatedEntity> {
+    from.entities
+        .iter()
+        .map(|old| match to.entity(old.id) {
+            Some(new) => InterpolatedEntity::blend(old, new, alpha),
+            None => InterpolatedEntity::from_state(old),
+        })
+        .collect()
+}
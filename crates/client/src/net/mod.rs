@@ -1,55 +1,473 @@
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
 /// Client-side networking module
-use tokio::net::TcpStream;
-use tracing::{error, info};
+use engine::config::{Config, MovementConfig, NetworkConditions, NetworkConfig, Transport};
+use engine::net_proto::{
+    ClientMessage, DisconnectReason, PingTimer, ServerMessage, TickClock, BUILD_HASH,
+    PROTOCOL_VERSION,
+};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+pub mod interpolation;
+pub mod snapshots;
+mod tcp;
+mod udp;
+
+/// Longest `disconnect` waits for queued messages to leave
+const LINGER: Duration = Duration::from_secs(1);
+
+/// Attempts `reconnect` makes before giving up
+const RECONNECT_ATTEMPTS: u32 = 6;
+
+/// Wait after the first failed reconnect attempt, doubled after each one
+const RECONNECT_BACKOFF: Duration = Duration::from_millis(250);
+
+/// Longest wait between reconnect attempts
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(4);
+
+/// Connection to the server, whatever the transport
+///
+/// Background tasks own the socket; `sender` finishes once everything queued
+/// before `outgoing` was dropped has been sent.
+struct Link {
+    outgoing: UnboundedSender<ClientMessage>,
+    incoming: UnboundedReceiver<ServerMessage>,
+    sender: JoinHandle<()>,
+}
+
+impl Link {
+    /// Stop sending, waiting up to `LINGER` for queued messages to go out
+    async fn close(self) {
+        let Self {
+            outgoing,
+            mut sender,
+            ..
+        } = self;
+        drop(outgoing);
+        if tokio::time::timeout(LINGER, &mut sender).await.is_err() {
+            sender.abort();
+        }
+    }
+}
+
+/// Changes in the connection's state, for the UI to show
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// The handshake completed
+    Connected { player_id: u32 },
+    /// The server ended the connection and said why
+    Disconnected { reason: DisconnectReason },
+    /// Reconnect attempt `attempt` is starting
+    Reconnecting { attempt: u32 },
+    /// The server handed back the player's session after a dropped connection
+    Reconnected { player_id: u32 },
+    /// The server went silent or the connection broke without a word, and
+    /// reconnecting failed or was not possible
+    ConnectionLost,
+}
+
+/// What the client needs to resume its session on a new connection
+#[derive(Debug, Clone)]
+struct ResumeInfo {
+    host: String,
+    port: u16,
+    player_name: String,
+    player_id: u32,
+    session_token: u64,
+}
 
 pub struct NetworkClient {
-    stream: Option<TcpStream>,
+    transport: Transport,
+    conditions: NetworkConditions,
+    timeout: Duration,
+    link: Option<Link>,
+    player_id: Option<u32>,
+    movement: Option<MovementConfig>,
+    tick_rate: Option<u32>,
+    pings: PingTimer,
+    server_tick: Option<TickClock>,
+    events: VecDeque<ConnectionEvent>,
+    server: Option<(String, u16)>,
+    resume: Option<ResumeInfo>,
 }
 
 impl NetworkClient {
     pub fn new() -> Self {
-        Self { stream: None }
+        Self {
+            transport: Transport::Tcp,
+            conditions: NetworkConditions::default(),
+            timeout: Duration::from_millis(NetworkConfig::default().timeout_ms as u64),
+            link: None,
+            player_id: None,
+            movement: None,
+            tick_rate: None,
+            pings: PingTimer::new(Instant::now()),
+            server_tick: None,
+            events: VecDeque::new(),
+            server: None,
+            resume: None,
+        }
+    }
+
+    /// Create a client using the configured transport and network conditions
+    pub fn from_config(config: &Config) -> Self {
+        Self::new()
+            .with_transport(config.network.transport)
+            .with_conditions(config.network.conditions.clone())
+            .with_timeout(Duration::from_millis(config.network.timeout_ms as u64))
+    }
+
+    /// Use `transport` for the next `connect`; it must match the server's
+    pub fn with_transport(mut self, transport: Transport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Simulate `conditions` on connections made from now on
+    pub fn with_conditions(mut self, conditions: NetworkConditions) -> Self {
+        self.conditions = conditions;
+        self
+    }
+
+    /// Consider the connection lost after hearing nothing for this long
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn transport(&self) -> Transport {
+        self.transport
     }
 
     pub async fn connect(&mut self, host: &str, port: u16) -> anyhow::Result<()> {
         let addr = format!("{}:{}", host, port);
-        info!("Connecting to server at {}", addr);
+        info!("Connecting to server at {} ({:?})", addr, self.transport);
 
-        let stream = TcpStream::connect(&addr).await?;
+        let link = match self.transport {
+            Transport::Tcp => tcp::connect(&addr, &self.conditions).await?,
+            // Datagrams need no connection; the handshake in `join` is the first contact
+            Transport::Udp => udp::connect(&addr, &self.conditions).await?,
+        };
+        if self.conditions.is_active() {
+            info!("Simulating network conditions: {:?}", self.conditions);
+        }
         info!("Connected to server");
 
-        self.stream = Some(stream);
+        self.link = Some(link);
+        self.server = Some((host.to_string(), port));
+        self.pings = PingTimer::new(Instant::now());
+        self.server_tick = None;
         Ok(())
     }
 
-    pub async fn send_message(&mut self, message: &str) -> anyhow::Result<String> {
-        let stream = self
-            .stream
-            .as_mut()
+    pub fn is_connected(&self) -> bool {
+        self.link.is_some()
+    }
+
+    /// Player ID assigned by the server during the handshake
+    pub fn player_id(&self) -> Option<u32> {
+        self.player_id
+    }
+
+    /// Movement constants received from the server during the handshake
+    pub fn movement_config(&self) -> Option<&MovementConfig> {
+        self.movement.as_ref()
+    }
+
+    /// Server simulation rate received during the handshake
+    pub fn tick_rate(&self) -> Option<u32> {
+        self.tick_rate
+    }
+
+    /// Take the connection events that happened since the last call
+    pub fn drain_events(&mut self) -> Vec<ConnectionEvent> {
+        self.events.drain(..).collect()
+    }
+
+    /// Smoothed round-trip time to the server, once a ping was answered
+    pub fn rtt(&self) -> Option<Duration> {
+        self.pings.rtt()
+    }
+
+    /// Mean deviation of the round-trip time
+    pub fn jitter(&self) -> Duration {
+        self.pings.jitter()
+    }
+
+    /// Estimated server tick right now, with the fraction of the tick elapsed
+    pub fn server_tick(&self) -> Option<f64> {
+        self.server_tick.as_ref()?.tick_at(Instant::now())
+    }
+
+    /// Interpolation delay that rides out the measured jitter: two snapshot
+    /// intervals plus twice the jitter
+    pub fn recommended_interpolation_delay(&self) -> Option<Duration> {
+        let tick_rate = self.tick_rate?;
+        self.rtt()?;
+        Some(Duration::from_secs_f64(2.0 / tick_rate.max(1) as f64) + self.jitter() * 2)
+    }
+
+    /// Perform the connection handshake and return the assigned player ID
+    ///
+    /// Fails with the server's reason if it refuses the connection, e.g.
+    /// because the client was built from a different protocol version.
+    pub async fn join(&mut self, player_name: &str) -> anyhow::Result<u32> {
+        self.resume = None;
+        match self.handshake(player_name, None).await? {
+            Some(player_id) => {
+                self.events
+                    .push_back(ConnectionEvent::Connected { player_id });
+                Ok(player_id)
+            }
+            None => {
+                self.events.push_back(ConnectionEvent::ConnectionLost);
+                anyhow::bail!("Lost connection to server during the handshake")
+            }
+        }
+    }
+
+    /// Connect again and take back the player's session, retrying with
+    /// exponential backoff
+    ///
+    /// The server keeps a dropped player's character for a grace period, so
+    /// the player comes back as the same entity. After that they join as a
+    /// new player, reported as `Connected` rather than `Reconnected`.
+    pub async fn reconnect(&mut self) -> anyhow::Result<u32> {
+        let resume = self
+            .resume
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("No session to resume"))?;
+        self.close(None).await;
+
+        let mut backoff = RECONNECT_BACKOFF;
+        for attempt in 1..=RECONNECT_ATTEMPTS {
+            if attempt > 1 {
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+            }
+            self.events
+                .push_back(ConnectionEvent::Reconnecting { attempt });
+            info!(
+                "Reconnecting to {}:{} (attempt {})",
+                resume.host, resume.port, attempt
+            );
+
+            if let Err(e) = self.connect(&resume.host, resume.port).await {
+                debug!("Reconnect attempt {} failed: {}", attempt, e);
+                continue;
+            }
+            let answer = match self
+                .handshake(&resume.player_name, Some(resume.session_token))
+                .await
+            {
+                Ok(answer) => answer,
+                Err(e) => {
+                    debug!("Reconnect attempt {} failed: {}", attempt, e);
+                    continue;
+                }
+            };
+            match answer {
+                Some(player_id) if player_id == resume.player_id => {
+                    self.events
+                        .push_back(ConnectionEvent::Reconnected { player_id });
+                    return Ok(player_id);
+                }
+                Some(player_id) => {
+                    self.events
+                        .push_back(ConnectionEvent::Connected { player_id });
+                    return Ok(player_id);
+                }
+                None => debug!("Reconnect attempt {} lost the connection", attempt),
+            }
+        }
+
+        self.resume = None;
+        self.events.push_back(ConnectionEvent::ConnectionLost);
+        anyhow::bail!("Could not reconnect after {} attempts", RECONNECT_ATTEMPTS)
+    }
+
+    /// Send `Connect` and wait for the answer
+    ///
+    /// Returns `None` if the connection was lost before an answer came.
+    async fn handshake(
+        &mut self,
+        player_name: &str,
+        session_token: Option<u64>,
+    ) -> anyhow::Result<Option<u32>> {
+        self.send_message(&ClientMessage::Connect {
+            player_name: player_name.to_string(),
+            protocol_version: PROTOCOL_VERSION,
+            build_hash: BUILD_HASH.to_string(),
+            session_token,
+        })
+        .await?;
+
+        loop {
+            let Some(message) = self.next_message().await? else {
+                return Ok(None);
+            };
+            match message {
+                ServerMessage::Welcome {
+                    player_id,
+                    session_token,
+                    movement,
+                    tick_rate,
+                } => {
+                    info!("Joined server as player {}", player_id);
+                    self.player_id = Some(player_id);
+                    self.movement = Some(movement);
+                    self.tick_rate = Some(tick_rate);
+                    self.server_tick = Some(TickClock::new(tick_rate));
+                    if let Some((host, port)) = self.server.clone() {
+                        self.resume = Some(ResumeInfo {
+                            host,
+                            port,
+                            player_name: player_name.to_string(),
+                            player_id,
+                            session_token,
+                        });
+                    }
+                    return Ok(Some(player_id));
+                }
+                ServerMessage::Disconnect { reason } => {
+                    anyhow::bail!("Server refused connection: {}", reason)
+                }
+                // Over UDP a state update can overtake a Welcome that had to be resent
+                ServerMessage::StateUpdate(_) | ServerMessage::StateDelta(_) => {}
+                other => anyhow::bail!("Unexpected handshake response: {:?}", other),
+            }
+        }
+    }
+
+    pub async fn send_message(&mut self, message: &ClientMessage) -> anyhow::Result<()> {
+        let link = self
+            .link
+            .as_ref()
             .ok_or_else(|| anyhow::anyhow!("Not connected to server"))?;
 
-        // Send message
-        stream.write_all(message.as_bytes()).await?;
-        info!("Sent: {}", message.trim());
+        link.outgoing
+            .send(message.clone())
+            .map_err(|_| anyhow::anyhow!("Connection to server is closed"))?;
+        debug!("Sent: {:?}", message);
+        Ok(())
+    }
 
-        // Read response
-        let mut buffer = [0; 1024];
-        let n = stream.read(&mut buffer).await?;
+    /// Wait for the next message from the server
+    ///
+    /// Once joined, this also keeps the clock in sync: it pings the server
+    /// every `PING_INTERVAL`, answers the server's pings and consumes the
+    /// pongs to its own pings, so callers only see the pongs they asked for.
+    ///
+    /// The server pings every `PING_INTERVAL`, so hearing nothing for the
+    /// timeout means the connection is lost, as does a closed connection.
+    /// The client then reconnects on its own, see `reconnect`, and carries on
+    /// waiting. A `ServerMessage::Disconnect` ends the connection and is
+    /// returned; after a timeout the next call reconnects. All of these queue
+    /// `ConnectionEvent`s.
+    pub async fn receive_message(&mut self) -> anyhow::Result<ServerMessage> {
+        loop {
+            // A timeout disconnect drops the link but keeps the session
+            if self.link.is_none() && self.resume.is_some() {
+                self.reconnect().await?;
+            }
+            if let Some(message) = self.next_message().await? {
+                return Ok(message);
+            }
+            if self.resume.is_none() {
+                self.events.push_back(ConnectionEvent::ConnectionLost);
+                anyhow::bail!("Lost connection to server");
+            }
+            self.reconnect().await?;
+        }
+    }
+
+    /// Wait for the next message for the caller, or `None` once the
+    /// connection is lost
+    async fn next_message(&mut self) -> anyhow::Result<Option<ServerMessage>> {
+        loop {
+            let joined = self.player_id.is_some();
+            let link = self
+                .link
+                .as_mut()
+                .ok_or_else(|| anyhow::anyhow!("Not connected to server"))?;
+
+            // Pinging before the handshake would be refused as a first message
+            if joined && self.pings.due(Instant::now()) {
+                let (id, timestamp) = self.pings.ping(Instant::now());
+                let _ = link.outgoing.send(ClientMessage::Ping { id, timestamp });
+            }
 
-        let response = String::from_utf8_lossy(&buffer[..n]).to_string();
-        info!("Received: {}", response.trim());
+            let message = match tokio::time::timeout(self.timeout, link.incoming.recv()).await {
+                Ok(Some(message)) => message,
+                Ok(None) => {
+                    warn!("Server closed the connection");
+                    self.close(None).await;
+                    return Ok(None);
+                }
+                Err(_) => {
+                    warn!("Server timed out after {:?}", self.timeout);
+                    self.close(None).await;
+                    return Ok(None);
+                }
+            };
+            debug!("Received: {:?}", message);
 
-        Ok(response)
+            match message {
+                ServerMessage::Ping { id, timestamp } => {
+                    let _ = link.outgoing.send(ClientMessage::Pong { id, timestamp });
+                }
+                ServerMessage::Pong {
+                    id,
+                    timestamp,
+                    tick,
+                } => {
+                    let now = Instant::now();
+                    if self.pings.pong(id, timestamp, now).is_none() {
+                        return Ok(Some(message));
+                    }
+                    if let (Some(clock), Some(rtt)) = (&mut self.server_tick, self.pings.rtt()) {
+                        clock.update(tick, rtt, now);
+                    }
+                }
+                ServerMessage::Disconnect { reason } => {
+                    info!("Server disconnected us: {}", reason);
+                    // Only a timeout leaves the session on the server to resume
+                    if reason != DisconnectReason::TimedOut {
+                        self.resume = None;
+                    }
+                    self.events.push_back(ConnectionEvent::Disconnected {
+                        reason: reason.clone(),
+                    });
+                    self.close(None).await;
+                    return Ok(Some(ServerMessage::Disconnect { reason }));
+                }
+                message => return Ok(Some(message)),
+            }
+        }
     }
 
     pub async fn disconnect(&mut self) -> anyhow::Result<()> {
-        if let Some(stream) = self.stream.take() {
-            drop(stream);
+        self.resume = None;
+        if self.link.is_some() {
+            // Over UDP nothing else tells the server we are gone
+            self.close(Some(DisconnectReason::Quit)).await;
             info!("Disconnected from server");
         }
         Ok(())
     }
+
+    /// Drop the link, first telling the server why if `reason` is given
+    async fn close(&mut self, reason: Option<DisconnectReason>) {
+        self.player_id = None;
+        if let Some(link) = self.link.take() {
+            if let Some(reason) = reason {
+                let _ = link.outgoing.send(ClientMessage::Disconnect { reason });
+            }
+            link.close().await;
+        }
+    }
 }
 
 impl Default for NetworkClient {
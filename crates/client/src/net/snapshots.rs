@@ -0,0 +1,75 @@
+/// Reassembly of full and delta-compressed state snapshots
+///
+/// The server encodes each update against the newest snapshot this client
+/// acknowledged, so decoded snapshots are kept until they are old enough that
+/// the server can no longer pick them as a baseline.
+use engine::net_proto::{ServerMessage, SnapshotDelta, StateSnapshot};
+use std::collections::VecDeque;
+
+/// Decoded snapshots kept as delta baselines; matches the server's history
+pub const SNAPSHOT_HISTORY_LEN: usize = 64;
+
+#[derive(Debug, Default)]
+pub struct SnapshotDecoder {
+    received: VecDeque<StateSnapshot>,
+}
+
+impl SnapshotDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Newest decoded snapshot
+    pub fn latest(&self) -> Option<&StateSnapshot> {
+        self.received.back()
+    }
+
+    /// Decode a `StateUpdate` or `StateDelta`
+    ///
+    /// Returns `Ok(None)` for other messages and for snapshots older than the
+    /// latest one. Every snapshot returned should be acknowledged with
+    /// `ClientMessage::SnapshotAck` so the server can use it as a baseline.
+    pub fn decode(&mut self, message: &ServerMessage) -> anyhow::Result<Option<StateSnapshot>> {
+        let snapshot = match message {
+            ServerMessage::StateUpdate(snapshot) => {
+                snapshot.check_schema()?;
+                snapshot.clone()
+            }
+            ServerMessage::StateDelta(delta) => self.apply_delta(delta)?,
+            _ => return Ok(None),
+        };
+
+        if self
+            .latest()
+            .is_some_and(|latest| snapshot.tick <= latest.tick)
+        {
+            return Ok(None);
+        }
+        if self.received.len() == SNAPSHOT_HISTORY_LEN {
+            self.received.pop_front();
+        }
+        self.received.push_back(snapshot.clone());
+        Ok(Some(snapshot))
+    }
+
+    fn apply_delta(&self, delta: &SnapshotDelta) -> anyhow::Result<StateSnapshot> {
+        let baseline = self
+            .received
+            .iter()
+            .find(|snapshot| snapshot.tick == delta.baseline_tick)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Delta for tick {} refers to unknown baseline {}",
+                    delta.tick,
+                    delta.baseline_tick
+                )
+            })?;
+        let snapshot = delta.apply(baseline)?;
+        snapshot.check_schema()?;
+        Ok(snapshot)
+    }
+
+    pub fn clear(&mut self) {
+        self.received.clear();
+    }
+}
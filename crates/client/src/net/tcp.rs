@@ -0,0 +1,72 @@
+/// Stream link to the server
+///
+/// A reader and a writer task move framed messages between the socket and the
+/// link's channels, through a stream simulator when conditions are set.
+use super::Link;
+use engine::config::NetworkConditions;
+use engine::net_proto::{delay_stream, ClientMessage, FrameReader, FrameWriter, ServerMessage};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tracing::{debug, warn};
+
+pub(super) async fn connect(addr: &str, conditions: &NetworkConditions) -> anyhow::Result<Link> {
+    let stream = TcpStream::connect(addr).await?;
+    let (read_half, write_half) = stream.into_split();
+
+    let (outgoing, mut outgoing_rx) = mpsc::unbounded_channel();
+    let (incoming_tx, mut incoming) = mpsc::unbounded_channel();
+    if conditions.is_active() {
+        outgoing_rx = delay_stream(conditions.clone(), outgoing_rx, encoded_size);
+        let inbound = NetworkConditions {
+            seed: conditions.seed.wrapping_add(1),
+            ..conditions.clone()
+        };
+        incoming = delay_stream(inbound, incoming, encoded_size);
+    }
+
+    tokio::spawn(read_inbound(FrameReader::new(read_half), incoming_tx));
+    let sender = tokio::spawn(write_outbound(FrameWriter::new(write_half), outgoing_rx));
+    Ok(Link {
+        outgoing,
+        incoming,
+        sender,
+    })
+}
+
+fn encoded_size<T: serde::Serialize>(message: &T) -> usize {
+    bincode::serialized_size(message).unwrap_or(0) as usize
+}
+
+async fn read_inbound(
+    mut reader: FrameReader<OwnedReadHalf>,
+    incoming: UnboundedSender<ServerMessage>,
+) {
+    loop {
+        match reader.read_message::<ServerMessage>().await {
+            Ok(Some(message)) => {
+                if incoming.send(message).is_err() {
+                    return;
+                }
+            }
+            Ok(None) => return,
+            Err(e) => {
+                warn!("Error reading from server: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+async fn write_outbound(
+    mut writer: FrameWriter<OwnedWriteHalf>,
+    mut outgoing: UnboundedReceiver<ClientMessage>,
+) {
+    while let Some(message) = outgoing.recv().await {
+        if let Err(e) = writer.write_message(&message).await {
+            debug!("Stopping writer: {}", e);
+            return;
+        }
+    }
+    let _ = writer.shutdown().await;
+}
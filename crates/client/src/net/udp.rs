@@ -0,0 +1,141 @@
+/// Datagram link to the server
+///
+/// A background task owns the socket and the `Endpoint`, sending each queued
+/// message on the channel its type asks for and flushing acks and reliable
+/// resends every `FLUSH_INTERVAL`. With network conditions set, datagrams pass
+/// through a simulator in each direction.
+use super::Link;
+use engine::config::NetworkConditions;
+use engine::net_proto::{
+    decode_message, encode_message, ClientMessage, Endpoint, LinkSimulator, ServerMessage,
+};
+use std::time::{Duration, Instant};
+use tokio::net::{lookup_host, UdpSocket};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tracing::{debug, warn};
+
+/// How often acks and reliable resends are flushed
+const FLUSH_INTERVAL: Duration = Duration::from_millis(10);
+
+pub(super) async fn connect(addr: &str, conditions: &NetworkConditions) -> anyhow::Result<Link> {
+    let server = lookup_host(addr)
+        .await?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("No address found for {}", addr))?;
+    let local = if server.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(server).await?;
+
+    let (outgoing, outgoing_rx) = mpsc::unbounded_channel();
+    let (incoming_tx, incoming) = mpsc::unbounded_channel();
+    let driver = Driver {
+        socket,
+        endpoint: Endpoint::new(),
+        uplink: LinkSimulator::datagrams(conditions.clone()),
+        downlink: LinkSimulator::datagrams(NetworkConditions {
+            seed: conditions.seed.wrapping_add(1),
+            ..conditions.clone()
+        }),
+        incoming: incoming_tx,
+    };
+    let sender = tokio::spawn(driver.run(outgoing_rx));
+    Ok(Link {
+        outgoing,
+        incoming,
+        sender,
+    })
+}
+
+struct Driver {
+    socket: UdpSocket,
+    endpoint: Endpoint,
+    uplink: LinkSimulator<Vec<u8>>,
+    downlink: LinkSimulator<Vec<u8>>,
+    incoming: UnboundedSender<ServerMessage>,
+}
+
+impl Driver {
+    /// Run until `outgoing` closes and every reliable message has been acked
+    async fn run(mut self, mut outgoing: UnboundedReceiver<ClientMessage>) {
+        let mut open = true;
+        let mut buffer = vec![0u8; 64 * 1024];
+        let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+
+        loop {
+            let wake = [self.uplink.next_release(), self.downlink.next_release()]
+                .into_iter()
+                .flatten()
+                .min()
+                .map(tokio::time::Instant::from_std);
+            tokio::select! {
+                received = self.socket.recv(&mut buffer) => match received {
+                    Ok(len) => self.downlink.send(Instant::now(), buffer[..len].to_vec(), len),
+                    // Mostly ICMP port-unreachable while the server is down
+                    Err(e) => debug!("UDP receive failed: {}", e),
+                },
+                message = outgoing.recv(), if open => match message {
+                    Some(message) => {
+                        let queued = encode_message(&message)
+                            .and_then(|payload| self.endpoint.send(message.channel(), payload));
+                        if let Err(e) = queued {
+                            warn!("Dropping message to server: {}", e);
+                        }
+                        self.transmit();
+                    }
+                    None => open = false,
+                },
+                _ = flush.tick() => {
+                    self.transmit();
+                    if !open && self.endpoint.unacked_reliable() == 0 && self.uplink.in_flight() == 0 {
+                        return;
+                    }
+                }
+                _ = tokio::time::sleep_until(wake.unwrap_or_else(tokio::time::Instant::now)), if wake.is_some() => {}
+            }
+            self.deliver();
+            self.release().await;
+        }
+    }
+
+    fn transmit(&mut self) {
+        let now = Instant::now();
+        for datagram in self.endpoint.poll_transmit(now) {
+            let len = datagram.len();
+            self.uplink.send(now, datagram, len);
+        }
+    }
+
+    /// Send datagrams whose simulated departure has come
+    async fn release(&mut self) {
+        for datagram in self.uplink.poll(Instant::now()) {
+            if let Err(e) = self.socket.send(&datagram).await {
+                debug!("UDP send failed: {}", e);
+            }
+        }
+    }
+
+    /// Process datagrams whose simulated arrival has come
+    fn deliver(&mut self) {
+        for datagram in self.downlink.poll(Instant::now()) {
+            let delivered = match self.endpoint.receive(&datagram, Instant::now()) {
+                Ok(delivered) => delivered,
+                Err(e) => {
+                    debug!("Bad datagram from server: {}", e);
+                    continue;
+                }
+            };
+            for payload in delivered {
+                match decode_message::<ServerMessage>(&payload) {
+                    Ok(message) => {
+                        let _ = self.incoming.send(message);
+                    }
+                    Err(e) => debug!("Undecodable message from server: {}", e),
+                }
+            }
+        }
+    }
+}
@@ -1,25 +1,366 @@
 /// Client-side physics prediction and reconciliation
-use engine::physics::PhysicsWorld;
+///
+/// Local inputs are applied to `predicted_world` immediately and kept until the
+/// server acknowledges them. Every server update resets the local character to
+/// the confirmed state and replays the inputs the server has not seen yet. The
+/// jump this causes is not shown at once: it is stored as a render offset that
+/// decays over a few frames.
+///
+/// Projectiles the local player casts are launched in `predicted_world` as
+/// soon as the spell goes off locally. Once a snapshot carries the server's
+/// projectile for the same cast, the prediction is linked to its ID and drawn
+/// in its place; it is removed when the server's projectile is gone.
+///
+/// Replicated surface states are applied to both worlds, so frozen surfaces
+/// have the same friction as on the server.
+use engine::config::MovementConfig;
+use engine::glam::Vec3;
+use engine::net_proto::{EntityKind, EntityState, PlayerInput, PlayerUpdate, SurfaceUpdate};
+use engine::physics::{
+    Character, CharacterController, CharacterState, EntityId, PhysicsWorld, SurfaceState,
+};
+use engine::spells::{SpellDef, SpellId};
+use std::collections::{BTreeMap, VecDeque};
+
+/// Time for a displayed prediction error to shrink to about a third (seconds)
+pub const CORRECTION_TIME_CONSTANT: f32 = 0.1;
+/// Prediction errors larger than this are shown at once, e.g. after a respawn
+pub const SNAP_DISTANCE: f32 = 2.0;
+/// Most unacknowledged inputs kept for replay, two seconds at 60 Hz
+pub const MAX_INPUT_HISTORY: usize = 120;
+/// Predicted projectiles the server has not confirmed by then are removed (seconds)
+pub const PROJECTILE_CONFIRM_TIMEOUT: f32 = 1.0;
+
+/// A projectile the local player launched ahead of the server
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PredictedProjectile {
+    /// Entity in the predicted world
+    pub local: EntityId,
+    pub spell_id: SpellId,
+    /// `PlayerInput::sequence` of the input that started the cast
+    pub cast_sequence: u32,
+    /// The server's projectile for the same cast, once a snapshot carried it
+    pub server: Option<EntityId>,
+    /// Seconds since it was launched
+    pub age: f32,
+}
 
 pub struct ClientPhysics {
     predicted_world: PhysicsWorld,
     confirmed_world: PhysicsWorld,
+    controller: CharacterController,
+    local_player: Option<Character>,
+    confirmed_player: Option<Character>,
+    /// Inputs not yet acknowledged by the server, in sequence order
+    input_history: VecDeque<PlayerInput>,
+    last_acked_sequence: Option<u32>,
+    /// Difference between the displayed and the predicted position
+    correction_offset: Vec3,
+    delta_time: f32,
+    /// Local projectiles, oldest first
+    projectiles: Vec<PredictedProjectile>,
+    /// Surface states last replicated, other than normal
+    surfaces: BTreeMap<EntityId, SurfaceState>,
 }
 
 impl ClientPhysics {
     pub fn new() -> Self {
+        Self::with_config(MovementConfig::default(), 1.0 / 60.0)
+    }
+
+    /// Create client physics using the server's movement constants
+    ///
+    /// `delta_time` must match the server tick so each input moves the
+    /// character exactly as far as it does on the server.
+    pub fn with_config(movement: MovementConfig, delta_time: f32) -> Self {
+        let mut predicted_world = PhysicsWorld::new();
+        let mut confirmed_world = PhysicsWorld::new();
+        // TODO: Load the same level geometry as the server
+        predicted_world.add_ground_plane(0.0);
+        confirmed_world.add_ground_plane(0.0);
+
         Self {
-            predicted_world: PhysicsWorld::new(),
-            confirmed_world: PhysicsWorld::new(),
+            predicted_world,
+            confirmed_world,
+            controller: CharacterController::new(movement),
+            local_player: None,
+            confirmed_player: None,
+            input_history: VecDeque::new(),
+            last_acked_sequence: None,
+            correction_offset: Vec3::ZERO,
+            delta_time,
+            projectiles: Vec::new(),
+            surfaces: BTreeMap::new(),
+        }
+    }
+
+    /// Spawn the locally controlled character
+    pub fn spawn_local_player(&mut self, position: Vec3) {
+        if let Some(old) = self.local_player.take() {
+            self.controller.despawn(&mut self.predicted_world, &old);
         }
+        if let Some(old) = self.confirmed_player.take() {
+            self.controller.despawn(&mut self.confirmed_world, &old);
+        }
+        self.local_player = Some(self.controller.spawn(&mut self.predicted_world, position));
+        self.confirmed_player = Some(self.controller.spawn(&mut self.confirmed_world, position));
+        self.input_history.clear();
+        self.last_acked_sequence = None;
+        self.correction_offset = Vec3::ZERO;
+    }
+
+    /// Predicted state of the local character
+    pub fn local_player_state(&self) -> Option<&CharacterState> {
+        self.local_player.as_ref().map(|character| &character.state)
+    }
+
+    /// Last state of the local character confirmed by the server
+    pub fn confirmed_player_state(&self) -> Option<&CharacterState> {
+        self.confirmed_player
+            .as_ref()
+            .map(|character| &character.state)
     }
 
-    pub fn predict(&mut self, _delta_time: f32) {
-        // TODO: Run prediction step
+    /// Where to draw the local character: the prediction plus the decaying correction
+    pub fn render_position(&self) -> Option<Vec3> {
+        self.local_player_state()
+            .map(|state| state.position + self.correction_offset)
     }
 
-    pub fn reconcile(&mut self) {
-        // TODO: Reconcile with server state
+    pub fn correction_offset(&self) -> Vec3 {
+        self.correction_offset
+    }
+
+    pub fn last_acked_sequence(&self) -> Option<u32> {
+        self.last_acked_sequence
+    }
+
+    /// Number of inputs sent but not yet acknowledged
+    pub fn pending_inputs(&self) -> usize {
+        self.input_history.len()
+    }
+
+    /// Move the local character immediately, exactly as the server will
+    ///
+    /// The input is remembered until a server update acknowledges its
+    /// `sequence`, so sequences must increase with every input.
+    pub fn apply_local_input(&mut self, input: &PlayerInput) {
+        if let Some(character) = self.local_player.as_mut() {
+            self.controller.apply_input(
+                &mut self.predicted_world,
+                character,
+                input,
+                self.delta_time,
+            );
+            if self.input_history.len() == MAX_INPUT_HISTORY {
+                self.input_history.pop_front();
+            }
+            self.input_history.push_back(input.clone());
+        }
+    }
+
+    /// Step the predicted world and let the displayed correction decay
+    ///
+    /// Projectile predictions the server never confirmed are dropped after
+    /// `PROJECTILE_CONFIRM_TIMEOUT`.
+    pub fn predict(&mut self, delta_time: f32) {
+        self.predicted_world.step(delta_time);
+        self.correction_offset *= (-delta_time / CORRECTION_TIME_CONSTANT).exp();
+
+        let world = &mut self.predicted_world;
+        self.projectiles.retain_mut(|projectile| {
+            projectile.age += delta_time;
+            let expired =
+                projectile.server.is_none() && projectile.age > PROJECTILE_CONFIRM_TIMEOUT;
+            if expired {
+                world.despawn(projectile.local);
+            }
+            !expired
+        });
+    }
+
+    pub fn predicted_world(&self) -> &PhysicsWorld {
+        &self.predicted_world
+    }
+
+    /// Launch a spell's projectile from the local character's eyes at once
+    ///
+    /// `cast_sequence` is the sequence of the input that started the cast,
+    /// which the server's projectile will carry. Returns `None` without a
+    /// local character or if the spell is not delivered by projectile.
+    pub fn predict_projectile(
+        &mut self,
+        spell_id: SpellId,
+        cast_sequence: u32,
+        spell: &SpellDef,
+    ) -> Option<EntityId> {
+        let character = self.local_player.as_ref()?;
+        let origin = self.controller.eye_position(&character.state);
+        let desc = spell.projectile(origin, character.state.aim(), Some(character.entity))?;
+        let local = self.predicted_world.spawn_projectile(&desc).ok()?;
+        self.projectiles.push(PredictedProjectile {
+            local,
+            spell_id,
+            cast_sequence,
+            server: None,
+            age: 0.0,
+        });
+        Some(local)
+    }
+
+    /// Projectiles launched locally that the server has not removed yet
+    pub fn predicted_projectiles(&self) -> &[PredictedProjectile] {
+        &self.projectiles
+    }
+
+    /// Local projectile standing in for the server's projectile `server`
+    ///
+    /// Renderers draw it instead of the interpolated server entity, so the
+    /// caster's projectile never jumps back by the round-trip time.
+    pub fn local_projectile(&self, server: EntityId) -> Option<EntityId> {
+        self.projectiles
+            .iter()
+            .find(|projectile| projectile.server == Some(server))
+            .map(|projectile| projectile.local)
+    }
+
+    /// Drop the unconfirmed projectiles of a cast the server interrupted or rejected
+    pub fn cancel_projectiles(&mut self, spell_id: SpellId) {
+        let world = &mut self.predicted_world;
+        self.projectiles.retain(|projectile| {
+            let cancelled = projectile.server.is_none() && projectile.spell_id == spell_id;
+            if cancelled {
+                world.despawn(projectile.local);
+            }
+            !cancelled
+        });
+    }
+
+    /// Match the local player's projectiles in a snapshot to the predicted ones
+    ///
+    /// Each server projectile is linked to the oldest unmatched prediction of
+    /// the same spell and cast. Predictions linked to a projectile that is no
+    /// longer in `entities` are removed from the predicted world.
+    pub fn reconcile_projectiles(&mut self, player_id: u32, entities: &[EntityState]) {
+        let server: BTreeMap<EntityId, (SpellId, u32)> = entities
+            .iter()
+            .filter_map(|entity| match entity.kind {
+                EntityKind::Projectile {
+                    owner,
+                    spell_id,
+                    cast_sequence,
+                } if owner == player_id => Some((entity.id, (spell_id, cast_sequence))),
+                _ => None,
+            })
+            .collect();
+
+        let world = &mut self.predicted_world;
+        self.projectiles.retain(|projectile| {
+            let gone = projectile
+                .server
+                .is_some_and(|id| !server.contains_key(&id));
+            if gone {
+                world.despawn(projectile.local);
+            }
+            !gone
+        });
+
+        for (id, (spell_id, cast_sequence)) in server {
+            if self
+                .projectiles
+                .iter()
+                .any(|projectile| projectile.server == Some(id))
+            {
+                continue;
+            }
+            if let Some(projectile) = self.projectiles.iter_mut().find(|projectile| {
+                projectile.server.is_none()
+                    && projectile.spell_id == spell_id
+                    && projectile.cast_sequence == cast_sequence
+            }) {
+                projectile.server = Some(id);
+            }
+        }
+    }
+
+    /// State of a surface as last replicated
+    pub fn surface(&self, entity: EntityId) -> SurfaceState {
+        self.surfaces.get(&entity).copied().unwrap_or_default()
+    }
+
+    /// Take over the surface states of a snapshot, e.g. to make ice slippery
+    ///
+    /// Surfaces missing from `surfaces` are back to normal. Level geometry
+    /// must have the same entity IDs as on the server.
+    pub fn apply_surfaces(&mut self, surfaces: &[SurfaceUpdate]) {
+        let current: BTreeMap<EntityId, SurfaceState> = surfaces
+            .iter()
+            .map(|surface| (surface.entity, surface.state))
+            .collect();
+        let changed: Vec<(EntityId, SurfaceState)> = self
+            .surfaces
+            .keys()
+            .filter(|entity| !current.contains_key(entity))
+            .map(|entity| (*entity, SurfaceState::Normal))
+            .chain(
+                current
+                    .iter()
+                    .filter(|(entity, state)| self.surfaces.get(entity) != Some(state))
+                    .map(|(entity, state)| (*entity, *state)),
+            )
+            .collect();
+        for (entity, state) in changed {
+            self.predicted_world.set_surface_state(entity, state);
+            self.confirmed_world.set_surface_state(entity, state);
+        }
+        self.surfaces = current;
+    }
+
+    /// Correct the prediction with the server's state of the local character
+    ///
+    /// Rewinds to the confirmed state, replays every input newer than
+    /// `update.last_input_sequence` and returns the prediction error that was
+    /// found. Updates older than one already applied are ignored.
+    pub fn reconcile(&mut self, update: &PlayerUpdate) -> Vec3 {
+        if update.last_input_sequence < self.last_acked_sequence {
+            return Vec3::ZERO;
+        }
+        let (Some(local), Some(confirmed)) =
+            (self.local_player.as_mut(), self.confirmed_player.as_mut())
+        else {
+            return Vec3::ZERO;
+        };
+
+        self.controller
+            .set_state(&mut self.confirmed_world, confirmed, update.state);
+        self.last_acked_sequence = update.last_input_sequence;
+        if let Some(acked) = update.last_input_sequence {
+            while self
+                .input_history
+                .front()
+                .is_some_and(|input| input.sequence <= acked)
+            {
+                self.input_history.pop_front();
+            }
+        }
+
+        let predicted = local.state.position;
+        self.controller
+            .set_state(&mut self.predicted_world, local, update.state);
+        for input in &self.input_history {
+            self.controller
+                .apply_input(&mut self.predicted_world, local, input, self.delta_time);
+        }
+        let replayed = local.state;
+        self.controller
+            .set_state(&mut self.predicted_world, local, replayed);
+
+        let error = predicted - replayed.position;
+        self.correction_offset += error;
+        if self.correction_offset.length() > SNAP_DISTANCE {
+            self.correction_offset = Vec3::ZERO;
+        }
+        error
     }
 }
 
@@ -2,8 +2,138 @@
 
 #[cfg(test)]
 mod tests {
+    use client::net::interpolation::InterpolationBuffer;
+    use engine::glam::{Quat, Vec3};
+    use engine::net_proto::{EntityKind, EntityState, StateSnapshot};
+    use engine::physics_core::EntityId;
+    use std::time::{Duration, Instant};
+
     #[test]
     fn test_client_compiles() {
         assert!(true);
     }
+
+    /// Snapshot with one prop at `x`, yawed by `yaw` and moving along x at `speed`
+    fn prop_snapshot(tick: u32, x: f32, yaw: f32, speed: f32) -> StateSnapshot {
+        let mut snapshot = StateSnapshot::new(tick);
+        snapshot.entities.push(EntityState::new(
+            EntityId(1),
+            EntityKind::Prop,
+            Vec3::new(x, 0.0, 0.0),
+            Quat::from_rotation_y(yaw),
+            Vec3::new(speed, 0.0, 0.0),
+        ));
+        snapshot
+    }
+
+    #[test]
+    fn test_interpolation_blends_between_snapshots() {
+        // 10 Hz ticks, rendered one tick behind
+        let mut buffer =
+            InterpolationBuffer::new(10, Duration::from_millis(100), Duration::from_millis(200));
+        let start = Instant::now();
+        buffer.push(prop_snapshot(0, 0.0, 0.0, 10.0), start);
+        buffer.push(
+            prop_snapshot(1, 1.0, std::f32::consts::FRAC_PI_2, 10.0),
+            start + Duration::from_millis(100),
+        );
+
+        let entities = buffer.sample(start + Duration::from_millis(150));
+        assert_eq!(entities.len(), 1);
+        let entity = entities[0];
+        assert!(!entity.extrapolated);
+        assert!(
+            (entity.position.x - 0.5).abs() < 0.01,
+            "{:?}",
+            entity.position
+        );
+        let expected = Quat::from_rotation_y(std::f32::consts::FRAC_PI_4);
+        assert!(entity.rotation.angle_between(expected) < 0.01);
+    }
+
+    #[test]
+    fn test_interpolation_extrapolates_for_a_bounded_time() {
+        let mut buffer =
+            InterpolationBuffer::new(10, Duration::from_millis(100), Duration::from_millis(200));
+        let start = Instant::now();
+        buffer.push(prop_snapshot(0, 0.0, 0.0, 10.0), start);
+
+        // 100ms past the only snapshot: moved along its velocity
+        let entity = buffer.sample(start + Duration::from_millis(200))[0];
+        assert!(entity.extrapolated);
+        assert!(
+            (entity.position.x - 1.0).abs() < 0.01,
+            "{:?}",
+            entity.position
+        );
+
+        // Long after updates stopped: held at the extrapolation limit
+        let entity = buffer.sample(start + Duration::from_secs(5))[0];
+        assert!(
+            (entity.position.x - 2.0).abs() < 0.01,
+            "{:?}",
+            entity.position
+        );
+    }
+
+    #[test]
+    fn test_interpolation_ignores_stale_and_duplicate_snapshots() {
+        let mut buffer =
+            InterpolationBuffer::new(10, Duration::from_millis(100), Duration::from_millis(200));
+        let start = Instant::now();
+        buffer.push(prop_snapshot(2, 2.0, 0.0, 0.0), start);
+        buffer.push(prop_snapshot(4, 4.0, 0.0, 0.0), start);
+        buffer.push(prop_snapshot(4, 40.0, 0.0, 0.0), start);
+        buffer.push(prop_snapshot(1, 1.0, 0.0, 0.0), start);
+        // Arriving out of order still lands between its neighbours
+        buffer.push(prop_snapshot(3, 3.0, 0.0, 0.0), start);
+        assert_eq!(buffer.len(), 3);
+
+        // The clock follows the newest tick, so render time is about tick 3;
+        // the late arrivals only pull it back slightly
+        let entity = buffer.sample(start)[0];
+        assert!(
+            (entity.position.x - 3.0).abs() < 0.05,
+            "{:?}",
+            entity.position
+        );
+    }
+
+    #[test]
+    fn test_interpolation_spawns_and_removes_entities_at_their_snapshot() {
+        let mut buffer =
+            InterpolationBuffer::new(10, Duration::from_millis(100), Duration::from_millis(200));
+        let start = Instant::now();
+        let mut first = prop_snapshot(0, 0.0, 0.0, 0.0);
+        first.entities.push(EntityState::new(
+            EntityId(2),
+            EntityKind::Projectile {
+                owner: 1,
+                spell_id: 0,
+                cast_sequence: 1,
+            },
+            Vec3::ZERO,
+            Quat::IDENTITY,
+            Vec3::ZERO,
+        ));
+        buffer.push(first, start);
+        let mut second = prop_snapshot(1, 1.0, 0.0, 0.0);
+        second.entities[0].id = EntityId(3);
+        buffer.push(second, start + Duration::from_millis(100));
+
+        // Halfway: the old entities are held, the new one is not yet shown
+        let ids: Vec<_> = buffer
+            .sample(start + Duration::from_millis(150))
+            .iter()
+            .map(|entity| entity.id)
+            .collect();
+        assert_eq!(ids, vec![EntityId(1), EntityId(2)]);
+
+        let ids: Vec<_> = buffer
+            .sample(start + Duration::from_millis(200))
+            .iter()
+            .map(|entity| entity.id)
+            .collect();
+        assert_eq!(ids, vec![EntityId(3)]);
+    }
 }
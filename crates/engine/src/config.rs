@@ -3,21 +3,157 @@ use serde::{Deserialize, Serialize};
 use std::path::Path;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
+    /// Simulation ticks per second
+    pub tick_rate: u32,
+    /// Most ticks run back-to-back to catch up after a stall; older backlog is dropped
+    pub max_catch_up_ticks: u32,
+    /// Furthest back lag compensation rewinds targets for a shot (ms)
+    pub max_rewind_ms: u32,
+    /// How long a dropped player's character waits for them to reconnect (ms)
+    pub session_grace_ms: u32,
+    /// Directory of the spell definitions players can cast
+    pub spells_dir: String,
+    pub input: InputLimits,
+}
+
+/// What the server accepts from a player's inputs
+///
+/// Inputs that break these limits are rejected or clamped and counted. The
+/// suspicious ones add strikes, and a player whose strikes reach
+/// `kick_threshold` is disconnected.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputLimits {
+    /// Most inputs applied for one player in a tick, after they sent none for
+    /// a while; on average only one per tick is applied
+    pub max_per_tick: u32,
+    /// Inputs queued per player; when full the oldest is dropped
+    pub max_buffered: u32,
+    /// Furthest ahead of the last applied sequence an input may be
+    pub sequence_window: u32,
+    /// Largest look delta on either axis; larger values are clamped
+    pub max_look_delta: f32,
+    /// Strikes at which a player is kicked; 0 never kicks
+    pub kick_threshold: u32,
+}
+
+/// How clients and the server exchange messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    /// One ordered stream; a lost packet delays everything behind it
+    #[default]
+    Tcp,
+    /// Datagrams with an unreliable channel for snapshots and inputs and a
+    /// reliable ordered one for everything else
+    Udp,
+}
+
+/// Settings shared by the server and clients, which must agree on them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    pub transport: Transport,
+    /// Silence after which either side drops the connection (ms); both send
+    /// pings every `PING_INTERVAL` as heartbeats
+    pub timeout_ms: u32,
+    /// Simulated network conditions; all zero disables the simulator
+    pub conditions: NetworkConditions,
+}
+
+/// Artificial network conditions for testing prediction and lag handling
+///
+/// Applied by whichever side enables them, separately to its outgoing and
+/// incoming traffic, so the round trip sees twice the latency. Over TCP only
+/// latency, jitter and the bandwidth cap apply, since a stream cannot lose,
+/// duplicate or reorder data.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkConditions {
+    /// Fixed one-way delay (ms)
+    pub latency_ms: u32,
+    /// Extra delay picked uniformly between zero and this (ms)
+    pub jitter_ms: u32,
+    /// Probability of dropping a packet, 0-1
+    pub loss: f32,
+    /// Probability of delivering a packet twice, 0-1
+    pub duplication: f32,
+    /// Probability of holding a packet back so later ones overtake it, 0-1
+    pub reordering: f32,
+    /// Link capacity in kilobits per second; 0 means unlimited
+    pub bandwidth_kbps: u32,
+    /// Seed for the random decisions, so runs can be repeated
+    pub seed: u64,
+}
+
+impl NetworkConditions {
+    /// Whether these conditions change anything at all
+    pub fn is_active(&self) -> bool {
+        self.latency_ms > 0
+            || self.jitter_ms > 0
+            || self.loss > 0.0
+            || self.duplication > 0.0
+            || self.reordering > 0.0
+            || self.bandwidth_kbps > 0
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ClientConfig {
     pub server_host: String,
     pub server_port: u16,
+    /// How far behind the newest server state remote entities are rendered (ms)
+    pub interpolation_delay_ms: u32,
+    /// Longest remote entities keep moving on their last velocity once updates stop (ms)
+    pub max_extrapolation_ms: u32,
+}
+
+/// Character movement constants
+///
+/// Owned by the server and sent to clients in `ServerMessage::Welcome`, so
+/// prediction always runs with exactly the values the server simulates with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MovementConfig {
+    /// Top horizontal speed while standing (m/s)
+    pub max_speed: f32,
+    /// Top horizontal speed while crouched (m/s)
+    pub crouch_speed: f32,
+    /// Horizontal acceleration towards the target speed on the ground (m/s²)
+    pub acceleration: f32,
+    /// Fraction of `acceleration` available while airborne
+    pub air_control: f32,
+    /// Initial upward speed of a jump (m/s)
+    pub jump_speed: f32,
+    /// Downward acceleration applied to characters (m/s²)
+    pub gravity: f32,
+    pub capsule_radius: f32,
+    /// Total capsule height while standing (m)
+    pub stand_height: f32,
+    /// Total capsule height while crouched (m)
+    pub crouch_height: f32,
+    /// Tallest obstacle climbed automatically (m)
+    pub step_height: f32,
+    /// Steepest walkable slope (degrees)
+    pub max_slope_degrees: f32,
+    /// Radians of rotation per unit of look delta
+    pub look_sensitivity: f32,
+    /// Pitch limit in either direction (degrees)
+    pub max_pitch_degrees: f32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
 pub struct Config {
     pub server: ServerConfig,
     pub client: ClientConfig,
+    pub network: NetworkConfig,
+    pub movement: MovementConfig,
 }
 
 impl Default for ServerConfig {
@@ -25,6 +161,34 @@ impl Default for ServerConfig {
         Self {
             host: "127.0.0.1".to_string(),
             port: 7777,
+            tick_rate: 60,
+            max_catch_up_ticks: 5,
+            max_rewind_ms: 250,
+            session_grace_ms: 30_000,
+            spells_dir: "assets/spells".to_string(),
+            input: InputLimits::default(),
+        }
+    }
+}
+
+impl Default for InputLimits {
+    fn default() -> Self {
+        Self {
+            max_per_tick: 3,
+            max_buffered: 32,
+            sequence_window: 256,
+            max_look_delta: 1000.0,
+            kick_threshold: 200,
+        }
+    }
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            transport: Transport::default(),
+            timeout_ms: 10_000,
+            conditions: NetworkConditions::default(),
         }
     }
 }
@@ -34,6 +198,28 @@ impl Default for ClientConfig {
         Self {
             server_host: "127.0.0.1".to_string(),
             server_port: 7777,
+            interpolation_delay_ms: 100,
+            max_extrapolation_ms: 250,
+        }
+    }
+}
+
+impl Default for MovementConfig {
+    fn default() -> Self {
+        Self {
+            max_speed: 6.0,
+            crouch_speed: 3.0,
+            acceleration: 60.0,
+            air_control: 0.3,
+            jump_speed: 5.5,
+            gravity: 9.81,
+            capsule_radius: 0.4,
+            stand_height: 1.8,
+            crouch_height: 1.1,
+            step_height: 0.35,
+            max_slope_degrees: 45.0,
+            look_sensitivity: 0.0025,
+            max_pitch_degrees: 89.0,
         }
     }
 }
@@ -5,6 +5,7 @@ pub mod io;
 pub mod math;
 pub mod net_proto;
 pub mod physics_core;
+pub mod spells;
 
 // Legacy module names for backwards compatibility
 pub mod physics {
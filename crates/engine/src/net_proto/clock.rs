@@ -0,0 +1,166 @@
+/// Round-trip time, jitter and server tick estimation
+///
+/// Both ends send timestamped pings every `PING_INTERVAL`. The receiver echoes
+/// the timestamp back in its pong, so the sender measures the round trip on
+/// its own clock and the two clocks never need to agree. Pongs from the server
+/// also carry its current tick, from which the client estimates the server
+/// tick at any instant. Time is passed in, so tests can drive it without
+/// sleeping.
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How often each end pings the other
+pub const PING_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Pings awaiting a pong at most; older ones are considered lost
+const MAX_OUTSTANDING: usize = 8;
+
+/// Server tick estimates further off than this are replaced instead of blended
+const MAX_TICK_DRIFT: f64 = 10.0;
+
+/// How much of a new tick estimate is blended in per pong
+const TICK_BLEND: f64 = 0.1;
+
+/// Smoothed round-trip time and its mean deviation, as TCP computes them
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RttEstimator {
+    rtt: Option<Duration>,
+    jitter: Duration,
+}
+
+impl RttEstimator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Smoothed round-trip time, once a sample arrived
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    /// Mean deviation of the round-trip time
+    pub fn jitter(&self) -> Duration {
+        self.jitter
+    }
+
+    pub fn sample(&mut self, sample: Duration) {
+        match self.rtt {
+            Some(rtt) => {
+                let deviation = rtt.abs_diff(sample);
+                self.jitter = self.jitter.mul_f64(0.75) + deviation.mul_f64(0.25);
+                self.rtt = Some(rtt.mul_f64(0.875) + sample.mul_f64(0.125));
+            }
+            None => {
+                self.rtt = Some(sample);
+                self.jitter = sample / 2;
+            }
+        }
+    }
+}
+
+/// Schedules timestamped pings and turns their pongs into RTT samples
+#[derive(Debug, Clone)]
+pub struct PingTimer {
+    epoch: Instant,
+    next_id: u32,
+    last_sent: Option<Instant>,
+    /// (id, timestamp) of pings not answered yet, oldest first
+    outstanding: VecDeque<(u32, u64)>,
+    estimator: RttEstimator,
+}
+
+impl PingTimer {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            epoch: now,
+            next_id: 0,
+            last_sent: None,
+            outstanding: VecDeque::new(),
+            estimator: RttEstimator::new(),
+        }
+    }
+
+    pub fn rtt(&self) -> Option<Duration> {
+        self.estimator.rtt()
+    }
+
+    pub fn jitter(&self) -> Duration {
+        self.estimator.jitter()
+    }
+
+    /// Whether `PING_INTERVAL` has passed since the last ping
+    pub fn due(&self, now: Instant) -> bool {
+        self.last_sent
+            .is_none_or(|sent| now.saturating_duration_since(sent) >= PING_INTERVAL)
+    }
+
+    /// Start a ping and return its id and timestamp
+    pub fn ping(&mut self, now: Instant) -> (u32, u64) {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        // Never zero, so hand-written pings with a zero timestamp never match
+        let timestamp = now.saturating_duration_since(self.epoch).as_micros() as u64 + 1;
+        self.last_sent = Some(now);
+        self.outstanding.push_back((id, timestamp));
+        if self.outstanding.len() > MAX_OUTSTANDING {
+            self.outstanding.pop_front();
+        }
+        (id, timestamp)
+    }
+
+    /// Match a pong against the pings sent and return the round trip it measured
+    ///
+    /// Returns `None` for pongs to pings this timer did not send, that were
+    /// already answered or that are too old to track.
+    pub fn pong(&mut self, id: u32, timestamp: u64, now: Instant) -> Option<Duration> {
+        let index = self
+            .outstanding
+            .iter()
+            .position(|&ping| ping == (id, timestamp))?;
+        self.outstanding.remove(index);
+        let sent = self.epoch + Duration::from_micros(timestamp - 1);
+        let sample = now.saturating_duration_since(sent);
+        self.estimator.sample(sample);
+        Some(sample)
+    }
+}
+
+/// Client-side estimate of the server's tick
+#[derive(Debug, Clone)]
+pub struct TickClock {
+    tick_duration: f64,
+    /// Estimated server tick at a local instant
+    base: Option<(f64, Instant)>,
+}
+
+impl TickClock {
+    pub fn new(tick_rate: u32) -> Self {
+        Self {
+            tick_duration: 1.0 / tick_rate.max(1) as f64,
+            base: None,
+        }
+    }
+
+    /// Fold in a pong that reported `tick` after a round trip of `rtt`
+    ///
+    /// The server is on average half a tick past the one it reports, and the
+    /// pong took about half the round trip to arrive.
+    pub fn update(&mut self, tick: u32, rtt: Duration, now: Instant) {
+        let observed = tick as f64 + 0.5 + rtt.as_secs_f64() / 2.0 / self.tick_duration;
+        let estimate = match self.tick_at(now) {
+            Some(current) if (observed - current).abs() <= MAX_TICK_DRIFT => {
+                current + (observed - current) * TICK_BLEND
+            }
+            _ => observed,
+        };
+        self.base = Some((estimate, now));
+    }
+
+    /// Estimated server tick at `now`, with the fraction of the tick elapsed
+    pub fn tick_at(&self, now: Instant) -> Option<f64> {
+        self.base.map(|(tick, at)| {
+            let elapsed = now.saturating_duration_since(at).as_secs_f64();
+            tick + elapsed / self.tick_duration
+        })
+    }
+}
@@ -0,0 +1,215 @@
+/// Simulated latency, jitter, loss, duplication, reordering and bandwidth
+///
+/// `LinkSimulator` sits between a transport and its socket: everything sent
+/// through it comes back out of `poll` once its simulated delivery time has
+/// passed. Datagram links can lose, duplicate and reorder; stream links only
+/// delay, keeping order like TCP does. Time is passed in, so tests can drive
+/// it without sleeping.
+use crate::config::NetworkConditions;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+/// Datagrams that would wait longer than this for bandwidth are dropped, as an
+/// overflowing router queue would
+const MAX_QUEUE_DELAY: Duration = Duration::from_secs(1);
+
+/// Smallest extra hold-back of a reordered datagram
+const MIN_REORDER_DELAY_MS: u32 = 20;
+
+/// What a simulator did to the traffic it carried
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LinkStats {
+    pub sent: u64,
+    /// Lost at random or to a full bandwidth queue
+    pub dropped: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+}
+
+#[derive(Debug)]
+pub struct LinkSimulator<T> {
+    conditions: NetworkConditions,
+    /// Streams keep order and never lose or duplicate
+    stream: bool,
+    rng: u64,
+    /// Items keyed by release time, then by send order
+    queue: BTreeMap<(Instant, u64), T>,
+    next_id: u64,
+    link_free_at: Option<Instant>,
+    last_release: Option<Instant>,
+    stats: LinkStats,
+}
+
+impl<T: Clone> LinkSimulator<T> {
+    /// Simulator for datagrams, applying every condition
+    pub fn datagrams(conditions: NetworkConditions) -> Self {
+        Self::new(conditions, false)
+    }
+
+    /// Simulator for an ordered stream, applying only latency, jitter and bandwidth
+    pub fn stream(conditions: NetworkConditions) -> Self {
+        Self::new(conditions, true)
+    }
+
+    fn new(conditions: NetworkConditions, stream: bool) -> Self {
+        Self {
+            // Spread simple seeds such as 0, 1, 2 over the whole state
+            rng: conditions.seed.wrapping_mul(0x2545_f491_4f6c_dd1d),
+            conditions,
+            stream,
+            queue: BTreeMap::new(),
+            next_id: 0,
+            link_free_at: None,
+            last_release: None,
+            stats: LinkStats::default(),
+        }
+    }
+
+    pub fn conditions(&self) -> &NetworkConditions {
+        &self.conditions
+    }
+
+    pub fn stats(&self) -> LinkStats {
+        self.stats
+    }
+
+    /// Number of items in flight
+    pub fn in_flight(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// When the next item is due, if any are in flight
+    pub fn next_release(&self) -> Option<Instant> {
+        self.queue.keys().next().map(|(release, _)| *release)
+    }
+
+    /// Put an item of `size` bytes on the link
+    pub fn send(&mut self, now: Instant, item: T, size: usize) {
+        self.stats.sent += 1;
+        let datagram = !self.stream;
+        if datagram && self.chance(self.conditions.loss) {
+            self.stats.dropped += 1;
+            return;
+        }
+
+        let mut departure = now;
+        if self.conditions.bandwidth_kbps > 0 {
+            let start = self.link_free_at.map_or(now, |free| free.max(now));
+            if datagram && start - now > MAX_QUEUE_DELAY {
+                self.stats.dropped += 1;
+                return;
+            }
+            let bits = size as f64 * 8.0;
+            let transmit =
+                Duration::from_secs_f64(bits / (self.conditions.bandwidth_kbps as f64 * 1000.0));
+            departure = start + transmit;
+            self.link_free_at = Some(departure);
+        }
+
+        let mut release =
+            departure + Duration::from_millis(self.conditions.latency_ms as u64) + self.jitter();
+        if datagram && self.chance(self.conditions.reordering) {
+            let hold = MIN_REORDER_DELAY_MS.max(2 * self.conditions.jitter_ms);
+            release += Duration::from_millis(self.uniform(1, hold) as u64);
+            self.stats.reordered += 1;
+        }
+        if self.stream {
+            release = self.last_release.map_or(release, |last| last.max(release));
+            self.last_release = Some(release);
+        }
+
+        if datagram && self.chance(self.conditions.duplication) {
+            let copy = release + self.jitter();
+            self.enqueue(copy, item.clone());
+            self.stats.duplicated += 1;
+        }
+        self.enqueue(release, item);
+    }
+
+    /// Take every item whose delivery time has come, in delivery order
+    pub fn poll(&mut self, now: Instant) -> Vec<T> {
+        let mut released = Vec::new();
+        while let Some(entry) = self.queue.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+            released.push(entry.remove());
+        }
+        released
+    }
+
+    fn enqueue(&mut self, release: Instant, item: T) {
+        self.queue.insert((release, self.next_id), item);
+        self.next_id += 1;
+    }
+
+    fn jitter(&mut self) -> Duration {
+        Duration::from_millis(self.uniform(0, self.conditions.jitter_ms) as u64)
+    }
+
+    fn chance(&mut self, probability: f32) -> bool {
+        if probability <= 0.0 {
+            return false;
+        }
+        // 24 random bits, exactly representable as f32
+        let roll = (self.next_random() >> 40) as f32 / (1u64 << 24) as f32;
+        roll < probability
+    }
+
+    /// Uniform integer in `min..=max`
+    fn uniform(&mut self, min: u32, max: u32) -> u32 {
+        if max <= min {
+            return min;
+        }
+        min + (self.next_random() % (max - min + 1) as u64) as u32
+    }
+
+    /// SplitMix64, good enough for test traffic and reproducible from the seed
+    fn next_random(&mut self) -> u64 {
+        self.rng = self.rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Pass a message channel through a stream simulator
+///
+/// Spawns a task that forwards everything from `input` once its simulated
+/// delivery time has come, in order. The returned channel closes after
+/// `input` closes and everything in flight has been delivered.
+pub fn delay_stream<T>(
+    conditions: NetworkConditions,
+    mut input: UnboundedReceiver<T>,
+    size: fn(&T) -> usize,
+) -> UnboundedReceiver<T>
+where
+    T: Clone + Send + 'static,
+{
+    let (output, delayed) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let mut link = LinkSimulator::stream(conditions);
+        let mut open = true;
+        while open || link.in_flight() > 0 {
+            let wake = link.next_release().map(tokio::time::Instant::from_std);
+            tokio::select! {
+                item = input.recv(), if open => match item {
+                    Some(item) => {
+                        let len = size(&item);
+                        link.send(Instant::now(), item, len);
+                    }
+                    None => open = false,
+                },
+                _ = tokio::time::sleep_until(wake.unwrap_or_else(tokio::time::Instant::now)), if wake.is_some() => {}
+            }
+            for item in link.poll(Instant::now()) {
+                if output.send(item).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    delayed
+}
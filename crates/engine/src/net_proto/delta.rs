@@ -0,0 +1,267 @@
+/// Delta compression of state snapshots
+///
+/// A `SnapshotDelta` describes a snapshot relative to an earlier one the
+/// client has acknowledged: only entities, fields and surfaces that changed
+/// are sent, plus the IDs of entities, players and surfaces that disappeared.
+/// Quantized values are compared exactly, so applying a delta reproduces the
+/// snapshot bit for bit.
+use super::snapshot::{
+    ActiveSpell, EntityKind, EntityState, HealthState, ManaState, PlayerUpdate, QuantizedPosition,
+    QuantizedRotation, QuantizedVelocity, StateSnapshot, StatusState, SurfaceUpdate,
+};
+use crate::physics_core::{EntityId, SurfaceState};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Changed fields of one entity; every field is set for entities new since the baseline
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EntityDelta {
+    pub id: EntityId,
+    pub kind: Option<EntityKind>,
+    pub position: Option<QuantizedPosition>,
+    pub rotation: Option<QuantizedRotation>,
+    pub velocity: Option<QuantizedVelocity>,
+    pub health: Option<Option<HealthState>>,
+    pub mana: Option<Option<ManaState>>,
+    pub active_spells: Option<Vec<ActiveSpell>>,
+    pub statuses: Option<Vec<StatusState>>,
+}
+
+impl EntityDelta {
+    fn full(entity: &EntityState) -> Self {
+        Self {
+            id: entity.id,
+            kind: Some(entity.kind),
+            position: Some(entity.position),
+            rotation: Some(entity.rotation),
+            velocity: Some(entity.velocity),
+            health: Some(entity.health),
+            mana: Some(entity.mana),
+            active_spells: Some(entity.active_spells.clone()),
+            statuses: Some(entity.statuses.clone()),
+        }
+    }
+
+    /// `None` if nothing changed
+    fn between(old: &EntityState, new: &EntityState) -> Option<Self> {
+        let delta = Self {
+            id: new.id,
+            kind: changed(&old.kind, &new.kind),
+            position: changed(&old.position, &new.position),
+            rotation: changed(&old.rotation, &new.rotation),
+            velocity: changed(&old.velocity, &new.velocity),
+            health: changed(&old.health, &new.health),
+            mana: changed(&old.mana, &new.mana),
+            active_spells: changed(&old.active_spells, &new.active_spells),
+            statuses: changed(&old.statuses, &new.statuses),
+        };
+        let unchanged = delta.kind.is_none()
+            && delta.position.is_none()
+            && delta.rotation.is_none()
+            && delta.velocity.is_none()
+            && delta.health.is_none()
+            && delta.mana.is_none()
+            && delta.active_spells.is_none()
+            && delta.statuses.is_none();
+        (!unchanged).then_some(delta)
+    }
+
+    fn apply_to(&self, entity: &mut EntityState) {
+        if let Some(kind) = self.kind {
+            entity.kind = kind;
+        }
+        if let Some(position) = self.position {
+            entity.position = position;
+        }
+        if let Some(rotation) = self.rotation {
+            entity.rotation = rotation;
+        }
+        if let Some(velocity) = self.velocity {
+            entity.velocity = velocity;
+        }
+        if let Some(health) = self.health {
+            entity.health = health;
+        }
+        if let Some(mana) = self.mana {
+            entity.mana = mana;
+        }
+        if let Some(active_spells) = &self.active_spells {
+            entity.active_spells = active_spells.clone();
+        }
+        if let Some(statuses) = &self.statuses {
+            entity.statuses = statuses.clone();
+        }
+    }
+
+    /// Build a new entity; fails unless every field is present
+    fn to_entity(&self) -> Option<EntityState> {
+        Some(EntityState {
+            id: self.id,
+            kind: self.kind?,
+            position: self.position?,
+            rotation: self.rotation?,
+            velocity: self.velocity?,
+            health: self.health?,
+            mana: self.mana?,
+            active_spells: self.active_spells.clone()?,
+            statuses: self.statuses.clone()?,
+        })
+    }
+}
+
+/// A snapshot encoded against an earlier, acknowledged baseline
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotDelta {
+    pub schema_version: u16,
+    pub tick: u32,
+    /// Tick of the snapshot this delta must be applied to
+    pub baseline_tick: u32,
+    /// New or changed entities in ascending ID order
+    pub entities: Vec<EntityDelta>,
+    pub removed_entities: Vec<EntityId>,
+    /// New or changed player updates
+    pub players: Vec<PlayerUpdate>,
+    pub removed_players: Vec<u32>,
+    /// New or changed surface states
+    pub surfaces: Vec<SurfaceUpdate>,
+    /// Surfaces back to normal
+    pub removed_surfaces: Vec<EntityId>,
+}
+
+impl SnapshotDelta {
+    /// Describe `current` relative to `baseline`
+    pub fn between(baseline: &StateSnapshot, current: &StateSnapshot) -> Self {
+        let old_entities: BTreeMap<_, _> = baseline
+            .entities
+            .iter()
+            .map(|entity| (entity.id, entity))
+            .collect();
+        let new_entities: BTreeMap<_, _> = current
+            .entities
+            .iter()
+            .map(|entity| (entity.id, entity))
+            .collect();
+        let entities = new_entities
+            .values()
+            .filter_map(|entity| match old_entities.get(&entity.id) {
+                Some(old) => EntityDelta::between(old, entity),
+                None => Some(EntityDelta::full(entity)),
+            })
+            .collect();
+        let removed_entities = old_entities
+            .keys()
+            .filter(|id| !new_entities.contains_key(id))
+            .copied()
+            .collect();
+
+        let old_players: BTreeMap<_, _> = baseline
+            .players
+            .iter()
+            .map(|player| (player.player_id, player))
+            .collect();
+        let players = current
+            .players
+            .iter()
+            .filter(|player| old_players.get(&player.player_id) != Some(player))
+            .copied()
+            .collect();
+        let removed_players = old_players
+            .keys()
+            .filter(|id| current.player(**id).is_none())
+            .copied()
+            .collect();
+
+        let surfaces = current
+            .surfaces
+            .iter()
+            .filter(|surface| baseline.surface(surface.entity) != surface.state)
+            .copied()
+            .collect();
+        let removed_surfaces = baseline
+            .surfaces
+            .iter()
+            .map(|surface| surface.entity)
+            .filter(|entity| current.surface(*entity) == SurfaceState::Normal)
+            .collect();
+
+        Self {
+            schema_version: current.schema_version,
+            tick: current.tick,
+            baseline_tick: baseline.tick,
+            entities,
+            removed_entities,
+            players,
+            removed_players,
+            surfaces,
+            removed_surfaces,
+        }
+    }
+
+    /// Rebuild the full snapshot from the baseline it was encoded against
+    pub fn apply(&self, baseline: &StateSnapshot) -> anyhow::Result<StateSnapshot> {
+        if baseline.tick != self.baseline_tick {
+            anyhow::bail!(
+                "Delta for tick {} needs baseline {}, got {}",
+                self.tick,
+                self.baseline_tick,
+                baseline.tick
+            );
+        }
+
+        let mut entities: BTreeMap<_, _> = baseline
+            .entities
+            .iter()
+            .map(|entity| (entity.id, entity.clone()))
+            .collect();
+        for id in &self.removed_entities {
+            entities.remove(id);
+        }
+        for delta in &self.entities {
+            match entities.get_mut(&delta.id) {
+                Some(entity) => delta.apply_to(entity),
+                None => {
+                    let entity = delta.to_entity().ok_or_else(|| {
+                        anyhow::anyhow!("New entity {:?} is missing fields", delta.id)
+                    })?;
+                    entities.insert(delta.id, entity);
+                }
+            }
+        }
+
+        let mut players: BTreeMap<_, _> = baseline
+            .players
+            .iter()
+            .map(|player| (player.player_id, *player))
+            .collect();
+        for id in &self.removed_players {
+            players.remove(id);
+        }
+        for player in &self.players {
+            players.insert(player.player_id, *player);
+        }
+
+        let mut surfaces: BTreeMap<_, _> = baseline
+            .surfaces
+            .iter()
+            .map(|surface| (surface.entity, *surface))
+            .collect();
+        for entity in &self.removed_surfaces {
+            surfaces.remove(entity);
+        }
+        for surface in &self.surfaces {
+            surfaces.insert(surface.entity, *surface);
+        }
+
+        Ok(StateSnapshot {
+            schema_version: self.schema_version,
+            tick: self.tick,
+            entities: entities.into_values().collect(),
+            players: players.into_values().collect(),
+            surfaces: surfaces.into_values().collect(),
+        })
+    }
+}
+
+fn changed<T: PartialEq + Clone>(old: &T, new: &T) -> Option<T> {
+    (old != new).then(|| new.clone())
+}
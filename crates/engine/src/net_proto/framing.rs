@@ -0,0 +1,163 @@
+/// Length-prefixed framing for protocol messages over byte streams
+///
+/// Every frame is a little-endian `u32` payload length followed by the
+/// bincode-encoded message. Frames larger than `MAX_FRAME_SIZE` are rejected
+/// on both ends so a corrupted or hostile length prefix cannot make us
+/// allocate unbounded memory.
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Size of the length prefix in bytes
+pub const FRAME_HEADER_SIZE: usize = 4;
+
+/// Largest payload accepted in a single frame (1 MiB)
+pub const MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Encode a message into a complete frame (length prefix + payload)
+pub fn encode_frame<T: Serialize>(msg: &T) -> anyhow::Result<Vec<u8>> {
+    let payload = bincode::serialize(msg)?;
+    if payload.len() > MAX_FRAME_SIZE {
+        anyhow::bail!(
+            "Frame payload of {} bytes exceeds maximum of {} bytes",
+            payload.len(),
+            MAX_FRAME_SIZE
+        );
+    }
+
+    let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+/// Incremental frame decoder
+///
+/// Bytes are pushed in as they arrive from the transport; complete frames are
+/// popped out once their full payload is buffered. Partial frames stay in the
+/// buffer until the rest of the data shows up.
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append raw bytes received from the transport
+    pub fn push_bytes(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Number of buffered bytes not yet consumed as a frame
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Pop the next complete frame payload, if one is fully buffered
+    pub fn next_frame(&mut self) -> anyhow::Result<Option<Vec<u8>>> {
+        if self.buffer.len() < FRAME_HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let mut header = [0u8; FRAME_HEADER_SIZE];
+        header.copy_from_slice(&self.buffer[..FRAME_HEADER_SIZE]);
+        let len = u32::from_le_bytes(header) as usize;
+        if len > MAX_FRAME_SIZE {
+            anyhow::bail!(
+                "Incoming frame of {} bytes exceeds maximum of {} bytes",
+                len,
+                MAX_FRAME_SIZE
+            );
+        }
+
+        if self.buffer.len() < FRAME_HEADER_SIZE + len {
+            return Ok(None);
+        }
+
+        let payload = self.buffer[FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + len].to_vec();
+        self.buffer.drain(..FRAME_HEADER_SIZE + len);
+        Ok(Some(payload))
+    }
+
+    /// Pop and decode the next complete message, if one is fully buffered
+    pub fn next_message<T: DeserializeOwned>(&mut self) -> anyhow::Result<Option<T>> {
+        match self.next_frame()? {
+            Some(payload) => Ok(Some(bincode::deserialize(&payload)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Reads framed messages from an async byte stream
+///
+/// `read_message` is cancel-safe: partially received frames are kept in the
+/// decoder, so it can be used inside `tokio::select!`.
+pub struct FrameReader<R> {
+    inner: R,
+    decoder: FrameDecoder,
+    read_buffer: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> FrameReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            decoder: FrameDecoder::new(),
+            read_buffer: vec![0; 4096],
+        }
+    }
+
+    /// Read the next message, returning `None` on a clean end of stream
+    pub async fn read_message<T: DeserializeOwned>(&mut self) -> anyhow::Result<Option<T>> {
+        loop {
+            if let Some(msg) = self.decoder.next_message()? {
+                return Ok(Some(msg));
+            }
+
+            let n = self.inner.read(&mut self.read_buffer).await?;
+            if n == 0 {
+                if self.decoder.buffered_len() == 0 {
+                    return Ok(None);
+                }
+                anyhow::bail!(
+                    "Connection closed with {} bytes of an incomplete frame",
+                    self.decoder.buffered_len()
+                );
+            }
+            self.decoder.push_bytes(&self.read_buffer[..n]);
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+/// Writes framed messages to an async byte stream
+pub struct FrameWriter<W> {
+    inner: W,
+}
+
+impl<W: AsyncWrite + Unpin> FrameWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    pub async fn write_message<T: Serialize>(&mut self, msg: &T) -> anyhow::Result<()> {
+        let frame = encode_frame(msg)?;
+        self.inner.write_all(&frame).await?;
+        self.inner.flush().await?;
+        Ok(())
+    }
+
+    pub async fn shutdown(&mut self) -> anyhow::Result<()> {
+        self.inner.shutdown().await?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
@@ -1,6 +1,37 @@
 /// Network protocol schema module
+use crate::config::MovementConfig;
 use serde::{Deserialize, Serialize};
 
+pub mod clock;
+pub mod conditions;
+pub mod delta;
+pub mod framing;
+pub mod reliability;
+pub mod snapshot;
+
+pub use clock::{PingTimer, RttEstimator, TickClock, PING_INTERVAL};
+pub use conditions::{delay_stream, LinkSimulator, LinkStats};
+pub use delta::{EntityDelta, SnapshotDelta};
+pub use framing::{encode_frame, FrameDecoder, FrameReader, FrameWriter, MAX_FRAME_SIZE};
+pub use reliability::{Channel, Endpoint, EndpointStats, MAX_DATAGRAM_SIZE, MAX_MESSAGE_SIZE};
+pub use snapshot::{
+    ActiveSpell, EntityKind, EntityState, HealthState, ManaState, PlayerUpdate, QuantizedPosition,
+    QuantizedRotation, QuantizedVelocity, StateSnapshot, StatusState, SurfaceUpdate,
+    SNAPSHOT_SCHEMA_VERSION,
+};
+
+/// Wire protocol version, bumped on every incompatible message change
+pub const PROTOCOL_VERSION: u32 = 14;
+
+/// Identifier of the build that produced this binary
+///
+/// Set `URMOM_BUILD_HASH` at compile time (e.g. to the git commit) to refuse
+/// connections between different builds; defaults to the crate version.
+pub const BUILD_HASH: &str = match option_env!("URMOM_BUILD_HASH") {
+    Some(hash) => hash,
+    None => env!("CARGO_PKG_VERSION"),
+};
+
 /// Player input state sent from client to server
 /// Contains only raw controller/keyboard state - server calculates actual movement
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -23,20 +54,182 @@ pub struct PlayerInput {
     pub crouch: bool,
     pub cast_spell: bool,
     pub use_item: bool,
+
+    /// Spell slot `cast_spell` casts from; switching slots cancels a cast
+    pub spell_slot: u8,
+
+    /// How far behind the server the client renders remote entities (ms), so
+    /// lag compensation can rewind to what the player saw
+    pub interpolation_delay_ms: u16,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ClientMessage {
-    Connect { player_name: String },
+    /// Must be the first message on a connection
+    Connect {
+        player_name: String,
+        protocol_version: u32,
+        build_hash: String,
+        /// Token from an earlier `Welcome`, to take back that player after a
+        /// dropped connection
+        session_token: Option<u64>,
+    },
     Input(PlayerInput),
-    Disconnect,
+    /// The snapshot for `tick` was received and can serve as a delta baseline
+    SnapshotAck {
+        tick: u32,
+    },
+    /// Liveness and round-trip probe, answered with `ServerMessage::Pong`
+    /// echoing `id` and `timestamp`
+    Ping {
+        id: u32,
+        /// Sender's clock, in microseconds since an arbitrary epoch
+        timestamp: u64,
+    },
+    /// Answer to `ServerMessage::Ping`
+    Pong {
+        id: u32,
+        timestamp: u64,
+    },
+    /// The client is leaving; the server ends the session
+    Disconnect {
+        reason: DisconnectReason,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ServerMessage {
-    Welcome { player_id: u32 },
-    StateUpdate { tick: u32, data: Vec<u8> },
-    Disconnect { reason: String },
+    Welcome {
+        player_id: u32,
+        /// Proves ownership of `player_id` when reconnecting
+        session_token: u64,
+        /// Movement constants the server simulates with, for client prediction
+        movement: MovementConfig,
+        /// Simulation ticks per second, to turn snapshot ticks into time
+        tick_rate: u32,
+    },
+    /// Full world state after a server tick
+    StateUpdate(StateSnapshot),
+    /// World state after a server tick, relative to a snapshot the client acknowledged
+    StateDelta(SnapshotDelta),
+    /// Sent periodically to measure the round trip to the client, answered
+    /// with `ClientMessage::Pong`
+    Ping { id: u32, timestamp: u64 },
+    /// Answer to `ClientMessage::Ping`
+    Pong {
+        id: u32,
+        timestamp: u64,
+        /// Last tick the server simulated when replying
+        tick: u32,
+    },
+    /// Casts that started, finished or failed during a server tick
+    CastEvents(Vec<CastEvent>),
+    /// The server is ending the session
+    Disconnect { reason: DisconnectReason },
+}
+
+/// Something that happened to a player's cast, sent to every client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CastEvent {
+    /// Server tick it happened in
+    pub tick: u32,
+    pub player_id: u32,
+    pub spell_id: u32,
+    pub kind: CastEventKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CastEventKind {
+    /// The wind-up began
+    Started,
+    /// The spell went off, or a channeled spell ended normally
+    Completed,
+    /// The cast stopped before completing
+    Interrupted(InterruptReason),
+    /// The cast button was pressed but the spell could not be cast
+    Rejected(CastRejection),
+}
+
+/// Why a cast stopped before completing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InterruptReason {
+    /// The caster moved during a spell not castable while moving
+    Moved,
+    /// The caster took damage
+    Damaged,
+    /// The caster selected another spell slot
+    Cancelled,
+    /// The caster could no longer pay the mana cost when the spell went off
+    OutOfMana,
+    /// The caster was silenced, frozen or stunned
+    Silenced,
+    /// The caster died
+    Died,
+}
+
+/// Why pressing the cast button did not start a cast
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CastRejection {
+    OnCooldown,
+    NotEnoughMana,
+    /// Moving, and the spell cannot be cast while moving
+    Moving,
+    /// Silenced, frozen or stunned
+    Silenced,
+}
+
+/// Why a connection ended, sent by whichever side ends it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DisconnectReason {
+    /// The player left the game
+    Quit,
+    /// Nothing arrived from the peer within the timeout
+    TimedOut,
+    /// The handshake failed, e.g. on a version mismatch
+    Refused(String),
+    /// Removed by the server, e.g. for cheating
+    Kicked(String),
+    /// The server is shutting down, e.g. the host of a LAN game quit
+    ServerShutdown,
+}
+
+impl std::fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Quit => write!(f, "Left the game"),
+            Self::TimedOut => write!(f, "Connection timed out"),
+            Self::Refused(reason) => write!(f, "Connection refused: {}", reason),
+            Self::Kicked(reason) => write!(f, "Kicked: {}", reason),
+            Self::ServerShutdown => write!(f, "Server shut down"),
+        }
+    }
+}
+
+impl ClientMessage {
+    /// Channel this message travels on over datagram transports
+    pub fn channel(&self) -> Channel {
+        match self {
+            // Superseded by the next one, so never worth resending
+            Self::Input(_) | Self::SnapshotAck { .. } | Self::Ping { .. } | Self::Pong { .. } => {
+                Channel::Unreliable
+            }
+            Self::Connect { .. } | Self::Disconnect { .. } => Channel::ReliableOrdered,
+        }
+    }
+}
+
+impl ServerMessage {
+    /// Channel this message travels on over datagram transports
+    pub fn channel(&self) -> Channel {
+        match self {
+            Self::StateUpdate(_) | Self::StateDelta(_) | Self::Ping { .. } | Self::Pong { .. } => {
+                Channel::Unreliable
+            }
+            Self::Welcome { .. } | Self::CastEvents(_) | Self::Disconnect { .. } => {
+                Channel::ReliableOrdered
+            }
+        }
+    }
 }
 
 pub fn encode_message<T: Serialize>(msg: &T) -> anyhow::Result<Vec<u8>> {
@@ -0,0 +1,361 @@
+/// Sequenced packets with acks and two channels, for datagram transports
+///
+/// Every packet carries a 16-bit sequence number plus the newest sequence
+/// received from the peer and a bitfield of the 31 before it, so a packet is
+/// acknowledged by any of the next 32 the peer sends. On top of that:
+///
+/// - `Channel::Unreliable` messages are sent once and delivered in whatever
+///   order their packets arrive; duplicates and packets too old to be acked
+///   are dropped. Snapshots and inputs go here, since a newer one replaces a
+///   lost one anyway.
+/// - `Channel::ReliableOrdered` messages are resent until a packet carrying
+///   them is acked and delivered exactly once, in send order.
+///
+/// `Endpoint` does no I/O: sockets feed it datagrams and send what it returns,
+/// with the current time passed in so tests can drive it deterministically.
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Identifies our datagrams; anything else arriving on the socket is ignored
+pub const PACKET_MAGIC: u32 = 0x5552_4d4d;
+
+/// Datagrams are filled with messages up to this size, safely below common MTUs
+pub const MAX_DATAGRAM_SIZE: usize = 1200;
+
+/// Largest single message; bigger than `MAX_DATAGRAM_SIZE` ones are sent in a
+/// datagram of their own and rely on IP fragmentation
+pub const MAX_MESSAGE_SIZE: usize = 60 * 1024;
+
+/// Sequences older than this relative to the newest can no longer be acked
+const ACK_WINDOW: u16 = 32;
+
+/// Reliable messages in flight or buffered out of order at most
+const RELIABLE_WINDOW: u16 = 1024;
+
+/// Resend delay until the first round trip has been measured
+const DEFAULT_RESEND_TIMEOUT: Duration = Duration::from_millis(100);
+const MIN_RESEND_TIMEOUT: Duration = Duration::from_millis(30);
+const MAX_RESEND_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Fixed bytes of an encoded packet: magic, sequence, ack, ack bits, two vec lengths
+const PACKET_OVERHEAD: usize = 4 + 2 + 2 + 4 + 8 + 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Channel {
+    Unreliable,
+    ReliableOrdered,
+}
+
+/// Packet and channel counters of one endpoint
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EndpointStats {
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    /// Sent packets the peer confirmed receiving
+    pub packets_acked: u64,
+    /// Received packets discarded as duplicates or too old
+    pub packets_dropped: u64,
+    /// Reliable messages sent again after their resend timeout
+    pub reliable_resends: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Packet {
+    magic: u32,
+    sequence: u16,
+    /// Newest sequence received from the peer
+    ack: u16,
+    /// Bit `i` set means `ack - i` was received; zero before anything arrived
+    ack_bits: u32,
+    reliable: Vec<(u16, Vec<u8>)>,
+    unreliable: Vec<Vec<u8>>,
+}
+
+#[derive(Debug)]
+struct SentPacket {
+    sent_at: Instant,
+    reliable_ids: Vec<u16>,
+}
+
+#[derive(Debug)]
+struct OutgoingReliable {
+    id: u16,
+    payload: Vec<u8>,
+    last_sent: Option<Instant>,
+    acked: bool,
+}
+
+/// One side of a datagram connection
+#[derive(Debug)]
+pub struct Endpoint {
+    next_sequence: u16,
+    sent: HashMap<u16, SentPacket>,
+    remote_sequence: u16,
+    received_bits: u32,
+    ack_pending: bool,
+
+    unreliable_queue: Vec<Vec<u8>>,
+    next_reliable_id: u16,
+    /// Unacked reliable messages in ID order
+    reliable_queue: VecDeque<OutgoingReliable>,
+    next_expected_reliable: u16,
+    reliable_received: HashMap<u16, Vec<u8>>,
+
+    rtt: Option<Duration>,
+    stats: EndpointStats,
+}
+
+impl Endpoint {
+    pub fn new() -> Self {
+        Self {
+            next_sequence: 0,
+            sent: HashMap::new(),
+            remote_sequence: 0,
+            received_bits: 0,
+            ack_pending: false,
+            unreliable_queue: Vec::new(),
+            next_reliable_id: 0,
+            reliable_queue: VecDeque::new(),
+            next_expected_reliable: 0,
+            reliable_received: HashMap::new(),
+            rtt: None,
+            stats: EndpointStats::default(),
+        }
+    }
+
+    /// Smoothed round-trip time measured from acks
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    pub fn stats(&self) -> EndpointStats {
+        self.stats
+    }
+
+    /// Number of reliable messages not yet acknowledged by the peer
+    pub fn unacked_reliable(&self) -> usize {
+        self.reliable_queue.len()
+    }
+
+    /// Queue a message for the next `poll_transmit`
+    pub fn send(&mut self, channel: Channel, payload: Vec<u8>) -> anyhow::Result<()> {
+        if payload.len() > MAX_MESSAGE_SIZE {
+            anyhow::bail!(
+                "Message of {} bytes exceeds maximum of {} bytes",
+                payload.len(),
+                MAX_MESSAGE_SIZE
+            );
+        }
+
+        match channel {
+            Channel::Unreliable => self.unreliable_queue.push(payload),
+            Channel::ReliableOrdered => {
+                self.reliable_queue.push_back(OutgoingReliable {
+                    id: self.next_reliable_id,
+                    payload,
+                    last_sent: None,
+                    acked: false,
+                });
+                self.next_reliable_id = self.next_reliable_id.wrapping_add(1);
+            }
+        }
+        Ok(())
+    }
+
+    /// Build the datagrams to send now
+    ///
+    /// Packs queued unreliable messages, reliable messages that were never
+    /// sent or whose resend timeout expired, and an ack-only packet if
+    /// something arrived since we last sent.
+    pub fn poll_transmit(&mut self, now: Instant) -> Vec<Vec<u8>> {
+        let resend_timeout = self.resend_timeout();
+        let window_start = self.reliable_queue.front().map(|message| message.id);
+        let mut reliable = Vec::new();
+        for message in self.reliable_queue.iter_mut() {
+            let offset = message.id.wrapping_sub(window_start.unwrap_or(message.id));
+            if offset >= RELIABLE_WINDOW {
+                break;
+            }
+            let due = match message.last_sent {
+                None => true,
+                Some(sent) => !message.acked && now.duration_since(sent) >= resend_timeout,
+            };
+            if due {
+                if message.last_sent.is_some() {
+                    self.stats.reliable_resends += 1;
+                }
+                message.last_sent = Some(now);
+                reliable.push((message.id, message.payload.clone()));
+            }
+        }
+        let unreliable = std::mem::take(&mut self.unreliable_queue);
+
+        let mut datagrams = Vec::new();
+        let mut packet = self.empty_packet();
+        let mut size = PACKET_OVERHEAD;
+        for (id, payload) in reliable {
+            let len = 2 + 8 + payload.len();
+            if size + len > MAX_DATAGRAM_SIZE && !packet.reliable.is_empty() {
+                datagrams.push(self.finish_packet(packet, now));
+                packet = self.empty_packet();
+                size = PACKET_OVERHEAD;
+            }
+            packet.reliable.push((id, payload));
+            size += len;
+        }
+        for payload in unreliable {
+            let len = 8 + payload.len();
+            let packet_empty = packet.reliable.is_empty() && packet.unreliable.is_empty();
+            if size + len > MAX_DATAGRAM_SIZE && !packet_empty {
+                datagrams.push(self.finish_packet(packet, now));
+                packet = self.empty_packet();
+                size = PACKET_OVERHEAD;
+            }
+            packet.unreliable.push(payload);
+            size += len;
+        }
+        if !packet.reliable.is_empty() || !packet.unreliable.is_empty() || self.ack_pending {
+            datagrams.push(self.finish_packet(packet, now));
+        }
+        datagrams
+    }
+
+    /// Process a received datagram and return the messages it delivers
+    ///
+    /// Reliable messages come first and only once every earlier one has been
+    /// delivered; unreliable ones follow as they arrived.
+    pub fn receive(&mut self, datagram: &[u8], now: Instant) -> anyhow::Result<Vec<Vec<u8>>> {
+        let packet: Packet = bincode::deserialize(datagram)?;
+        if packet.magic != PACKET_MAGIC {
+            anyhow::bail!("Not a game packet");
+        }
+        self.stats.packets_received += 1;
+
+        self.process_acks(packet.ack, packet.ack_bits, now);
+        if !self.record_received(packet.sequence) {
+            self.stats.packets_dropped += 1;
+            return Ok(Vec::new());
+        }
+        // Ack-only packets are not acked themselves, or two idle peers would
+        // bounce acks forever
+        if !packet.reliable.is_empty() || !packet.unreliable.is_empty() {
+            self.ack_pending = true;
+        }
+
+        let mut delivered = Vec::new();
+        for (id, payload) in packet.reliable {
+            let offset = id.wrapping_sub(self.next_expected_reliable);
+            if offset < RELIABLE_WINDOW {
+                self.reliable_received.entry(id).or_insert(payload);
+            }
+        }
+        while let Some(payload) = self.reliable_received.remove(&self.next_expected_reliable) {
+            delivered.push(payload);
+            self.next_expected_reliable = self.next_expected_reliable.wrapping_add(1);
+        }
+        delivered.extend(packet.unreliable);
+        Ok(delivered)
+    }
+
+    fn resend_timeout(&self) -> Duration {
+        self.rtt
+            .map(|rtt| (rtt * 2).clamp(MIN_RESEND_TIMEOUT, MAX_RESEND_TIMEOUT))
+            .unwrap_or(DEFAULT_RESEND_TIMEOUT)
+    }
+
+    fn empty_packet(&self) -> Packet {
+        Packet {
+            magic: PACKET_MAGIC,
+            sequence: 0,
+            ack: self.remote_sequence,
+            ack_bits: self.received_bits,
+            reliable: Vec::new(),
+            unreliable: Vec::new(),
+        }
+    }
+
+    fn finish_packet(&mut self, mut packet: Packet, now: Instant) -> Vec<u8> {
+        packet.sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        // Packets this far back can no longer be acked
+        self.sent.remove(&packet.sequence.wrapping_sub(ACK_WINDOW));
+        self.sent.insert(
+            packet.sequence,
+            SentPacket {
+                sent_at: now,
+                reliable_ids: packet.reliable.iter().map(|(id, _)| *id).collect(),
+            },
+        );
+        self.ack_pending = false;
+        self.stats.packets_sent += 1;
+        bincode::serialize(&packet).expect("packets always serialize")
+    }
+
+    fn process_acks(&mut self, ack: u16, ack_bits: u32, now: Instant) {
+        for bit in 0..ACK_WINDOW {
+            if ack_bits & (1 << bit) == 0 {
+                continue;
+            }
+            let Some(sent) = self.sent.remove(&ack.wrapping_sub(bit)) else {
+                continue;
+            };
+            self.stats.packets_acked += 1;
+            let sample = now.duration_since(sent.sent_at);
+            self.rtt = Some(match self.rtt {
+                Some(rtt) => rtt.mul_f32(0.9) + sample.mul_f32(0.1),
+                None => sample,
+            });
+
+            let Some(first) = self.reliable_queue.front().map(|message| message.id) else {
+                continue;
+            };
+            for id in sent.reliable_ids {
+                let index = id.wrapping_sub(first) as usize;
+                if let Some(message) = self.reliable_queue.get_mut(index) {
+                    message.acked = true;
+                }
+            }
+            while self
+                .reliable_queue
+                .front()
+                .is_some_and(|message| message.acked)
+            {
+                self.reliable_queue.pop_front();
+            }
+        }
+    }
+
+    /// Mark a sequence as received; false for duplicates and packets too old to track
+    fn record_received(&mut self, sequence: u16) -> bool {
+        if self.received_bits == 0 {
+            self.remote_sequence = sequence;
+            self.received_bits = 1;
+            return true;
+        }
+
+        let ahead = sequence.wrapping_sub(self.remote_sequence);
+        if ahead != 0 && ahead < u16::MAX / 2 {
+            self.received_bits = if ahead < ACK_WINDOW {
+                (self.received_bits << ahead) | 1
+            } else {
+                1
+            };
+            self.remote_sequence = sequence;
+            return true;
+        }
+
+        let behind = self.remote_sequence.wrapping_sub(sequence);
+        if behind >= ACK_WINDOW || self.received_bits & (1 << behind) != 0 {
+            return false;
+        }
+        self.received_bits |= 1 << behind;
+        true
+    }
+}
+
+impl Default for Endpoint {
+    fn default() -> Self {
+        Self::new()
+    }
+}
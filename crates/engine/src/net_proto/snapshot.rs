@@ -0,0 +1,291 @@
+/// Typed world state sent to clients every tick
+///
+/// Positions, rotations and velocities of replicated entities are quantized to
+/// keep updates small. The local player's `CharacterState` in `PlayerUpdate`
+/// stays at full precision because client reconciliation replays inputs from
+/// it and must end up exactly where the server is.
+use crate::physics_core::{CharacterState, EntityId, SurfaceState};
+use crate::spells::StatusKind;
+use glam::{Quat, Vec3};
+use serde::{Deserialize, Serialize};
+
+/// Version of the snapshot layout, bumped whenever a field changes meaning or encoding
+pub const SNAPSHOT_SCHEMA_VERSION: u16 = 4;
+
+/// Size of one position step in metres
+pub const POSITION_STEP: f32 = 1.0 / 1024.0;
+/// Size of one velocity step in metres per second
+pub const VELOCITY_STEP: f32 = 1.0 / 64.0;
+
+/// Server-confirmed state of one player's character
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PlayerUpdate {
+    pub player_id: u32,
+    /// `PlayerInput::sequence` of the last input applied to `state`, if any
+    pub last_input_sequence: Option<u32>,
+    pub state: CharacterState,
+}
+
+/// Elemental state of an entity's surface, including level geometry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SurfaceUpdate {
+    pub entity: EntityId,
+    pub state: SurfaceState,
+}
+
+/// Everything a client needs to show the world after one server tick
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    pub schema_version: u16,
+    pub tick: u32,
+    /// Replicated entities in ascending ID order; static level geometry is not included
+    pub entities: Vec<EntityState>,
+    /// Authoritative state and input acknowledgement of every player
+    pub players: Vec<PlayerUpdate>,
+    /// Surfaces that are not in their normal state, in ascending entity order
+    pub surfaces: Vec<SurfaceUpdate>,
+}
+
+impl StateSnapshot {
+    pub fn new(tick: u32) -> Self {
+        Self {
+            schema_version: SNAPSHOT_SCHEMA_VERSION,
+            tick,
+            entities: Vec::new(),
+            players: Vec::new(),
+            surfaces: Vec::new(),
+        }
+    }
+
+    /// Reject snapshots written with a different schema
+    pub fn check_schema(&self) -> anyhow::Result<()> {
+        if self.schema_version != SNAPSHOT_SCHEMA_VERSION {
+            anyhow::bail!(
+                "Snapshot schema {} is not supported (expected {})",
+                self.schema_version,
+                SNAPSHOT_SCHEMA_VERSION
+            );
+        }
+        Ok(())
+    }
+
+    pub fn entity(&self, id: EntityId) -> Option<&EntityState> {
+        self.entities
+            .binary_search_by_key(&id, |entity| entity.id)
+            .ok()
+            .map(|index| &self.entities[index])
+    }
+
+    /// State of an entity's surface; unlisted surfaces are normal
+    pub fn surface(&self, entity: EntityId) -> SurfaceState {
+        self.surfaces
+            .binary_search_by_key(&entity, |surface| surface.entity)
+            .map_or(SurfaceState::Normal, |index| self.surfaces[index].state)
+    }
+
+    pub fn player(&self, player_id: u32) -> Option<&PlayerUpdate> {
+        self.players
+            .iter()
+            .find(|player| player.player_id == player_id)
+    }
+}
+
+/// What a replicated entity is, so clients know how to render it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EntityKind {
+    Player {
+        player_id: u32,
+    },
+    /// A spell projectile; the caster's client matches it to the one it predicted
+    Projectile {
+        /// Player who cast it
+        owner: u32,
+        spell_id: u32,
+        /// `PlayerInput::sequence` of the input that started the cast
+        cast_sequence: u32,
+    },
+    /// Any other moving body, e.g. a crate or door
+    Prop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthState {
+    pub current: u16,
+    pub max: u16,
+    /// Damage the entity's shields still absorb
+    pub shield: u16,
+}
+
+impl HealthState {
+    /// Health is rounded up, so it replicates as 0 only once the entity died
+    pub fn is_dead(&self) -> bool {
+        self.current == 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManaState {
+    pub current: u16,
+    pub max: u16,
+}
+
+/// A timed status effect on an entity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatusState {
+    pub kind: StatusKind,
+    pub stacks: u32,
+    /// Server tick at which it wears off
+    pub until_tick: u32,
+}
+
+/// A spell an entity is currently casting or sustaining
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActiveSpell {
+    pub spell_id: u32,
+    /// Ticks since the spell started
+    pub elapsed_ticks: u32,
+}
+
+/// Quantized state of one replicated entity
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EntityState {
+    pub id: EntityId,
+    pub kind: EntityKind,
+    pub position: QuantizedPosition,
+    pub rotation: QuantizedRotation,
+    pub velocity: QuantizedVelocity,
+    pub health: Option<HealthState>,
+    pub mana: Option<ManaState>,
+    pub active_spells: Vec<ActiveSpell>,
+    /// Status effects in `StatusKind` order
+    pub statuses: Vec<StatusState>,
+}
+
+impl EntityState {
+    pub fn new(
+        id: EntityId,
+        kind: EntityKind,
+        position: Vec3,
+        rotation: Quat,
+        velocity: Vec3,
+    ) -> Self {
+        Self {
+            id,
+            kind,
+            position: QuantizedPosition::new(position),
+            rotation: QuantizedRotation::new(rotation),
+            velocity: QuantizedVelocity::new(velocity),
+            health: None,
+            mana: None,
+            active_spells: Vec::new(),
+            statuses: Vec::new(),
+        }
+    }
+
+    pub fn position(&self) -> Vec3 {
+        self.position.get()
+    }
+
+    pub fn rotation(&self) -> Quat {
+        self.rotation.get()
+    }
+
+    pub fn velocity(&self) -> Vec3 {
+        self.velocity.get()
+    }
+}
+
+/// Position in `POSITION_STEP` units, covering about ±2000 km
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuantizedPosition(pub [i32; 3]);
+
+impl QuantizedPosition {
+    pub fn new(position: Vec3) -> Self {
+        Self(
+            position
+                .to_array()
+                .map(|v| quantize(v, POSITION_STEP) as i32),
+        )
+    }
+
+    pub fn get(&self) -> Vec3 {
+        Vec3::from_array(self.0.map(|v| v as f32 * POSITION_STEP))
+    }
+}
+
+/// Velocity in `VELOCITY_STEP` units, saturating at ±512 m/s
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuantizedVelocity(pub [i16; 3]);
+
+impl QuantizedVelocity {
+    pub fn new(velocity: Vec3) -> Self {
+        Self(
+            velocity
+                .to_array()
+                .map(|v| quantize(v, VELOCITY_STEP).clamp(i16::MIN as f32, i16::MAX as f32) as i16),
+        )
+    }
+
+    pub fn get(&self) -> Vec3 {
+        Vec3::from_array(self.0.map(|v| v as f32 * VELOCITY_STEP))
+    }
+}
+
+/// Unit quaternion packed with the "smallest three" scheme
+///
+/// The two top bits hold the index of the largest component, which is dropped
+/// and rebuilt from the other three; those are stored as 10-bit values in the
+/// range ±1/√2. The error is below 0.2 degrees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuantizedRotation(pub u32);
+
+impl QuantizedRotation {
+    const BITS: u32 = 10;
+    const MAX: f32 = ((1 << Self::BITS) - 1) as f32;
+    const RANGE: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+    pub fn new(rotation: Quat) -> Self {
+        let mut components = rotation.normalize().to_array();
+        let largest = (0..4)
+            .max_by(|a, b| components[*a].abs().total_cmp(&components[*b].abs()))
+            .unwrap_or(3);
+        // q and -q are the same rotation; make the dropped component positive
+        if components[largest] < 0.0 {
+            components = components.map(|c| -c);
+        }
+
+        let mut packed = (largest as u32) << (3 * Self::BITS);
+        let mut shift = 2 * Self::BITS;
+        for (index, component) in components.iter().enumerate() {
+            if index == largest {
+                continue;
+            }
+            let normalized = (component / Self::RANGE).clamp(-1.0, 1.0) * 0.5 + 0.5;
+            packed |= ((normalized * Self::MAX).round() as u32) << shift;
+            shift = shift.wrapping_sub(Self::BITS);
+        }
+        Self(packed)
+    }
+
+    pub fn get(&self) -> Quat {
+        let largest = (self.0 >> (3 * Self::BITS)) as usize;
+        let mut components = [0.0; 4];
+        let mut shift = 2 * Self::BITS;
+        let mut sum = 0.0;
+        for (index, component) in components.iter_mut().enumerate() {
+            if index == largest {
+                continue;
+            }
+            let raw = (self.0 >> shift) & ((1 << Self::BITS) - 1);
+            *component = (raw as f32 / Self::MAX - 0.5) * 2.0 * Self::RANGE;
+            sum += *component * *component;
+            shift = shift.wrapping_sub(Self::BITS);
+        }
+        components[largest] = (1.0 - sum).max(0.0).sqrt();
+        Quat::from_array(components).normalize()
+    }
+}
+
+fn quantize(value: f32, step: f32) -> f32 {
+    (value / step).round()
+}
@@ -0,0 +1,450 @@
+/// Body and collider management with stable game-level entity IDs
+///
+/// Rapier handles are only meaningful inside one `PhysicsWorld`, so everything
+/// outside the physics core refers to bodies by `EntityId`. The server assigns
+/// IDs and clients spawn their mirrored bodies with the same IDs through
+/// `spawn_with_id`, which makes an ID valid on both ends of the connection.
+use super::materials::{ColliderMaterial, Material};
+use super::{from_vector, to_vector, PhysicsWorld};
+use glam::{Quat, Vec3};
+use rapier3d::na;
+use rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Stable identifier of a physics entity, shared between server and clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct EntityId(pub u64);
+
+/// Kind of rigid body, mirroring Rapier's `RigidBodyType`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum BodyKind {
+    #[default]
+    Dynamic,
+    /// Static level geometry that never moves
+    Fixed,
+    /// Moved by setting target positions, e.g. characters and platforms
+    KinematicPositionBased,
+    /// Moved by setting velocities
+    KinematicVelocityBased,
+}
+
+impl From<RigidBodyType> for BodyKind {
+    fn from(body_type: RigidBodyType) -> Self {
+        match body_type {
+            RigidBodyType::Dynamic => BodyKind::Dynamic,
+            RigidBodyType::Fixed => BodyKind::Fixed,
+            RigidBodyType::KinematicPositionBased => BodyKind::KinematicPositionBased,
+            RigidBodyType::KinematicVelocityBased => BodyKind::KinematicVelocityBased,
+        }
+    }
+}
+
+impl From<BodyKind> for RigidBodyType {
+    fn from(kind: BodyKind) -> Self {
+        match kind {
+            BodyKind::Dynamic => RigidBodyType::Dynamic,
+            BodyKind::Fixed => RigidBodyType::Fixed,
+            BodyKind::KinematicPositionBased => RigidBodyType::KinematicPositionBased,
+            BodyKind::KinematicVelocityBased => RigidBodyType::KinematicVelocityBased,
+        }
+    }
+}
+
+/// Collision geometry, in the body's local space
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ColliderShape {
+    Box {
+        half_extents: Vec3,
+    },
+    Sphere {
+        radius: f32,
+    },
+    /// Capsule aligned with the local Y axis
+    Capsule {
+        half_height: f32,
+        radius: f32,
+    },
+    TriMesh {
+        vertices: Vec<Vec3>,
+        indices: Vec<[u32; 3]>,
+    },
+    /// Grid of `rows * cols` heights in row-major order, stretched by `scale`
+    HeightField {
+        heights: Vec<f32>,
+        rows: usize,
+        cols: usize,
+        scale: Vec3,
+    },
+}
+
+impl ColliderShape {
+    pub(super) fn to_shared_shape(&self) -> anyhow::Result<SharedShape> {
+        Ok(match self {
+            ColliderShape::Box { half_extents } => {
+                SharedShape::cuboid(half_extents.x, half_extents.y, half_extents.z)
+            }
+            ColliderShape::Sphere { radius } => SharedShape::ball(*radius),
+            ColliderShape::Capsule {
+                half_height,
+                radius,
+            } => SharedShape::capsule_y(*half_height, *radius),
+            ColliderShape::TriMesh { vertices, indices } => {
+                if let Some(index) = indices
+                    .iter()
+                    .flatten()
+                    .find(|i| **i as usize >= vertices.len())
+                {
+                    anyhow::bail!(
+                        "Triangle index {} out of range for {} vertices",
+                        index,
+                        vertices.len()
+                    );
+                }
+                let points = vertices.iter().map(|v| point![v.x, v.y, v.z]).collect();
+                SharedShape::trimesh(points, indices.clone())
+            }
+            ColliderShape::HeightField {
+                heights,
+                rows,
+                cols,
+                scale,
+            } => {
+                if *rows < 2 || *cols < 2 || heights.len() != rows * cols {
+                    anyhow::bail!(
+                        "Heightfield needs at least 2x2 samples and rows * cols heights, got {}x{} with {}",
+                        rows,
+                        cols,
+                        heights.len()
+                    );
+                }
+                let heights = na::DMatrix::from_row_slice(*rows, *cols, heights);
+                SharedShape::heightfield(heights, to_vector(*scale))
+            }
+        })
+    }
+}
+
+/// Collision layers as bitmasks
+///
+/// Two colliders interact only if each one's `filter` shares a bit with the
+/// other's `memberships`. Scene queries use the same rule against the query's
+/// own groups.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CollisionGroups {
+    pub memberships: u32,
+    pub filter: u32,
+}
+
+impl CollisionGroups {
+    /// Member of every group and interacts with everything
+    pub const ALL: Self = Self::new(u32::MAX, u32::MAX);
+
+    pub const fn new(memberships: u32, filter: u32) -> Self {
+        Self {
+            memberships,
+            filter,
+        }
+    }
+}
+
+impl Default for CollisionGroups {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+impl From<CollisionGroups> for InteractionGroups {
+    fn from(groups: CollisionGroups) -> Self {
+        InteractionGroups::new(
+            Group::from_bits_truncate(groups.memberships),
+            Group::from_bits_truncate(groups.filter),
+        )
+    }
+}
+
+/// Description of a collider attached to a body
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ColliderDesc {
+    pub shape: ColliderShape,
+    /// Position relative to the body
+    pub offset: Vec3,
+    pub friction: f32,
+    pub restitution: f32,
+    pub density: f32,
+    /// What it is made of, for elemental spell effects
+    pub material: Material,
+    /// Sensors report overlaps but do not generate contacts
+    pub sensor: bool,
+    pub groups: CollisionGroups,
+    /// Report `PhysicsEvent::ContactForce` when contacts push harder than this
+    pub contact_force_threshold: Option<f32>,
+}
+
+impl ColliderDesc {
+    pub fn new(shape: ColliderShape) -> Self {
+        Self {
+            shape,
+            offset: Vec3::ZERO,
+            friction: 0.5,
+            restitution: 0.0,
+            density: 1.0,
+            material: Material::default(),
+            sensor: false,
+            groups: CollisionGroups::ALL,
+            contact_force_threshold: None,
+        }
+    }
+}
+
+/// Description of a body to spawn
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct BodyDesc {
+    pub kind: BodyKind,
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub linear_velocity: Vec3,
+    pub angular_velocity: Vec3,
+    pub colliders: Vec<ColliderDesc>,
+}
+
+impl BodyDesc {
+    pub fn new(kind: BodyKind, position: Vec3) -> Self {
+        Self {
+            kind,
+            position,
+            ..Default::default()
+        }
+    }
+
+    /// Add a collider to the description
+    pub fn with_collider(mut self, collider: ColliderDesc) -> Self {
+        self.colliders.push(collider);
+        self
+    }
+}
+
+impl PhysicsWorld {
+    /// Spawn a body under a freshly allocated entity ID
+    pub fn spawn(&mut self, desc: &BodyDesc) -> anyhow::Result<EntityId> {
+        let id = EntityId(self.next_entity_id);
+        self.spawn_with_id(id, desc)?;
+        Ok(id)
+    }
+
+    /// Spawn a body under an ID chosen by the caller, e.g. one assigned by the server
+    pub fn spawn_with_id(&mut self, id: EntityId, desc: &BodyDesc) -> anyhow::Result<()> {
+        if self.entities.contains_key(&id) {
+            anyhow::bail!("Entity {:?} already exists", id);
+        }
+        // Validate every shape before touching the world
+        let shapes = desc
+            .colliders
+            .iter()
+            .map(|collider| collider.shape.to_shared_shape())
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let body = RigidBodyBuilder::new(desc.kind.into())
+            .position(isometry(desc.position, desc.rotation))
+            .linvel(to_vector(desc.linear_velocity))
+            .angvel(to_vector(desc.angular_velocity))
+            .build();
+        let handle = self.rigid_body_set.insert(body);
+
+        for (collider, shape) in desc.colliders.iter().zip(shapes) {
+            self.insert_collider(handle, collider, shape);
+        }
+
+        self.entities.insert(id, handle);
+        self.body_entities.insert(handle, id);
+        self.next_entity_id = self.next_entity_id.max(id.0 + 1);
+        self.update_query_pipeline();
+        Ok(())
+    }
+
+    /// Attach another collider to an existing entity
+    pub fn add_collider(
+        &mut self,
+        id: EntityId,
+        collider: &ColliderDesc,
+    ) -> anyhow::Result<ColliderHandle> {
+        let handle = self
+            .body_handle(id)
+            .ok_or_else(|| anyhow::anyhow!("Entity {:?} does not exist", id))?;
+        let shape = collider.shape.to_shared_shape()?;
+        let collider = self.insert_collider(handle, collider, shape);
+        self.update_query_pipeline();
+        Ok(collider)
+    }
+
+    /// Remove an entity's body and all its colliders; returns false if it did not exist
+    pub fn despawn(&mut self, id: EntityId) -> bool {
+        let Some(handle) = self.entities.remove(&id) else {
+            return false;
+        };
+        self.body_entities.remove(&handle);
+        self.projectiles.remove(&id);
+        for collider in self.rigid_body_set[handle].colliders() {
+            self.collider_materials.remove(collider);
+        }
+        self.rigid_body_set.remove(
+            handle,
+            &mut self.island_manager,
+            &mut self.collider_set,
+            &mut self.impulse_joint_set,
+            &mut self.multibody_joint_set,
+            true,
+        );
+        self.update_query_pipeline();
+        true
+    }
+
+    pub fn contains(&self, id: EntityId) -> bool {
+        self.entities.contains_key(&id)
+    }
+
+    /// All entities in ascending ID order
+    pub fn entities(&self) -> impl Iterator<Item = EntityId> + '_ {
+        self.entities.keys().copied()
+    }
+
+    pub fn body_handle(&self, id: EntityId) -> Option<RigidBodyHandle> {
+        self.entities.get(&id).copied()
+    }
+
+    pub fn entity_of_body(&self, handle: RigidBodyHandle) -> Option<EntityId> {
+        self.body_entities.get(&handle).copied()
+    }
+
+    pub fn entity_of_collider(&self, handle: ColliderHandle) -> Option<EntityId> {
+        self.collider_set
+            .get(handle)
+            .and_then(|collider| collider.parent())
+            .and_then(|body| self.entity_of_body(body))
+    }
+
+    pub fn body_kind(&self, id: EntityId) -> Option<BodyKind> {
+        self.body(id).map(|body| body.body_type().into())
+    }
+
+    /// World position and rotation of an entity
+    pub fn transform(&self, id: EntityId) -> Option<(Vec3, Quat)> {
+        self.body(id).map(|body| {
+            let rotation = body.rotation();
+            (
+                from_vector(body.translation()),
+                Quat::from_xyzw(rotation.i, rotation.j, rotation.k, rotation.w),
+            )
+        })
+    }
+
+    /// Teleport an entity; returns false if it does not exist
+    pub fn set_transform(&mut self, id: EntityId, position: Vec3, rotation: Quat) -> bool {
+        let Some(handle) = self.body_handle(id) else {
+            return false;
+        };
+        let pose = isometry(position, rotation);
+        let body = &mut self.rigid_body_set[handle];
+        body.set_position(pose, true);
+        if body.is_kinematic() {
+            body.set_next_kinematic_position(pose);
+        }
+        // Move attached colliders now so queries see the new pose before the next step
+        for collider in body.colliders().to_vec() {
+            if let Some(collider) = self.collider_set.get_mut(collider) {
+                let local = collider.position_wrt_parent().copied().unwrap_or_default();
+                collider.set_position(pose * local);
+            }
+        }
+        self.update_query_pipeline();
+        true
+    }
+
+    /// Move a kinematic entity towards a pose over the next step, pushing what it hits
+    pub fn set_kinematic_target(&mut self, id: EntityId, position: Vec3, rotation: Quat) -> bool {
+        match self.body_mut(id) {
+            Some(body) if body.is_kinematic() => {
+                body.set_next_kinematic_position(isometry(position, rotation));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Linear and angular velocity of an entity
+    pub fn velocity(&self, id: EntityId) -> Option<(Vec3, Vec3)> {
+        self.body(id)
+            .map(|body| (from_vector(body.linvel()), from_vector(body.angvel())))
+    }
+
+    /// Set linear and angular velocity; returns false if the entity does not exist
+    pub fn set_velocity(&mut self, id: EntityId, linear: Vec3, angular: Vec3) -> bool {
+        let Some(body) = self.body_mut(id) else {
+            return false;
+        };
+        body.set_linvel(to_vector(linear), true);
+        body.set_angvel(to_vector(angular), true);
+        true
+    }
+
+    pub(super) fn body(&self, id: EntityId) -> Option<&RigidBody> {
+        self.body_handle(id)
+            .and_then(|handle| self.rigid_body_set.get(handle))
+    }
+
+    pub(super) fn body_mut(&mut self, id: EntityId) -> Option<&mut RigidBody> {
+        self.body_handle(id)
+            .and_then(|handle| self.rigid_body_set.get_mut(handle))
+    }
+
+    fn insert_collider(
+        &mut self,
+        body: RigidBodyHandle,
+        desc: &ColliderDesc,
+        shape: SharedShape,
+    ) -> ColliderHandle {
+        let mut events = ActiveEvents::COLLISION_EVENTS;
+        if desc.contact_force_threshold.is_some() {
+            events |= ActiveEvents::CONTACT_FORCE_EVENTS;
+        }
+        let mut collider = ColliderBuilder::new(shape)
+            .translation(to_vector(desc.offset))
+            .friction(desc.friction)
+            .restitution(desc.restitution)
+            .density(desc.density)
+            .sensor(desc.sensor)
+            .collision_groups(desc.groups.into())
+            .active_events(events)
+            .contact_force_event_threshold(desc.contact_force_threshold.unwrap_or(Real::MAX));
+        if desc.sensor {
+            // Trigger volumes must also notice kinematic characters, but not the
+            // level geometry they are placed in
+            collider = collider.active_collision_types(
+                ActiveCollisionTypes::default()
+                    | ActiveCollisionTypes::KINEMATIC_FIXED
+                    | ActiveCollisionTypes::KINEMATIC_KINEMATIC,
+            );
+        }
+        let collider = collider.build();
+        let handle = self
+            .collider_set
+            .insert_with_parent(collider, body, &mut self.rigid_body_set);
+        self.collider_materials.insert(
+            handle,
+            ColliderMaterial {
+                material: desc.material,
+                friction: desc.friction,
+            },
+        );
+        handle
+    }
+}
+
+pub(crate) fn isometry(position: Vec3, rotation: Quat) -> Isometry<Real> {
+    let rotation = na::UnitQuaternion::from_quaternion(na::Quaternion::new(
+        rotation.w, rotation.x, rotation.y, rotation.z,
+    ));
+    Isometry::from_parts(
+        na::Translation3::new(position.x, position.y, position.z),
+        rotation,
+    )
+}
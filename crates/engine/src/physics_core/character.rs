@@ -0,0 +1,367 @@
+/// Kinematic character controller shared by server and client
+///
+/// The server runs it authoritatively and the client runs the exact same code
+/// for prediction, so for identical inputs, movement constants and level
+/// geometry both sides produce identical character states.
+use super::{
+    from_vector, to_vector, BodyDesc, BodyKind, ColliderDesc, ColliderShape, EntityId, Material,
+    PhysicsWorld,
+};
+use crate::config::MovementConfig;
+use crate::net_proto::PlayerInput;
+use glam::{Quat, Vec3};
+use rapier3d::control::{CharacterAutostep, CharacterLength, KinematicCharacterController};
+use rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Eye height as a fraction of the capsule height
+const EYE_HEIGHT_FRACTION: f32 = 0.9;
+
+/// Mass of a character, which knockback impulses are divided by (kg)
+pub const CHARACTER_MASS: f32 = 80.0;
+
+/// Ground friction at which characters get their full acceleration; on
+/// slipperier ground, e.g. ice, they speed up and slow down proportionally less
+pub const FULL_TRACTION_FRICTION: f32 = 0.5;
+
+/// How far below its feet a character looks for the ground it stands on (m)
+const GROUND_PROBE: f32 = 0.2;
+
+/// Simulation state of one character
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct CharacterState {
+    /// Position of the bottom of the capsule
+    pub position: Vec3,
+    pub velocity: Vec3,
+    /// Rotation around the up axis in radians; zero faces +Z
+    pub yaw: f32,
+    /// Look angle above (positive) or below the horizon in radians
+    pub pitch: f32,
+    pub grounded: bool,
+    pub crouching: bool,
+    /// Fraction of its speed taken away by status effects, from 0 to 1; at 1
+    /// it can neither walk nor jump
+    pub slow: f32,
+}
+
+impl CharacterState {
+    /// Horizontal unit vector the character is facing
+    pub fn forward(&self) -> Vec3 {
+        Vec3::new(self.yaw.sin(), 0.0, self.yaw.cos())
+    }
+
+    /// Horizontal unit vector to the character's right
+    pub fn right(&self) -> Vec3 {
+        Vec3::new(self.yaw.cos(), 0.0, -self.yaw.sin())
+    }
+
+    /// Unit vector the character is looking along, including pitch
+    pub fn aim(&self) -> Vec3 {
+        self.forward() * self.pitch.cos() + Vec3::Y * self.pitch.sin()
+    }
+}
+
+/// A character spawned in a `PhysicsWorld`
+#[derive(Debug, Clone)]
+pub struct Character {
+    pub entity: EntityId,
+    pub body: RigidBodyHandle,
+    pub collider: ColliderHandle,
+    pub state: CharacterState,
+}
+
+pub struct CharacterController {
+    config: MovementConfig,
+    controller: KinematicCharacterController,
+}
+
+impl CharacterController {
+    pub fn new(config: MovementConfig) -> Self {
+        let max_slope = config.max_slope_degrees.to_radians();
+        let controller = KinematicCharacterController {
+            offset: CharacterLength::Absolute(0.01),
+            autostep: Some(CharacterAutostep {
+                max_height: CharacterLength::Absolute(config.step_height),
+                min_width: CharacterLength::Absolute(config.capsule_radius * 0.5),
+                include_dynamic_bodies: false,
+            }),
+            max_slope_climb_angle: max_slope,
+            min_slope_slide_angle: max_slope,
+            snap_to_ground: Some(CharacterLength::Absolute(config.step_height)),
+            ..Default::default()
+        };
+
+        Self { config, controller }
+    }
+
+    pub fn config(&self) -> &MovementConfig {
+        &self.config
+    }
+
+    /// Where a character in `state` sees from, and casts spells from
+    pub fn eye_position(&self, state: &CharacterState) -> Vec3 {
+        let height = if state.crouching {
+            self.config.crouch_height
+        } else {
+            self.config.stand_height
+        };
+        state.position + Vec3::Y * height * EYE_HEIGHT_FRACTION
+    }
+
+    /// Spawn a standing character with its feet at `position`
+    pub fn spawn(&self, world: &mut PhysicsWorld, position: Vec3) -> Character {
+        let id = EntityId(world.next_entity_id);
+        self.spawn_with_id(world, id, position)
+            .expect("fresh entity ID with a capsule shape is always valid")
+    }
+
+    /// Spawn a character under a caller-chosen entity ID, e.g. one assigned by the server
+    pub fn spawn_with_id(
+        &self,
+        world: &mut PhysicsWorld,
+        entity: EntityId,
+        position: Vec3,
+    ) -> anyhow::Result<Character> {
+        let state = CharacterState {
+            position,
+            ..Default::default()
+        };
+
+        let desc = BodyDesc::new(
+            BodyKind::KinematicPositionBased,
+            self.capsule_center(&state),
+        )
+        .with_collider(ColliderDesc {
+            material: Material::Flesh,
+            ..ColliderDesc::new(self.capsule_desc(false))
+        });
+        world.spawn_with_id(entity, &desc)?;
+        let body = world.entities[&entity];
+        let collider = world.rigid_body_set[body].colliders()[0];
+
+        Ok(Character {
+            entity,
+            body,
+            collider,
+            state,
+        })
+    }
+
+    /// Remove a character's body and collider from the world
+    pub fn despawn(&self, world: &mut PhysicsWorld, character: &Character) {
+        world.despawn(character.entity);
+    }
+
+    /// Overwrite a character's state, e.g. with a server correction, and teleport its body to match
+    pub fn set_state(
+        &self,
+        world: &mut PhysicsWorld,
+        character: &mut Character,
+        state: CharacterState,
+    ) {
+        character.state = state;
+        if let Some(collider) = world.collider_set.get_mut(character.collider) {
+            collider.set_shape(self.capsule_shape(state.crouching));
+        }
+        world.set_transform(
+            character.entity,
+            self.capsule_center(&state),
+            Quat::IDENTITY,
+        );
+    }
+
+    /// Advance a character by one input over `delta_time` seconds
+    pub fn apply_input(
+        &self,
+        world: &mut PhysicsWorld,
+        character: &mut Character,
+        input: &PlayerInput,
+        delta_time: f32,
+    ) {
+        let traction = self.traction(world, character);
+        let state = &mut character.state;
+
+        // Look
+        let max_pitch = self.config.max_pitch_degrees.to_radians();
+        state.yaw -= input.look_delta_x * self.config.look_sensitivity;
+        state.yaw = state.yaw.rem_euclid(std::f32::consts::TAU);
+        state.pitch = (state.pitch - input.look_delta_y * self.config.look_sensitivity)
+            .clamp(-max_pitch, max_pitch);
+
+        // Crouch; standing back up needs enough headroom
+        if input.crouch != state.crouching
+            && (input.crouch || self.has_headroom(world, character.body, state))
+        {
+            state.crouching = input.crouch;
+            if let Some(collider) = world.collider_set.get_mut(character.collider) {
+                collider.set_shape(self.capsule_shape(state.crouching));
+            }
+        }
+
+        // Horizontal velocity accelerates towards the requested direction
+        let mut wish = Vec3::ZERO;
+        if input.move_forward {
+            wish += state.forward();
+        }
+        if input.move_backward {
+            wish -= state.forward();
+        }
+        if input.move_right {
+            wish += state.right();
+        }
+        if input.move_left {
+            wish -= state.right();
+        }
+        let max_speed = if state.crouching {
+            self.config.crouch_speed
+        } else {
+            self.config.max_speed
+        } * (1.0 - state.slow.clamp(0.0, 1.0));
+        let target = wish.normalize_or_zero() * max_speed;
+        let acceleration = if state.grounded {
+            self.config.acceleration * traction
+        } else {
+            self.config.acceleration * self.config.air_control
+        };
+        let horizontal = move_towards(
+            Vec3::new(state.velocity.x, 0.0, state.velocity.z),
+            target,
+            acceleration * delta_time,
+        );
+
+        // Vertical velocity
+        let mut vertical = state.velocity.y - self.config.gravity * delta_time;
+        if state.grounded && input.jump && !state.crouching && state.slow < 1.0 {
+            vertical = self.config.jump_speed;
+        }
+
+        let desired = Vec3::new(horizontal.x, vertical, horizontal.z) * delta_time;
+        let shape = self.capsule_shape(state.crouching);
+        let center = self.capsule_center(state);
+        let center = Isometry::translation(center.x, center.y, center.z);
+        let movement = self.controller.move_shape(
+            delta_time,
+            &world.rigid_body_set,
+            &world.collider_set,
+            &world.query_pipeline,
+            shape.as_ref(),
+            &center,
+            to_vector(desired),
+            QueryFilter::default()
+                .exclude_rigid_body(character.body)
+                .exclude_sensors(),
+            |_| {},
+        );
+
+        let applied = from_vector(&movement.translation);
+        state.position += applied;
+        state.grounded = movement.grounded;
+        state.velocity = Vec3::new(horizontal.x, vertical, horizontal.z);
+        if state.grounded && state.velocity.y < 0.0 {
+            state.velocity.y = 0.0;
+        }
+        // Bumped into a ceiling
+        if desired.y > 0.0 && applied.y < desired.y * 0.5 {
+            state.velocity.y = 0.0;
+        }
+
+        if let Some(body) = world.rigid_body_set.get_mut(character.body) {
+            body.set_next_kinematic_translation(to_vector(self.capsule_center(state)));
+        }
+    }
+
+    /// Fraction of its acceleration the ground under a character allows,
+    /// from its collider's friction
+    ///
+    /// Both sides read the same friction, because the server replicates the
+    /// surface states that change it.
+    fn traction(&self, world: &PhysicsWorld, character: &Character) -> f32 {
+        let origin = character.state.position + Vec3::Y * (GROUND_PROBE * 0.5);
+        let ray = Ray::new(to_vector(origin).into(), -Vector::y());
+        let filter = QueryFilter::default()
+            .exclude_rigid_body(character.body)
+            .exclude_sensors();
+        world
+            .query_pipeline
+            .cast_ray(
+                &world.rigid_body_set,
+                &world.collider_set,
+                &ray,
+                GROUND_PROBE,
+                true,
+                filter,
+            )
+            .and_then(|(collider, _)| world.collider_set.get(collider))
+            .map_or(1.0, |ground| {
+                (ground.friction() / FULL_TRACTION_FRICTION).clamp(0.0, 1.0)
+            })
+    }
+
+    fn capsule_height(&self, crouching: bool) -> f32 {
+        if crouching {
+            self.config.crouch_height
+        } else {
+            self.config.stand_height
+        }
+    }
+
+    fn capsule_shape(&self, crouching: bool) -> SharedShape {
+        let radius = self.config.capsule_radius;
+        let half_height = (self.capsule_height(crouching) * 0.5 - radius).max(0.0);
+        SharedShape::capsule_y(half_height, radius)
+    }
+
+    fn capsule_desc(&self, crouching: bool) -> ColliderShape {
+        let radius = self.config.capsule_radius;
+        ColliderShape::Capsule {
+            half_height: (self.capsule_height(crouching) * 0.5 - radius).max(0.0),
+            radius,
+        }
+    }
+
+    fn capsule_center(&self, state: &CharacterState) -> Vec3 {
+        state.position + Vec3::Y * (self.capsule_height(state.crouching) * 0.5)
+    }
+
+    fn has_headroom(
+        &self,
+        world: &PhysicsWorld,
+        body: RigidBodyHandle,
+        state: &CharacterState,
+    ) -> bool {
+        let standing = CharacterState {
+            crouching: false,
+            ..*state
+        };
+        let center = self.capsule_center(&standing);
+        let shape = self.capsule_shape(false);
+        world
+            .query_pipeline
+            .intersection_with_shape(
+                &world.rigid_body_set,
+                &world.collider_set,
+                &Isometry::translation(center.x, center.y, center.z),
+                shape.as_ref(),
+                QueryFilter::default()
+                    .exclude_rigid_body(body)
+                    .exclude_sensors(),
+            )
+            .is_none()
+    }
+}
+
+impl Default for CharacterController {
+    fn default() -> Self {
+        Self::new(MovementConfig::default())
+    }
+}
+
+fn move_towards(current: Vec3, target: Vec3, max_delta: f32) -> Vec3 {
+    let delta = target - current;
+    let distance = delta.length();
+    if distance <= max_delta || distance < crate::math::EPSILON {
+        target
+    } else {
+        current + delta / distance * max_delta
+    }
+}
@@ -0,0 +1,146 @@
+/// Collision and trigger events produced by `PhysicsWorld::step`
+///
+/// Rapier reports events through an `EventHandler` with raw collider handles.
+/// They are collected during the step and translated to entity IDs afterwards,
+/// so consumers such as the server's game logic never see Rapier handles.
+use super::{from_vector, EntityId, PhysicsWorld};
+use bevy_ecs::event::Event;
+use glam::Vec3;
+use rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+/// Something that happened between two entities during a step
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Event)]
+pub enum PhysicsEvent {
+    /// Two solid colliders started touching
+    ContactStarted { a: EntityId, b: EntityId },
+    /// Two solid colliders stopped touching
+    ContactStopped { a: EntityId, b: EntityId },
+    /// An entity entered a sensor, e.g. a damage zone or pickup
+    TriggerEntered { sensor: EntityId, other: EntityId },
+    /// An entity left a sensor
+    TriggerExited { sensor: EntityId, other: EntityId },
+    /// A contact pushed harder than one of the colliders' `contact_force_threshold`
+    ContactForce {
+        a: EntityId,
+        b: EntityId,
+        /// Sum of all contact force magnitudes in newtons
+        magnitude: f32,
+        /// Direction of the strongest contact force, applied on `a`
+        direction: Vec3,
+    },
+    /// A projectile struck an entity, bouncing off, passing through or
+    /// stopping there
+    ProjectileHit {
+        projectile: EntityId,
+        target: EntityId,
+        /// Where the projectile was after the step
+        position: Vec3,
+        /// Velocity it struck with
+        velocity: Vec3,
+        /// The projectile ended here and was removed
+        stopped: bool,
+    },
+    /// A projectile's lifetime ran out and it was removed
+    ProjectileExpired {
+        projectile: EntityId,
+        position: Vec3,
+    },
+}
+
+/// Raw Rapier events gathered while the physics pipeline runs
+#[derive(Default)]
+pub(super) struct EventCollector {
+    collisions: Mutex<Vec<CollisionEvent>>,
+    forces: Mutex<Vec<ContactForceEvent>>,
+}
+
+impl EventHandler for EventCollector {
+    fn handle_collision_event(
+        &self,
+        _bodies: &RigidBodySet,
+        _colliders: &ColliderSet,
+        event: CollisionEvent,
+        _contact_pair: Option<&ContactPair>,
+    ) {
+        self.collisions.lock().unwrap().push(event);
+    }
+
+    fn handle_contact_force_event(
+        &self,
+        dt: Real,
+        _bodies: &RigidBodySet,
+        _colliders: &ColliderSet,
+        contact_pair: &ContactPair,
+        total_force_magnitude: Real,
+    ) {
+        self.forces
+            .lock()
+            .unwrap()
+            .push(ContactForceEvent::from_contact_pair(
+                dt,
+                contact_pair,
+                total_force_magnitude,
+            ));
+    }
+}
+
+impl PhysicsWorld {
+    /// Events produced by the most recent `step`
+    pub fn events(&self) -> &[PhysicsEvent] {
+        &self.events
+    }
+
+    /// Take the events produced by the most recent `step`
+    pub fn drain_events(&mut self) -> Vec<PhysicsEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Translate the raw events of a step, in the order Rapier reported them
+    ///
+    /// Events involving a collider that no longer belongs to an entity (it was
+    /// despawned) are dropped; whoever despawned it already knows.
+    pub(super) fn collect_events(&mut self, collector: EventCollector) {
+        self.events.clear();
+        for event in collector.collisions.into_inner().unwrap() {
+            let (first, second) = (event.collider1(), event.collider2());
+            let (Some(a), Some(b)) = (
+                self.entity_of_collider(first),
+                self.entity_of_collider(second),
+            ) else {
+                continue;
+            };
+            let event = if event.sensor() {
+                let first_is_sensor = self
+                    .collider_set
+                    .get(first)
+                    .is_some_and(|collider| collider.is_sensor());
+                let (sensor, other) = if first_is_sensor { (a, b) } else { (b, a) };
+                if event.started() {
+                    PhysicsEvent::TriggerEntered { sensor, other }
+                } else {
+                    PhysicsEvent::TriggerExited { sensor, other }
+                }
+            } else if event.started() {
+                PhysicsEvent::ContactStarted { a, b }
+            } else {
+                PhysicsEvent::ContactStopped { a, b }
+            };
+            self.events.push(event);
+        }
+        for force in collector.forces.into_inner().unwrap() {
+            if let (Some(a), Some(b)) = (
+                self.entity_of_collider(force.collider1),
+                self.entity_of_collider(force.collider2),
+            ) {
+                self.events.push(PhysicsEvent::ContactForce {
+                    a,
+                    b,
+                    magnitude: force.total_force_magnitude,
+                    direction: from_vector(&force.max_force_direction),
+                });
+            }
+        }
+    }
+}
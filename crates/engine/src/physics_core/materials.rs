@@ -0,0 +1,105 @@
+/// What colliders are made of and the elemental state of their surface
+///
+/// Every collider carries a `Material` tag from its `ColliderDesc`. Game logic
+/// decides how spells change a surface's `SurfaceState` and tells the world
+/// through `set_surface_state`, which gives frozen surfaces the friction of
+/// ice and restores the collider's own friction afterwards.
+use super::{EntityId, PhysicsWorld};
+use rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Friction of a frozen surface
+pub const ICE_FRICTION: f32 = 0.02;
+
+/// What a collider is made of, deciding how elements affect it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Material {
+    #[default]
+    Stone,
+    Wood,
+    Grass,
+    Cloth,
+    Metal,
+    /// Characters and creatures
+    Flesh,
+}
+
+impl Material {
+    /// Whether fire sets it alight
+    pub fn flammable(self) -> bool {
+        matches!(self, Material::Wood | Material::Grass | Material::Cloth)
+    }
+
+    /// Whether lightning passes through it even when dry
+    pub fn conductive(self) -> bool {
+        matches!(self, Material::Metal)
+    }
+}
+
+/// Elemental state of an entity's surface
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SurfaceState {
+    #[default]
+    Normal,
+    Burning,
+    Wet,
+    /// Iced over and slippery
+    Frozen,
+    /// Burnt out; never catches fire again
+    Charred,
+}
+
+/// Material and own friction of a collider
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct ColliderMaterial {
+    pub material: Material,
+    pub friction: f32,
+}
+
+impl PhysicsWorld {
+    /// Material of an entity's first collider, which spells treat the whole
+    /// entity as made of
+    ///
+    /// Colliders created without a `ColliderDesc`, e.g. projectiles, are stone.
+    pub fn material(&self, id: EntityId) -> Option<Material> {
+        let body = self.body(id)?;
+        Some(
+            body.colliders()
+                .first()
+                .map_or(Material::default(), |collider| {
+                    self.collider_materials
+                        .get(collider)
+                        .map_or(Material::default(), |material| material.material)
+                }),
+        )
+    }
+
+    /// Give every collider of an entity the friction of its surface state
+    ///
+    /// Frozen colliders get `ICE_FRICTION`, combined with the other collider's
+    /// by taking the lower, so anything on them slides. Returns false if the
+    /// entity does not exist.
+    pub fn set_surface_state(&mut self, id: EntityId, state: SurfaceState) -> bool {
+        let Some(body) = self.body(id) else {
+            return false;
+        };
+        for handle in body.colliders().to_vec() {
+            let own = self
+                .collider_materials
+                .get(&handle)
+                .map(|material| material.friction);
+            let Some(collider) = self.collider_set.get_mut(handle) else {
+                continue;
+            };
+            if state == SurfaceState::Frozen {
+                collider.set_friction(ICE_FRICTION);
+                collider.set_friction_combine_rule(CoefficientCombineRule::Min);
+            } else if let Some(friction) = own {
+                collider.set_friction(friction);
+                collider.set_friction_combine_rule(CoefficientCombineRule::Average);
+            }
+        }
+        true
+    }
+}
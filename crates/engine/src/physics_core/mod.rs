@@ -1,5 +1,25 @@
 /// Deterministic physics core module
+use glam::Vec3;
 use rapier3d::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+
+pub mod bodies;
+pub mod character;
+pub mod events;
+pub mod materials;
+pub mod projectiles;
+pub mod queries;
+pub mod snapshot;
+
+pub use bodies::{BodyDesc, BodyKind, ColliderDesc, ColliderShape, CollisionGroups, EntityId};
+pub use character::{
+    Character, CharacterController, CharacterState, CHARACTER_MASS, FULL_TRACTION_FRICTION,
+};
+pub use events::PhysicsEvent;
+pub use materials::{Material, SurfaceState, ICE_FRICTION};
+pub use projectiles::{Projectile, ProjectileDesc, PROJECTILE_GROUP};
+pub use queries::{QueryOptions, RayHit, ShapeHit};
+pub use snapshot::{BodySnapshot, ColliderSnapshot, Difference, SnapshotDiff, WorldSnapshot};
 
 pub struct PhysicsWorld {
     physics_pipeline: PhysicsPipeline,
@@ -13,6 +33,13 @@ pub struct PhysicsWorld {
     impulse_joint_set: ImpulseJointSet,
     multibody_joint_set: MultibodyJointSet,
     ccd_solver: CCDSolver,
+    query_pipeline: QueryPipeline,
+    entities: BTreeMap<EntityId, RigidBodyHandle>,
+    body_entities: HashMap<RigidBodyHandle, EntityId>,
+    next_entity_id: u64,
+    events: Vec<PhysicsEvent>,
+    projectiles: BTreeMap<EntityId, projectiles::Projectile>,
+    collider_materials: HashMap<ColliderHandle, materials::ColliderMaterial>,
 }
 
 impl PhysicsWorld {
@@ -29,11 +56,26 @@ impl PhysicsWorld {
             impulse_joint_set: ImpulseJointSet::new(),
             multibody_joint_set: MultibodyJointSet::new(),
             ccd_solver: CCDSolver::new(),
+            query_pipeline: QueryPipeline::new(),
+            entities: BTreeMap::new(),
+            body_entities: HashMap::new(),
+            next_entity_id: 1,
+            events: Vec::new(),
+            projectiles: BTreeMap::new(),
+            collider_materials: HashMap::new(),
         }
     }
 
+    /// Advance the simulation; the events it produced are available from `events()`
     pub fn step(&mut self, delta_time: f32) {
         self.integration_parameters.dt = delta_time;
+        let collector = events::EventCollector::default();
+        let hooks = self.prepare_projectiles();
+        let launch_velocities = self
+            .projectiles
+            .keys()
+            .filter_map(|id| Some((*id, self.velocity(*id)?.0)))
+            .collect();
         self.physics_pipeline.step(
             &self.gravity,
             &self.integration_parameters,
@@ -45,10 +87,33 @@ impl PhysicsWorld {
             &mut self.impulse_joint_set,
             &mut self.multibody_joint_set,
             &mut self.ccd_solver,
-            None,
-            &(),
-            &(),
+            Some(&mut self.query_pipeline),
+            &hooks,
+            &collector,
         );
+        self.collect_events(collector);
+        self.update_projectiles(delta_time, &launch_velocities);
+    }
+
+    /// Add a fixed box to the level geometry
+    pub fn add_static_box(&mut self, center: Vec3, half_extents: Vec3) -> EntityId {
+        let desc = BodyDesc::new(BodyKind::Fixed, center)
+            .with_collider(ColliderDesc::new(ColliderShape::Box { half_extents }));
+        self.spawn(&desc).expect("box collider is always valid")
+    }
+
+    /// Add a large flat floor whose top surface is at `height`
+    pub fn add_ground_plane(&mut self, height: f32) -> EntityId {
+        self.add_static_box(
+            Vec3::new(0.0, height - 0.5, 0.0),
+            Vec3::new(500.0, 0.5, 500.0),
+        )
+    }
+
+    /// Refresh scene queries after bodies or colliders changed outside `step`
+    fn update_query_pipeline(&mut self) {
+        self.query_pipeline
+            .update(&self.rigid_body_set, &self.collider_set);
     }
 }
 
@@ -57,3 +122,11 @@ impl Default for PhysicsWorld {
         Self::new()
     }
 }
+
+pub(crate) fn to_vector(v: Vec3) -> Vector<Real> {
+    vector![v.x, v.y, v.z]
+}
+
+pub(crate) fn from_vector(v: &Vector<Real>) -> Vec3 {
+    Vec3::new(v.x, v.y, v.z)
+}
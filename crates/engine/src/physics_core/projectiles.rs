@@ -0,0 +1,320 @@
+/// Spell projectiles: fast bodies that bounce, pierce, push and expire
+///
+/// A projectile is an ordinary dynamic ball with CCD enabled, so even a fast
+/// one cannot tunnel through a thin wall between two steps. A contact hook
+/// switches off the physical response to its owner and to the targets it
+/// pierces, and after every step `PhysicsWorld` turns its contacts into
+/// `PhysicsEvent::ProjectileHit`, pushing the dynamic bodies it hit and
+/// removing the projectile once it stopped or its lifetime ran out.
+use super::{
+    from_vector, to_vector, BodyKind, CollisionGroups, EntityId, PhysicsEvent, PhysicsWorld,
+};
+use glam::Vec3;
+use rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Collision group of every projectile; projectiles never hit each other
+pub const PROJECTILE_GROUP: u32 = 1 << 31;
+
+/// Description of a projectile to launch
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProjectileDesc {
+    pub position: Vec3,
+    pub velocity: Vec3,
+    pub radius: f32,
+    /// Multiplier of world gravity; 0 flies straight
+    pub gravity_scale: f32,
+    /// Linear damping; roughly the fraction of speed lost per second
+    pub drag: f32,
+    /// Seconds before the projectile expires
+    pub lifetime: f32,
+    /// Bounces off level geometry before the next impact stops it
+    pub bounces: u32,
+    /// Targets passed through before the next one stops it
+    pub pierce: u32,
+    /// Impulse along the direction of travel given to dynamic bodies it hits (N·s)
+    pub impulse: f32,
+    /// How much speed a bounce keeps, from 0 to 1
+    pub restitution: f32,
+    /// Entity that launched it, e.g. the caster's character; never hit
+    pub owner: Option<EntityId>,
+}
+
+impl ProjectileDesc {
+    pub fn new(position: Vec3, velocity: Vec3, radius: f32) -> Self {
+        Self {
+            position,
+            velocity,
+            radius,
+            gravity_scale: 0.0,
+            drag: 0.0,
+            lifetime: 5.0,
+            bounces: 0,
+            pierce: 0,
+            impulse: 0.0,
+            restitution: 0.6,
+            owner: None,
+        }
+    }
+}
+
+/// A projectile in flight
+#[derive(Debug, Clone, PartialEq)]
+pub struct Projectile {
+    pub owner: Option<EntityId>,
+    /// Seconds left before it expires
+    pub remaining: f32,
+    pub bounces_left: u32,
+    pub pierces_left: u32,
+    pub impulse: f32,
+    /// Targets already passed through, which it never hits again
+    pub pierced: Vec<EntityId>,
+}
+
+/// Bodies each projectile collider passes through, captured before a step
+#[derive(Default)]
+pub(super) struct ProjectileHooks {
+    colliders: HashMap<ColliderHandle, PassThrough>,
+}
+
+struct PassThrough {
+    /// The owner and the targets already pierced
+    bodies: Vec<RigidBodyHandle>,
+    /// Whether it still pierces anything that is not level geometry
+    pierces: bool,
+}
+
+impl PhysicsHooks for ProjectileHooks {
+    fn modify_solver_contacts(&self, context: &mut ContactModificationContext) {
+        let pairs = [
+            (context.collider1, context.rigid_body2),
+            (context.collider2, context.rigid_body1),
+        ];
+        for (collider, other) in pairs {
+            let (Some(pass), Some(other)) = (self.colliders.get(&collider), other) else {
+                continue;
+            };
+            let fixed = context.bodies.get(other).is_none_or(|body| body.is_fixed());
+            if pass.bodies.contains(&other) || (pass.pierces && !fixed) {
+                // The contact is still reported, but nothing bounces off
+                context.solver_contacts.clear();
+                return;
+            }
+        }
+    }
+}
+
+impl PhysicsWorld {
+    /// Launch a projectile under a freshly allocated entity ID
+    pub fn spawn_projectile(&mut self, desc: &ProjectileDesc) -> anyhow::Result<EntityId> {
+        let id = EntityId(self.next_entity_id);
+        self.spawn_projectile_with_id(id, desc)?;
+        Ok(id)
+    }
+
+    /// Launch a projectile under an ID chosen by the caller
+    pub fn spawn_projectile_with_id(
+        &mut self,
+        id: EntityId,
+        desc: &ProjectileDesc,
+    ) -> anyhow::Result<()> {
+        if self.entities.contains_key(&id) {
+            anyhow::bail!("Entity {:?} already exists", id);
+        }
+        if !(desc.radius.is_finite() && desc.radius > 0.0) {
+            anyhow::bail!("Projectile radius must be positive, got {}", desc.radius);
+        }
+
+        let body = RigidBodyBuilder::dynamic()
+            .translation(to_vector(desc.position))
+            .linvel(to_vector(desc.velocity))
+            .gravity_scale(desc.gravity_scale)
+            .linear_damping(desc.drag.max(0.0))
+            .ccd_enabled(true)
+            .can_sleep(false)
+            .build();
+        let handle = self.rigid_body_set.insert(body);
+        let groups = CollisionGroups::new(PROJECTILE_GROUP, !PROJECTILE_GROUP);
+        let collider = ColliderBuilder::ball(desc.radius)
+            .restitution(desc.restitution.clamp(0.0, 1.0))
+            .restitution_combine_rule(CoefficientCombineRule::Max)
+            .friction(0.0)
+            .collision_groups(groups.into())
+            .active_events(ActiveEvents::COLLISION_EVENTS)
+            .active_hooks(ActiveHooks::MODIFY_SOLVER_CONTACTS)
+            .build();
+        self.collider_set
+            .insert_with_parent(collider, handle, &mut self.rigid_body_set);
+
+        self.entities.insert(id, handle);
+        self.body_entities.insert(handle, id);
+        self.next_entity_id = self.next_entity_id.max(id.0 + 1);
+        self.projectiles.insert(
+            id,
+            Projectile {
+                owner: desc.owner,
+                remaining: desc.lifetime,
+                bounces_left: desc.bounces,
+                pierces_left: desc.pierce,
+                impulse: desc.impulse,
+                pierced: Vec::new(),
+            },
+        );
+        self.update_query_pipeline();
+        Ok(())
+    }
+
+    pub fn projectile(&self, id: EntityId) -> Option<&Projectile> {
+        self.projectiles.get(&id)
+    }
+
+    /// Projectiles in flight, in ascending ID order
+    pub fn projectiles(&self) -> impl Iterator<Item = (EntityId, &Projectile)> + '_ {
+        self.projectiles
+            .iter()
+            .map(|(id, projectile)| (*id, projectile))
+    }
+
+    /// Work out what each projectile passes through during the next step
+    ///
+    /// CCD would hold a projectile back at a body it is inside of, so it is
+    /// off while the projectile passes through its owner or a pierced target.
+    pub(super) fn prepare_projectiles(&mut self) -> ProjectileHooks {
+        let mut hooks = ProjectileHooks::default();
+        let mut passing = Vec::new();
+        for (id, projectile) in &self.projectiles {
+            let Some(body) = self.body_handle(*id) else {
+                continue;
+            };
+            let bodies: Vec<RigidBodyHandle> = projectile
+                .owner
+                .iter()
+                .chain(&projectile.pierced)
+                .filter_map(|entity| self.body_handle(*entity))
+                .collect();
+            let colliders = self.rigid_body_set[body].colliders();
+            let inside = colliders.iter().any(|collider| {
+                self.narrow_phase
+                    .contact_pairs_with(*collider)
+                    .filter(|pair| pair.has_any_active_contact)
+                    .map(|pair| {
+                        if pair.collider1 == *collider {
+                            pair.collider2
+                        } else {
+                            pair.collider1
+                        }
+                    })
+                    .filter_map(|other| self.collider_set.get(other)?.parent())
+                    .any(|other| bodies.contains(&other))
+            });
+            passing.push((body, inside));
+            for collider in colliders {
+                hooks.colliders.insert(
+                    *collider,
+                    PassThrough {
+                        bodies: bodies.clone(),
+                        pierces: projectile.pierces_left > 0,
+                    },
+                );
+            }
+        }
+        for (body, inside) in passing {
+            self.rigid_body_set[body].enable_ccd(!inside);
+        }
+        hooks
+    }
+
+    /// Resolve the impacts of the step just taken and age every projectile
+    ///
+    /// `launch_velocities` are the velocities before the step, which give the
+    /// direction of each impact.
+    pub(super) fn update_projectiles(
+        &mut self,
+        delta_time: f32,
+        launch_velocities: &BTreeMap<EntityId, Vec3>,
+    ) {
+        let contacts: Vec<(EntityId, EntityId)> = self
+            .events
+            .iter()
+            .filter_map(|event| match *event {
+                PhysicsEvent::ContactStarted { a, b } => Some((a, b)),
+                _ => None,
+            })
+            .flat_map(|(a, b)| [(a, b), (b, a)])
+            .filter(|(projectile, _)| self.projectiles.contains_key(projectile))
+            .collect();
+        for (projectile, target) in contacts {
+            let velocity = launch_velocities
+                .get(&projectile)
+                .copied()
+                .unwrap_or_default();
+            self.projectile_contact(projectile, target, velocity);
+        }
+
+        let mut expired = Vec::new();
+        for (id, projectile) in self.projectiles.iter_mut() {
+            projectile.remaining -= delta_time;
+            if projectile.remaining <= 0.0 {
+                expired.push(*id);
+            }
+        }
+        for id in expired {
+            let position = self
+                .transform(id)
+                .map_or(Vec3::ZERO, |(position, _)| position);
+            self.events.push(PhysicsEvent::ProjectileExpired {
+                projectile: id,
+                position,
+            });
+            self.despawn(id);
+        }
+    }
+
+    /// Handle one projectile touching another entity
+    fn projectile_contact(&mut self, id: EntityId, target: EntityId, velocity: Vec3) {
+        // Already stopped by an earlier contact of this step
+        let Some(projectile) = self.projectiles.get(&id) else {
+            return;
+        };
+        if projectile.owner == Some(target) || projectile.pierced.contains(&target) {
+            return;
+        }
+        let impulse = projectile.impulse;
+        let level = self.body_kind(target) == Some(BodyKind::Fixed);
+
+        if !level && impulse != 0.0 {
+            if let Some(body) = self.body_mut(target) {
+                if body.is_dynamic() {
+                    body.apply_impulse(to_vector(velocity.normalize_or_zero() * impulse), true);
+                }
+            }
+        }
+        let projectile = self.projectiles.get_mut(&id).expect("checked above");
+        let stopped = if level {
+            let bounces = projectile.bounces_left > 0;
+            projectile.bounces_left = projectile.bounces_left.saturating_sub(1);
+            !bounces
+        } else if projectile.pierces_left > 0 {
+            projectile.pierces_left -= 1;
+            projectile.pierced.push(target);
+            false
+        } else {
+            true
+        };
+
+        let position = self
+            .body(id)
+            .map_or(Vec3::ZERO, |body| from_vector(body.translation()));
+        self.events.push(PhysicsEvent::ProjectileHit {
+            projectile: id,
+            target,
+            position,
+            velocity,
+            stopped,
+        });
+        if stopped {
+            self.despawn(id);
+        }
+    }
+}
@@ -0,0 +1,304 @@
+/// Scene queries: raycasts, shape casts and overlap tests
+///
+/// Queries run against the `QueryPipeline`, which `step` refreshes after every
+/// simulation step and which body/collider changes refresh immediately, so
+/// results always match the current world state.
+use super::bodies::isometry;
+use super::{from_vector, to_vector, ColliderShape, CollisionGroups, EntityId, PhysicsWorld};
+use glam::{Quat, Vec3};
+use rapier3d::parry::query::{self, TOIStatus};
+use rapier3d::prelude::*;
+
+/// Which colliders a query considers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueryOptions {
+    /// Only colliders whose groups interact with these are considered
+    pub groups: CollisionGroups,
+    /// Ignore every collider of this entity, e.g. the caster itself
+    pub exclude: Option<EntityId>,
+    /// Whether sensors (trigger volumes) can be hit
+    pub include_sensors: bool,
+}
+
+impl QueryOptions {
+    /// Default options that ignore one entity
+    pub fn excluding(entity: EntityId) -> Self {
+        Self {
+            exclude: Some(entity),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_groups(mut self, groups: CollisionGroups) -> Self {
+        self.groups = groups;
+        self
+    }
+}
+
+impl Default for QueryOptions {
+    fn default() -> Self {
+        Self {
+            groups: CollisionGroups::ALL,
+            exclude: None,
+            include_sensors: false,
+        }
+    }
+}
+
+/// First collider hit by a ray
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    /// `None` for colliders not spawned through the entity API
+    pub entity: Option<EntityId>,
+    pub collider: ColliderHandle,
+    pub point: Vec3,
+    /// Surface normal at `point`, facing the ray origin
+    pub normal: Vec3,
+    /// Distance from the ray origin to `point`
+    pub distance: f32,
+}
+
+/// First collider hit by a swept shape
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShapeHit {
+    pub entity: Option<EntityId>,
+    pub collider: ColliderHandle,
+    /// How far the shape travelled before touching the collider
+    pub distance: f32,
+    /// Contact point on the hit collider
+    pub point: Vec3,
+    /// Surface normal of the hit collider at `point`
+    pub normal: Vec3,
+    /// The shape already overlapped the collider at its start position
+    pub penetrating: bool,
+}
+
+impl PhysicsWorld {
+    /// Cast a ray and return the closest hit within `max_distance`
+    pub fn raycast(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        max_distance: f32,
+        options: &QueryOptions,
+    ) -> Option<RayHit> {
+        let ray = ray(origin, direction)?;
+        self.query_pipeline
+            .cast_ray_and_get_normal(
+                &self.rigid_body_set,
+                &self.collider_set,
+                &ray,
+                max_distance,
+                true,
+                self.query_filter(options),
+            )
+            .map(|(collider, hit)| self.ray_hit(&ray, collider, hit))
+    }
+
+    /// Cast a ray and return every hit within `max_distance`, closest first
+    pub fn raycast_all(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        max_distance: f32,
+        options: &QueryOptions,
+    ) -> Vec<RayHit> {
+        let Some(ray) = ray(origin, direction) else {
+            return Vec::new();
+        };
+        let mut hits = Vec::new();
+        self.query_pipeline.intersections_with_ray(
+            &self.rigid_body_set,
+            &self.collider_set,
+            &ray,
+            max_distance,
+            true,
+            self.query_filter(options),
+            |collider, hit| {
+                hits.push(self.ray_hit(&ray, collider, hit));
+                true
+            },
+        );
+        // Ties are broken by handle so the order is deterministic
+        hits.sort_by(|a, b| {
+            a.distance.total_cmp(&b.distance).then_with(|| {
+                a.collider
+                    .into_raw_parts()
+                    .cmp(&b.collider.into_raw_parts())
+            })
+        });
+        hits
+    }
+
+    /// Whether nothing blocks the straight line between two points
+    pub fn line_of_sight(&self, from: Vec3, to: Vec3, options: &QueryOptions) -> bool {
+        let offset = to - from;
+        self.raycast(from, offset, offset.length(), options)
+            .is_none()
+    }
+
+    /// Sweep a shape along `direction` and return the first collider it touches
+    pub fn shape_cast(
+        &self,
+        shape: &ColliderShape,
+        position: Vec3,
+        rotation: Quat,
+        direction: Vec3,
+        max_distance: f32,
+        options: &QueryOptions,
+    ) -> anyhow::Result<Option<ShapeHit>> {
+        let shape = shape.to_shared_shape()?;
+        let direction = direction.normalize_or_zero();
+        if direction == Vec3::ZERO {
+            return Ok(None);
+        }
+        let hit = self.query_pipeline.cast_shape(
+            &self.rigid_body_set,
+            &self.collider_set,
+            &isometry(position, rotation),
+            &to_vector(direction),
+            shape.as_ref(),
+            max_distance,
+            true,
+            self.query_filter(options),
+        );
+        Ok(hit.map(|(collider, toi)| ShapeHit {
+            entity: self.entity_of_collider(collider),
+            collider,
+            distance: toi.toi,
+            point: from_vector(&toi.witness1.coords),
+            normal: from_vector(&toi.normal1),
+            penetrating: toi.status == TOIStatus::Penetrating,
+        }))
+    }
+
+    /// Entities with at least one collider overlapping the shape, in ascending ID order
+    pub fn overlap(
+        &self,
+        shape: &ColliderShape,
+        position: Vec3,
+        rotation: Quat,
+        options: &QueryOptions,
+    ) -> anyhow::Result<Vec<EntityId>> {
+        let shape = shape.to_shared_shape()?;
+        let mut entities = Vec::new();
+        self.query_pipeline.intersections_with_shape(
+            &self.rigid_body_set,
+            &self.collider_set,
+            &isometry(position, rotation),
+            shape.as_ref(),
+            self.query_filter(options),
+            |collider| {
+                entities.extend(self.entity_of_collider(collider));
+                true
+            },
+        );
+        entities.sort();
+        entities.dedup();
+        Ok(entities)
+    }
+
+    /// Entities overlapping a sphere, in ascending ID order
+    pub fn overlap_sphere(
+        &self,
+        center: Vec3,
+        radius: f32,
+        options: &QueryOptions,
+    ) -> Vec<EntityId> {
+        self.overlap(
+            &ColliderShape::Sphere { radius },
+            center,
+            Quat::IDENTITY,
+            options,
+        )
+        .expect("sphere shape is always valid")
+    }
+
+    /// Entities overlapping a box, in ascending ID order
+    pub fn overlap_box(
+        &self,
+        center: Vec3,
+        half_extents: Vec3,
+        rotation: Quat,
+        options: &QueryOptions,
+    ) -> Vec<EntityId> {
+        self.overlap(
+            &ColliderShape::Box { half_extents },
+            center,
+            rotation,
+            options,
+        )
+        .expect("box shape is always valid")
+    }
+
+    /// Entities with a collider within `distance` of any collider of `id`, in
+    /// ascending ID order and without `id` itself
+    ///
+    /// Used for things that spread between touching bodies, such as fire.
+    pub fn nearby(&self, id: EntityId, distance: f32, options: &QueryOptions) -> Vec<EntityId> {
+        let Some(body) = self.body(id) else {
+            return Vec::new();
+        };
+        let filter = self.query_filter(&QueryOptions {
+            exclude: Some(id),
+            ..*options
+        });
+        let mut entities = Vec::new();
+        for handle in body.colliders() {
+            let collider = &self.collider_set[*handle];
+            let aabb = collider.compute_aabb().loosened(distance);
+            self.query_pipeline
+                .colliders_with_aabb_intersecting_aabb(&aabb, |other| {
+                    let other_collider = &self.collider_set[*other];
+                    let close = filter.test(&self.rigid_body_set, *other, other_collider)
+                        && query::distance(
+                            collider.position(),
+                            collider.shape(),
+                            other_collider.position(),
+                            other_collider.shape(),
+                        )
+                        .is_ok_and(|gap| gap <= distance);
+                    if close {
+                        entities.extend(self.entity_of_collider(*other));
+                    }
+                    true
+                });
+        }
+        entities.sort();
+        entities.dedup();
+        entities
+    }
+
+    fn query_filter(&self, options: &QueryOptions) -> QueryFilter<'static> {
+        let mut filter = QueryFilter::new().groups(options.groups.into());
+        if !options.include_sensors {
+            filter = filter.exclude_sensors();
+        }
+        if let Some(body) = options.exclude.and_then(|entity| self.body_handle(entity)) {
+            filter = filter.exclude_rigid_body(body);
+        }
+        filter
+    }
+
+    fn ray_hit(&self, ray: &Ray, collider: ColliderHandle, hit: RayIntersection) -> RayHit {
+        RayHit {
+            entity: self.entity_of_collider(collider),
+            collider,
+            point: from_vector(&ray.point_at(hit.toi).coords),
+            normal: from_vector(&hit.normal),
+            distance: hit.toi,
+        }
+    }
+}
+
+/// Ray with a unit direction, so time of impact equals distance
+fn ray(origin: Vec3, direction: Vec3) -> Option<Ray> {
+    let direction = direction.normalize_or_zero();
+    if direction == Vec3::ZERO {
+        return None;
+    }
+    Some(Ray::new(
+        point![origin.x, origin.y, origin.z],
+        to_vector(direction),
+    ))
+}
@@ -0,0 +1,392 @@
+/// Physics world snapshots, state hashing and comparison
+///
+/// Snapshots capture every body and collider in a serializable form. The state
+/// hash is computed over the exact bit patterns of positions, rotations and
+/// velocities, so two worlds hash equal only if they are bit-identical; the
+/// diff pinpoints which body and field diverged first.
+use super::{BodyKind, EntityId, PhysicsWorld};
+use rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ColliderSnapshot {
+    /// Rapier collider handle as `(index, generation)`
+    pub handle: (u32, u32),
+    /// Shape type name, e.g. `Cuboid` or `Capsule`
+    pub shape: String,
+    /// World-space position
+    pub translation: [f32; 3],
+    /// World-space rotation quaternion as `[x, y, z, w]`
+    pub rotation: [f32; 4],
+    pub friction: f32,
+    pub restitution: f32,
+    pub sensor: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BodySnapshot {
+    /// Rapier rigid body handle as `(index, generation)`
+    pub handle: (u32, u32),
+    /// Game-level ID, if the body was spawned through the entity API
+    pub entity: Option<EntityId>,
+    pub kind: BodyKind,
+    pub translation: [f32; 3],
+    /// Rotation quaternion as `[x, y, z, w]`
+    pub rotation: [f32; 4],
+    pub linvel: [f32; 3],
+    pub angvel: [f32; 3],
+    pub sleeping: bool,
+    pub colliders: Vec<ColliderSnapshot>,
+}
+
+/// Complete serializable state of a `PhysicsWorld`
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct WorldSnapshot {
+    /// Bodies ordered by handle
+    pub bodies: Vec<BodySnapshot>,
+    /// Colliders not attached to any body, ordered by handle
+    pub static_colliders: Vec<ColliderSnapshot>,
+}
+
+impl WorldSnapshot {
+    /// Hash of all positions, rotations and velocities, stable across runs and platforms
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = StateHasher::new();
+        for body in &self.bodies {
+            hasher.write_u32(body.handle.0);
+            hasher.write_u32(body.handle.1);
+            hasher.write_floats(&body.translation);
+            hasher.write_floats(&body.rotation);
+            hasher.write_floats(&body.linvel);
+            hasher.write_floats(&body.angvel);
+            for collider in &body.colliders {
+                hasher.write_floats(&collider.translation);
+                hasher.write_floats(&collider.rotation);
+            }
+        }
+        for collider in &self.static_colliders {
+            hasher.write_u32(collider.handle.0);
+            hasher.write_u32(collider.handle.1);
+            hasher.write_floats(&collider.translation);
+            hasher.write_floats(&collider.rotation);
+        }
+        hasher.finish()
+    }
+
+    /// List every difference between `self` (left) and `other` (right)
+    pub fn diff(&self, other: &WorldSnapshot) -> SnapshotDiff {
+        let mut differences = Vec::new();
+
+        diff_by_handle(
+            &self.bodies,
+            &other.bodies,
+            |body| body.handle,
+            &mut differences,
+            |left, right, out| {
+                compare(out, left.handle, "entity", &left.entity, &right.entity);
+                compare(out, left.handle, "kind", &left.kind, &right.kind);
+                compare_floats(
+                    out,
+                    left.handle,
+                    "translation",
+                    &left.translation,
+                    &right.translation,
+                );
+                compare_floats(
+                    out,
+                    left.handle,
+                    "rotation",
+                    &left.rotation,
+                    &right.rotation,
+                );
+                compare_floats(out, left.handle, "linvel", &left.linvel, &right.linvel);
+                compare_floats(out, left.handle, "angvel", &left.angvel, &right.angvel);
+                compare(
+                    out,
+                    left.handle,
+                    "sleeping",
+                    &left.sleeping,
+                    &right.sleeping,
+                );
+                diff_colliders(&left.colliders, &right.colliders, out);
+            },
+        );
+        diff_colliders(
+            &self.static_colliders,
+            &other.static_colliders,
+            &mut differences,
+        );
+
+        SnapshotDiff { differences }
+    }
+}
+
+/// A single field that differs between two snapshots
+#[derive(Debug, Clone, PartialEq)]
+pub enum Difference {
+    /// An object exists only in the left snapshot
+    OnlyInLeft { handle: (u32, u32) },
+    /// An object exists only in the right snapshot
+    OnlyInRight { handle: (u32, u32) },
+    /// A field has different values on each side
+    Changed {
+        handle: (u32, u32),
+        field: &'static str,
+        left: String,
+        right: String,
+    },
+}
+
+/// Report of every difference between two snapshots
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SnapshotDiff {
+    pub differences: Vec<Difference>,
+}
+
+impl SnapshotDiff {
+    pub fn is_empty(&self) -> bool {
+        self.differences.is_empty()
+    }
+}
+
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "snapshots are identical");
+        }
+        writeln!(f, "{} difference(s):", self.differences.len())?;
+        for difference in &self.differences {
+            match difference {
+                Difference::OnlyInLeft { handle } => writeln!(f, "  {:?}: only in left", handle)?,
+                Difference::OnlyInRight { handle } => writeln!(f, "  {:?}: only in right", handle)?,
+                Difference::Changed {
+                    handle,
+                    field,
+                    left,
+                    right,
+                } => writeln!(f, "  {:?}.{}: {} != {}", handle, field, left, right)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PhysicsWorld {
+    /// Capture the full state of every body and collider
+    pub fn snapshot(&self) -> WorldSnapshot {
+        let mut bodies: Vec<BodySnapshot> = self
+            .rigid_body_set
+            .iter()
+            .map(|(handle, body)| BodySnapshot {
+                handle: handle.into_raw_parts(),
+                entity: self.entity_of_body(handle),
+                kind: body.body_type().into(),
+                translation: vector3(body.translation()),
+                rotation: quaternion(body.rotation()),
+                linvel: vector3(body.linvel()),
+                angvel: vector3(body.angvel()),
+                sleeping: body.is_sleeping(),
+                colliders: body
+                    .colliders()
+                    .iter()
+                    .filter_map(|handle| {
+                        self.collider_set
+                            .get(*handle)
+                            .map(|collider| collider_snapshot(*handle, collider))
+                    })
+                    .collect(),
+            })
+            .collect();
+        bodies.sort_by_key(|body| body.handle);
+
+        let mut static_colliders: Vec<ColliderSnapshot> = self
+            .collider_set
+            .iter()
+            .filter(|(_, collider)| collider.parent().is_none())
+            .map(|(handle, collider)| collider_snapshot(handle, collider))
+            .collect();
+        static_colliders.sort_by_key(|collider| collider.handle);
+
+        WorldSnapshot {
+            bodies,
+            static_colliders,
+        }
+    }
+
+    /// Bit-exact hash of the current world state, see `WorldSnapshot::state_hash`
+    pub fn state_hash(&self) -> u64 {
+        self.snapshot().state_hash()
+    }
+}
+
+fn collider_snapshot(handle: ColliderHandle, collider: &Collider) -> ColliderSnapshot {
+    ColliderSnapshot {
+        handle: handle.into_raw_parts(),
+        shape: format!("{:?}", collider.shape().shape_type()),
+        translation: vector3(collider.translation()),
+        rotation: quaternion(collider.rotation()),
+        friction: collider.friction(),
+        restitution: collider.restitution(),
+        sensor: collider.is_sensor(),
+    }
+}
+
+fn vector3(v: &Vector<Real>) -> [f32; 3] {
+    [v.x, v.y, v.z]
+}
+
+fn quaternion(q: &Rotation<Real>) -> [f32; 4] {
+    [q.i, q.j, q.k, q.w]
+}
+
+fn diff_colliders(
+    left: &[ColliderSnapshot],
+    right: &[ColliderSnapshot],
+    out: &mut Vec<Difference>,
+) {
+    diff_by_handle(
+        left,
+        right,
+        |collider| collider.handle,
+        out,
+        |left, right, out| {
+            compare(out, left.handle, "shape", &left.shape, &right.shape);
+            compare_floats(
+                out,
+                left.handle,
+                "translation",
+                &left.translation,
+                &right.translation,
+            );
+            compare_floats(
+                out,
+                left.handle,
+                "rotation",
+                &left.rotation,
+                &right.rotation,
+            );
+            compare_floats(
+                out,
+                left.handle,
+                "friction",
+                &[left.friction],
+                &[right.friction],
+            );
+            compare_floats(
+                out,
+                left.handle,
+                "restitution",
+                &[left.restitution],
+                &[right.restitution],
+            );
+            compare(out, left.handle, "sensor", &left.sensor, &right.sensor);
+        },
+    );
+}
+
+/// Walk two handle-sorted lists, reporting unmatched entries and comparing matched ones
+fn diff_by_handle<T>(
+    left: &[T],
+    right: &[T],
+    handle: impl Fn(&T) -> (u32, u32),
+    out: &mut Vec<Difference>,
+    mut compare_items: impl FnMut(&T, &T, &mut Vec<Difference>),
+) {
+    let (mut i, mut j) = (0, 0);
+    while i < left.len() || j < right.len() {
+        match (left.get(i), right.get(j)) {
+            (Some(l), Some(r)) if handle(l) == handle(r) => {
+                compare_items(l, r, out);
+                i += 1;
+                j += 1;
+            }
+            (Some(l), Some(r)) if handle(l) < handle(r) => {
+                out.push(Difference::OnlyInLeft { handle: handle(l) });
+                i += 1;
+            }
+            (Some(l), None) => {
+                out.push(Difference::OnlyInLeft { handle: handle(l) });
+                i += 1;
+            }
+            (_, Some(r)) => {
+                out.push(Difference::OnlyInRight { handle: handle(r) });
+                j += 1;
+            }
+            (None, None) => break,
+        }
+    }
+}
+
+fn compare<T: PartialEq + fmt::Debug>(
+    out: &mut Vec<Difference>,
+    handle: (u32, u32),
+    field: &'static str,
+    left: &T,
+    right: &T,
+) {
+    if left != right {
+        out.push(Difference::Changed {
+            handle,
+            field,
+            left: format!("{:?}", left),
+            right: format!("{:?}", right),
+        });
+    }
+}
+
+/// Compare float arrays bit for bit, so `-0.0` vs `0.0` and NaN payloads count
+fn compare_floats(
+    out: &mut Vec<Difference>,
+    handle: (u32, u32),
+    field: &'static str,
+    left: &[f32],
+    right: &[f32],
+) {
+    let same = left.len() == right.len()
+        && left
+            .iter()
+            .zip(right)
+            .all(|(l, r)| l.to_bits() == r.to_bits());
+    if !same {
+        out.push(Difference::Changed {
+            handle,
+            field,
+            left: format!("{:?}", left),
+            right: format!("{:?}", right),
+        });
+    }
+}
+
+/// 64-bit FNV-1a; unlike `DefaultHasher` its output is fixed forever
+struct StateHasher(u64);
+
+impl StateHasher {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    fn write_floats(&mut self, values: &[f32]) {
+        for value in values {
+            self.write_u32(value.to_bits());
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
@@ -0,0 +1,193 @@
+/// Flat forms of the tagged enums in spell files
+///
+/// Serde buffers internally tagged enums before picking the variant, which
+/// loses track of the field an error is about. Reading the table into a flat
+/// struct first keeps errors pointing at e.g. `delivery.speed`, and lets us
+/// name the fields a variant requires or does not take.
+use super::{Delivery, Effect, StatusKind};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum DeliveryKind {
+    Hitscan,
+    Projectile,
+    Area,
+    #[serde(rename = "self")]
+    SelfCast,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(super) struct DeliveryFields {
+    #[serde(rename = "type")]
+    kind: DeliveryKind,
+    range: Option<f32>,
+    speed: Option<f32>,
+    radius: Option<f32>,
+    gravity_scale: Option<f32>,
+    lifetime_ms: Option<u32>,
+    drag: Option<f32>,
+    bounces: Option<u32>,
+    pierce: Option<u32>,
+}
+
+impl TryFrom<DeliveryFields> for Delivery {
+    type Error = String;
+
+    fn try_from(fields: DeliveryFields) -> Result<Self, String> {
+        let DeliveryFields {
+            kind,
+            range,
+            speed,
+            radius,
+            gravity_scale,
+            lifetime_ms,
+            drag,
+            bounces,
+            pierce,
+        } = fields;
+        let (name, allowed): (&str, &[&str]) = match kind {
+            DeliveryKind::Hitscan => ("hitscan", &["range"]),
+            DeliveryKind::Projectile => (
+                "projectile",
+                &[
+                    "speed",
+                    "radius",
+                    "gravity_scale",
+                    "lifetime_ms",
+                    "drag",
+                    "bounces",
+                    "pierce",
+                ],
+            ),
+            DeliveryKind::Area => ("area", &["radius", "range"]),
+            DeliveryKind::SelfCast => ("self", &[]),
+        };
+        check_fields(
+            &format!("{} delivery", name),
+            &[
+                ("range", range.is_some()),
+                ("speed", speed.is_some()),
+                ("radius", radius.is_some()),
+                ("gravity_scale", gravity_scale.is_some()),
+                ("lifetime_ms", lifetime_ms.is_some()),
+                ("drag", drag.is_some()),
+                ("bounces", bounces.is_some()),
+                ("pierce", pierce.is_some()),
+            ],
+            allowed,
+        )?;
+        let what = format!("{} delivery", name);
+        Ok(match kind {
+            DeliveryKind::Hitscan => Delivery::Hitscan {
+                range: required(range, "range", &what)?,
+            },
+            DeliveryKind::Projectile => Delivery::Projectile {
+                speed: required(speed, "speed", &what)?,
+                radius: required(radius, "radius", &what)?,
+                gravity_scale: gravity_scale.unwrap_or(0.0),
+                lifetime_ms: required(lifetime_ms, "lifetime_ms", &what)?,
+                drag: drag.unwrap_or(0.0),
+                bounces: bounces.unwrap_or(0),
+                pierce: pierce.unwrap_or(0),
+            },
+            DeliveryKind::Area => Delivery::Area {
+                radius: required(radius, "radius", &what)?,
+                range: range.unwrap_or(0.0),
+            },
+            DeliveryKind::SelfCast => Delivery::SelfCast,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum EffectKind {
+    Damage,
+    Heal,
+    Shield,
+    Knockback,
+    ApplyStatus,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(super) struct EffectFields {
+    #[serde(rename = "type")]
+    kind: EffectKind,
+    amount: Option<f32>,
+    duration_ms: Option<u32>,
+    impulse: Option<f32>,
+    status: Option<StatusKind>,
+    stacks: Option<u32>,
+}
+
+impl TryFrom<EffectFields> for Effect {
+    type Error = String;
+
+    fn try_from(fields: EffectFields) -> Result<Self, String> {
+        let EffectFields {
+            kind,
+            amount,
+            duration_ms,
+            impulse,
+            status,
+            stacks,
+        } = fields;
+        let (name, allowed): (&str, &[&str]) = match kind {
+            EffectKind::Damage => ("damage", &["amount"]),
+            EffectKind::Heal => ("heal", &["amount"]),
+            EffectKind::Shield => ("shield", &["amount", "duration_ms"]),
+            EffectKind::Knockback => ("knockback", &["impulse"]),
+            EffectKind::ApplyStatus => ("apply_status", &["status", "duration_ms", "stacks"]),
+        };
+        let what = format!("{} effect", name);
+        check_fields(
+            &what,
+            &[
+                ("amount", amount.is_some()),
+                ("duration_ms", duration_ms.is_some()),
+                ("impulse", impulse.is_some()),
+                ("status", status.is_some()),
+                ("stacks", stacks.is_some()),
+            ],
+            allowed,
+        )?;
+        Ok(match kind {
+            EffectKind::Damage => Effect::Damage {
+                amount: required(amount, "amount", &what)?,
+            },
+            EffectKind::Heal => Effect::Heal {
+                amount: required(amount, "amount", &what)?,
+            },
+            EffectKind::Shield => Effect::Shield {
+                amount: required(amount, "amount", &what)?,
+                duration_ms: required(duration_ms, "duration_ms", &what)?,
+            },
+            EffectKind::Knockback => Effect::Knockback {
+                impulse: required(impulse, "impulse", &what)?,
+            },
+            EffectKind::ApplyStatus => Effect::ApplyStatus {
+                status: required(status, "status", &what)?,
+                duration_ms: required(duration_ms, "duration_ms", &what)?,
+                stacks: stacks.unwrap_or(1),
+            },
+        })
+    }
+}
+
+/// Reject fields given that `what` does not take
+fn check_fields(what: &str, present: &[(&str, bool)], allowed: &[&str]) -> Result<(), String> {
+    match present
+        .iter()
+        .find(|(name, given)| *given && !allowed.contains(name))
+    {
+        Some((name, _)) => Err(format!("field `{}` does not apply to {}", name, what)),
+        None => Ok(()),
+    }
+}
+
+fn required<T>(value: Option<T>, name: &str, what: &str) -> Result<T, String> {
+    value.ok_or_else(|| format!("missing field `{}` for {}", name, what))
+}
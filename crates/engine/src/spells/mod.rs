@@ -0,0 +1,204 @@
+/// Data-driven spell definitions
+///
+/// Spells are described in TOML files under `assets/spells/`, one spell per
+/// file, so designers can add and tune them without touching Rust. A
+/// `SpellRegistry` loads and validates every file in the directory and
+/// assigns each spell the numeric ID used on the wire.
+use crate::physics_core::{EntityId, ProjectileDesc};
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+mod format;
+pub mod registry;
+
+pub use registry::{parse_spell, SpellError, SpellErrors, SpellId, SpellRegistry};
+
+/// Element of a spell, deciding how it interacts with the world
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Element {
+    Fire,
+    Water,
+    Ice,
+    Lightning,
+    Earth,
+    Air,
+    /// Pure magic, interacting with nothing
+    Arcane,
+}
+
+/// How a spell reaches its targets
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[serde(try_from = "format::DeliveryFields")]
+pub enum Delivery {
+    /// Instant ray from the caster's eyes
+    Hitscan {
+        /// Longest distance the ray reaches (m)
+        range: f32,
+    },
+    /// Physical projectile launched from the caster's eyes
+    Projectile {
+        /// Launch speed (m/s)
+        speed: f32,
+        /// Collision radius (m)
+        radius: f32,
+        /// Multiplier of world gravity; 0 (the default) flies straight
+        gravity_scale: f32,
+        /// Time before the projectile fizzles (ms)
+        lifetime_ms: u32,
+        /// Fraction of speed lost per second; 0 (the default) keeps it
+        drag: f32,
+        /// Bounces off level geometry before it stops, 0 by default
+        bounces: u32,
+        /// Targets it passes through before it stops, 0 by default
+        pierce: u32,
+    },
+    /// Everything within `radius` of a point `range` ahead of the caster
+    Area {
+        radius: f32,
+        /// Distance ahead of the caster; 0 (the default) centers the area on them
+        range: f32,
+    },
+    /// Affects only the caster
+    #[serde(rename = "self")]
+    SelfCast,
+}
+
+/// Status effects spells can apply
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatusKind {
+    Burning,
+    Chilled,
+    Frozen,
+    Wet,
+    Shocked,
+    Stunned,
+    Slowed,
+    /// Cannot cast spells
+    Silenced,
+}
+
+/// What a spell does to each target it reaches
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[serde(try_from = "format::EffectFields")]
+pub enum Effect {
+    /// Damage of the spell's element
+    Damage {
+        amount: f32,
+    },
+    Heal {
+        amount: f32,
+    },
+    /// Absorbs damage until used up or expired
+    Shield {
+        amount: f32,
+        duration_ms: u32,
+    },
+    /// Impulse pushing targets away from the impact (N·s)
+    Knockback {
+        impulse: f32,
+    },
+    ApplyStatus {
+        status: StatusKind,
+        duration_ms: u32,
+        /// Stacks added per application, 1 by default
+        stacks: u32,
+    },
+}
+
+/// Keeps a spell going after its wind-up for as long as the cast button is held
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Channel {
+    /// Longest the spell can be channeled (ms)
+    pub duration_ms: u32,
+    /// Time between two applications of the spell's effects (ms)
+    pub interval_ms: u32,
+}
+
+impl Channel {
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.duration_ms as u64)
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms as u64)
+    }
+}
+
+/// One spell as written in its definition file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpellDef {
+    /// Name shown to players
+    pub name: String,
+    pub element: Element,
+    /// Mana spent when the spell goes off after its wind-up
+    pub mana_cost: f32,
+    /// Wind-up before the spell goes off (ms)
+    pub cast_time_ms: u32,
+    /// Wait after the spell went off before it can be cast again (ms)
+    pub cooldown_ms: u32,
+    /// Whether moving leaves the cast alone instead of interrupting it
+    #[serde(default)]
+    pub cast_while_moving: bool,
+    /// Repeats the effects while the button is held; cast once if absent
+    #[serde(default)]
+    pub channel: Option<Channel>,
+    pub delivery: Delivery,
+    pub effects: Vec<Effect>,
+}
+
+impl SpellDef {
+    pub fn cast_time(&self) -> Duration {
+        Duration::from_millis(self.cast_time_ms as u64)
+    }
+
+    pub fn cooldown(&self) -> Duration {
+        Duration::from_millis(self.cooldown_ms as u64)
+    }
+
+    /// Projectile this spell launches from `origin` towards `direction`
+    ///
+    /// Its impulse is the sum of the spell's knockback effects. `None` unless
+    /// the spell is delivered by projectile.
+    pub fn projectile(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        owner: Option<EntityId>,
+    ) -> Option<ProjectileDesc> {
+        let Delivery::Projectile {
+            speed,
+            radius,
+            gravity_scale,
+            lifetime_ms,
+            drag,
+            bounces,
+            pierce,
+        } = self.delivery
+        else {
+            return None;
+        };
+        let mut desc = ProjectileDesc::new(origin, direction.normalize_or_zero() * speed, radius);
+        desc.gravity_scale = gravity_scale;
+        desc.drag = drag;
+        desc.lifetime = lifetime_ms as f32 / 1000.0;
+        desc.bounces = bounces;
+        desc.pierce = pierce;
+        desc.impulse = self
+            .effects
+            .iter()
+            .map(|effect| match *effect {
+                Effect::Knockback { impulse } => impulse,
+                _ => 0.0,
+            })
+            .sum();
+        desc.owner = owner;
+        Some(desc)
+    }
+}
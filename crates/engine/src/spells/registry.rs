@@ -0,0 +1,371 @@
+/// Loading and validation of spell definition files
+use super::{Delivery, Effect, SpellDef};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Numeric spell ID used on the wire, e.g. in `ActiveSpell::spell_id`
+///
+/// IDs follow the alphabetical order of the spell keys, so every build that
+/// loads the same files agrees on them.
+pub type SpellId = u32;
+
+/// A problem with one spell definition file
+#[derive(Debug, Error)]
+pub enum SpellError {
+    #[error("{}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The file is not valid TOML or does not match the format
+    #[error("{}: {}{message}", path.display(), field_prefix(field))]
+    Parse {
+        path: PathBuf,
+        field: String,
+        message: String,
+    },
+    /// The file parsed, but a value makes no sense
+    #[error("{}: `{field}` {message}", path.display())]
+    Invalid {
+        path: PathBuf,
+        field: String,
+        message: String,
+    },
+}
+
+fn field_prefix(field: &str) -> String {
+    if field.is_empty() {
+        String::new()
+    } else {
+        format!("`{}`: ", field)
+    }
+}
+
+impl SpellError {
+    /// File the error is about
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Io { path, .. } | Self::Parse { path, .. } | Self::Invalid { path, .. } => path,
+        }
+    }
+
+    /// Field the error is about, e.g. `effects[1].amount`, if known
+    pub fn field(&self) -> Option<&str> {
+        match self {
+            Self::Io { .. } => None,
+            Self::Parse { field, .. } | Self::Invalid { field, .. } => {
+                Some(field.as_str()).filter(|field| !field.is_empty())
+            }
+        }
+    }
+}
+
+/// Every problem found while loading a spell directory
+#[derive(Debug)]
+pub struct SpellErrors(pub Vec<SpellError>);
+
+impl fmt::Display for SpellErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} invalid spell definition(s)", self.0.len())?;
+        for error in &self.0 {
+            write!(f, "\n  {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SpellErrors {}
+
+/// Parse and validate one spell definition
+pub fn parse_spell(path: &Path, contents: &str) -> Result<SpellDef, SpellError> {
+    let spell: SpellDef = serde_path_to_error::deserialize(toml::Deserializer::new(contents))
+        .map_err(|e| parse_error(path, contents, e))?;
+    validate(&spell).map_err(|(field, message)| SpellError::Invalid {
+        path: path.to_path_buf(),
+        field,
+        message,
+    })?;
+    Ok(spell)
+}
+
+/// Turn a deserialization error into one naming the full path of the field
+fn parse_error(
+    path: &Path,
+    contents: &str,
+    error: serde_path_to_error::Error<toml::de::Error>,
+) -> SpellError {
+    let table = error.path().to_string();
+    let error = error.into_inner();
+    let message = error.message().trim().replace('\n', "; ");
+    // Serde reports missing fields at the table that should hold them
+    let named = message.split('`').nth(1).filter(|_| {
+        ["missing field", "unknown field", "field `"]
+            .iter()
+            .any(|prefix| message.starts_with(prefix))
+    });
+    let field = match (table.as_str(), named) {
+        (".", Some(name)) => name.to_string(),
+        (".", None) => {
+            // Not even valid TOML; point at the line instead
+            let line = error
+                .span()
+                .map_or(1, |span| contents[..span.start].lines().count().max(1));
+            return SpellError::Parse {
+                path: path.to_path_buf(),
+                field: String::new(),
+                message: format!("line {}: {}", line, message),
+            };
+        }
+        (table, Some(name)) if !table.ends_with(name) => format!("{}.{}", table, name),
+        _ => table,
+    };
+    SpellError::Parse {
+        path: path.to_path_buf(),
+        field,
+        message,
+    }
+}
+
+/// Check the values serde cannot, returning the offending field
+fn validate(spell: &SpellDef) -> Result<(), (String, String)> {
+    let positive = |field: &str, value: f32| {
+        if value.is_finite() && value > 0.0 {
+            Ok(())
+        } else {
+            Err((
+                field.to_string(),
+                format!("must be positive, got {}", value),
+            ))
+        }
+    };
+    let not_negative = |field: &str, value: f32| {
+        if value.is_finite() && value >= 0.0 {
+            Ok(())
+        } else {
+            Err((
+                field.to_string(),
+                format!("must not be negative, got {}", value),
+            ))
+        }
+    };
+
+    if spell.name.trim().is_empty() {
+        return Err(("name".to_string(), "must not be empty".to_string()));
+    }
+    not_negative("mana_cost", spell.mana_cost)?;
+    if let Some(channel) = spell.channel {
+        if channel.duration_ms == 0 {
+            return Err((
+                "channel.duration_ms".to_string(),
+                "must be positive".to_string(),
+            ));
+        }
+        if channel.interval_ms == 0 || channel.interval_ms > channel.duration_ms {
+            return Err((
+                "channel.interval_ms".to_string(),
+                format!(
+                    "must be between 1 and duration_ms ({}), got {}",
+                    channel.duration_ms, channel.interval_ms
+                ),
+            ));
+        }
+    }
+
+    match spell.delivery {
+        Delivery::Hitscan { range } => positive("delivery.range", range)?,
+        Delivery::Projectile {
+            speed,
+            radius,
+            gravity_scale,
+            lifetime_ms,
+            drag,
+            ..
+        } => {
+            positive("delivery.speed", speed)?;
+            positive("delivery.radius", radius)?;
+            if !gravity_scale.is_finite() {
+                return Err((
+                    "delivery.gravity_scale".to_string(),
+                    "must be a finite number".to_string(),
+                ));
+            }
+            if lifetime_ms == 0 {
+                return Err((
+                    "delivery.lifetime_ms".to_string(),
+                    "must be positive".to_string(),
+                ));
+            }
+            not_negative("delivery.drag", drag)?;
+        }
+        Delivery::Area { radius, range } => {
+            positive("delivery.radius", radius)?;
+            not_negative("delivery.range", range)?;
+        }
+        Delivery::SelfCast => {}
+    }
+
+    if spell.effects.is_empty() {
+        return Err((
+            "effects".to_string(),
+            "must list at least one effect".to_string(),
+        ));
+    }
+    for (index, effect) in spell.effects.iter().enumerate() {
+        let field = |name: &str| format!("effects[{}].{}", index, name);
+        match *effect {
+            Effect::Damage { amount } | Effect::Heal { amount } => {
+                positive(&field("amount"), amount)?;
+            }
+            Effect::Shield {
+                amount,
+                duration_ms,
+            } => {
+                positive(&field("amount"), amount)?;
+                if duration_ms == 0 {
+                    return Err((field("duration_ms"), "must be positive".to_string()));
+                }
+            }
+            Effect::Knockback { impulse } => {
+                if !impulse.is_finite() {
+                    return Err((field("impulse"), "must be a finite number".to_string()));
+                }
+            }
+            Effect::ApplyStatus {
+                duration_ms,
+                stacks,
+                ..
+            } => {
+                if duration_ms == 0 {
+                    return Err((field("duration_ms"), "must be positive".to_string()));
+                }
+                if stacks == 0 {
+                    return Err((field("stacks"), "must be at least 1".to_string()));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// All spells of the game, keyed by the name of their file without extension
+#[derive(Debug, Clone, Default)]
+pub struct SpellRegistry {
+    /// Sorted by key, so the index is the spell ID
+    spells: Vec<(String, SpellDef)>,
+}
+
+impl SpellRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load every `.toml` file in `dir`
+    ///
+    /// Reports every invalid file, not just the first, so designers can fix
+    /// them all in one go.
+    pub fn load_dir(dir: &Path) -> Result<Self, SpellErrors> {
+        let entries = std::fs::read_dir(dir).map_err(|source| {
+            SpellErrors(vec![SpellError::Io {
+                path: dir.to_path_buf(),
+                source,
+            }])
+        })?;
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+            .collect();
+        paths.sort();
+
+        let mut files = Vec::new();
+        let mut errors = Vec::new();
+        for path in paths {
+            match std::fs::read_to_string(&path) {
+                Ok(contents) => files.push((path, contents)),
+                Err(source) => errors.push(SpellError::Io { path, source }),
+            }
+        }
+        match Self::from_sources(
+            files
+                .iter()
+                .map(|(path, contents)| (path.as_path(), contents.as_str())),
+        ) {
+            Ok(registry) if errors.is_empty() => Ok(registry),
+            Ok(_) => Err(SpellErrors(errors)),
+            Err(SpellErrors(more)) => {
+                errors.extend(more);
+                Err(SpellErrors(errors))
+            }
+        }
+    }
+
+    /// Build a registry from file paths and their contents, e.g. embedded
+    /// assets; the key of each spell is its file stem
+    pub fn from_sources<'a>(
+        sources: impl IntoIterator<Item = (&'a Path, &'a str)>,
+    ) -> Result<Self, SpellErrors> {
+        let mut spells = BTreeMap::new();
+        let mut errors = Vec::new();
+        for (path, contents) in sources {
+            let Some(key) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                errors.push(SpellError::Invalid {
+                    path: path.to_path_buf(),
+                    field: "file name".to_string(),
+                    message: "must be valid UTF-8".to_string(),
+                });
+                continue;
+            };
+            match parse_spell(path, contents) {
+                Ok(spell) => {
+                    if spells.insert(key.to_string(), spell).is_some() {
+                        errors.push(SpellError::Invalid {
+                            path: path.to_path_buf(),
+                            field: "file name".to_string(),
+                            message: format!("repeats spell key '{}'", key),
+                        });
+                    }
+                }
+                Err(error) => errors.push(error),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(SpellErrors(errors));
+        }
+        Ok(Self {
+            spells: spells.into_iter().collect(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.spells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spells.is_empty()
+    }
+
+    pub fn get(&self, id: SpellId) -> Option<&SpellDef> {
+        self.spells.get(id as usize).map(|(_, spell)| spell)
+    }
+
+    /// ID of the spell defined in `<key>.toml`
+    pub fn id(&self, key: &str) -> Option<SpellId> {
+        self.spells
+            .binary_search_by(|(other, _)| other.as_str().cmp(key))
+            .ok()
+            .map(|index| index as SpellId)
+    }
+
+    pub fn key(&self, id: SpellId) -> Option<&str> {
+        self.spells.get(id as usize).map(|(key, _)| key.as_str())
+    }
+
+    /// Spells in ID order
+    pub fn iter(&self) -> impl Iterator<Item = (SpellId, &str, &SpellDef)> {
+        self.spells
+            .iter()
+            .enumerate()
+            .map(|(id, (key, spell))| (id as SpellId, key.as_str(), spell))
+    }
+}
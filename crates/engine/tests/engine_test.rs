@@ -2,11 +2,1315 @@
 
 #[cfg(test)]
 mod tests {
-    use engine::physics_core::PhysicsWorld;
+    use engine::config::NetworkConditions;
+    use engine::glam::Quat;
+    use engine::glam::Vec3;
+    use engine::net_proto::{
+        decode_message, encode_message, EntityKind, EntityState, HealthState, PlayerInput,
+        PlayerUpdate, QuantizedRotation, QuantizedVelocity, SnapshotDelta, StateSnapshot,
+        StatusState, SurfaceUpdate, SNAPSHOT_SCHEMA_VERSION,
+    };
+    use engine::net_proto::{
+        encode_frame, Channel, ClientMessage, Endpoint, FrameDecoder, FrameReader, FrameWriter,
+        LinkSimulator, PingTimer, TickClock, BUILD_HASH, MAX_DATAGRAM_SIZE, MAX_FRAME_SIZE,
+        PROTOCOL_VERSION,
+    };
+    use engine::physics_core::{
+        BodyDesc, BodyKind, CharacterController, ColliderDesc, ColliderShape, CollisionGroups,
+        EntityId, Material, PhysicsEvent, PhysicsWorld, ProjectileDesc, QueryOptions, SurfaceState,
+    };
+    use engine::spells::{parse_spell, Delivery, Effect, SpellError, SpellRegistry, StatusKind};
+    use std::path::Path;
+
+    use std::time::{Duration, Instant};
+
+    const DT: f32 = 1.0 / 60.0;
+
+    fn run_inputs(
+        world: &mut PhysicsWorld,
+        controller: &CharacterController,
+        character: &mut engine::physics_core::Character,
+        input: &PlayerInput,
+        ticks: usize,
+    ) {
+        for _ in 0..ticks {
+            controller.apply_input(world, character, input, DT);
+            world.step(DT);
+        }
+    }
 
     #[test]
     fn test_physics_world_creation() {
         let _world = PhysicsWorld::new();
         assert!(true);
     }
+
+    #[test]
+    fn test_frame_decoder_handles_split_frames() {
+        let first = encode_frame(&ClientMessage::Ping {
+            id: 1,
+            timestamp: 0,
+        })
+        .unwrap();
+        let second = encode_frame(&ClientMessage::Ping {
+            id: 2,
+            timestamp: 0,
+        })
+        .unwrap();
+        let mut stream = first.clone();
+        stream.extend_from_slice(&second);
+
+        let mut decoder = FrameDecoder::new();
+        let mut received = Vec::new();
+        // Feed one byte at a time to exercise every partial-read boundary
+        for byte in stream {
+            decoder.push_bytes(&[byte]);
+            while let Some(msg) = decoder.next_message::<ClientMessage>().unwrap() {
+                received.push(msg);
+            }
+        }
+
+        assert_eq!(received.len(), 2);
+        assert!(matches!(
+            received[0],
+            ClientMessage::Ping {
+                id: 1,
+                timestamp: 0
+            }
+        ));
+        assert!(matches!(
+            received[1],
+            ClientMessage::Ping {
+                id: 2,
+                timestamp: 0
+            }
+        ));
+        assert_eq!(decoder.buffered_len(), 0);
+    }
+
+    #[test]
+    fn test_frame_decoder_rejects_oversized_frame() {
+        let mut decoder = FrameDecoder::new();
+        decoder.push_bytes(&((MAX_FRAME_SIZE as u32) + 1).to_le_bytes());
+        assert!(decoder.next_frame().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_frame_reader_writer_round_trip() {
+        let (client, server) = tokio::io::duplex(64);
+        let mut writer = FrameWriter::new(client);
+        let mut reader = FrameReader::new(server);
+
+        let name = "a".repeat(4096);
+        let send = tokio::spawn(async move {
+            writer
+                .write_message(&ClientMessage::Connect {
+                    player_name: name,
+                    protocol_version: PROTOCOL_VERSION,
+                    build_hash: BUILD_HASH.to_string(),
+                    session_token: None,
+                })
+                .await
+                .unwrap();
+        });
+
+        let msg = reader.read_message::<ClientMessage>().await.unwrap();
+        send.await.unwrap();
+        match msg {
+            Some(ClientMessage::Connect { player_name, .. }) => {
+                assert_eq!(player_name.len(), 4096)
+            }
+            other => panic!("Unexpected message: {:?}", other),
+        }
+        assert!(reader
+            .read_message::<ClientMessage>()
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_character_walks_forward_on_ground() {
+        let mut world = PhysicsWorld::new();
+        world.add_ground_plane(0.0);
+        let controller = CharacterController::default();
+        let mut character = controller.spawn(&mut world, Vec3::ZERO);
+
+        let forward = PlayerInput {
+            move_forward: true,
+            ..Default::default()
+        };
+        run_inputs(&mut world, &controller, &mut character, &forward, 60);
+
+        let state = character.state;
+        assert!(state.grounded);
+        assert!(state.position.z > 4.0, "moved {}", state.position.z);
+        assert!(state.position.y.abs() < 0.05, "height {}", state.position.y);
+        let speed = Vec3::new(state.velocity.x, 0.0, state.velocity.z).length();
+        assert!((speed - controller.config().max_speed).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_character_jumps_and_lands() {
+        let mut world = PhysicsWorld::new();
+        world.add_ground_plane(0.0);
+        let controller = CharacterController::default();
+        let mut character = controller.spawn(&mut world, Vec3::ZERO);
+
+        // Settle onto the ground first
+        run_inputs(
+            &mut world,
+            &controller,
+            &mut character,
+            &PlayerInput::default(),
+            5,
+        );
+        assert!(character.state.grounded);
+
+        let jump = PlayerInput {
+            jump: true,
+            ..Default::default()
+        };
+        run_inputs(&mut world, &controller, &mut character, &jump, 1);
+        assert!(!character.state.grounded);
+        run_inputs(
+            &mut world,
+            &controller,
+            &mut character,
+            &PlayerInput::default(),
+            20,
+        );
+        assert!(character.state.position.y > 0.5);
+
+        run_inputs(
+            &mut world,
+            &controller,
+            &mut character,
+            &PlayerInput::default(),
+            120,
+        );
+        assert!(character.state.grounded);
+        assert!(character.state.position.y.abs() < 0.05);
+    }
+
+    #[test]
+    fn test_character_steps_up_small_ledge_but_not_wall() {
+        let mut world = PhysicsWorld::new();
+        world.add_ground_plane(0.0);
+        // 20 cm ledge starting 2 m ahead, 3 m wall 10 m ahead
+        world.add_static_box(Vec3::new(0.0, 0.1, 6.0), Vec3::new(5.0, 0.1, 4.0));
+        world.add_static_box(Vec3::new(0.0, 1.5, 10.5), Vec3::new(5.0, 1.5, 0.5));
+        let controller = CharacterController::default();
+        let mut character = controller.spawn(&mut world, Vec3::ZERO);
+
+        let forward = PlayerInput {
+            move_forward: true,
+            ..Default::default()
+        };
+        run_inputs(&mut world, &controller, &mut character, &forward, 180);
+
+        let state = character.state;
+        assert!(state.position.y > 0.15, "should be on the ledge");
+        let radius = controller.config().capsule_radius;
+        assert!(state.position.z < 10.0 - radius + 0.05, "went through wall");
+    }
+
+    #[test]
+    fn test_character_crouch_and_look() {
+        let mut world = PhysicsWorld::new();
+        world.add_ground_plane(0.0);
+        let controller = CharacterController::default();
+        let mut character = controller.spawn(&mut world, Vec3::ZERO);
+
+        let crouch_walk = PlayerInput {
+            move_forward: true,
+            crouch: true,
+            look_delta_y: -100_000.0,
+            ..Default::default()
+        };
+        run_inputs(&mut world, &controller, &mut character, &crouch_walk, 60);
+
+        let state = character.state;
+        assert!(state.crouching);
+        let speed = Vec3::new(state.velocity.x, 0.0, state.velocity.z).length();
+        assert!((speed - controller.config().crouch_speed).abs() < 1e-3);
+        // Pitch is clamped no matter how far the mouse moved
+        let max_pitch = controller.config().max_pitch_degrees.to_radians();
+        assert!((state.pitch - max_pitch).abs() < 1e-5);
+
+        // Standing up again is allowed in the open
+        run_inputs(
+            &mut world,
+            &controller,
+            &mut character,
+            &PlayerInput::default(),
+            1,
+        );
+        assert!(!character.state.crouching);
+    }
+
+    #[test]
+    fn test_entity_lifecycle_and_stable_ids() {
+        let mut world = PhysicsWorld::new();
+        let ground = world.add_ground_plane(0.0);
+        let ball = world
+            .spawn(
+                &BodyDesc::new(BodyKind::Dynamic, Vec3::new(0.0, 5.0, 0.0))
+                    .with_collider(ColliderDesc::new(ColliderShape::Sphere { radius: 0.5 })),
+            )
+            .unwrap();
+        assert_ne!(ground, ball);
+        assert_eq!(world.body_kind(ball), Some(BodyKind::Dynamic));
+
+        // IDs chosen by the server are honoured and never handed out again
+        let remote = EntityId(100);
+        world
+            .spawn_with_id(remote, &BodyDesc::new(BodyKind::Fixed, Vec3::ZERO))
+            .unwrap();
+        assert!(world
+            .spawn_with_id(remote, &BodyDesc::new(BodyKind::Fixed, Vec3::ZERO))
+            .is_err());
+        let next = world.spawn(&BodyDesc::default()).unwrap();
+        assert!(next.0 > remote.0);
+
+        let handle = world.body_handle(ball).unwrap();
+        assert_eq!(world.entity_of_body(handle), Some(ball));
+
+        assert!(world.despawn(ball));
+        assert!(!world.contains(ball));
+        assert!(!world.despawn(ball));
+        assert_eq!(
+            world.entities().collect::<Vec<_>>(),
+            vec![ground, remote, next]
+        );
+    }
+
+    #[test]
+    fn test_entity_transform_and_velocity() {
+        let mut world = PhysicsWorld::new();
+        let crate_id =
+            world
+                .spawn(&BodyDesc::new(BodyKind::Dynamic, Vec3::ZERO).with_collider(
+                    ColliderDesc::new(ColliderShape::Box {
+                        half_extents: Vec3::splat(0.5),
+                    }),
+                ))
+                .unwrap();
+
+        let rotation = Quat::from_rotation_y(1.0);
+        assert!(world.set_transform(crate_id, Vec3::new(1.0, 2.0, 3.0), rotation));
+        let (position, actual) = world.transform(crate_id).unwrap();
+        assert_eq!(position, Vec3::new(1.0, 2.0, 3.0));
+        assert!(actual.abs_diff_eq(rotation, 1e-6));
+
+        // Gravity acts on top of the velocity that was set
+        assert!(world.set_velocity(crate_id, Vec3::X, Vec3::ZERO));
+        world.step(DT);
+        let (linear, _) = world.velocity(crate_id).unwrap();
+        assert_eq!(linear.x, 1.0);
+        assert!(linear.y < 0.0);
+
+        // Only kinematic bodies accept targets
+        assert!(!world.set_kinematic_target(crate_id, Vec3::ZERO, Quat::IDENTITY));
+        let platform = world
+            .spawn(&BodyDesc::new(BodyKind::KinematicPositionBased, Vec3::ZERO))
+            .unwrap();
+        assert!(world.set_kinematic_target(platform, Vec3::Y, Quat::IDENTITY));
+        world.step(DT);
+        assert_eq!(world.transform(platform).unwrap().0, Vec3::Y);
+    }
+
+    #[test]
+    fn test_mesh_and_heightfield_colliders() {
+        let mut world = PhysicsWorld::new();
+        let terrain = ColliderShape::HeightField {
+            heights: vec![0.0, 1.0, 0.0, 1.0, 2.0, 1.0, 0.0, 1.0, 0.0],
+            rows: 3,
+            cols: 3,
+            scale: Vec3::new(10.0, 1.0, 10.0),
+        };
+        let level = world
+            .spawn(
+                &BodyDesc::new(BodyKind::Fixed, Vec3::ZERO)
+                    .with_collider(ColliderDesc::new(terrain)),
+            )
+            .unwrap();
+
+        let ramp = ColliderShape::TriMesh {
+            vertices: vec![Vec3::ZERO, Vec3::X, Vec3::new(0.0, 1.0, 1.0)],
+            indices: vec![[0, 1, 2]],
+        };
+        let collider = world.add_collider(level, &ColliderDesc::new(ramp)).unwrap();
+        assert_eq!(world.entity_of_collider(collider), Some(level));
+        assert_eq!(world.snapshot().bodies[0].colliders.len(), 2);
+
+        // Bad geometry is rejected without leaving a half-built body behind
+        let broken = ColliderShape::TriMesh {
+            vertices: vec![Vec3::ZERO],
+            indices: vec![[0, 1, 2]],
+        };
+        let before = world.entities().count();
+        assert!(world
+            .spawn(&BodyDesc::default().with_collider(ColliderDesc::new(broken)))
+            .is_err());
+        assert_eq!(world.entities().count(), before);
+        assert!(world
+            .add_collider(
+                level,
+                &ColliderDesc::new(ColliderShape::HeightField {
+                    heights: vec![0.0; 3],
+                    rows: 2,
+                    cols: 2,
+                    scale: Vec3::ONE,
+                })
+            )
+            .is_err());
+    }
+
+    fn spawn_box(world: &mut PhysicsWorld, kind: BodyKind, center: Vec3, half: Vec3) -> EntityId {
+        world
+            .spawn(
+                &BodyDesc::new(kind, center)
+                    .with_collider(ColliderDesc::new(ColliderShape::Box { half_extents: half })),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn test_raycast_filters() {
+        let mut world = PhysicsWorld::new();
+        let near = spawn_box(
+            &mut world,
+            BodyKind::Fixed,
+            Vec3::new(0.0, 1.0, 5.0),
+            Vec3::new(2.0, 1.0, 0.5),
+        );
+        let far = spawn_box(
+            &mut world,
+            BodyKind::Fixed,
+            Vec3::new(0.0, 1.0, 10.0),
+            Vec3::new(2.0, 1.0, 0.5),
+        );
+        let mut trigger = ColliderDesc::new(ColliderShape::Box {
+            half_extents: Vec3::new(2.0, 1.0, 0.5),
+        });
+        trigger.sensor = true;
+        let zone = world
+            .spawn(&BodyDesc::new(BodyKind::Fixed, Vec3::new(0.0, 1.0, 2.0)).with_collider(trigger))
+            .unwrap();
+
+        let origin = Vec3::new(0.0, 1.0, 0.0);
+        let hit = world
+            .raycast(origin, Vec3::Z, 100.0, &QueryOptions::default())
+            .unwrap();
+        assert_eq!(hit.entity, Some(near));
+        assert!((hit.distance - 4.5).abs() < 1e-4);
+        assert!((hit.point - Vec3::new(0.0, 1.0, 4.5)).length() < 1e-4);
+        assert!((hit.normal - Vec3::NEG_Z).length() < 1e-4);
+
+        // Out of range, excluded body, sensors on request
+        assert!(world
+            .raycast(origin, Vec3::Z, 4.0, &QueryOptions::default())
+            .is_none());
+        let skip_near = QueryOptions::excluding(near);
+        assert_eq!(
+            world
+                .raycast(origin, Vec3::Z, 100.0, &skip_near)
+                .unwrap()
+                .entity,
+            Some(far)
+        );
+        let sensors = QueryOptions {
+            include_sensors: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            world
+                .raycast(origin, Vec3::Z, 100.0, &sensors)
+                .unwrap()
+                .entity,
+            Some(zone)
+        );
+
+        let all: Vec<_> = world
+            .raycast_all(origin, Vec3::Z, 100.0, &sensors)
+            .into_iter()
+            .map(|hit| hit.entity.unwrap())
+            .collect();
+        assert_eq!(all, vec![zone, near, far]);
+        assert!(!world.line_of_sight(origin, Vec3::new(0.0, 1.0, 20.0), &QueryOptions::default()));
+        assert!(world.line_of_sight(origin, Vec3::new(0.0, 1.0, 3.0), &QueryOptions::default()));
+    }
+
+    #[test]
+    fn test_query_collision_groups() {
+        const WORLD: u32 = 1;
+        const GLASS: u32 = 2;
+
+        let mut world = PhysicsWorld::new();
+        let wall = spawn_box(
+            &mut world,
+            BodyKind::Fixed,
+            Vec3::new(0.0, 0.0, 10.0),
+            Vec3::splat(1.0),
+        );
+        let mut pane = ColliderDesc::new(ColliderShape::Box {
+            half_extents: Vec3::new(1.0, 1.0, 0.05),
+        });
+        pane.groups = CollisionGroups::new(GLASS, u32::MAX);
+        let window = world
+            .spawn(&BodyDesc::new(BodyKind::Fixed, Vec3::new(0.0, 0.0, 5.0)).with_collider(pane))
+            .unwrap();
+        assert_ne!(window, wall);
+
+        let everything = QueryOptions::default();
+        let see_through =
+            QueryOptions::default().with_groups(CollisionGroups::new(u32::MAX, WORLD));
+        assert_eq!(
+            world
+                .raycast(Vec3::ZERO, Vec3::Z, 100.0, &everything)
+                .unwrap()
+                .entity,
+            Some(window)
+        );
+        assert_eq!(
+            world
+                .raycast(Vec3::ZERO, Vec3::Z, 100.0, &see_through)
+                .unwrap()
+                .entity,
+            Some(wall)
+        );
+    }
+
+    #[test]
+    fn test_queries_follow_simulation() {
+        let mut world = PhysicsWorld::new();
+        let ground = world.add_ground_plane(0.0);
+        let ball = world
+            .spawn(
+                &BodyDesc::new(BodyKind::Dynamic, Vec3::new(0.0, 5.0, 0.0))
+                    .with_collider(ColliderDesc::new(ColliderShape::Sphere { radius: 0.5 })),
+            )
+            .unwrap();
+        let down = Vec3::NEG_Y;
+        let options = QueryOptions::excluding(ground);
+
+        let before = world
+            .raycast(Vec3::new(0.0, 10.0, 0.0), down, 20.0, &options)
+            .unwrap();
+        assert_eq!(before.entity, Some(ball));
+        assert!((before.point.y - 5.5).abs() < 1e-4);
+
+        for _ in 0..30 {
+            world.step(DT);
+        }
+        let after = world
+            .raycast(Vec3::new(0.0, 10.0, 0.0), down, 20.0, &options)
+            .unwrap();
+        let (position, _) = world.transform(ball).unwrap();
+        assert!((after.point.y - (position.y + 0.5)).abs() < 1e-4);
+        assert!(after.point.y < before.point.y);
+
+        // Teleports are visible to queries before the next step
+        world.set_transform(ball, Vec3::new(20.0, 1.0, 0.0), Quat::IDENTITY);
+        assert_eq!(
+            world.overlap_sphere(Vec3::new(20.0, 1.0, 0.0), 0.1, &options),
+            vec![ball]
+        );
+        assert!(world
+            .overlap_sphere(Vec3::new(0.0, 1.0, 0.0), 0.6, &options)
+            .is_empty());
+        assert_eq!(
+            world.overlap_box(
+                Vec3::new(20.0, 0.0, 0.0),
+                Vec3::splat(1.0),
+                Quat::IDENTITY,
+                &QueryOptions::default()
+            ),
+            vec![ground, ball]
+        );
+
+        // A sphere swept towards the ball stops one combined radius short of it
+        let hit = world
+            .shape_cast(
+                &ColliderShape::Sphere { radius: 0.25 },
+                Vec3::new(15.0, 1.0, 0.0),
+                Quat::IDENTITY,
+                Vec3::X,
+                10.0,
+                &options,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(hit.entity, Some(ball));
+        assert!((hit.distance - 4.25).abs() < 1e-3);
+        assert!(!hit.penetrating);
+    }
+
+    #[test]
+    fn test_contact_events() {
+        let mut world = PhysicsWorld::new();
+        let ground = world.add_ground_plane(0.0);
+        let mut ball_collider = ColliderDesc::new(ColliderShape::Sphere { radius: 0.5 });
+        ball_collider.contact_force_threshold = Some(0.0);
+        let ball = world
+            .spawn(
+                &BodyDesc::new(BodyKind::Dynamic, Vec3::new(0.0, 2.0, 0.0))
+                    .with_collider(ball_collider),
+            )
+            .unwrap();
+
+        let mut events = Vec::new();
+        for _ in 0..120 {
+            world.step(DT);
+            events.extend(world.drain_events());
+        }
+        let started = events
+            .iter()
+            .filter(|event| matches!(event, PhysicsEvent::ContactStarted { .. }))
+            .count();
+        assert_eq!(started, 1);
+        assert!(events.iter().any(|event| matches!(
+            event,
+            PhysicsEvent::ContactStarted { a, b } if [*a, *b].contains(&ball) && [*a, *b].contains(&ground)
+        )));
+        assert!(events.iter().any(|event| matches!(
+            event,
+            PhysicsEvent::ContactForce { magnitude, .. } if *magnitude > 0.0
+        )));
+
+        // Queue holds one step only and drains empty
+        world.set_transform(ball, Vec3::new(0.0, 10.0, 0.0), Quat::IDENTITY);
+        world.step(DT);
+        assert!(world
+            .events()
+            .iter()
+            .any(|event| matches!(event, PhysicsEvent::ContactStopped { .. })));
+        world.step(DT);
+        assert!(world.drain_events().is_empty());
+        assert!(world.events().is_empty());
+    }
+
+    /// Step until `ticks` ran out, returning every projectile event
+    fn projectile_events(world: &mut PhysicsWorld, ticks: usize) -> Vec<PhysicsEvent> {
+        let mut events = Vec::new();
+        for _ in 0..ticks {
+            world.step(DT);
+            events.extend(world.drain_events().into_iter().filter(|event| {
+                matches!(
+                    event,
+                    PhysicsEvent::ProjectileHit { .. } | PhysicsEvent::ProjectileExpired { .. }
+                )
+            }));
+        }
+        events
+    }
+
+    #[test]
+    fn test_materials_nearby_bodies_and_frozen_friction() {
+        let mut world = PhysicsWorld::new();
+        let ground = world.add_ground_plane(0.0);
+        let wooden = |position| {
+            BodyDesc::new(BodyKind::Fixed, position).with_collider(ColliderDesc {
+                material: Material::Wood,
+                ..ColliderDesc::new(ColliderShape::Box {
+                    half_extents: Vec3::splat(0.5),
+                })
+            })
+        };
+        let crate_a = world.spawn(&wooden(Vec3::new(0.0, 0.5, 0.0))).unwrap();
+        let crate_b = world.spawn(&wooden(Vec3::new(1.1, 0.5, 0.0))).unwrap();
+        let far = world.spawn(&wooden(Vec3::new(5.0, 0.5, 0.0))).unwrap();
+        let character = CharacterController::default().spawn(&mut world, Vec3::new(0.0, 0.0, 5.0));
+
+        assert_eq!(world.material(ground), Some(Material::Stone));
+        assert_eq!(world.material(crate_a), Some(Material::Wood));
+        assert_eq!(world.material(character.entity), Some(Material::Flesh));
+        assert_eq!(world.material(EntityId(999)), None);
+        assert!(Material::Wood.flammable() && !Material::Stone.flammable());
+
+        // Bodies within the distance, the floor they stand on included
+        let options = QueryOptions::default();
+        assert_eq!(world.nearby(crate_a, 0.05, &options), vec![ground]);
+        assert_eq!(world.nearby(crate_a, 0.2, &options), vec![ground, crate_b]);
+        assert!(!world.nearby(crate_b, 0.2, &options).contains(&far));
+
+        // A box pushed across frozen ground slides much further
+        let slide = |states: &[SurfaceState]| {
+            let mut world = PhysicsWorld::new();
+            let ground = world.add_ground_plane(0.0);
+            for state in states {
+                assert!(world.set_surface_state(ground, *state));
+            }
+            let desc = BodyDesc::new(BodyKind::Dynamic, Vec3::new(0.0, 0.25, 0.0)).with_collider(
+                ColliderDesc::new(ColliderShape::Box {
+                    half_extents: Vec3::splat(0.25),
+                }),
+            );
+            let body = world.spawn(&desc).unwrap();
+            world.set_velocity(body, Vec3::X * 4.0, Vec3::ZERO);
+            for _ in 0..60 {
+                world.step(DT);
+            }
+            world.transform(body).unwrap().0.x
+        };
+        let normal = slide(&[]);
+        let frozen = slide(&[SurfaceState::Frozen]);
+        assert!(
+            frozen > normal * 2.0,
+            "normal {}, frozen {}",
+            normal,
+            frozen
+        );
+        // Thawing gives the floor its own friction back
+        assert_eq!(slide(&[SurfaceState::Frozen, SurfaceState::Wet]), normal);
+    }
+
+    #[test]
+    fn test_character_slides_on_frozen_ground() {
+        // Run up to full speed, then let go of the keys for half a second
+        let coast = |state: Option<SurfaceState>| {
+            let mut world = PhysicsWorld::new();
+            let ground = world.add_ground_plane(0.0);
+            let controller = CharacterController::default();
+            let mut character = controller.spawn(&mut world, Vec3::ZERO);
+            let forward = PlayerInput {
+                move_forward: true,
+                ..Default::default()
+            };
+            run_inputs(&mut world, &controller, &mut character, &forward, 60);
+            if let Some(state) = state {
+                assert!(world.set_surface_state(ground, state));
+            }
+            let idle = PlayerInput::default();
+            run_inputs(&mut world, &controller, &mut character, &idle, 30);
+            let velocity = character.state.velocity;
+            Vec3::new(velocity.x, 0.0, velocity.z).length()
+        };
+
+        assert!(coast(None) < 1e-3);
+        let frozen = coast(Some(SurfaceState::Frozen));
+        assert!(frozen > 4.0, "speed on ice {}", frozen);
+        assert!(coast(Some(SurfaceState::Wet)) < 1e-3);
+    }
+
+    #[test]
+    fn test_projectiles_fly_collide_continuously_and_expire() {
+        let mut world = PhysicsWorld::new();
+
+        // 6 m per tick would skip a 10 cm wall without CCD
+        let wall = world.add_static_box(Vec3::new(0.0, 1.0, 20.0), Vec3::new(5.0, 5.0, 0.05));
+        let bolt = world
+            .spawn_projectile(&ProjectileDesc::new(
+                Vec3::new(0.0, 1.0, 0.0),
+                Vec3::new(0.0, 0.0, 360.0),
+                0.1,
+            ))
+            .unwrap();
+        match projectile_events(&mut world, 10).as_slice() {
+            [PhysicsEvent::ProjectileHit {
+                projectile,
+                target,
+                position,
+                stopped: true,
+                ..
+            }] => {
+                assert_eq!((*projectile, *target), (bolt, wall));
+                assert!(position.z < 20.0, "{:?}", position);
+            }
+            other => panic!("Expected one stopping hit, got {:?}", other),
+        }
+        assert!(!world.contains(bolt) && world.projectile(bolt).is_none());
+
+        // Gravity scale and drag, far above anything to hit
+        let mut arcing = ProjectileDesc::new(Vec3::new(0.0, 500.0, 0.0), Vec3::X * 10.0, 0.1);
+        arcing.gravity_scale = 1.0;
+        let arcing = world.spawn_projectile(&arcing).unwrap();
+        let mut slowing = ProjectileDesc::new(Vec3::new(50.0, 500.0, 0.0), Vec3::X * 10.0, 0.1);
+        slowing.drag = 1.0;
+        slowing.lifetime = 0.5;
+        let slowing = world.spawn_projectile(&slowing).unwrap();
+
+        let events = projectile_events(&mut world, 30);
+        assert!(matches!(
+            events.as_slice(),
+            [PhysicsEvent::ProjectileExpired { projectile, .. }] if *projectile == slowing
+        ));
+        assert!(!world.contains(slowing));
+        let (velocity, _) = world.velocity(arcing).unwrap();
+        assert!((velocity.y + 9.81 * 0.5).abs() < 0.1, "{:?}", velocity);
+        assert!((velocity.x - 10.0).abs() < 1e-3);
+        assert_eq!(world.projectiles().count(), 1);
+        assert!(world.projectile(arcing).is_some());
+    }
+
+    #[test]
+    fn test_projectiles_bounce_pierce_and_push() {
+        let mut world = PhysicsWorld::new();
+        let ground = world.add_ground_plane(0.0);
+
+        // One bounce off the floor, then the next impact stops it
+        let mut bouncing = ProjectileDesc::new(Vec3::new(0.0, 2.0, 0.0), Vec3::Y * -10.0, 0.1);
+        bouncing.gravity_scale = 1.0;
+        bouncing.bounces = 1;
+        bouncing.restitution = 0.8;
+        let bouncing = world.spawn_projectile(&bouncing).unwrap();
+        let mut rebounded = false;
+        let mut events = Vec::new();
+        for _ in 0..240 {
+            world.step(DT);
+            events.extend(
+                world
+                    .drain_events()
+                    .into_iter()
+                    .filter(|event| matches!(event, PhysicsEvent::ProjectileHit { .. })),
+            );
+            if world.contains(bouncing) {
+                rebounded |= world.velocity(bouncing).unwrap().0.y > 1.0;
+            }
+        }
+        assert!(rebounded, "the first impact bounces");
+        let stops: Vec<(EntityId, bool)> = events
+            .iter()
+            .filter_map(|event| match *event {
+                PhysicsEvent::ProjectileHit {
+                    target, stopped, ..
+                } => Some((target, stopped)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(stops, vec![(ground, false), (ground, true)]);
+
+        // Launched from inside its owner, through the first crate into the second
+        let crate_at = |world: &mut PhysicsWorld, z: f32| {
+            world
+                .spawn(
+                    &BodyDesc::new(BodyKind::Dynamic, Vec3::new(0.0, 50.0, z)).with_collider(
+                        ColliderDesc::new(ColliderShape::Box {
+                            half_extents: Vec3::splat(0.5),
+                        }),
+                    ),
+                )
+                .unwrap()
+        };
+        let owner = crate_at(&mut world, 0.0);
+        let first = crate_at(&mut world, 5.0);
+        let second = crate_at(&mut world, 10.0);
+        let mut shard = ProjectileDesc::new(Vec3::new(0.0, 50.0, 0.0), Vec3::Z * 60.0, 0.1);
+        shard.pierce = 1;
+        shard.impulse = 5.0;
+        shard.owner = Some(owner);
+        let shard = world.spawn_projectile(&shard).unwrap();
+
+        let hits: Vec<(EntityId, bool)> = projectile_events(&mut world, 30)
+            .into_iter()
+            .filter_map(|event| match event {
+                PhysicsEvent::ProjectileHit {
+                    projectile,
+                    target,
+                    stopped,
+                    ..
+                } if projectile == shard => Some((target, stopped)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(hits, vec![(first, false), (second, true)]);
+        assert!(!world.contains(shard));
+        for pushed in [first, second] {
+            assert!(world.velocity(pushed).unwrap().0.z > 1.0, "{:?}", pushed);
+        }
+        assert!(world.velocity(owner).unwrap().0.z.abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_trigger_events_for_character() {
+        let mut world = PhysicsWorld::new();
+        world.add_ground_plane(0.0);
+        let mut zone = ColliderDesc::new(ColliderShape::Box {
+            half_extents: Vec3::new(2.0, 1.0, 0.5),
+        });
+        zone.sensor = true;
+        let zone = world
+            .spawn(&BodyDesc::new(BodyKind::Fixed, Vec3::new(0.0, 1.0, 3.0)).with_collider(zone))
+            .unwrap();
+
+        let controller = CharacterController::default();
+        let mut character = controller.spawn(&mut world, Vec3::ZERO);
+        let forward = PlayerInput {
+            move_forward: true,
+            ..Default::default()
+        };
+        let mut events = Vec::new();
+        for _ in 0..180 {
+            controller.apply_input(&mut world, &mut character, &forward, DT);
+            world.step(DT);
+            events.extend(world.drain_events());
+        }
+
+        assert_eq!(
+            events,
+            vec![
+                PhysicsEvent::TriggerEntered {
+                    sensor: zone,
+                    other: character.entity
+                },
+                PhysicsEvent::TriggerExited {
+                    sensor: zone,
+                    other: character.entity
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_snapshot_quantization_precision() {
+        let position = Vec3::new(123.456, -7.891, 0.0004);
+        let rotation = Quat::from_euler(engine::glam::EulerRot::YXZ, 2.5, -0.7, 0.3);
+        let velocity = Vec3::new(3.3, -12.0, 0.01);
+        let entity = EntityState::new(EntityId(9), EntityKind::Prop, position, rotation, velocity);
+
+        assert!((entity.position() - position).abs().max_element() <= 0.5 / 1024.0);
+        assert!((entity.velocity() - velocity).abs().max_element() <= 0.5 / 64.0);
+        assert!(entity.rotation().angle_between(rotation).to_degrees() < 0.2);
+
+        // Every axis-aligned and negated quaternion survives the round trip
+        for rotation in [
+            Quat::IDENTITY,
+            Quat::from_rotation_x(std::f32::consts::PI),
+            Quat::from_rotation_y(-std::f32::consts::FRAC_PI_2),
+            Quat::from_xyzw(0.0, 0.0, 0.0, -1.0),
+        ] {
+            let decoded = QuantizedRotation::new(rotation).get();
+            assert!(decoded.angle_between(rotation).to_degrees() < 0.2);
+        }
+
+        // Velocities beyond the encodable range saturate instead of wrapping
+        assert_eq!(QuantizedVelocity::new(Vec3::splat(1e6)).0, [i16::MAX; 3]);
+    }
+
+    #[test]
+    fn test_state_snapshot_round_trip_and_schema() {
+        let mut snapshot = StateSnapshot::new(42);
+        snapshot.entities.push(EntityState::new(
+            EntityId(3),
+            EntityKind::Player { player_id: 1 },
+            Vec3::new(1.0, 0.0, 2.0),
+            Quat::IDENTITY,
+            Vec3::ZERO,
+        ));
+
+        let decoded: StateSnapshot = decode_message(&encode_message(&snapshot).unwrap()).unwrap();
+        assert_eq!(decoded, snapshot);
+        assert!(decoded.check_schema().is_ok());
+        assert_eq!(
+            decoded.entity(EntityId(3)).unwrap().position(),
+            Vec3::new(1.0, 0.0, 2.0)
+        );
+        assert!(decoded.entity(EntityId(4)).is_none());
+
+        snapshot.schema_version = SNAPSHOT_SCHEMA_VERSION + 1;
+        assert!(snapshot.check_schema().is_err());
+    }
+
+    #[test]
+    fn test_snapshot_delta_round_trip() {
+        let prop = |id, x| {
+            EntityState::new(
+                EntityId(id),
+                EntityKind::Prop,
+                Vec3::new(x, 1.0, 0.0),
+                Quat::IDENTITY,
+                Vec3::ZERO,
+            )
+        };
+        let mut baseline = StateSnapshot::new(10);
+        baseline.entities = (1..=20).map(|id| prop(id, id as f32)).collect();
+        baseline.players.push(PlayerUpdate {
+            player_id: 1,
+            last_input_sequence: Some(5),
+            state: Default::default(),
+        });
+
+        // One entity moves, one is damaged and set alight, one disappears and
+        // one appears
+        let mut current = baseline.clone();
+        current.tick = 12;
+        current.entities[2] = prop(3, 3.5);
+        current.entities[4].health = Some(HealthState {
+            current: 40,
+            max: 100,
+            shield: 0,
+        });
+        current.entities[4].statuses = vec![StatusState {
+            kind: StatusKind::Burning,
+            stacks: 2,
+            until_tick: 200,
+        }];
+        current.entities.remove(9);
+        current.entities.push(prop(21, 21.0));
+        current.players.clear();
+        // The floor freezes over and a crate dries
+        let surface = |id, state| SurfaceUpdate {
+            entity: EntityId(id),
+            state,
+        };
+        baseline.surfaces = vec![surface(1, SurfaceState::Wet), surface(5, SurfaceState::Wet)];
+        current.surfaces = vec![surface(1, SurfaceState::Frozen)];
+
+        let delta = SnapshotDelta::between(&baseline, &current);
+        assert_eq!(delta.baseline_tick, 10);
+        assert_eq!(delta.entities.len(), 3);
+        let burning = &delta.entities[1];
+        assert!(burning.health.is_some() && burning.statuses.is_some());
+        assert!(burning.position.is_none() && burning.mana.is_none());
+        assert_eq!(delta.removed_entities, vec![EntityId(10)]);
+        assert_eq!(delta.removed_players, vec![1]);
+        assert_eq!(delta.surfaces, vec![surface(1, SurfaceState::Frozen)]);
+        assert_eq!(delta.removed_surfaces, vec![EntityId(5)]);
+        assert!(
+            encode_message(&delta).unwrap().len() < encode_message(&current).unwrap().len() / 2
+        );
+
+        let decoded: SnapshotDelta = decode_message(&encode_message(&delta).unwrap()).unwrap();
+        assert_eq!(decoded.apply(&baseline).unwrap(), current);
+
+        // An unchanged world encodes to an empty delta
+        let mut idle = current.clone();
+        idle.tick = 13;
+        let empty = SnapshotDelta::between(&current, &idle);
+        assert!(empty.entities.is_empty() && empty.players.is_empty());
+        assert!(empty.surfaces.is_empty() && empty.removed_surfaces.is_empty());
+        assert_eq!(idle.surface(EntityId(1)), SurfaceState::Frozen);
+        assert_eq!(idle.surface(EntityId(5)), SurfaceState::Normal);
+        assert_eq!(empty.apply(&current).unwrap(), idle);
+
+        // Applying to the wrong baseline is an error
+        assert!(delta.apply(&idle).is_err());
+    }
+
+    #[test]
+    fn test_reliable_channel_survives_loss_in_order() {
+        let mut sender = Endpoint::new();
+        let mut receiver = Endpoint::new();
+        let mut now = Instant::now();
+        let mut delivered = Vec::new();
+        let mut unreliable_received = 0;
+
+        for round in 0..200u32 {
+            if round < 50 {
+                sender
+                    .send(Channel::ReliableOrdered, round.to_le_bytes().to_vec())
+                    .unwrap();
+                sender.send(Channel::Unreliable, vec![0xff]).unwrap();
+            }
+            // Drop two of every three packets in both directions
+            for (index, datagram) in sender.poll_transmit(now).into_iter().enumerate() {
+                assert!(datagram.len() <= MAX_DATAGRAM_SIZE);
+                if (round + index as u32).is_multiple_of(3) {
+                    for payload in receiver.receive(&datagram, now).unwrap() {
+                        match payload.as_slice() {
+                            [0xff] => unreliable_received += 1,
+                            bytes => delivered.push(u32::from_le_bytes(bytes.try_into().unwrap())),
+                        }
+                    }
+                }
+            }
+            // The receiver only has acks to send back
+            for datagram in receiver.poll_transmit(now) {
+                if round % 3 != 1 {
+                    sender.receive(&datagram, now).unwrap();
+                }
+            }
+            now += Duration::from_millis(20);
+        }
+
+        assert_eq!(delivered, (0..50).collect::<Vec<_>>());
+        assert_eq!(sender.unacked_reliable(), 0);
+        assert!(sender.stats().reliable_resends > 0);
+        // Lost unreliable messages stay lost
+        assert!(unreliable_received > 0 && unreliable_received < 50);
+        assert!(sender.rtt().is_some());
+    }
+
+    #[test]
+    fn test_endpoint_drops_duplicates_and_foreign_datagrams() {
+        let mut sender = Endpoint::new();
+        let mut receiver = Endpoint::new();
+        let now = Instant::now();
+
+        sender.send(Channel::Unreliable, vec![1]).unwrap();
+        sender.send(Channel::ReliableOrdered, vec![2]).unwrap();
+        let datagrams = sender.poll_transmit(now);
+        assert_eq!(datagrams.len(), 1);
+
+        assert_eq!(
+            receiver.receive(&datagrams[0], now).unwrap(),
+            vec![vec![2], vec![1]]
+        );
+        assert!(receiver.receive(&datagrams[0], now).unwrap().is_empty());
+        assert_eq!(receiver.stats().packets_dropped, 1);
+        assert!(receiver.receive(b"not a packet", now).is_err());
+
+        // Idle endpoints only ack packets that carried messages
+        let acks = receiver.poll_transmit(now);
+        assert_eq!(acks.len(), 1);
+        assert!(sender.receive(&acks[0], now).unwrap().is_empty());
+        assert_eq!(sender.stats().packets_acked, 1);
+        assert!(sender.poll_transmit(now).is_empty());
+
+        // Oversized messages are refused; large ones get a datagram of their own
+        assert!(sender
+            .send(Channel::Unreliable, vec![0; 1024 * 1024])
+            .is_err());
+        sender.send(Channel::Unreliable, vec![0; 100]).unwrap();
+        sender.send(Channel::Unreliable, vec![0; 4000]).unwrap();
+        assert_eq!(sender.poll_transmit(now).len(), 2);
+    }
+
+    #[test]
+    fn test_link_simulator_conditions() {
+        let start = Instant::now();
+        let ms = |n| start + Duration::from_millis(n);
+
+        // Fixed latency, nothing else
+        let mut link = LinkSimulator::datagrams(NetworkConditions {
+            latency_ms: 50,
+            ..Default::default()
+        });
+        link.send(start, 1, 100);
+        link.send(ms(10), 2, 100);
+        assert_eq!(link.next_release(), Some(ms(50)));
+        assert!(link.poll(ms(49)).is_empty());
+        assert_eq!(link.poll(ms(60)), vec![1, 2]);
+
+        // Loss, duplication and reordering at roughly the configured rates
+        let conditions = NetworkConditions {
+            jitter_ms: 30,
+            loss: 0.2,
+            duplication: 0.1,
+            reordering: 0.1,
+            seed: 7,
+            ..Default::default()
+        };
+        let mut link = LinkSimulator::datagrams(conditions.clone());
+        for i in 0..1000u32 {
+            link.send(ms(i as u64), i, 100);
+        }
+        let stats = link.stats();
+        assert!((150..250).contains(&stats.dropped), "{:?}", stats);
+        assert!((50..130).contains(&stats.duplicated), "{:?}", stats);
+        assert!((50..130).contains(&stats.reordered), "{:?}", stats);
+        let received = link.poll(ms(5000));
+        assert_eq!(
+            received.len() as u64,
+            stats.sent - stats.dropped + stats.duplicated
+        );
+        assert!(received.windows(2).any(|pair| pair[0] > pair[1]));
+
+        // The same seed reproduces the same run
+        let mut again = LinkSimulator::datagrams(conditions.clone());
+        for i in 0..1000u32 {
+            again.send(ms(i as u64), i, 100);
+        }
+        assert_eq!(again.poll(ms(5000)), received);
+
+        // A stream applies jitter but never reorders, loses or duplicates
+        let mut stream = LinkSimulator::stream(conditions);
+        for i in 0..1000u32 {
+            stream.send(ms(i as u64), i, 100);
+        }
+        assert_eq!(stream.poll(ms(5000)), (0..1000).collect::<Vec<_>>());
+
+        // 80 kbit/s carries one 1000 byte packet every 100 ms
+        let mut link = LinkSimulator::datagrams(NetworkConditions {
+            bandwidth_kbps: 80,
+            ..Default::default()
+        });
+        for i in 0..3 {
+            link.send(start, i, 1000);
+        }
+        assert_eq!(link.poll(ms(100)), vec![0]);
+        assert_eq!(link.poll(ms(250)), vec![1]);
+        assert_eq!(link.poll(ms(300)), vec![2]);
+        // Beyond a second of queued traffic, datagrams are dropped
+        for i in 0..20 {
+            link.send(ms(300), i, 1000);
+        }
+        assert!(link.stats().dropped > 0);
+    }
+
+    #[test]
+    fn test_ping_timer_and_tick_clock() {
+        let start = Instant::now();
+        let ms = |n| start + Duration::from_millis(n);
+
+        let mut pings = PingTimer::new(start);
+        assert!(pings.due(start));
+        let (first, first_at) = pings.ping(start);
+        assert!(!pings.due(ms(100)));
+        assert!(pings.due(ms(500)));
+
+        // Unknown, hand-written and repeated pongs are ignored
+        assert_eq!(pings.pong(first + 1, first_at, ms(100)), None);
+        assert_eq!(pings.pong(first, 0, ms(100)), None);
+        assert_eq!(
+            pings.pong(first, first_at, ms(100)),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(pings.pong(first, first_at, ms(120)), None);
+        assert_eq!(pings.rtt(), Some(Duration::from_millis(100)));
+
+        // A slower round trip raises the estimate and the jitter gradually
+        let (second, second_at) = pings.ping(ms(500));
+        pings.pong(second, second_at, ms(700)).unwrap();
+        let rtt = pings.rtt().unwrap();
+        assert!(rtt > Duration::from_millis(100) && rtt < Duration::from_millis(200));
+        assert!(pings.jitter() > Duration::ZERO);
+
+        // 10 Hz: a pong saying tick 20 after a 100ms round trip means the
+        // server is at 20.5 ticks plus half a tick of travel
+        let mut clock = TickClock::new(10);
+        assert_eq!(clock.tick_at(start), None);
+        clock.update(20, Duration::from_millis(100), start);
+        assert!((clock.tick_at(start).unwrap() - 21.0).abs() < 1e-6);
+        assert!((clock.tick_at(ms(1000)).unwrap() - 31.0).abs() < 1e-6);
+
+        // Small disagreements are blended in, large ones replace the estimate
+        clock.update(21, Duration::from_millis(100), ms(1000));
+        let blended = clock.tick_at(ms(1000)).unwrap();
+        assert!(blended < 31.0 && blended > 30.0, "{}", blended);
+        clock.update(100, Duration::from_millis(100), ms(1000));
+        assert!((clock.tick_at(ms(1000)).unwrap() - 101.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_spell_registry_loads_assets() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../assets/spells");
+        let registry = SpellRegistry::load_dir(&dir).unwrap_or_else(|e| panic!("{}", e));
+        assert!(!registry.is_empty());
+
+        // IDs follow the keys' alphabetical order
+        let keys: Vec<&str> = registry.iter().map(|(_, key, _)| key).collect();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys, sorted);
+
+        let id = registry.id("fireball").unwrap();
+        assert_eq!(registry.key(id), Some("fireball"));
+        let fireball = registry.get(id).unwrap();
+        assert_eq!(fireball.name, "Fireball");
+        assert!(matches!(fireball.delivery, Delivery::Projectile { .. }));
+        assert!(fireball.effects.contains(&Effect::ApplyStatus {
+            status: StatusKind::Burning,
+            duration_ms: 3000,
+            stacks: 1,
+        }));
+        assert_eq!(registry.id("no_such_spell"), None);
+
+        let mend = registry.get(registry.id("mend").unwrap()).unwrap();
+        assert_eq!(mend.channel.map(|channel| channel.interval_ms), Some(500));
+        assert!(!mend.cast_while_moving);
+
+        // Projectile spells describe the body they launch
+        let boulder = registry.get(registry.id("boulder").unwrap()).unwrap();
+        let desc = boulder
+            .projectile(Vec3::ZERO, Vec3::new(0.0, 0.0, 2.0), Some(EntityId(7)))
+            .unwrap();
+        assert_eq!(desc.velocity, Vec3::new(0.0, 0.0, 14.0));
+        assert_eq!((desc.bounces, desc.pierce), (2, 0));
+        assert_eq!(desc.impulse, 60.0);
+        assert_eq!(desc.owner, Some(EntityId(7)));
+        assert!((desc.lifetime - 4.0).abs() < 1e-6);
+        let ice_shard = registry.get(registry.id("ice_shard").unwrap()).unwrap();
+        assert!(matches!(
+            ice_shard.delivery,
+            Delivery::Projectile { pierce: 1, .. }
+        ));
+        assert_eq!(mend.projectile(Vec3::ZERO, Vec3::Z, None), None);
+    }
+
+    #[test]
+    fn test_spell_errors_name_file_and_field() {
+        let spell = |delivery: &str, effects: &str| {
+            format!(
+                "name = \"Test\"\nelement = \"fire\"\nmana_cost = 10.0\n\
+                 cast_time_ms = 100\ncooldown_ms = 1000\n\n[delivery]\n{}\n\n{}",
+                delivery, effects
+            )
+        };
+        let damage = "[[effects]]\ntype = \"damage\"\namount = 5.0";
+        let path = Path::new("assets/spells/test.toml");
+        let field = |contents: &str| {
+            let error = parse_spell(path, contents).unwrap_err();
+            assert_eq!(error.path(), path);
+            assert!(error.to_string().starts_with("assets/spells/test.toml: "));
+            error.field().map(str::to_string)
+        };
+
+        assert!(parse_spell(path, &spell("type = \"hitscan\"\nrange = 50.0", damage)).is_ok());
+
+        // Wrong type, unknown variant, typo and missing field
+        let contents = spell("type = \"hitscan\"\nrange = \"far\"", damage);
+        assert_eq!(field(&contents).as_deref(), Some("delivery.range"));
+        let contents = spell("type = \"beam\"", damage);
+        assert_eq!(field(&contents).as_deref(), Some("delivery.type"));
+        let contents = spell(
+            "type = \"self\"",
+            "[[effects]]\ntype = \"heal\"\namount = 5.0\n\n[[effects]]\ntype = \"heal\"\namont = 5.0",
+        );
+        assert_eq!(field(&contents).as_deref(), Some("effects[1].amont"));
+        let contents = spell("type = \"projectile\"\nspeed = 20.0\nradius = 0.2", damage);
+        assert_eq!(field(&contents).as_deref(), Some("delivery.lifetime_ms"));
+        let contents = spell("type = \"hitscan\"\nrange = 5.0\npierce = 1", damage);
+        assert_eq!(field(&contents).as_deref(), Some("delivery.pierce"));
+        let contents = spell("type = \"self\"\nrange = 5.0", damage);
+        assert_eq!(field(&contents).as_deref(), Some("delivery.range"));
+        let contents = spell("type = \"self\"", damage).replace("cooldown_ms = 1000\n", "");
+        assert_eq!(field(&contents).as_deref(), Some("cooldown_ms"));
+
+        // Values serde accepts but that make no sense
+        let contents = spell("type = \"area\"\nradius = -1.0", damage);
+        assert!(matches!(
+            parse_spell(path, &contents),
+            Err(SpellError::Invalid { ref field, .. }) if field == "delivery.radius"
+        ));
+        let contents = spell(
+            "type = \"self\"",
+            "[[effects]]\ntype = \"heal\"\namount = 0.0",
+        );
+        assert_eq!(field(&contents).as_deref(), Some("effects[0].amount"));
+        let contents = format!("effects = []\n{}", spell("type = \"self\"", ""));
+        assert_eq!(field(&contents).as_deref(), Some("effects"));
+        let channel = |interval_ms: u32| {
+            spell("type = \"self\"", damage).replace(
+                "[delivery]",
+                &format!(
+                    "[channel]\nduration_ms = 1000\ninterval_ms = {}\n\n[delivery]",
+                    interval_ms
+                ),
+            )
+        };
+        assert!(parse_spell(path, &channel(250)).is_ok());
+        assert_eq!(
+            field(&channel(2000)).as_deref(),
+            Some("channel.interval_ms")
+        );
+
+        // Broken TOML has no field, but says where
+        let error = parse_spell(path, "name = \"Test\"\n[delivery\n").unwrap_err();
+        assert_eq!(error.field(), None);
+        assert!(error.to_string().contains("line 2"), "{}", error);
+
+        // Every broken file is reported, not just the first
+        let good = spell("type = \"self\"", damage);
+        let bad = spell("type = \"self\"", "");
+        let errors = SpellRegistry::from_sources([
+            (Path::new("a.toml"), bad.as_str()),
+            (Path::new("b.toml"), good.as_str()),
+            (Path::new("c.toml"), bad.as_str()),
+        ])
+        .unwrap_err();
+        let paths: Vec<&Path> = errors.0.iter().map(SpellError::path).collect();
+        assert_eq!(paths, [Path::new("a.toml"), Path::new("c.toml")]);
+    }
 }
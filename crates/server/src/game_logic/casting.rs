@@ -0,0 +1,370 @@
+/// Server-authoritative spell casting
+///
+/// Every player has a `Caster` and a `Mana` component in the game world.
+/// Inputs only select a spell slot and hold the cast button; the server
+/// decides when a cast starts, when its wind-up ends, whether moving, taking
+/// damage or being silenced interrupts it, and whether the player can pay for
+/// it. All timing is
+/// counted in ticks, so the same inputs always cast the same way.
+use bevy_ecs::component::Component;
+use bevy_ecs::event::Event;
+use engine::net_proto::{CastEventKind, CastRejection, InterruptReason, PlayerInput};
+use engine::spells::{SpellDef, SpellId, SpellRegistry};
+use std::collections::HashMap;
+
+/// Spell slots a player can select from
+pub const SPELL_SLOTS: usize = 8;
+
+/// Mana a player starts with and can hold
+pub const DEFAULT_MAX_MANA: f32 = 100.0;
+
+/// Mana regained per second
+pub const DEFAULT_MANA_REGEN: f32 = 5.0;
+
+/// A player took damage this tick, interrupting their cast
+#[derive(Debug, Clone, Copy, PartialEq, Event)]
+pub struct DamageTaken {
+    pub player_id: u32,
+    pub amount: f32,
+}
+
+/// Mana pool paying for spells
+#[derive(Debug, Clone, Copy, PartialEq, Component)]
+pub struct Mana {
+    pub current: f32,
+    pub max: f32,
+    /// Mana regained per second
+    pub regen: f32,
+}
+
+impl Default for Mana {
+    fn default() -> Self {
+        Self {
+            current: DEFAULT_MAX_MANA,
+            max: DEFAULT_MAX_MANA,
+            regen: DEFAULT_MANA_REGEN,
+        }
+    }
+}
+
+/// What a caster is doing, with ticks counted from the server's tick number
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CastState {
+    #[default]
+    Idle,
+    /// Waiting for the spell to go off at tick `release`
+    WindingUp {
+        spell_id: SpellId,
+        started: u32,
+        release: u32,
+    },
+    /// Applying a channeled spell every interval until `end`
+    Channeling {
+        spell_id: SpellId,
+        started: u32,
+        next_pulse: u32,
+        end: u32,
+    },
+}
+
+impl CastState {
+    /// Spell being cast and the tick it started, unless idle
+    pub fn active(&self) -> Option<(SpellId, u32)> {
+        match *self {
+            Self::Idle => None,
+            Self::WindingUp {
+                spell_id, started, ..
+            }
+            | Self::Channeling {
+                spell_id, started, ..
+            } => Some((spell_id, started)),
+        }
+    }
+}
+
+/// What the inputs applied since the last tick asked for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct PendingInput {
+    /// Latest slot selection, if any input arrived
+    slot: Option<u8>,
+    /// Sequence of the input whose cast button went down, if one did
+    pressed: Option<u32>,
+    moving: bool,
+}
+
+/// Outcome of one tick of casting
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CastStep {
+    /// What clients are told, in order
+    pub events: Vec<(SpellId, CastEventKind)>,
+    /// Spell whose effects apply this tick
+    pub released: Option<SpellId>,
+}
+
+/// A player's spell slots and the state of their cast
+#[derive(Debug, Clone, Component)]
+pub struct Caster {
+    pub player_id: u32,
+    pub slots: [Option<SpellId>; SPELL_SLOTS],
+    selected: u8,
+    state: CastState,
+    /// `PlayerInput::sequence` of the last press that tried to start a cast
+    cast_sequence: u32,
+    /// Tick from which each spell can be cast again
+    ready_at: HashMap<SpellId, u32>,
+    /// Whether the latest input held the cast button
+    button_down: bool,
+    pending: PendingInput,
+}
+
+impl Caster {
+    pub fn new(player_id: u32, slots: [Option<SpellId>; SPELL_SLOTS]) -> Self {
+        Self {
+            player_id,
+            slots,
+            selected: 0,
+            state: CastState::Idle,
+            cast_sequence: 0,
+            ready_at: HashMap::new(),
+            button_down: false,
+            pending: PendingInput::default(),
+        }
+    }
+
+    pub fn selected(&self) -> u8 {
+        self.selected
+    }
+
+    pub fn state(&self) -> CastState {
+        self.state
+    }
+
+    /// Sequence of the input that last tried to start a cast
+    ///
+    /// The caster's client uses it to match what the spell spawned with what
+    /// it predicted.
+    pub fn cast_sequence(&self) -> u32 {
+        self.cast_sequence
+    }
+
+    /// Whether `spell_id` is off cooldown at `tick`
+    pub fn is_ready(&self, spell_id: SpellId, tick: u32) -> bool {
+        self.ready_at
+            .get(&spell_id)
+            .is_none_or(|&ready| reached(tick, ready))
+    }
+
+    /// Fold in an input applied this tick; several may arrive per tick
+    pub fn queue_input(&mut self, input: &PlayerInput) {
+        self.pending.slot = Some(input.spell_slot);
+        if input.cast_spell && !self.button_down && self.pending.pressed.is_none() {
+            self.pending.pressed = Some(input.sequence);
+        }
+        self.pending.moving |= input.move_forward
+            || input.move_backward
+            || input.move_left
+            || input.move_right
+            || input.jump;
+        self.button_down = input.cast_spell;
+    }
+
+    /// Advance the cast by one tick
+    ///
+    /// `damaged` says whether the player took damage since the last step, and
+    /// `silenced` whether a status effect keeps them from casting. Mana
+    /// regenerates before anything is paid for.
+    pub fn step(
+        &mut self,
+        tick: u32,
+        delta_time: f32,
+        damaged: bool,
+        silenced: bool,
+        mana: &mut Mana,
+        spells: &SpellRegistry,
+    ) -> CastStep {
+        let input = std::mem::take(&mut self.pending);
+        let mut step = CastStep::default();
+        mana.current = (mana.current + mana.regen * delta_time).min(mana.max);
+
+        if let Some(slot) = input.slot.filter(|&slot| (slot as usize) < SPELL_SLOTS) {
+            if slot != self.selected {
+                self.selected = slot;
+                self.interrupt(InterruptReason::Cancelled, &mut step);
+            }
+        }
+
+        let Some((spell_id, _)) = self.state.active() else {
+            if let Some(sequence) = input.pressed {
+                self.cast_sequence = sequence;
+                match self.selected_spell(spells) {
+                    Some((spell_id, _)) if silenced => step
+                        .events
+                        .push((spell_id, CastEventKind::Rejected(CastRejection::Silenced))),
+                    _ => self.start(tick, delta_time, input.moving, mana, spells, &mut step),
+                }
+            }
+            return step;
+        };
+        let Some(spell) = spells.get(spell_id) else {
+            self.interrupt(InterruptReason::Cancelled, &mut step);
+            return step;
+        };
+        if damaged {
+            self.interrupt(InterruptReason::Damaged, &mut step);
+            return step;
+        }
+        if silenced {
+            self.interrupt(InterruptReason::Silenced, &mut step);
+            return step;
+        }
+        if input.moving && !spell.cast_while_moving {
+            self.interrupt(InterruptReason::Moved, &mut step);
+            return step;
+        }
+
+        match self.state {
+            CastState::WindingUp { release, .. } if reached(tick, release) => {
+                self.release(tick, delta_time, spell_id, spell, mana, &mut step);
+            }
+            CastState::Channeling {
+                spell_id,
+                started,
+                next_pulse,
+                end,
+            } => {
+                if !self.button_down || reached(tick, end) {
+                    self.state = CastState::Idle;
+                    step.events.push((spell_id, CastEventKind::Completed));
+                } else if reached(tick, next_pulse) {
+                    let interval = spell.channel.map_or(1, |channel| {
+                        ticks_for(channel.interval_ms, delta_time).max(1)
+                    });
+                    self.state = CastState::Channeling {
+                        spell_id,
+                        started,
+                        next_pulse: tick.wrapping_add(interval),
+                        end,
+                    };
+                    step.released = Some(spell_id);
+                }
+            }
+            _ => {}
+        }
+        step
+    }
+
+    /// Stop the current cast and drop the inputs queued since the last step,
+    /// e.g. because the player died
+    pub fn stop(&mut self, reason: InterruptReason) -> CastStep {
+        self.pending = PendingInput::default();
+        let mut step = CastStep::default();
+        self.interrupt(reason, &mut step);
+        step
+    }
+
+    /// Begin casting the selected spell if the player can
+    fn start(
+        &mut self,
+        tick: u32,
+        delta_time: f32,
+        moving: bool,
+        mana: &mut Mana,
+        spells: &SpellRegistry,
+        step: &mut CastStep,
+    ) {
+        // An empty slot has nothing to cast or report
+        let Some((spell_id, spell)) = self.selected_spell(spells) else {
+            return;
+        };
+        let rejection = if !self.is_ready(spell_id, tick) {
+            Some(CastRejection::OnCooldown)
+        } else if mana.current < spell.mana_cost {
+            Some(CastRejection::NotEnoughMana)
+        } else if moving && !spell.cast_while_moving {
+            Some(CastRejection::Moving)
+        } else {
+            None
+        };
+        if let Some(rejection) = rejection {
+            step.events
+                .push((spell_id, CastEventKind::Rejected(rejection)));
+            return;
+        }
+
+        step.events.push((spell_id, CastEventKind::Started));
+        let release = tick.wrapping_add(ticks_for(spell.cast_time_ms, delta_time));
+        self.state = CastState::WindingUp {
+            spell_id,
+            started: tick,
+            release,
+        };
+        if release == tick {
+            self.release(tick, delta_time, spell_id, spell, mana, step);
+        }
+    }
+
+    /// Spell in the selected slot, if it holds one that exists
+    fn selected_spell<'a>(&self, spells: &'a SpellRegistry) -> Option<(SpellId, &'a SpellDef)> {
+        self.slots[self.selected as usize]
+            .and_then(|spell_id| spells.get(spell_id).map(|spell| (spell_id, spell)))
+    }
+
+    /// Pay for the spell and let it go off
+    fn release(
+        &mut self,
+        tick: u32,
+        delta_time: f32,
+        spell_id: SpellId,
+        spell: &SpellDef,
+        mana: &mut Mana,
+        step: &mut CastStep,
+    ) {
+        if mana.current < spell.mana_cost {
+            self.interrupt(InterruptReason::OutOfMana, step);
+            return;
+        }
+        mana.current -= spell.mana_cost;
+        self.ready_at.insert(
+            spell_id,
+            tick.wrapping_add(ticks_for(spell.cooldown_ms, delta_time)),
+        );
+        step.released = Some(spell_id);
+
+        let started = self.state.active().map_or(tick, |(_, started)| started);
+        self.state = match spell.channel {
+            Some(channel) => CastState::Channeling {
+                spell_id,
+                started,
+                next_pulse: tick.wrapping_add(ticks_for(channel.interval_ms, delta_time).max(1)),
+                end: tick.wrapping_add(ticks_for(channel.duration_ms, delta_time)),
+            },
+            None => {
+                step.events.push((spell_id, CastEventKind::Completed));
+                CastState::Idle
+            }
+        };
+    }
+
+    /// Stop the current cast, if any
+    fn interrupt(&mut self, reason: InterruptReason, step: &mut CastStep) {
+        if let Some((spell_id, _)) = self.state.active() {
+            self.state = CastState::Idle;
+            step.events
+                .push((spell_id, CastEventKind::Interrupted(reason)));
+        }
+    }
+}
+
+/// Whether `tick` is at or past `target`, allowing for wrap-around
+pub(super) fn reached(tick: u32, target: u32) -> bool {
+    (tick.wrapping_sub(target) as i32) >= 0
+}
+
+/// Whole ticks covering `ms`, ignoring float error in the tick length
+pub fn ticks_for(ms: u32, delta_time: f32) -> u32 {
+    let tick_ms = delta_time as f64 * 1000.0;
+    if tick_ms <= 0.0 {
+        return 0;
+    }
+    (ms as f64 / tick_ms - 1e-6).ceil().max(0.0) as u32
+}
@@ -0,0 +1,235 @@
+/// Elemental reactions between spells and the surfaces they reach
+///
+/// Fire sets flammable bodies alight, and every second the fire spreads to
+/// flammable bodies touching them until it burns out. Water puts fires out and
+/// soaks surfaces, cold freezes wet surfaces into slippery ice, and lightning
+/// jumps from a wet or metal target to every wet or metal body touching it.
+/// States wear off after a while: fires burn out and leave the body charred
+/// for good, wet surfaces dry and ice thaws back into water.
+///
+/// Flesh has no surface state. Players carry their wetness as the `Wet`
+/// status instead, which `GameLogic::hit_player` applies and lightning reads.
+///
+/// Everything is counted in ticks and visited in entity order, and fire only
+/// spreads from what was burning at the start of a tick, so the same hits
+/// always burn the same way on every run.
+use super::casting::{reached, ticks_for};
+use bevy_ecs::system::Resource;
+use engine::net_proto::SurfaceUpdate;
+use engine::physics_core::{EntityId, Material, PhysicsWorld, QueryOptions, SurfaceState};
+use engine::spells::Element;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+/// How long a fire burns before going out (ms)
+pub const BURN_MS: u32 = 5000;
+/// Time between two spreads of a fire (ms)
+pub const SPREAD_INTERVAL_MS: u32 = 1000;
+/// Fire reaches flammable bodies this close to a burning one (m)
+pub const SPREAD_DISTANCE: f32 = 0.25;
+/// How long a surface stays wet (ms)
+pub const WET_MS: u32 = 10_000;
+/// How long ice lasts before it thaws back into water (ms)
+pub const FROZEN_MS: u32 = 8000;
+/// Lightning jumps between wet or metal bodies this close (m)
+pub const CHAIN_DISTANCE: f32 = 0.25;
+/// Most bodies one lightning strike jumps on to
+pub const MAX_CHAIN: usize = 4;
+
+/// A surface that is not in its normal state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Surface {
+    pub state: SurfaceState,
+    /// Tick at which the state wears off; charred surfaces stay charred
+    pub until: Option<u32>,
+    /// Tick at which a fire next spreads
+    pub next_spread: u32,
+}
+
+/// State an element leaves a surface in, or `None` if it does nothing
+///
+/// Lightning never changes a surface; see `Surfaces::strike`. Neither does
+/// anything change flesh, whose state lives in status effects.
+pub fn react(state: SurfaceState, material: Material, element: Element) -> Option<SurfaceState> {
+    if material == Material::Flesh {
+        return None;
+    }
+    match (element, state) {
+        // Melts, then steams dry
+        (Element::Fire, SurfaceState::Frozen) => Some(SurfaceState::Wet),
+        (Element::Fire, SurfaceState::Wet) => Some(SurfaceState::Normal),
+        (Element::Fire, SurfaceState::Normal | SurfaceState::Burning) if material.flammable() => {
+            Some(SurfaceState::Burning)
+        }
+        (Element::Water, SurfaceState::Normal | SurfaceState::Burning | SurfaceState::Wet) => {
+            Some(SurfaceState::Wet)
+        }
+        (Element::Ice, SurfaceState::Wet | SurfaceState::Frozen) => Some(SurfaceState::Frozen),
+        _ => None,
+    }
+}
+
+/// Elemental state of every surface, shared by all players
+#[derive(Debug, Clone, Default, Resource)]
+pub struct Surfaces {
+    surfaces: BTreeMap<EntityId, Surface>,
+}
+
+impl Surfaces {
+    pub fn state(&self, entity: EntityId) -> SurfaceState {
+        self.surfaces
+            .get(&entity)
+            .map_or(SurfaceState::Normal, |surface| surface.state)
+    }
+
+    pub fn get(&self, entity: EntityId) -> Option<&Surface> {
+        self.surfaces.get(&entity)
+    }
+
+    /// Surfaces that are not normal, in ascending entity order, for snapshots
+    pub fn updates(&self) -> Vec<SurfaceUpdate> {
+        self.surfaces
+            .iter()
+            .map(|(entity, surface)| SurfaceUpdate {
+                entity: *entity,
+                state: surface.state,
+            })
+            .collect()
+    }
+
+    /// Apply a spell's element to the entity it reached
+    ///
+    /// Returns the other bodies a lightning strike jumped to, nearest in the
+    /// chain first; `soaked` are the bodies of players with the `Wet` status.
+    pub fn hit(
+        &mut self,
+        world: &mut PhysicsWorld,
+        entity: EntityId,
+        element: Element,
+        soaked: &BTreeSet<EntityId>,
+        tick: u32,
+        delta_time: f32,
+    ) -> Vec<EntityId> {
+        if element == Element::Lightning {
+            return self.strike(world, entity, soaked);
+        }
+        let Some(material) = world.material(entity) else {
+            return Vec::new();
+        };
+        if let Some(state) = react(self.state(entity), material, element) {
+            self.set(world, entity, state, tick, delta_time);
+        }
+        Vec::new()
+    }
+
+    /// Bodies a lightning strike on `entity` jumps to
+    ///
+    /// It only jumps from wet or metal bodies to wet or metal bodies touching
+    /// them, breadth first and in entity order, up to `MAX_CHAIN` of them.
+    /// Bodies in `soaked` count as wet.
+    pub fn strike(
+        &self,
+        world: &PhysicsWorld,
+        entity: EntityId,
+        soaked: &BTreeSet<EntityId>,
+    ) -> Vec<EntityId> {
+        let conducts = |entity: EntityId| {
+            self.state(entity) == SurfaceState::Wet
+                || soaked.contains(&entity)
+                || world.material(entity).is_some_and(Material::conductive)
+        };
+        let mut chain = Vec::new();
+        if !conducts(entity) {
+            return chain;
+        }
+        let mut reached = BTreeSet::from([entity]);
+        let mut queue = VecDeque::from([entity]);
+        while let Some(from) = queue.pop_front() {
+            for next in world.nearby(from, CHAIN_DISTANCE, &QueryOptions::default()) {
+                if chain.len() == MAX_CHAIN {
+                    return chain;
+                }
+                if conducts(next) && reached.insert(next) {
+                    chain.push(next);
+                    queue.push_back(next);
+                }
+            }
+        }
+        chain
+    }
+
+    /// Wear states off and spread fires, once per tick
+    ///
+    /// Surfaces of entities that left the world are forgotten.
+    pub fn update(&mut self, world: &mut PhysicsWorld, tick: u32, delta_time: f32) {
+        self.surfaces.retain(|entity, _| world.contains(*entity));
+
+        let mut ignite = BTreeSet::new();
+        let spread = ticks_for(SPREAD_INTERVAL_MS, delta_time).max(1);
+        for (entity, surface) in self.surfaces.iter_mut() {
+            if surface.state != SurfaceState::Burning || !reached(tick, surface.next_spread) {
+                continue;
+            }
+            surface.next_spread = tick.wrapping_add(spread);
+            ignite.extend(
+                world
+                    .nearby(*entity, SPREAD_DISTANCE, &QueryOptions::default())
+                    .into_iter()
+                    .filter(|next| world.material(*next).is_some_and(Material::flammable)),
+            );
+        }
+
+        let expired: Vec<(EntityId, SurfaceState)> = self
+            .surfaces
+            .iter()
+            .filter(|(_, surface)| surface.until.is_some_and(|until| reached(tick, until)))
+            .map(|(entity, surface)| (*entity, surface.state))
+            .collect();
+        for (entity, state) in expired {
+            let next = match state {
+                SurfaceState::Burning => SurfaceState::Charred,
+                SurfaceState::Frozen => SurfaceState::Wet,
+                _ => SurfaceState::Normal,
+            };
+            self.set(world, entity, next, tick, delta_time);
+        }
+
+        // Only dry bodies that never burnt catch fire
+        for entity in ignite {
+            if self.state(entity) == SurfaceState::Normal {
+                self.set(world, entity, SurfaceState::Burning, tick, delta_time);
+            }
+        }
+    }
+
+    /// Put a surface in `state` from `tick`, restarting its timers
+    fn set(
+        &mut self,
+        world: &mut PhysicsWorld,
+        entity: EntityId,
+        state: SurfaceState,
+        tick: u32,
+        delta_time: f32,
+    ) {
+        if self.state(entity) != state {
+            world.set_surface_state(entity, state);
+        }
+        let duration = match state {
+            SurfaceState::Normal => {
+                self.surfaces.remove(&entity);
+                return;
+            }
+            SurfaceState::Burning => Some(BURN_MS),
+            SurfaceState::Wet => Some(WET_MS),
+            SurfaceState::Frozen => Some(FROZEN_MS),
+            SurfaceState::Charred => None,
+        };
+        self.surfaces.insert(
+            entity,
+            Surface {
+                state,
+                until: duration.map(|ms| tick.wrapping_add(ticks_for(ms, delta_time))),
+                next_spread: tick.wrapping_add(ticks_for(SPREAD_INTERVAL_MS, delta_time).max(1)),
+            },
+        );
+    }
+}
@@ -1,20 +1,460 @@
 /// Game logic and entity management
+use bevy_ecs::entity::Entity;
+use bevy_ecs::event::Events;
+use bevy_ecs::query::Has;
 use bevy_ecs::world::World;
+use engine::net_proto::{
+    ActiveSpell, CastEvent, EntityState, HealthState, InterruptReason, ManaState, PlayerInput,
+    SurfaceUpdate,
+};
+use engine::physics_core::{EntityId, PhysicsEvent, PhysicsWorld};
+use engine::spells::{Element, SpellId, SpellRegistry, StatusKind};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::sync::Arc;
+
+pub mod casting;
+pub mod elements;
+pub mod status;
+pub mod vitals;
+
+pub use casting::{CastState, CastStep, Caster, DamageTaken, Mana, SPELL_SLOTS};
+pub use elements::{Surface, Surfaces, WET_MS};
+pub use status::{StatusEffect, StatusEffects};
+pub use vitals::{Dead, Health, Immunities, Shield, Shields};
 
 pub struct GameLogic {
     world: World,
+    spells: Arc<SpellRegistry>,
+    /// Game entity of each player, in ID order so every tick runs the same way
+    players: BTreeMap<u32, Entity>,
+    cast_events: Vec<CastEvent>,
+    /// (player_id, spell_id) of spells whose effects apply this tick
+    released: Vec<(u32, SpellId)>,
+    /// Players who died this tick
+    deaths: Vec<u32>,
 }
 
 impl GameLogic {
     pub fn new() -> Self {
+        let mut world = World::new();
+        world.init_resource::<Events<PhysicsEvent>>();
+        world.init_resource::<Events<DamageTaken>>();
+        world.init_resource::<Surfaces>();
         Self {
-            world: World::new(),
+            world,
+            spells: Arc::new(SpellRegistry::new()),
+            players: BTreeMap::new(),
+            cast_events: Vec::new(),
+            released: Vec::new(),
+            deaths: Vec::new(),
+        }
+    }
+
+    /// Cast the spells of `spells`; new players get the first `SPELL_SLOTS`
+    pub fn set_spells(&mut self, spells: Arc<SpellRegistry>) {
+        self.spells = spells;
+    }
+
+    pub fn spells(&self) -> &Arc<SpellRegistry> {
+        &self.spells
+    }
+
+    /// Give a new player a caster with the default spell slots, full health
+    /// and mana, and no shields, statuses or immunities
+    pub fn spawn_player(&mut self, player_id: u32) {
+        let mut slots = [None; SPELL_SLOTS];
+        for (slot, (spell_id, _, _)) in slots.iter_mut().zip(self.spells.iter()) {
+            *slot = Some(spell_id);
+        }
+        let entity = self
+            .world
+            .spawn((
+                Caster::new(player_id, slots),
+                Mana::default(),
+                Health::default(),
+                Shields::default(),
+                StatusEffects::default(),
+                Immunities::default(),
+            ))
+            .id();
+        if let Some(old) = self.players.insert(player_id, entity) {
+            self.world.despawn(old);
+        }
+    }
+
+    pub fn remove_player(&mut self, player_id: u32) {
+        if let Some(entity) = self.players.remove(&player_id) {
+            self.world.despawn(entity);
         }
     }
 
-    pub fn update(&mut self, _delta_time: f32) {
-        // TODO: Update game entities and systems
+    pub fn caster(&self, player_id: u32) -> Option<&Caster> {
+        self.world.get::<Caster>(*self.players.get(&player_id)?)
+    }
+
+    pub fn mana(&self, player_id: u32) -> Option<&Mana> {
+        self.world.get::<Mana>(*self.players.get(&player_id)?)
+    }
+
+    pub fn health(&self, player_id: u32) -> Option<&Health> {
+        self.world.get::<Health>(*self.players.get(&player_id)?)
+    }
+
+    pub fn shields(&self, player_id: u32) -> Option<&Shields> {
+        self.world.get::<Shields>(*self.players.get(&player_id)?)
+    }
+
+    pub fn statuses(&self, player_id: u32) -> Option<&StatusEffects> {
+        self.world
+            .get::<StatusEffects>(*self.players.get(&player_id)?)
+    }
+
+    /// Whether a player's health ran out; dead players neither move nor cast
+    pub fn is_dead(&self, player_id: u32) -> bool {
+        self.health(player_id).is_some_and(Health::is_dead)
+    }
+
+    pub fn set_immunities(&mut self, player_id: u32, immunities: Immunities) {
+        if let Some(&entity) = self.players.get(&player_id) {
+            self.world.entity_mut(entity).insert(immunities);
+        }
+    }
+
+    /// Put spells in a player's slots
+    pub fn set_spell_slots(&mut self, player_id: u32, slots: [Option<SpellId>; SPELL_SLOTS]) {
+        if let Some(&entity) = self.players.get(&player_id) {
+            if let Some(mut caster) = self.world.get_mut::<Caster>(entity) {
+                caster.slots = slots;
+            }
+        }
+    }
+
+    /// Pass an input the physics just applied on to the player's caster
+    pub fn queue_input(&mut self, player_id: u32, input: &PlayerInput) {
+        if let Some(&entity) = self.players.get(&player_id) {
+            if let Some(mut caster) = self.world.get_mut::<Caster>(entity) {
+                caster.queue_input(input);
+            }
+        }
+    }
+
+    /// Deal damage to a player, interrupting their cast next update
+    ///
+    /// Returns the damage that got past their shields and immunities; damage
+    /// that did not interrupts nothing.
+    pub fn damage_player(&mut self, player_id: u32, amount: f32, element: Element) -> f32 {
+        let dealt = self.deal_damage(player_id, amount, element);
+        self.world
+            .resource_mut::<Events<DamageTaken>>()
+            .send(DamageTaken {
+                player_id,
+                amount: dealt,
+            });
+        dealt
+    }
+
+    /// Restore a player's health; returns how much was restored
+    pub fn heal_player(&mut self, player_id: u32, amount: f32) -> f32 {
+        let Some(&entity) = self.players.get(&player_id) else {
+            return 0.0;
+        };
+        self.world
+            .get_mut::<Health>(entity)
+            .map_or(0.0, |mut health| health.heal(amount))
+    }
+
+    /// Give a player a shield absorbing `amount` for `duration_ms` from `tick`
+    pub fn shield_player(
+        &mut self,
+        player_id: u32,
+        amount: f32,
+        duration_ms: u32,
+        tick: u32,
+        delta_time: f32,
+    ) {
+        if let Some(&entity) = self.players.get(&player_id) {
+            if let Some(mut shields) = self.world.get_mut::<Shields>(entity) {
+                let until = tick.wrapping_add(casting::ticks_for(duration_ms, delta_time).max(1));
+                shields.add(amount, until);
+            }
+        }
+    }
+
+    /// Apply a status to a player under its stacking rules; returns whether it
+    /// took hold
+    pub fn apply_status(
+        &mut self,
+        player_id: u32,
+        kind: StatusKind,
+        stacks: u32,
+        duration_ms: u32,
+        tick: u32,
+        delta_time: f32,
+    ) -> bool {
+        let Some(&entity) = self.players.get(&player_id) else {
+            return false;
+        };
+        let mut query = self.world.query::<(&mut StatusEffects, &Immunities)>();
+        let Ok((mut statuses, immunities)) = query.get_mut(&mut self.world, entity) else {
+            return false;
+        };
+        statuses.apply(kind, stacks, duration_ms, immunities, tick, delta_time)
+    }
+
+    /// Fraction of movement speed each player's statuses take away
+    pub fn slows(&self) -> Vec<(u32, f32)> {
+        self.players
+            .iter()
+            .filter_map(|(&player_id, &entity)| {
+                Some((player_id, self.world.get::<StatusEffects>(entity)?.slow()))
+            })
+            .collect()
     }
+
+    /// Fill in a player's replicated health, shields, mana and statuses
+    pub fn write_vitals(&self, player_id: u32, entity: &mut EntityState) {
+        let Some(&game_entity) = self.players.get(&player_id) else {
+            return;
+        };
+        let shield = self
+            .world
+            .get::<Shields>(game_entity)
+            .map_or(0.0, Shields::total);
+        entity.health = self
+            .world
+            .get::<Health>(game_entity)
+            .map(|health| HealthState {
+                current: replicated(health.current),
+                max: replicated(health.max),
+                shield: replicated(shield),
+            });
+        entity.mana = self.world.get::<Mana>(game_entity).map(|mana| ManaState {
+            current: replicated(mana.current),
+            max: replicated(mana.max),
+        });
+        entity.statuses = self
+            .world
+            .get::<StatusEffects>(game_entity)
+            .map(StatusEffects::states)
+            .unwrap_or_default();
+    }
+
+    /// Damage through shields and immunities, without interrupting anything
+    fn deal_damage(&mut self, player_id: u32, amount: f32, element: Element) -> f32 {
+        let Some(&entity) = self.players.get(&player_id) else {
+            return 0.0;
+        };
+        let mut query = self
+            .world
+            .query::<(&mut Health, &mut Shields, &Immunities)>();
+        let Ok((mut health, mut shields, immunities)) = query.get_mut(&mut self.world, entity)
+        else {
+            return 0.0;
+        };
+        vitals::deal_damage(&mut health, &mut shields, immunities, amount, element)
+    }
+
+    /// Hand over the collision and trigger events of the physics step just taken
+    ///
+    /// Events from the previous tick are discarded, so `physics_events` only
+    /// ever returns what happened during the current tick.
+    pub fn queue_physics_events(&mut self, events: impl IntoIterator<Item = PhysicsEvent>) {
+        let mut queue = self.world.resource_mut::<Events<PhysicsEvent>>();
+        queue.clear();
+        queue.extend(events);
+    }
+
+    /// Physics events of the current tick, in the order they occurred
+    pub fn physics_events(&self) -> impl Iterator<Item = &PhysicsEvent> {
+        self.world
+            .resource::<Events<PhysicsEvent>>()
+            .iter_current_update_events()
+    }
+
+    /// Cast events of the last update, for every client
+    pub fn cast_events(&self) -> &[CastEvent] {
+        &self.cast_events
+    }
+
+    /// (player_id, spell_id) of spells that went off in the last update
+    pub fn released_spells(&self) -> &[(u32, SpellId)] {
+        &self.released
+    }
+
+    /// Players who died in the last update, in ID order
+    pub fn deaths(&self) -> &[u32] {
+        &self.deaths
+    }
+
+    /// Spells each player is winding up or channeling at `tick`
+    pub fn active_spells(&self, tick: u32) -> Vec<(u32, ActiveSpell)> {
+        self.players
+            .iter()
+            .filter_map(|(&player_id, &entity)| {
+                let (spell_id, started) = self.world.get::<Caster>(entity)?.state().active()?;
+                Some((
+                    player_id,
+                    ActiveSpell {
+                        spell_id,
+                        elapsed_ticks: tick.wrapping_sub(started),
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    pub fn surfaces(&self) -> &Surfaces {
+        self.world.resource::<Surfaces>()
+    }
+
+    /// Surfaces that are not normal, for snapshots
+    pub fn surface_updates(&self) -> Vec<SurfaceUpdate> {
+        self.surfaces().updates()
+    }
+
+    /// Apply a spell's element to an entity it reached at `tick`
+    ///
+    /// Returns the other bodies a lightning strike jumped to; `soaked` are
+    /// the bodies of the players `wet_players` lists.
+    pub fn hit_surface(
+        &mut self,
+        physics: &mut PhysicsWorld,
+        entity: EntityId,
+        element: Element,
+        soaked: &BTreeSet<EntityId>,
+        tick: u32,
+        delta_time: f32,
+    ) -> Vec<EntityId> {
+        self.world
+            .resource_mut::<Surfaces>()
+            .hit(physics, entity, element, soaked, tick, delta_time)
+    }
+
+    /// Apply a spell's element to a player it reached at `tick`
+    ///
+    /// A player's wetness is their `Wet` status, the counterpart of a wet
+    /// surface: water soaks them for `WET_MS`, and fire and cold dry them.
+    pub fn hit_player(&mut self, player_id: u32, element: Element, tick: u32, delta_time: f32) {
+        match element {
+            Element::Water => {
+                self.apply_status(player_id, StatusKind::Wet, 1, WET_MS, tick, delta_time);
+            }
+            Element::Fire | Element::Ice => {
+                if let Some(&entity) = self.players.get(&player_id) {
+                    if let Some(mut statuses) = self.world.get_mut::<StatusEffects>(entity) {
+                        statuses.remove(StatusKind::Wet);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Players with the `Wet` status, who conduct lightning, in ID order
+    pub fn wet_players(&self) -> Vec<u32> {
+        self.players
+            .iter()
+            .filter(|(_, &entity)| {
+                self.world
+                    .get::<StatusEffects>(entity)
+                    .is_some_and(|statuses| statuses.has(StatusKind::Wet))
+            })
+            .map(|(&player_id, _)| player_id)
+            .collect()
+    }
+
+    /// Wear surface states off and spread fires
+    pub fn update_surfaces(&mut self, physics: &mut PhysicsWorld, tick: u32, delta_time: f32) {
+        self.world
+            .resource_mut::<Surfaces>()
+            .update(physics, tick, delta_time);
+    }
+
+    /// Advance every player by one tick
+    ///
+    /// Shields and statuses wear off and damage over time is dealt first;
+    /// damage over time does not interrupt casts. Players whose health ran
+    /// out since the last update die, which stops their cast. Then every
+    /// living caster steps.
+    pub fn update(&mut self, tick: u32, delta_time: f32) {
+        self.cast_events.clear();
+        self.released.clear();
+        self.deaths.clear();
+
+        let mut pulses = Vec::new();
+        let mut timed = self.world.query::<(&mut Shields, &mut StatusEffects)>();
+        for (&player_id, &entity) in &self.players {
+            let Ok((mut shields, mut statuses)) = timed.get_mut(&mut self.world, entity) else {
+                continue;
+            };
+            shields.expire(tick);
+            let player_pulses = statuses.update(tick, delta_time);
+            pulses.extend(
+                player_pulses
+                    .into_iter()
+                    .map(|(element, amount)| (player_id, element, amount)),
+            );
+        }
+        for (player_id, element, amount) in pulses {
+            self.deal_damage(player_id, amount, element);
+        }
+
+        let mut damage = self.world.resource_mut::<Events<DamageTaken>>();
+        let damaged: HashSet<u32> = damage
+            .drain()
+            .filter(|event| event.amount > 0.0)
+            .map(|event| event.player_id)
+            .collect();
+
+        for (&player_id, &entity) in &self.players {
+            let entity = self.world.entity(entity);
+            if entity.get::<Health>().is_some_and(Health::is_dead) && !entity.contains::<Dead>() {
+                self.deaths.push(player_id);
+            }
+        }
+        for player_id in &self.deaths {
+            self.world.entity_mut(self.players[player_id]).insert(Dead);
+        }
+
+        let mut casters = self
+            .world
+            .query::<(&mut Caster, &mut Mana, &StatusEffects, Has<Dead>)>();
+        for (&player_id, &entity) in &self.players {
+            let Ok((mut caster, mut mana, statuses, dead)) =
+                casters.get_mut(&mut self.world, entity)
+            else {
+                continue;
+            };
+            let step = if dead {
+                caster.stop(InterruptReason::Died)
+            } else {
+                caster.step(
+                    tick,
+                    delta_time,
+                    damaged.contains(&player_id),
+                    statuses.silenced(),
+                    &mut mana,
+                    &self.spells,
+                )
+            };
+            self.cast_events
+                .extend(step.events.into_iter().map(|(spell_id, kind)| CastEvent {
+                    tick,
+                    player_id,
+                    spell_id,
+                    kind,
+                }));
+            if let Some(spell_id) = step.released {
+                self.released.push((player_id, spell_id));
+            }
+        }
+        // TODO: React to damage zones and pickups
+    }
+}
+
+/// Round a replicated amount up, so anything left shows as at least 1
+fn replicated(amount: f32) -> u16 {
+    amount.max(0.0).ceil().min(u16::MAX as f32) as u16
 }
 
 impl Default for GameLogic {
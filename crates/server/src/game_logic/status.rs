@@ -0,0 +1,293 @@
+/// Timed status effects and the rules for stacking them
+///
+/// Every kind of status has a `StatusRule`. Its `Stacking` says what applying
+/// it again does: add stacks, refresh the timer or override the old
+/// application. Some statuses cancel each other out: fire on a wet or frozen
+/// target only dries or thaws it, while water puts a fire out and soaks the
+/// target. Chill piles up until the target freezes solid.
+///
+/// Like casting, everything is counted in ticks, and damage over time is dealt
+/// in pulses every `PULSE_MS`, so the same hits always do the same damage.
+use super::casting::{reached, ticks_for};
+use super::vitals::Immunities;
+use bevy_ecs::component::Component;
+use engine::net_proto::StatusState;
+use engine::spells::{Element, StatusKind};
+use std::collections::BTreeMap;
+
+/// Time between two pulses of damage over time (ms)
+pub const PULSE_MS: u32 = 500;
+
+/// What applying a status again does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stacking {
+    /// Every application adds its stacks, up to `max`
+    Stack { max: u32 },
+    /// There is only ever one stack
+    Refresh,
+    /// The newest application replaces the old one's stacks and timer, even
+    /// if it ends sooner
+    Override,
+}
+
+/// How a kind of status behaves
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StatusRule {
+    pub stacking: Stacking,
+    /// Statuses that cancel this one out: applying it removes them instead
+    /// of taking hold
+    pub countered_by: &'static [StatusKind],
+    /// Statuses it removes when it takes hold
+    pub removes: &'static [StatusKind],
+    /// Status it turns into on reaching its maximum stacks
+    pub overflow: Option<StatusKind>,
+    /// Fraction of movement speed it takes away, per stack
+    pub slow: f32,
+    /// Whether it keeps the target from casting
+    pub silences: bool,
+    /// Element and damage per second per stack it deals
+    pub damage_over_time: Option<(Element, f32)>,
+}
+
+impl StatusRule {
+    const fn new(stacking: Stacking) -> Self {
+        Self {
+            stacking,
+            countered_by: &[],
+            removes: &[],
+            overflow: None,
+            slow: 0.0,
+            silences: false,
+            damage_over_time: None,
+        }
+    }
+}
+
+/// Rule of each kind of status
+///
+/// Applying any status again never shortens it, except under `Override`: the
+/// timer runs to whichever ends later, the old or the new application.
+pub fn rule(kind: StatusKind) -> StatusRule {
+    use StatusKind::*;
+    match kind {
+        Burning => StatusRule {
+            countered_by: &[Wet, Frozen],
+            removes: &[Chilled],
+            damage_over_time: Some((Element::Fire, 4.0)),
+            ..StatusRule::new(Stacking::Stack { max: 3 })
+        },
+        Chilled => StatusRule {
+            countered_by: &[Burning],
+            overflow: Some(Frozen),
+            slow: 0.1,
+            ..StatusRule::new(Stacking::Stack { max: 5 })
+        },
+        Frozen => StatusRule {
+            countered_by: &[Burning],
+            removes: &[Chilled, Wet],
+            slow: 1.0,
+            silences: true,
+            ..StatusRule::new(Stacking::Refresh)
+        },
+        Wet => StatusRule {
+            removes: &[Burning],
+            ..StatusRule::new(Stacking::Refresh)
+        },
+        Shocked => StatusRule {
+            slow: 0.2,
+            ..StatusRule::new(Stacking::Override)
+        },
+        Stunned => StatusRule {
+            slow: 1.0,
+            silences: true,
+            ..StatusRule::new(Stacking::Override)
+        },
+        Slowed => StatusRule {
+            slow: 0.4,
+            ..StatusRule::new(Stacking::Refresh)
+        },
+        Silenced => StatusRule {
+            silences: true,
+            ..StatusRule::new(Stacking::Refresh)
+        },
+    }
+}
+
+/// One status on a player
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatusEffect {
+    pub stacks: u32,
+    /// Tick at which it wears off
+    pub until: u32,
+    /// Tick of its next pulse of damage over time
+    pub next_pulse: u32,
+}
+
+/// Status effects on a player, one per kind
+#[derive(Debug, Clone, Default, PartialEq, Component)]
+pub struct StatusEffects {
+    effects: BTreeMap<StatusKind, StatusEffect>,
+}
+
+impl StatusEffects {
+    pub fn get(&self, kind: StatusKind) -> Option<&StatusEffect> {
+        self.effects.get(&kind)
+    }
+
+    pub fn has(&self, kind: StatusKind) -> bool {
+        self.effects.contains_key(&kind)
+    }
+
+    /// Stacks of `kind`, 0 if absent
+    pub fn stacks(&self, kind: StatusKind) -> u32 {
+        self.effects.get(&kind).map_or(0, |effect| effect.stacks)
+    }
+
+    /// Statuses in `StatusKind` order
+    pub fn iter(&self) -> impl Iterator<Item = (StatusKind, &StatusEffect)> {
+        self.effects.iter().map(|(kind, effect)| (*kind, effect))
+    }
+
+    pub fn remove(&mut self, kind: StatusKind) -> bool {
+        self.effects.remove(&kind).is_some()
+    }
+
+    /// Apply `stacks` of `kind` lasting `duration_ms` from `tick`
+    ///
+    /// Returns whether it took hold; it does not if the player is immune or a
+    /// status countering it was removed instead.
+    pub fn apply(
+        &mut self,
+        kind: StatusKind,
+        stacks: u32,
+        duration_ms: u32,
+        immunities: &Immunities,
+        tick: u32,
+        delta_time: f32,
+    ) -> bool {
+        if stacks == 0 || immunities.statuses.contains(&kind) {
+            return false;
+        }
+        let rule = rule(kind);
+        let mut countered = false;
+        for other in rule.countered_by {
+            countered |= self.remove(*other);
+        }
+        if countered {
+            return false;
+        }
+        for other in rule.removes {
+            self.remove(*other);
+        }
+
+        let until = tick.wrapping_add(ticks_for(duration_ms, delta_time).max(1));
+        let next_pulse = tick.wrapping_add(pulse_ticks(delta_time));
+        let effect = match (self.effects.get(&kind), rule.stacking) {
+            (None, Stacking::Stack { max }) => StatusEffect {
+                stacks: stacks.min(max),
+                until,
+                next_pulse,
+            },
+            (None, Stacking::Refresh) => StatusEffect {
+                stacks: 1,
+                until,
+                next_pulse,
+            },
+            (None, Stacking::Override) => StatusEffect {
+                stacks,
+                until,
+                next_pulse,
+            },
+            (Some(old), Stacking::Stack { max }) => StatusEffect {
+                stacks: old.stacks.saturating_add(stacks).min(max),
+                until: later(old.until, until),
+                next_pulse: old.next_pulse,
+            },
+            (Some(old), Stacking::Refresh) => StatusEffect {
+                until: later(old.until, until),
+                ..*old
+            },
+            (Some(old), Stacking::Override) => StatusEffect {
+                stacks,
+                until,
+                next_pulse: old.next_pulse,
+            },
+        };
+        self.effects.insert(kind, effect);
+
+        if let (Stacking::Stack { max }, Some(overflow)) = (rule.stacking, rule.overflow) {
+            if effect.stacks >= max
+                && self.apply(overflow, 1, duration_ms, immunities, tick, delta_time)
+            {
+                self.remove(kind);
+            }
+        }
+        true
+    }
+
+    /// Wear statuses off and deal their damage over time, once per tick
+    ///
+    /// Returns the element and amount of every pulse due at `tick`. A status
+    /// still pulses on the tick it wears off.
+    pub fn update(&mut self, tick: u32, delta_time: f32) -> Vec<(Element, f32)> {
+        let interval = pulse_ticks(delta_time);
+        let mut pulses = Vec::new();
+        for (kind, effect) in self.effects.iter_mut() {
+            let Some((element, per_second)) = rule(*kind).damage_over_time else {
+                continue;
+            };
+            if reached(tick, effect.next_pulse) {
+                effect.next_pulse = tick.wrapping_add(interval);
+                let seconds = interval as f32 * delta_time;
+                pulses.push((element, per_second * effect.stacks as f32 * seconds));
+            }
+        }
+        self.effects
+            .retain(|_, effect| !reached(tick, effect.until));
+        pulses
+    }
+
+    /// Fraction of movement speed taken away; the strongest slow applies
+    pub fn slow(&self) -> f32 {
+        self.effects
+            .iter()
+            .map(|(kind, effect)| {
+                let rule = rule(*kind);
+                match rule.stacking {
+                    Stacking::Stack { .. } => rule.slow * effect.stacks as f32,
+                    Stacking::Refresh | Stacking::Override => rule.slow,
+                }
+            })
+            .fold(0.0, f32::max)
+            .min(1.0)
+    }
+
+    /// Whether a status keeps the player from casting
+    pub fn silenced(&self) -> bool {
+        self.effects.keys().any(|kind| rule(*kind).silences)
+    }
+
+    /// Statuses as replicated in snapshots
+    pub fn states(&self) -> Vec<StatusState> {
+        self.iter()
+            .map(|(kind, effect)| StatusState {
+                kind,
+                stacks: effect.stacks,
+                until_tick: effect.until,
+            })
+            .collect()
+    }
+}
+
+fn pulse_ticks(delta_time: f32) -> u32 {
+    ticks_for(PULSE_MS, delta_time).max(1)
+}
+
+/// Whichever of two ticks comes later, allowing for wrap-around
+fn later(a: u32, b: u32) -> u32 {
+    if reached(a, b) {
+        a
+    } else {
+        b
+    }
+}
@@ -0,0 +1,138 @@
+/// Health, shields and immunities of players
+///
+/// Damage first wears down a player's shields, the one that runs out soonest
+/// first, and only what they do not absorb reaches their health. Immunity to
+/// an element blocks its damage entirely. Health never drops below zero or
+/// rises above its maximum, and a player at zero is dead and cannot be healed.
+use super::casting::reached;
+use bevy_ecs::component::Component;
+use engine::spells::{Element, StatusKind};
+use std::collections::BTreeSet;
+
+/// Health a player starts with and can have
+pub const DEFAULT_MAX_HEALTH: f32 = 100.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Component)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            current: DEFAULT_MAX_HEALTH,
+            max: DEFAULT_MAX_HEALTH,
+        }
+    }
+}
+
+impl Health {
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0.0
+    }
+
+    /// Restore up to `amount` of health; returns how much was restored
+    pub fn heal(&mut self, amount: f32) -> f32 {
+        if self.is_dead() {
+            return 0.0;
+        }
+        let healed = amount.max(0.0).min(self.max - self.current).max(0.0);
+        self.current += healed;
+        healed
+    }
+}
+
+/// Marks a player who died; `GameLogic` adds it once their health runs out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Component)]
+pub struct Dead;
+
+/// One shield, absorbing damage until used up or expired
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Shield {
+    /// Damage it still absorbs
+    pub remaining: f32,
+    /// Tick at which it expires
+    pub until: u32,
+}
+
+/// Shields of a player, in the order they expire
+///
+/// Shields do not merge: every application adds one, which keeps its own
+/// amount and expiry.
+#[derive(Debug, Clone, Default, PartialEq, Component)]
+pub struct Shields {
+    shields: Vec<Shield>,
+}
+
+impl Shields {
+    /// Add a shield absorbing `amount` until `until`; shields expiring at the
+    /// same tick are used up in the order they were added
+    pub fn add(&mut self, amount: f32, until: u32) {
+        if amount <= 0.0 {
+            return;
+        }
+        let index = self
+            .shields
+            .iter()
+            .position(|shield| !reached(until, shield.until))
+            .unwrap_or(self.shields.len());
+        self.shields.insert(
+            index,
+            Shield {
+                remaining: amount,
+                until,
+            },
+        );
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Shield> {
+        self.shields.iter()
+    }
+
+    /// Damage all shields together still absorb
+    pub fn total(&self) -> f32 {
+        self.shields.iter().map(|shield| shield.remaining).sum()
+    }
+
+    /// Absorb as much of `amount` as the shields can; returns the rest
+    pub fn absorb(&mut self, mut amount: f32) -> f32 {
+        for shield in &mut self.shields {
+            let absorbed = shield.remaining.min(amount);
+            shield.remaining -= absorbed;
+            amount -= absorbed;
+        }
+        self.shields.retain(|shield| shield.remaining > 0.0);
+        amount
+    }
+
+    /// Drop the shields that expired by `tick`
+    pub fn expire(&mut self, tick: u32) {
+        self.shields.retain(|shield| !reached(tick, shield.until));
+    }
+}
+
+/// Elements whose damage and statuses a player ignores
+#[derive(Debug, Clone, Default, PartialEq, Eq, Component)]
+pub struct Immunities {
+    pub elements: BTreeSet<Element>,
+    pub statuses: BTreeSet<StatusKind>,
+}
+
+/// Deal `amount` of `element` damage through shields and immunities
+///
+/// Returns the damage that reached health.
+pub fn deal_damage(
+    health: &mut Health,
+    shields: &mut Shields,
+    immunities: &Immunities,
+    amount: f32,
+    element: Element,
+) -> f32 {
+    if amount <= 0.0 || immunities.elements.contains(&element) {
+        return 0.0;
+    }
+    let dealt = shields.absorb(amount).min(health.current.max(0.0));
+    health.current -= dealt;
+    dealt
+}
@@ -4,8 +4,10 @@ pub mod game_logic;
 pub mod net;
 pub mod physics;
 pub mod player_data;
+pub mod tick;
 
 pub use game_logic::GameLogic;
 pub use net::NetworkServer;
 pub use physics::AuthoritativePhysics;
 pub use player_data::PlayerDataStore;
+pub use tick::TickLoop;
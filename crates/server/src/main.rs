@@ -1,12 +1,11 @@
 use engine::config::Config;
+use engine::spells::SpellRegistry;
+use server::net;
+use server::tick::TickLoop;
 use std::path::Path;
+use std::sync::Arc;
 use tracing::info;
 
-mod game_logic;
-mod net;
-mod physics;
-mod player_data;
-
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     // Initialize logging
@@ -21,8 +20,17 @@ async fn main() -> anyhow::Result<()> {
         config.server.host, config.server.port
     );
 
+    // Load the spells players can cast, refusing to start with a broken one
+    let spells = SpellRegistry::load_dir(Path::new(&config.server.spells_dir))?;
+    info!("Loaded {} spells", spells.len());
+
     // Initialize network server
-    let mut server = net::NetworkServer::new(&config.server.host, config.server.port);
+    let mut server = net::NetworkServer::from_config(&config);
+
+    // Initialize the authoritative simulation, fed by the network server
+    let mut tick_loop = TickLoop::new(&config, server.inbound_events(), server.sessions())
+        .with_spells(Arc::new(spells));
+    tokio::spawn(async move { tick_loop.run().await });
 
     info!("Server subsystems initialized");
 
@@ -1,32 +1,199 @@
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
 /// Server-side networking and RPC module
-use tokio::net::{TcpListener, TcpStream};
-use tracing::{error, info};
+use engine::config::{
+    Config, MovementConfig, NetworkConditions, NetworkConfig, ServerConfig, Transport,
+};
+use engine::net_proto::{
+    delay_stream, ClientMessage, DisconnectReason, FrameReader, FrameWriter, PingTimer,
+    PlayerInput, ServerMessage, BUILD_HASH, PING_INTERVAL, PROTOCOL_VERSION,
+};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tracing::{debug, error, info, warn};
+
+pub mod replication;
+pub mod session;
+mod udp;
+
+pub use replication::{Replication, ReplicationStats, SNAPSHOT_HISTORY_LEN};
+pub use session::{Session, SessionTable, SharedSessions};
+
+/// Player events forwarded from connection tasks to the simulation
+#[derive(Debug, Clone)]
+pub enum InboundEvent {
+    PlayerJoined {
+        player_id: u32,
+    },
+    /// A player reconnected to their session; their character was kept
+    PlayerResumed {
+        player_id: u32,
+    },
+    Input {
+        player_id: u32,
+        input: PlayerInput,
+    },
+    SnapshotAck {
+        player_id: u32,
+        tick: u32,
+    },
+    PlayerLeft {
+        player_id: u32,
+    },
+}
+
+/// Messages to and from one connection, whatever the transport
+struct ClientLink {
+    addr: SocketAddr,
+    incoming: UnboundedReceiver<ClientMessage>,
+    outgoing: UnboundedSender<ServerMessage>,
+}
+
+/// What every connection task needs to know about the server
+#[derive(Debug, Clone)]
+struct SessionSettings {
+    movement: MovementConfig,
+    tick_rate: u32,
+    timeout: Duration,
+    grace: Duration,
+}
 
 pub struct NetworkServer {
     addr: String,
+    transport: Transport,
+    conditions: NetworkConditions,
+    sessions: SharedSessions,
+    inbound: Option<UnboundedSender<InboundEvent>>,
+    settings: SessionSettings,
+    listener: Option<Listener>,
+}
+
+/// A bound socket waiting for `start` to serve it
+enum Listener {
+    Tcp(TcpListener),
+    Udp(UdpSocket),
 }
 
 impl NetworkServer {
     pub fn new(host: &str, port: u16) -> Self {
         Self {
             addr: format!("{}:{}", host, port),
+            transport: Transport::Tcp,
+            conditions: NetworkConditions::default(),
+            sessions: Arc::new(Mutex::new(SessionTable::new())),
+            inbound: None,
+            settings: SessionSettings {
+                movement: MovementConfig::default(),
+                tick_rate: ServerConfig::default().tick_rate,
+                timeout: Duration::from_millis(NetworkConfig::default().timeout_ms as u64),
+                grace: Duration::from_millis(ServerConfig::default().session_grace_ms as u64),
+            },
+            listener: None,
         }
     }
 
+    /// Create a server listening on the configured address
+    pub fn from_config(config: &Config) -> Self {
+        let mut server = Self::new(&config.server.host, config.server.port)
+            .with_transport(config.network.transport)
+            .with_conditions(config.network.conditions.clone())
+            .with_timeout(Duration::from_millis(config.network.timeout_ms as u64))
+            .with_session_grace(Duration::from_millis(config.server.session_grace_ms as u64));
+        server.settings.movement = config.movement.clone();
+        server.settings.tick_rate = config.server.tick_rate;
+        server
+    }
+
+    pub fn with_transport(mut self, transport: Transport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Simulate `conditions` on all traffic, e.g. to test lag handling on loopback
+    pub fn with_conditions(mut self, conditions: NetworkConditions) -> Self {
+        self.conditions = conditions;
+        self
+    }
+
+    /// Drop clients that send nothing, not even a pong, for this long
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.settings.timeout = timeout;
+        self
+    }
+
+    /// Keep a dropped player's character this long for them to reconnect
+    pub fn with_session_grace(mut self, grace: Duration) -> Self {
+        self.settings.grace = grace;
+        self
+    }
+
+    pub fn transport(&self) -> Transport {
+        self.transport
+    }
+
+    /// Sessions of all players that completed the handshake
+    pub fn sessions(&self) -> SharedSessions {
+        self.sessions.clone()
+    }
+
+    /// Smoothed round-trip time to a connected player, once measured
+    pub fn player_rtt(&self, player_id: u32) -> Option<Duration> {
+        self.sessions.lock().unwrap().rtt(player_id)
+    }
+
+    /// Route player events to the simulation
+    ///
+    /// Until this is called, inputs from connected players are discarded.
+    pub fn inbound_events(&mut self) -> UnboundedReceiver<InboundEvent> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.inbound = Some(tx);
+        rx
+    }
+
+    /// Bind the configured address and return the address actually bound,
+    /// e.g. to learn the port picked for port 0
+    ///
+    /// `start` binds on its own if this was not called.
+    pub async fn bind(&mut self) -> anyhow::Result<SocketAddr> {
+        let (listener, addr) = match self.transport {
+            Transport::Tcp => {
+                let listener = TcpListener::bind(&self.addr).await?;
+                let addr = listener.local_addr()?;
+                (Listener::Tcp(listener), addr)
+            }
+            Transport::Udp => {
+                let socket = UdpSocket::bind(&self.addr).await?;
+                let addr = socket.local_addr()?;
+                (Listener::Udp(socket), addr)
+            }
+        };
+        info!("Server listening on {} ({:?})", addr, self.transport);
+        self.listener = Some(listener);
+        Ok(addr)
+    }
+
     pub async fn start(&mut self) -> anyhow::Result<()> {
-        let listener = TcpListener::bind(&self.addr).await?;
-        info!("Server listening on {}", self.addr);
+        if self.conditions.is_active() {
+            info!("Simulating network conditions: {:?}", self.conditions);
+        }
+        if self.listener.is_none() {
+            self.bind().await?;
+        }
+        match self.listener.take() {
+            Some(Listener::Tcp(listener)) => self.serve_tcp(listener).await,
+            Some(Listener::Udp(socket)) => self.serve_udp(socket).await,
+            None => unreachable!("bound above"),
+        }
+    }
 
+    async fn serve_tcp(&mut self, listener: TcpListener) -> anyhow::Result<()> {
         loop {
             match listener.accept().await {
                 Ok((socket, addr)) => {
                     info!("New connection from: {}", addr);
-                    tokio::spawn(async move {
-                        if let Err(e) = handle_client(socket).await {
-                            error!("Error handling client: {}", e);
-                        }
-                    });
+                    self.spawn_client(tcp_link(socket, addr, &self.conditions));
                 }
                 Err(e) => {
                     error!("Error accepting connection: {}", e);
@@ -34,35 +201,342 @@ impl NetworkServer {
             }
         }
     }
+
+    async fn serve_udp(&mut self, socket: UdpSocket) -> anyhow::Result<()> {
+        udp::serve(socket, self.conditions.clone(), |link| {
+            info!("New connection from: {}", link.addr);
+            self.spawn_client(link);
+        })
+        .await
+    }
+
+    fn spawn_client(&self, link: ClientLink) {
+        let sessions = self.sessions.clone();
+        let inbound = self.inbound.clone();
+        let settings = self.settings.clone();
+        tokio::spawn(handle_client(link, sessions, inbound, settings));
+    }
 }
 
-async fn handle_client(mut socket: TcpStream) -> anyhow::Result<()> {
-    let mut buffer = [0; 1024];
+/// Check the version information a client sent in `ClientMessage::Connect`
+///
+/// Returns the reason to send back in `ServerMessage::Disconnect` on mismatch.
+pub fn validate_handshake(protocol_version: u32, build_hash: &str) -> Result<(), String> {
+    if protocol_version != PROTOCOL_VERSION {
+        return Err(format!(
+            "Protocol version mismatch: server speaks v{}, client speaks v{}",
+            PROTOCOL_VERSION, protocol_version
+        ));
+    }
+    if build_hash != BUILD_HASH {
+        return Err(format!(
+            "Build mismatch: server is '{}', client is '{}'",
+            BUILD_HASH, build_hash
+        ));
+    }
+    Ok(())
+}
 
-    loop {
-        let n = socket.read(&mut buffer).await?;
+/// Run a TCP connection's reader and writer tasks and link them to the session
+fn tcp_link(socket: TcpStream, addr: SocketAddr, conditions: &NetworkConditions) -> ClientLink {
+    let (read_half, write_half) = socket.into_split();
+    let (incoming_tx, mut incoming) = mpsc::unbounded_channel();
+    let (outgoing, mut outgoing_rx) = mpsc::unbounded_channel();
+    if conditions.is_active() {
+        outgoing_rx = delay_stream(conditions.clone(), outgoing_rx, encoded_size);
+        let inbound = NetworkConditions {
+            seed: conditions.seed.wrapping_add(1),
+            ..conditions.clone()
+        };
+        incoming = delay_stream(inbound, incoming, encoded_size);
+    }
+    tokio::spawn(read_inbound(FrameReader::new(read_half), addr, incoming_tx));
+    tokio::spawn(write_outbound(FrameWriter::new(write_half), outgoing_rx));
+    ClientLink {
+        addr,
+        incoming,
+        outgoing,
+    }
+}
+
+fn encoded_size<T: serde::Serialize>(message: &T) -> usize {
+    bincode::serialized_size(message).unwrap_or(0) as usize
+}
+
+async fn handle_client(
+    mut link: ClientLink,
+    sessions: SharedSessions,
+    inbound: Option<UnboundedSender<InboundEvent>>,
+    settings: SessionSettings,
+) {
+    let addr = link.addr;
 
-        if n == 0 {
-            // Connection closed
-            return Ok(());
+    // The first message must be a valid Connect
+    let first = match tokio::time::timeout(settings.timeout, link.incoming.recv()).await {
+        Ok(first) => first,
+        Err(_) => {
+            warn!(
+                "Rejecting {}: no Connect within {:?}",
+                addr, settings.timeout
+            );
+            return refuse(&link, DisconnectReason::TimedOut);
         }
+    };
+    let (player_id, resumed, connection) = match first {
+        Some(ClientMessage::Connect {
+            player_name,
+            protocol_version,
+            build_hash,
+            session_token,
+        }) => {
+            if let Err(reason) = validate_handshake(protocol_version, &build_hash) {
+                warn!("Rejecting {} ('{}'): {}", addr, player_name, reason);
+                return refuse(&link, DisconnectReason::Refused(reason));
+            }
+
+            // Queue Welcome while holding the lock so no broadcast can overtake it
+            let mut sessions = sessions.lock().unwrap();
+            let resumed =
+                session_token.and_then(|token| sessions.resume(token, addr, link.outgoing.clone()));
+            let player_id = match resumed {
+                Some(player_id) => {
+                    info!("Player '{}' resumed as {}", player_name, player_id);
+                    player_id
+                }
+                None => {
+                    if session_token.is_some() {
+                        info!("Session of '{}' expired, joining anew", player_name);
+                    }
+                    let player_id = sessions.register(&player_name, addr, link.outgoing.clone());
+                    info!("Player '{}' joined as {}", player_name, player_id);
+                    player_id
+                }
+            };
+            let session = sessions
+                .get(player_id)
+                .expect("session was just registered");
+            let _ = link.outgoing.send(ServerMessage::Welcome {
+                player_id,
+                session_token: session.token,
+                movement: settings.movement.clone(),
+                tick_rate: settings.tick_rate,
+            });
+            (player_id, resumed.is_some(), session.connection)
+        }
+        Some(other) => {
+            warn!("Rejecting {}: expected Connect, got {:?}", addr, other);
+            return refuse(
+                &link,
+                DisconnectReason::Refused("Expected Connect as first message".to_string()),
+            );
+        }
+        None => return,
+    };
+
+    if resumed {
+        notify(&inbound, InboundEvent::PlayerResumed { player_id });
+    } else {
+        notify(&inbound, InboundEvent::PlayerJoined { player_id });
+    }
+
+    let end = serve_session(
+        player_id,
+        connection,
+        &mut link.incoming,
+        &link.outgoing,
+        &inbound,
+        &sessions,
+        settings.timeout,
+    )
+    .await;
+
+    match end {
+        SessionEnd::Left => {
+            if let Some(session) = sessions.lock().unwrap().remove(player_id) {
+                info!("Player '{}' ({}) left", session.player_name, player_id);
+            }
+            notify(&inbound, InboundEvent::PlayerLeft { player_id });
+        }
+        SessionEnd::Dropped => {
+            if sessions.lock().unwrap().suspend(player_id, connection) {
+                info!(
+                    "Holding player {} for {:?} to reconnect",
+                    player_id, settings.grace
+                );
+                tokio::spawn(expire_session(
+                    player_id,
+                    connection,
+                    sessions.clone(),
+                    inbound.clone(),
+                    settings.grace,
+                ));
+            }
+        }
+        SessionEnd::Replaced => {
+            debug!("Player {} resumed on another connection", player_id);
+        }
+    }
+    // Dropping the link's last sender lets the transport flush its queue and close
+}
 
-        let request = String::from_utf8_lossy(&buffer[..n]);
-        info!("Received: {}", request.trim());
+/// How a connection stopped serving its session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SessionEnd {
+    /// The player quit or was kicked; the session is over
+    Left,
+    /// The connection broke or timed out; the player may resume
+    Dropped,
+    /// The player resumed the session on a newer connection
+    Replaced,
+}
 
-        // Simple "Hello World" response
-        let response = if request.trim() == "HELLO" {
-            "WORLD\n"
-        } else if request.trim() == "PING" {
-            "PONG\n"
-        } else {
-            "OK\n"
+async fn serve_session(
+    player_id: u32,
+    connection: u64,
+    incoming: &mut UnboundedReceiver<ClientMessage>,
+    outbound: &UnboundedSender<ServerMessage>,
+    inbound: &Option<UnboundedSender<InboundEvent>>,
+    sessions: &SharedSessions,
+    timeout: Duration,
+) -> SessionEnd {
+    // Pings double as heartbeats: a client that answers nothing for `timeout`
+    // is gone, even if its socket never closed
+    let mut pings = PingTimer::new(Instant::now());
+    let mut ping_interval = tokio::time::interval(PING_INTERVAL);
+    let mut last_heard = Instant::now();
+    loop {
+        {
+            let sessions = sessions.lock().unwrap();
+            if !sessions.is_current(player_id, connection) {
+                // Kicked by the tick loop, or resumed elsewhere
+                return if sessions.contains(player_id) {
+                    SessionEnd::Replaced
+                } else {
+                    SessionEnd::Left
+                };
+            }
+        }
+        let message = tokio::select! {
+            message = incoming.recv() => match message {
+                Some(message) => message,
+                None => return SessionEnd::Dropped,
+            },
+            _ = ping_interval.tick() => {
+                let (id, timestamp) = pings.ping(Instant::now());
+                let _ = outbound.send(ServerMessage::Ping { id, timestamp });
+                continue;
+            }
+            _ = tokio::time::sleep_until((last_heard + timeout).into()) => {
+                info!("Player {} timed out", player_id);
+                let _ = outbound.send(ServerMessage::Disconnect {
+                    reason: DisconnectReason::TimedOut,
+                });
+                return SessionEnd::Dropped;
+            }
         };
+        last_heard = Instant::now();
+        debug!("Received from {}: {:?}", player_id, message);
 
-        socket.write_all(response.as_bytes()).await?;
+        match message {
+            ClientMessage::Connect { .. } => {
+                warn!("Player {} sent a second Connect, ignoring", player_id);
+            }
+            ClientMessage::Ping { id, timestamp } => {
+                let tick = sessions.lock().unwrap().current_tick();
+                let _ = outbound.send(ServerMessage::Pong {
+                    id,
+                    timestamp,
+                    tick,
+                });
+            }
+            ClientMessage::Pong { id, timestamp } => {
+                if pings.pong(id, timestamp, Instant::now()).is_some() {
+                    if let Some(rtt) = pings.rtt() {
+                        sessions
+                            .lock()
+                            .unwrap()
+                            .record_rtt(player_id, rtt, pings.jitter());
+                    }
+                }
+            }
+            ClientMessage::Input(input) => {
+                notify(inbound, InboundEvent::Input { player_id, input });
+            }
+            ClientMessage::SnapshotAck { tick } => {
+                notify(inbound, InboundEvent::SnapshotAck { player_id, tick });
+            }
+            ClientMessage::Disconnect { reason } => {
+                info!("Player {} disconnected: {}", player_id, reason);
+                return SessionEnd::Left;
+            }
+        }
     }
 }
 
+/// Remove a dropped player's character once the grace period passes without
+/// them resuming
+async fn expire_session(
+    player_id: u32,
+    connection: u64,
+    sessions: SharedSessions,
+    inbound: Option<UnboundedSender<InboundEvent>>,
+    grace: Duration,
+) {
+    tokio::time::sleep(grace).await;
+    if sessions.lock().unwrap().expire(player_id, connection) {
+        info!("Player {} did not reconnect in time", player_id);
+        notify(&inbound, InboundEvent::PlayerLeft { player_id });
+    }
+}
+
+/// Forward messages read from a TCP connection until it closes
+async fn read_inbound(
+    mut reader: FrameReader<OwnedReadHalf>,
+    addr: SocketAddr,
+    incoming: UnboundedSender<ClientMessage>,
+) {
+    loop {
+        match reader.read_message::<ClientMessage>().await {
+            Ok(Some(message)) => {
+                if incoming.send(message).is_err() {
+                    return;
+                }
+            }
+            Ok(None) => return,
+            Err(e) => {
+                error!("Error reading from {}: {}", addr, e);
+                return;
+            }
+        }
+    }
+}
+
+/// Drain a connection's outbound queue onto the socket
+async fn write_outbound(
+    mut writer: FrameWriter<OwnedWriteHalf>,
+    mut outbound: UnboundedReceiver<ServerMessage>,
+) {
+    while let Some(message) = outbound.recv().await {
+        if let Err(e) = writer.write_message(&message).await {
+            debug!("Stopping writer: {}", e);
+            return;
+        }
+    }
+    let _ = writer.shutdown().await;
+}
+
+fn notify(inbound: &Option<UnboundedSender<InboundEvent>>, event: InboundEvent) {
+    if let Some(inbound) = inbound {
+        // The simulation may already be shut down
+        let _ = inbound.send(event);
+    }
+}
+
+/// Tell the client why it is refused; the transport closes once the link is dropped
+fn refuse(link: &ClientLink, reason: DisconnectReason) {
+    let _ = link.outgoing.send(ServerMessage::Disconnect { reason });
+}
+
 impl Default for NetworkServer {
     fn default() -> Self {
         Self::new("127.0.0.1", 7777)
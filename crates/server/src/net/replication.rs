@@ -0,0 +1,117 @@
+/// Per-client snapshot history and delta encoding
+///
+/// Every client has a ring of the snapshots recently sent to it. Updates are
+/// encoded as deltas against the newest snapshot the client acknowledged that
+/// is still in its ring; without one (just joined, or acks stopped arriving
+/// for longer than the ring covers) a full snapshot is sent instead. Snapshots
+/// are shared between clients through `Arc`, so the rings only cost pointers.
+use engine::net_proto::{ServerMessage, SnapshotDelta, StateSnapshot};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+
+/// Snapshots kept per client, a bit over one second at 60 Hz
+pub const SNAPSHOT_HISTORY_LEN: usize = 64;
+
+/// Bandwidth accounting for snapshot replication
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplicationStats {
+    pub full_snapshots: u64,
+    pub delta_snapshots: u64,
+    /// Encoded size of everything actually sent
+    pub bytes_sent: u64,
+    /// Encoded size had every update been a full snapshot
+    pub bytes_full: u64,
+}
+
+impl ReplicationStats {
+    pub fn bytes_saved(&self) -> u64 {
+        self.bytes_full.saturating_sub(self.bytes_sent)
+    }
+}
+
+/// Snapshots sent to one client and the newest one it acknowledged
+#[derive(Debug, Default)]
+struct ClientHistory {
+    sent: VecDeque<Arc<StateSnapshot>>,
+    acked_tick: Option<u32>,
+}
+
+impl ClientHistory {
+    fn baseline(&self) -> Option<&StateSnapshot> {
+        let acked = self.acked_tick?;
+        self.sent
+            .iter()
+            .find(|snapshot| snapshot.tick == acked)
+            .map(|snapshot| snapshot.as_ref())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Replication {
+    clients: BTreeMap<u32, ClientHistory>,
+    stats: ReplicationStats,
+}
+
+impl Replication {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stats(&self) -> ReplicationStats {
+        self.stats
+    }
+
+    /// Record that a client received the snapshot for `tick`
+    ///
+    /// Acks for snapshots never sent, or older than one already acknowledged,
+    /// are ignored.
+    pub fn acknowledge(&mut self, player_id: u32, tick: u32) {
+        let Some(client) = self.clients.get_mut(&player_id) else {
+            return;
+        };
+        let known = client.sent.iter().any(|snapshot| snapshot.tick == tick);
+        // Compared through the wrap, as the tick counter eventually overflows
+        let newer = |acked: u32| tick.wrapping_sub(acked) as i32 > 0;
+        if known && client.acked_tick.is_none_or(newer) {
+            client.acked_tick = Some(tick);
+        }
+    }
+
+    /// Forget a client, e.g. when the player leaves
+    pub fn remove_client(&mut self, player_id: u32) {
+        self.clients.remove(&player_id);
+    }
+
+    /// Build the message carrying `snapshot` for one client and remember it was sent
+    ///
+    /// `full_size` is the encoded size of the full snapshot, used for the stats.
+    pub fn encode_for(
+        &mut self,
+        player_id: u32,
+        snapshot: &Arc<StateSnapshot>,
+        full_size: u64,
+    ) -> ServerMessage {
+        let client = self.clients.entry(player_id).or_default();
+
+        let message = match client.baseline() {
+            Some(baseline) => {
+                let delta = SnapshotDelta::between(baseline, snapshot);
+                self.stats.delta_snapshots += 1;
+                self.stats.bytes_sent += bincode::serialized_size(&delta).unwrap_or(full_size);
+                ServerMessage::StateDelta(delta)
+            }
+            None => {
+                self.stats.full_snapshots += 1;
+                self.stats.bytes_sent += full_size;
+                ServerMessage::StateUpdate(snapshot.as_ref().clone())
+            }
+        };
+        self.stats.bytes_full += full_size;
+
+        if client.sent.len() == SNAPSHOT_HISTORY_LEN {
+            client.sent.pop_front();
+        }
+        client.sent.push_back(snapshot.clone());
+        message
+    }
+}
@@ -0,0 +1,274 @@
+/// Connected player sessions
+use engine::net_proto::{DisconnectReason, ServerMessage};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::mpsc::UnboundedSender;
+
+/// A player that completed the connection handshake
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub player_id: u32,
+    pub player_name: String,
+    pub addr: SocketAddr,
+    /// Outbound queue drained by the connection's writer task
+    pub sender: UnboundedSender<ServerMessage>,
+    /// Secret the client presents to resume this session after a dropped
+    /// connection; replaced on every resume
+    pub token: u64,
+    /// Identifies the connection serving this session, which changes when a
+    /// client resumes it from a new connection
+    pub connection: u64,
+    /// Smoothed round-trip time, once the first ping was answered
+    pub rtt: Option<Duration>,
+    /// Mean deviation of the round-trip time
+    pub jitter: Duration,
+}
+
+/// A session whose connection dropped, waiting for its player to come back
+#[derive(Debug, Clone)]
+pub struct SuspendedSession {
+    pub session: Session,
+    pub since: Instant,
+}
+
+/// Table of active sessions, keyed by player ID
+///
+/// Player IDs are handed out monotonically and never reused while the server
+/// is running, so a stale ID can never address a newer player. Sessions whose
+/// connection dropped are suspended rather than removed, so the player can
+/// resume them with their token.
+#[derive(Debug)]
+pub struct SessionTable {
+    sessions: HashMap<u32, Session>,
+    suspended: HashMap<u32, SuspendedSession>,
+    next_player_id: u32,
+    next_connection: u64,
+    /// Keyed per table, so tokens cannot be predicted from player IDs
+    token_hasher: RandomState,
+    /// Last tick the simulation ran, reported in pongs
+    current_tick: u32,
+}
+
+/// Session table shared between connection tasks
+pub type SharedSessions = Arc<Mutex<SessionTable>>;
+
+impl SessionTable {
+    pub fn new() -> Self {
+        Self {
+            sessions: HashMap::new(),
+            suspended: HashMap::new(),
+            next_player_id: 1,
+            next_connection: 1,
+            token_hasher: RandomState::new(),
+            current_tick: 0,
+        }
+    }
+
+    /// Register a new session and return its freshly assigned player ID
+    pub fn register(
+        &mut self,
+        player_name: &str,
+        addr: SocketAddr,
+        sender: UnboundedSender<ServerMessage>,
+    ) -> u32 {
+        let player_id = self.next_player_id;
+        self.next_player_id += 1;
+        let (token, connection) = self.next_connection(player_id);
+        self.sessions.insert(
+            player_id,
+            Session {
+                player_id,
+                player_name: player_name.to_string(),
+                addr,
+                sender,
+                token,
+                connection,
+                rtt: None,
+                jitter: Duration::ZERO,
+            },
+        );
+        player_id
+    }
+
+    /// Hand a session back to the client holding `token` on a new connection
+    ///
+    /// Works for suspended sessions and for live ones whose connection the
+    /// server has not noticed dropping yet; the old connection then finds it
+    /// was replaced. Returns the player ID, or `None` for unknown tokens.
+    pub fn resume(
+        &mut self,
+        token: u64,
+        addr: SocketAddr,
+        sender: UnboundedSender<ServerMessage>,
+    ) -> Option<u32> {
+        let player_id = self
+            .sessions
+            .values()
+            .map(|session| (session.player_id, session.token))
+            .chain(
+                self.suspended
+                    .values()
+                    .map(|suspended| (suspended.session.player_id, suspended.session.token)),
+            )
+            .find(|&(_, session_token)| session_token == token)?
+            .0;
+        let mut session = match self.suspended.remove(&player_id) {
+            Some(suspended) => suspended.session,
+            None => self.sessions.remove(&player_id)?,
+        };
+        (session.token, session.connection) = self.next_connection(player_id);
+        session.addr = addr;
+        session.sender = sender;
+        self.sessions.insert(player_id, session);
+        Some(player_id)
+    }
+
+    /// Keep a session whose connection dropped so its player can resume it
+    ///
+    /// Does nothing unless `connection` still serves the session.
+    pub fn suspend(&mut self, player_id: u32, connection: u64) -> bool {
+        if !self.is_current(player_id, connection) {
+            return false;
+        }
+        let Some(session) = self.sessions.remove(&player_id) else {
+            return false;
+        };
+        self.suspended.insert(
+            player_id,
+            SuspendedSession {
+                session,
+                since: Instant::now(),
+            },
+        );
+        true
+    }
+
+    /// End a suspended session for good if it was not resumed since
+    /// `connection` dropped; returns whether it was
+    pub fn expire(&mut self, player_id: u32, connection: u64) -> bool {
+        let expired = self
+            .suspended
+            .get(&player_id)
+            .is_some_and(|suspended| suspended.session.connection == connection);
+        if expired {
+            self.suspended.remove(&player_id);
+        }
+        expired
+    }
+
+    pub fn suspended(&self, player_id: u32) -> Option<&SuspendedSession> {
+        self.suspended.get(&player_id)
+    }
+
+    /// Whether `connection` still serves the player's session, i.e. it was
+    /// neither kicked, suspended nor resumed elsewhere
+    pub fn is_current(&self, player_id: u32, connection: u64) -> bool {
+        self.sessions
+            .get(&player_id)
+            .is_some_and(|session| session.connection == connection)
+    }
+
+    pub fn remove(&mut self, player_id: u32) -> Option<Session> {
+        self.suspended.remove(&player_id);
+        self.sessions.remove(&player_id)
+    }
+
+    fn next_connection(&mut self, player_id: u32) -> (u64, u64) {
+        let connection = self.next_connection;
+        self.next_connection += 1;
+        let token = self
+            .token_hasher
+            .hash_one((player_id, connection, SystemTime::now()));
+        (token, connection)
+    }
+
+    pub fn get(&self, player_id: u32) -> Option<&Session> {
+        self.sessions.get(&player_id)
+    }
+
+    /// Record a player's latest round-trip estimate
+    pub fn record_rtt(&mut self, player_id: u32, rtt: Duration, jitter: Duration) {
+        if let Some(session) = self.sessions.get_mut(&player_id) {
+            session.rtt = Some(rtt);
+            session.jitter = jitter;
+        }
+    }
+
+    /// Smoothed round-trip time to a player, once measured
+    pub fn rtt(&self, player_id: u32) -> Option<Duration> {
+        self.sessions
+            .get(&player_id)
+            .and_then(|session| session.rtt)
+    }
+
+    pub fn current_tick(&self) -> u32 {
+        self.current_tick
+    }
+
+    pub fn set_current_tick(&mut self, tick: u32) {
+        self.current_tick = tick;
+    }
+
+    pub fn contains(&self, player_id: u32) -> bool {
+        self.sessions.contains_key(&player_id)
+    }
+
+    /// Tell a player why they are removed and end their session
+    ///
+    /// Returns false if they were already gone.
+    pub fn kick(&mut self, player_id: u32, reason: DisconnectReason) -> bool {
+        let Some(session) = self.sessions.remove(&player_id) else {
+            return false;
+        };
+        let _ = session.sender.send(ServerMessage::Disconnect { reason });
+        true
+    }
+
+    /// Tell every player why they are removed and end all sessions,
+    /// including suspended ones
+    pub fn disconnect_all(&mut self, reason: DisconnectReason) {
+        self.suspended.clear();
+        for (_, session) in self.sessions.drain() {
+            let _ = session.sender.send(ServerMessage::Disconnect {
+                reason: reason.clone(),
+            });
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Session> {
+        self.sessions.values()
+    }
+
+    /// Queue a message for one player; returns false if they are gone
+    pub fn send_to(&self, player_id: u32, message: ServerMessage) -> bool {
+        self.sessions
+            .get(&player_id)
+            .is_some_and(|session| session.sender.send(message).is_ok())
+    }
+
+    /// Queue a message for every connected player
+    pub fn broadcast(&self, message: &ServerMessage) {
+        for session in self.sessions.values() {
+            // A closed queue means the connection is being torn down
+            let _ = session.sender.send(message.clone());
+        }
+    }
+}
+
+impl Default for SessionTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
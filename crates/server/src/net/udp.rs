@@ -0,0 +1,208 @@
+/// Datagram transport: one socket shared by every client
+///
+/// Each remote address gets its own `Endpoint`. The first valid packet from a
+/// new address opens a connection and hands a `ClientLink` to the session
+/// code; messages the session queues are sent on the channel their type asks
+/// for. When a session ends its connection lingers until the peer has acked
+/// every reliable message (such as a refusal) or `LINGER` runs out. With
+/// network conditions set, all datagrams pass through a simulator in each
+/// direction.
+use super::ClientLink;
+use engine::config::NetworkConditions;
+use engine::net_proto::{
+    decode_message, encode_message, ClientMessage, Endpoint, LinkSimulator, ServerMessage,
+};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tracing::{debug, warn};
+
+/// How often acks and reliable resends are flushed
+const FLUSH_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Longest a closed connection waits for its reliable messages to be acked
+const LINGER: Duration = Duration::from_secs(1);
+
+struct Connection {
+    endpoint: Endpoint,
+    incoming: UnboundedSender<ClientMessage>,
+    /// When the session ended, if it did
+    closed_at: Option<Instant>,
+}
+
+/// Socket state shared by all connections
+struct Driver {
+    socket: UdpSocket,
+    connections: HashMap<SocketAddr, Connection>,
+    uplink: LinkSimulator<(SocketAddr, Vec<u8>)>,
+    downlink: LinkSimulator<(SocketAddr, Vec<u8>)>,
+    /// Messages queued by sessions; `None` marks the end of a session
+    outgoing: UnboundedSender<(SocketAddr, Option<ServerMessage>)>,
+}
+
+/// Run the socket forever, calling `accept` for every new connection
+pub(super) async fn serve(
+    socket: UdpSocket,
+    conditions: NetworkConditions,
+    mut accept: impl FnMut(ClientLink),
+) -> anyhow::Result<()> {
+    let (outgoing, mut outgoing_rx) = mpsc::unbounded_channel();
+    let mut driver = Driver {
+        socket,
+        connections: HashMap::new(),
+        downlink: LinkSimulator::datagrams(NetworkConditions {
+            seed: conditions.seed.wrapping_add(1),
+            ..conditions.clone()
+        }),
+        uplink: LinkSimulator::datagrams(conditions),
+        outgoing,
+    };
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+
+    loop {
+        let wake = [driver.uplink.next_release(), driver.downlink.next_release()]
+            .into_iter()
+            .flatten()
+            .min()
+            .map(tokio::time::Instant::from_std);
+        tokio::select! {
+            received = driver.socket.recv_from(&mut buffer) => match received {
+                Ok((len, addr)) => {
+                    driver.downlink.send(Instant::now(), (addr, buffer[..len].to_vec()), len);
+                }
+                Err(e) => debug!("UDP receive failed: {}", e),
+            },
+            Some((addr, message)) = outgoing_rx.recv() => {
+                driver.queue(addr, message);
+            }
+            _ = flush.tick() => {
+                let addrs: Vec<_> = driver.connections.keys().copied().collect();
+                for addr in addrs {
+                    driver.transmit(addr);
+                }
+                driver.close_finished();
+            }
+            _ = tokio::time::sleep_until(wake.unwrap_or_else(tokio::time::Instant::now)), if wake.is_some() => {}
+        }
+        driver.deliver(&mut accept);
+        driver.release().await;
+    }
+}
+
+impl Driver {
+    fn queue(&mut self, addr: SocketAddr, message: Option<ServerMessage>) {
+        let Some(connection) = self.connections.get_mut(&addr) else {
+            return;
+        };
+        match message {
+            Some(message) => {
+                let queued = encode_message(&message)
+                    .and_then(|payload| connection.endpoint.send(message.channel(), payload));
+                if let Err(e) = queued {
+                    warn!("Dropping message to {}: {}", addr, e);
+                }
+                self.transmit(addr);
+            }
+            None => connection.closed_at = Some(Instant::now()),
+        }
+    }
+
+    fn transmit(&mut self, addr: SocketAddr) {
+        let Some(connection) = self.connections.get_mut(&addr) else {
+            return;
+        };
+        let now = Instant::now();
+        for datagram in connection.endpoint.poll_transmit(now) {
+            let len = datagram.len();
+            self.uplink.send(now, (addr, datagram), len);
+        }
+    }
+
+    fn close_finished(&mut self) {
+        self.connections.retain(|addr, connection| {
+            let finished = connection.closed_at.is_some_and(|closed| {
+                connection.endpoint.unacked_reliable() == 0 || closed.elapsed() > LINGER
+            });
+            if finished {
+                debug!("Closed UDP connection to {}", addr);
+            }
+            !finished
+        });
+    }
+
+    /// Send datagrams whose simulated departure has come
+    async fn release(&mut self) {
+        for (addr, datagram) in self.uplink.poll(Instant::now()) {
+            if let Err(e) = self.socket.send_to(&datagram, addr).await {
+                debug!("UDP send to {} failed: {}", addr, e);
+            }
+        }
+    }
+
+    /// Process datagrams whose simulated arrival has come
+    fn deliver(&mut self, accept: &mut impl FnMut(ClientLink)) {
+        for (addr, datagram) in self.downlink.poll(Instant::now()) {
+            let now = Instant::now();
+            if !self.connections.contains_key(&addr) {
+                let mut endpoint = Endpoint::new();
+                let Ok(delivered) = endpoint.receive(&datagram, now) else {
+                    debug!("Ignoring stray datagram from {}", addr);
+                    continue;
+                };
+                let connection = self.open(addr, endpoint, accept);
+                forward(connection, addr, delivered);
+                continue;
+            }
+
+            let connection = self.connections.get_mut(&addr).unwrap();
+            match connection.endpoint.receive(&datagram, now) {
+                Ok(delivered) => forward(connection, addr, delivered),
+                Err(e) => debug!("Bad datagram from {}: {}", addr, e),
+            }
+        }
+    }
+
+    fn open(
+        &mut self,
+        addr: SocketAddr,
+        endpoint: Endpoint,
+        accept: &mut impl FnMut(ClientLink),
+    ) -> &Connection {
+        let (incoming_tx, incoming) = mpsc::unbounded_channel();
+        let (outgoing, mut session_rx) = mpsc::unbounded_channel();
+        let forward = self.outgoing.clone();
+        tokio::spawn(async move {
+            while let Some(message) = session_rx.recv().await {
+                if forward.send((addr, Some(message))).is_err() {
+                    return;
+                }
+            }
+            let _ = forward.send((addr, None));
+        });
+        accept(ClientLink {
+            addr,
+            incoming,
+            outgoing,
+        });
+        self.connections.entry(addr).or_insert(Connection {
+            endpoint,
+            incoming: incoming_tx,
+            closed_at: None,
+        })
+    }
+}
+
+fn forward(connection: &Connection, addr: SocketAddr, payloads: Vec<Vec<u8>>) {
+    for payload in payloads {
+        match decode_message::<ClientMessage>(&payload) {
+            // The session may already be over
+            Ok(message) => {
+                let _ = connection.incoming.send(message);
+            }
+            Err(e) => debug!("Undecodable message from {}: {}", addr, e),
+        }
+    }
+}
@@ -0,0 +1,85 @@
+/// Player hitbox history for lag-compensated hit detection
+///
+/// A client aims at remote players as it renders them: its inputs reach the
+/// server half a round trip late, and the players it sees are already half a
+/// round trip old plus its interpolation delay. To judge a shot fairly the
+/// server keeps every character's state for the last few ticks and rewinds the
+/// targets to the tick the shooter saw before casting the ray. The window is
+/// capped so a client cannot claim arbitrarily old positions by faking lag.
+use engine::physics_core::CharacterState;
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+
+/// Character states of every player at one tick
+#[derive(Debug, Clone)]
+struct HistoryFrame {
+    tick: u32,
+    players: BTreeMap<u32, CharacterState>,
+}
+
+/// Character states of the last `capacity` ticks, oldest first
+#[derive(Debug, Clone)]
+pub struct HitboxHistory {
+    frames: VecDeque<HistoryFrame>,
+    capacity: usize,
+}
+
+impl HitboxHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            frames: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// History long enough to rewind `max_rewind` at `tick_duration` per tick
+    pub fn for_window(max_rewind: Duration, tick_duration: Duration) -> Self {
+        let ticks = max_rewind.as_secs_f64() / tick_duration.as_secs_f64().max(f64::EPSILON);
+        Self::new(ticks.ceil() as usize + 1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Remember the states at `tick`, dropping the oldest tick once full
+    pub fn record(&mut self, tick: u32, players: impl IntoIterator<Item = (u32, CharacterState)>) {
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(HistoryFrame {
+            tick,
+            players: players.into_iter().collect(),
+        });
+    }
+
+    /// States at `tick`, or at the oldest tick kept if `tick` is older
+    ///
+    /// Returns `None` before anything was recorded or for ticks not reached yet.
+    pub fn at(&self, tick: u32) -> Option<(u32, &BTreeMap<u32, CharacterState>)> {
+        let newest = self.frames.back()?;
+        let back = newest.tick.wrapping_sub(tick) as usize;
+        if back > u32::MAX as usize / 2 {
+            return None;
+        }
+        let index = self.frames.len().saturating_sub(1 + back);
+        let frame = &self.frames[index];
+        Some((frame.tick, &frame.players))
+    }
+}
+
+/// Number of ticks to rewind for a shooter with round trip `rtt` who renders
+/// `interpolation_delay` behind the server, capped at `max_rewind`
+pub fn rewind_ticks(
+    rtt: Duration,
+    interpolation_delay: Duration,
+    tick_duration: Duration,
+    max_rewind: Duration,
+) -> u32 {
+    let behind = (rtt + interpolation_delay).min(max_rewind);
+    (behind.as_secs_f64() / tick_duration.as_secs_f64().max(f64::EPSILON)).round() as u32
+}
@@ -1,49 +1,388 @@
-use engine::net_proto::PlayerInput;
+use engine::config::{MovementConfig, ServerConfig};
+use engine::glam::{Quat, Vec3};
+use engine::net_proto::{EntityKind, EntityState, PlayerInput, PlayerUpdate};
 /// Authoritative server physics simulation
-use engine::physics_core::PhysicsWorld;
+use engine::physics_core::{
+    BodyKind, Character, CharacterController, CharacterState, EntityId, PhysicsEvent, PhysicsWorld,
+    QueryOptions, CHARACTER_MASS,
+};
+use engine::spells::{SpellDef, SpellId};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+pub mod lag_compensation;
+
+pub use lag_compensation::{rewind_ticks, HitboxHistory};
+
+/// Where newly joined players appear
+pub const SPAWN_POINT: Vec3 = Vec3::new(0.0, 0.0, 0.0);
+
+/// A hitscan ray fired by a player, checked against rewound targets
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HitscanHit {
+    pub shooter: u32,
+    /// Player hit, if the ray stopped at a character rather than the level
+    pub target: Option<u32>,
+    pub entity: Option<EntityId>,
+    pub point: Vec3,
+    pub distance: f32,
+    /// Tick the other players were rewound to
+    pub tick: u32,
+}
+
+/// Who cast a projectile in flight, and with which input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpellProjectile {
+    pub owner: u32,
+    pub spell_id: SpellId,
+    /// `PlayerInput::sequence` of the input that started the cast
+    pub cast_sequence: u32,
+}
+
+/// A player's character and the last of their inputs it has processed
+struct Player {
+    character: Character,
+    last_input_sequence: Option<u32>,
+}
 
 pub struct AuthoritativePhysics {
     world: PhysicsWorld,
+    controller: CharacterController,
+    players: BTreeMap<u32, Player>,
+    delta_time: f32,
+    history: HitboxHistory,
+    /// Spell projectiles, kept one step after they leave the world so the
+    /// hits of the step that removed them can still be resolved
+    projectiles: BTreeMap<EntityId, SpellProjectile>,
 }
 
 impl AuthoritativePhysics {
     pub fn new() -> Self {
+        Self::with_config(MovementConfig::default(), 1.0 / 60.0)
+    }
+
+    /// Create the simulation with server-owned movement constants
+    ///
+    /// `delta_time` is the duration each player input covers (one tick).
+    pub fn with_config(movement: MovementConfig, delta_time: f32) -> Self {
+        let mut world = PhysicsWorld::new();
+        // TODO: Load level geometry instead of a bare floor
+        world.add_ground_plane(0.0);
+
         Self {
-            world: PhysicsWorld::new(),
+            world,
+            controller: CharacterController::new(movement),
+            players: BTreeMap::new(),
+            delta_time,
+            history: HitboxHistory::for_window(
+                Duration::from_millis(ServerConfig::default().max_rewind_ms as u64),
+                Duration::from_secs_f32(delta_time),
+            ),
+            projectiles: BTreeMap::new(),
         }
     }
 
+    /// Keep enough hitbox history to rewind up to `max_rewind`
+    ///
+    /// Clears the history recorded so far.
+    pub fn set_max_rewind(&mut self, max_rewind: Duration) {
+        self.history =
+            HitboxHistory::for_window(max_rewind, Duration::from_secs_f32(self.delta_time));
+    }
+
+    pub fn history(&self) -> &HitboxHistory {
+        &self.history
+    }
+
+    /// Remember every player's hitbox at `tick`, after its step
+    pub fn record_history(&mut self, tick: u32) {
+        self.history.record(
+            tick,
+            self.players
+                .iter()
+                .map(|(player_id, player)| (*player_id, player.character.state)),
+        );
+    }
+
+    /// Fire a ray from a player's eyes along their aim, with every other
+    /// player rewound to where they were at `tick`
+    ///
+    /// Ticks older than the history reach are clamped to the oldest one kept.
+    /// Targets are put back before returning. Returns `None` if the shooter
+    /// has no character or the ray hits nothing within `range`.
+    pub fn hitscan(&mut self, shooter: u32, tick: u32, range: f32) -> Option<HitscanHit> {
+        let shooter_character = &self.players.get(&shooter)?.character;
+        let state = shooter_character.state;
+        let options = QueryOptions::excluding(shooter_character.entity);
+        let origin = self.controller.eye_position(&state);
+        let direction = state.aim();
+
+        let (rewound_tick, past) = match self.history.at(tick) {
+            Some((rewound_tick, past)) => (rewound_tick, past.clone()),
+            None => (tick, BTreeMap::new()),
+        };
+        let rewind = |world: &mut PhysicsWorld, current: bool| {
+            for (player_id, player) in &self.players {
+                let Some(past_state) = past.get(player_id) else {
+                    continue;
+                };
+                if *player_id == shooter {
+                    continue;
+                }
+                let state = if current {
+                    player.character.state
+                } else {
+                    *past_state
+                };
+                let mut character = player.character.clone();
+                self.controller.set_state(world, &mut character, state);
+            }
+        };
+        rewind(&mut self.world, false);
+        let hit = self.world.raycast(origin, direction, range, &options);
+        rewind(&mut self.world, true);
+
+        let hit = hit?;
+        let target = hit.entity.and_then(|entity| self.player_for_entity(entity));
+        Some(HitscanHit {
+            shooter,
+            target,
+            entity: hit.entity,
+            point: hit.point,
+            distance: hit.distance,
+            tick: rewound_tick,
+        })
+    }
+
+    /// Launch a spell's projectile from a player's eyes along their aim
+    ///
+    /// Returns `None` if the player has no character or the spell is not
+    /// delivered by projectile.
+    pub fn launch_projectile(
+        &mut self,
+        player_id: u32,
+        spell_id: SpellId,
+        cast_sequence: u32,
+        spell: &SpellDef,
+    ) -> Option<EntityId> {
+        let character = &self.players.get(&player_id)?.character;
+        let origin = self.controller.eye_position(&character.state);
+        let desc = spell.projectile(origin, character.state.aim(), Some(character.entity))?;
+        let entity = self.world.spawn_projectile(&desc).ok()?;
+        self.projectiles.insert(
+            entity,
+            SpellProjectile {
+                owner: player_id,
+                spell_id,
+                cast_sequence,
+            },
+        );
+        Some(entity)
+    }
+
+    /// Center of a player's area spell, `range` ahead of their eyes along
+    /// their aim, and the entities within `radius` of it except the player,
+    /// in ascending ID order
+    ///
+    /// Returns `None` if the player has no character.
+    pub fn area(&self, player_id: u32, radius: f32, range: f32) -> Option<(Vec3, Vec<EntityId>)> {
+        let character = &self.players.get(&player_id)?.character;
+        let center = self.controller.eye_position(&character.state) + character.state.aim() * range;
+        let mut entities =
+            self.world
+                .overlap_sphere(center, radius, &QueryOptions::excluding(character.entity));
+        entities.sort();
+        entities.dedup();
+        Some((center, entities))
+    }
+
+    /// Where a player's character sees and casts spells from
+    pub fn eye_position(&self, player_id: u32) -> Option<Vec3> {
+        let character = &self.players.get(&player_id)?.character;
+        Some(self.controller.eye_position(&character.state))
+    }
+
+    /// Spell projectile `entity`, including one removed during the last step
+    pub fn spell_projectile(&self, entity: EntityId) -> Option<&SpellProjectile> {
+        self.projectiles.get(&entity)
+    }
+
+    /// Player whose character is `entity`
+    pub fn entity_for_player(&self, player_id: u32) -> Option<EntityId> {
+        self.players
+            .get(&player_id)
+            .map(|player| player.character.entity)
+    }
+
+    pub fn player_for_entity(&self, entity: EntityId) -> Option<u32> {
+        self.players
+            .iter()
+            .find(|(_, player)| player.character.entity == entity)
+            .map(|(player_id, _)| *player_id)
+    }
+
+    pub fn world(&self) -> &PhysicsWorld {
+        &self.world
+    }
+
+    /// Direct access for level setup and server-spawned bodies
+    pub fn world_mut(&mut self) -> &mut PhysicsWorld {
+        &mut self.world
+    }
+
+    pub fn movement_config(&self) -> &MovementConfig {
+        self.controller.config()
+    }
+
     pub fn step(&mut self, delta_time: f32) {
+        let world = &self.world;
+        self.projectiles
+            .retain(|entity, _| world.projectile(*entity).is_some());
         self.world.step(delta_time);
     }
 
-    /// Process player input and calculate movement
-    /// Server authoritative - client only sends control inputs
-    pub fn process_input(&mut self, input: &PlayerInput) -> bool {
-        // TODO: Calculate movement vector from binary inputs
-        // Server controls physics constants (acceleration, max speed, etc.)
+    /// Take the collision and trigger events of the last step
+    pub fn drain_events(&mut self) -> Vec<PhysicsEvent> {
+        self.world.drain_events()
+    }
 
-        // Example: Convert binary inputs to movement direction
-        let mut _move_x = 0.0;
-        let mut _move_z = 0.0;
+    /// Spawn a character for a player that joined
+    pub fn spawn_player(&mut self, player_id: u32, position: Vec3) {
+        if let Some(old) = self.players.remove(&player_id) {
+            self.controller.despawn(&mut self.world, &old.character);
+        }
+        let character = self.controller.spawn(&mut self.world, position);
+        self.players.insert(
+            player_id,
+            Player {
+                character,
+                last_input_sequence: None,
+            },
+        );
+    }
 
-        if input.move_forward {
-            _move_z += 1.0;
+    /// Accept inputs numbered from scratch, e.g. from a client that reconnected
+    pub fn reset_input_sequence(&mut self, player_id: u32) {
+        if let Some(player) = self.players.get_mut(&player_id) {
+            player.last_input_sequence = None;
         }
-        if input.move_backward {
-            _move_z -= 1.0;
+    }
+
+    /// Slow a player's character down by its status effects, from 0 to 1
+    ///
+    /// The slow is part of the `CharacterState` replicated to its client, so
+    /// the client replays its inputs just as slowly.
+    pub fn set_slow(&mut self, player_id: u32, slow: f32) {
+        if let Some(player) = self.players.get_mut(&player_id) {
+            player.character.state.slow = slow.clamp(0.0, 1.0);
         }
-        if input.move_right {
-            _move_x += 1.0;
+    }
+
+    /// Push a player's character with an impulse (N·s)
+    ///
+    /// Characters are kinematic, so the impulse goes into the velocity of
+    /// their `CharacterState`, which their next inputs carry on from.
+    pub fn knock_back(&mut self, player_id: u32, impulse: Vec3) {
+        if let Some(player) = self.players.get_mut(&player_id) {
+            player.character.state.velocity += impulse / CHARACTER_MASS;
         }
-        if input.move_left {
-            _move_x -= 1.0;
+    }
+
+    /// Remove a player's character from the world
+    pub fn remove_player(&mut self, player_id: u32) {
+        if let Some(player) = self.players.remove(&player_id) {
+            self.controller.despawn(&mut self.world, &player.character);
         }
+    }
+
+    pub fn player_state(&self, player_id: u32) -> Option<&CharacterState> {
+        self.players
+            .get(&player_id)
+            .map(|player| &player.character.state)
+    }
 
-        // TODO: Apply movement to player entity in physics world
-        // TODO: Process look delta for camera rotation
-        // TODO: Handle jump, crouch, spell casting
+    /// Confirmed state and input acknowledgement of every player, in ID order
+    pub fn player_updates(&self) -> Vec<PlayerUpdate> {
+        self.players
+            .iter()
+            .map(|(player_id, player)| PlayerUpdate {
+                player_id: *player_id,
+                last_input_sequence: player.last_input_sequence,
+                state: player.character.state,
+            })
+            .collect()
+    }
+
+    /// Quantized state of every moving entity, in ascending ID order
+    ///
+    /// Fixed bodies are level geometry that clients load themselves.
+    pub fn entity_states(&self) -> Vec<EntityState> {
+        let players: BTreeMap<_, _> = self
+            .players
+            .iter()
+            .map(|(player_id, player)| {
+                (
+                    player.character.entity,
+                    (*player_id, &player.character.state),
+                )
+            })
+            .collect();
+
+        self.world
+            .entities()
+            .filter_map(|entity| {
+                if let Some((player_id, state)) = players.get(&entity) {
+                    return Some(EntityState::new(
+                        entity,
+                        EntityKind::Player {
+                            player_id: *player_id,
+                        },
+                        state.position,
+                        Quat::from_rotation_y(state.yaw),
+                        state.velocity,
+                    ));
+                }
+                if self.world.body_kind(entity)? == BodyKind::Fixed {
+                    return None;
+                }
+                let kind = match self.projectiles.get(&entity) {
+                    Some(projectile) => EntityKind::Projectile {
+                        owner: projectile.owner,
+                        spell_id: projectile.spell_id,
+                        cast_sequence: projectile.cast_sequence,
+                    },
+                    None => EntityKind::Prop,
+                };
+                let (position, rotation) = self.world.transform(entity)?;
+                let (velocity, _) = self.world.velocity(entity)?;
+                Some(EntityState::new(entity, kind, position, rotation, velocity))
+            })
+            .collect()
+    }
+
+    /// Process player input and calculate movement
+    /// Server authoritative - client only sends control inputs
+    ///
+    /// Returns false if the player has no character in the world or the
+    /// input is not newer than the last one processed.
+    pub fn process_input(&mut self, player_id: u32, input: &PlayerInput) -> bool {
+        let Some(player) = self.players.get_mut(&player_id) else {
+            return false;
+        };
+        if player
+            .last_input_sequence
+            .is_some_and(|last| input.sequence <= last)
+        {
+            return false;
+        }
 
+        self.controller.apply_input(
+            &mut self.world,
+            &mut player.character,
+            input,
+            self.delta_time,
+        );
+        player.last_input_sequence = Some(input.sequence);
         true
     }
 }
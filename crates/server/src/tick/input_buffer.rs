@@ -0,0 +1,193 @@
+/// Per-player input queues with validation and rate limiting
+///
+/// Inputs wait in a queue ordered by `sequence` until a tick applies them.
+/// Each tick a player earns one credit, banked up to `max_per_tick`, and every
+/// applied input spends one, so a client can catch up after a stall but never
+/// run faster than the server for long. Inputs that are malformed or could only
+/// come from a modified client add strikes, which fade slowly; players whose
+/// strikes reach `kick_threshold` are reported for kicking.
+use engine::config::InputLimits;
+use engine::net_proto::PlayerInput;
+use std::collections::BTreeMap;
+
+/// Strikes forgiven per tick, so rare glitches never add up to a kick
+const STRIKES_FORGIVEN_PER_TICK: f32 = 0.05;
+
+/// Why an input was not queued
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputRejection {
+    UnknownPlayer,
+    /// The same sequence is already queued
+    Duplicate,
+    /// Not newer than the last applied input, e.g. reordered on the way
+    Stale,
+    /// Too far ahead of the last applied input
+    OutOfWindow,
+}
+
+/// What happened to a player's inputs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InputCounters {
+    pub accepted: u64,
+    pub applied: u64,
+    pub duplicate: u64,
+    pub stale: u64,
+    pub out_of_window: u64,
+    /// Queued inputs dropped because the queue was full
+    pub overflow: u64,
+    /// Inputs whose look delta was not finite or over the limit
+    pub clamped: u64,
+}
+
+impl InputCounters {
+    /// Inputs rejected or dropped for any reason
+    pub fn rejected(&self) -> u64 {
+        self.duplicate + self.stale + self.out_of_window + self.overflow
+    }
+}
+
+#[derive(Debug, Default)]
+struct PlayerInputs {
+    queue: BTreeMap<u32, PlayerInput>,
+    last_applied: Option<u32>,
+    credits: u32,
+    strikes: f32,
+    counters: InputCounters,
+}
+
+impl PlayerInputs {
+    fn strike(&mut self) {
+        self.strikes += 1.0;
+    }
+}
+
+#[derive(Debug)]
+pub struct InputBuffer {
+    limits: InputLimits,
+    players: BTreeMap<u32, PlayerInputs>,
+}
+
+impl InputBuffer {
+    pub fn new(limits: InputLimits) -> Self {
+        Self {
+            limits,
+            players: BTreeMap::new(),
+        }
+    }
+
+    pub fn limits(&self) -> &InputLimits {
+        &self.limits
+    }
+
+    pub fn add_player(&mut self, player_id: u32) {
+        self.players.insert(
+            player_id,
+            PlayerInputs {
+                credits: self.limits.max_per_tick,
+                ..Default::default()
+            },
+        );
+    }
+
+    pub fn remove_player(&mut self, player_id: u32) {
+        self.players.remove(&player_id);
+    }
+
+    pub fn counters(&self, player_id: u32) -> Option<InputCounters> {
+        self.players.get(&player_id).map(|player| player.counters)
+    }
+
+    /// Inputs waiting for a tick
+    pub fn queued(&self, player_id: u32) -> usize {
+        self.players
+            .get(&player_id)
+            .map_or(0, |player| player.queue.len())
+    }
+
+    /// Validate an input and queue it, clamping its look delta if needed
+    pub fn push(&mut self, player_id: u32, mut input: PlayerInput) -> Result<(), InputRejection> {
+        let limits = &self.limits;
+        let player = self
+            .players
+            .get_mut(&player_id)
+            .ok_or(InputRejection::UnknownPlayer)?;
+
+        if player
+            .last_applied
+            .is_some_and(|last| input.sequence <= last)
+        {
+            player.counters.stale += 1;
+            return Err(InputRejection::Stale);
+        }
+        if player
+            .last_applied
+            .is_some_and(|last| input.sequence - last > limits.sequence_window)
+        {
+            player.counters.out_of_window += 1;
+            player.strike();
+            return Err(InputRejection::OutOfWindow);
+        }
+        if player.queue.contains_key(&input.sequence) {
+            player.counters.duplicate += 1;
+            return Err(InputRejection::Duplicate);
+        }
+
+        let max = limits.max_look_delta;
+        let clamp = |delta: f32| {
+            if delta.is_finite() {
+                delta.clamp(-max, max)
+            } else {
+                0.0
+            }
+        };
+        let (x, y) = (clamp(input.look_delta_x), clamp(input.look_delta_y));
+        if x != input.look_delta_x || y != input.look_delta_y {
+            input.look_delta_x = x;
+            input.look_delta_y = y;
+            player.counters.clamped += 1;
+            player.strike();
+        }
+
+        player.queue.insert(input.sequence, input);
+        player.counters.accepted += 1;
+        if player.queue.len() > limits.max_buffered.max(1) as usize {
+            // Sent faster than the server applies them
+            player.queue.pop_first();
+            player.counters.overflow += 1;
+            player.strike();
+        }
+        Ok(())
+    }
+
+    /// Take the inputs to apply this tick, oldest first, players in ID order
+    pub fn drain_tick(&mut self) -> Vec<(u32, PlayerInput)> {
+        let mut inputs = Vec::new();
+        for (player_id, player) in self.players.iter_mut() {
+            player.credits = (player.credits + 1).min(self.limits.max_per_tick.max(1));
+            player.strikes = (player.strikes - STRIKES_FORGIVEN_PER_TICK).max(0.0);
+            while player.credits > 0 {
+                let Some((sequence, input)) = player.queue.pop_first() else {
+                    break;
+                };
+                player.credits -= 1;
+                player.last_applied = Some(sequence);
+                player.counters.applied += 1;
+                inputs.push((*player_id, input));
+            }
+        }
+        inputs
+    }
+
+    /// Players whose strikes reached the kick threshold
+    pub fn players_to_kick(&self) -> Vec<u32> {
+        let threshold = self.limits.kick_threshold;
+        if threshold == 0 {
+            return Vec::new();
+        }
+        self.players
+            .iter()
+            .filter(|(_, player)| player.strikes >= threshold as f32)
+            .map(|(player_id, _)| *player_id)
+            .collect()
+    }
+}
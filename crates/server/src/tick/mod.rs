@@ -0,0 +1,648 @@
+/// Fixed-rate authoritative simulation loop
+///
+/// Each tick drains buffered player events, steps the physics world, runs the
+/// game systems and sends every session the new state, delta-compressed
+/// against the last snapshot that session acknowledged.
+use crate::game_logic::GameLogic;
+use crate::net::{InboundEvent, Replication, ReplicationStats, SharedSessions};
+use crate::physics::{
+    rewind_ticks, AuthoritativePhysics, HitscanHit, SpellProjectile, SPAWN_POINT,
+};
+use engine::config::{Config, InputLimits, MovementConfig, ServerConfig};
+use engine::glam::Vec3;
+use engine::net_proto::{
+    CastEvent, DisconnectReason, EntityKind, PlayerInput, ServerMessage, StateSnapshot,
+};
+use engine::physics_core::{EntityId, PhysicsEvent};
+use engine::spells::{Delivery, Effect, SpellDef, SpellId, SpellRegistry};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info, warn};
+
+pub mod input_buffer;
+
+pub use input_buffer::{InputBuffer, InputCounters, InputRejection};
+
+/// Accumulator turning wall-clock time into a whole number of ticks
+#[derive(Debug, Clone)]
+pub struct FixedTimestep {
+    tick_duration: Duration,
+    accumulator: Duration,
+    max_catch_up_ticks: u32,
+}
+
+impl FixedTimestep {
+    pub fn new(tick_rate: u32, max_catch_up_ticks: u32) -> Self {
+        Self {
+            tick_duration: Duration::from_nanos(1_000_000_000 / tick_rate.max(1) as u64),
+            accumulator: Duration::ZERO,
+            max_catch_up_ticks: max_catch_up_ticks.max(1),
+        }
+    }
+
+    pub fn tick_duration(&self) -> Duration {
+        self.tick_duration
+    }
+
+    /// Tick length in seconds, as passed to the simulation
+    pub fn delta_time(&self) -> f32 {
+        self.tick_duration.as_secs_f32()
+    }
+
+    /// Add elapsed time and return `(ticks_to_run, ticks_dropped)`
+    ///
+    /// At most `max_catch_up_ticks` are run at once; any further backlog is
+    /// discarded so a long stall cannot snowball into a death spiral.
+    pub fn advance(&mut self, elapsed: Duration) -> (u32, u32) {
+        self.accumulator += elapsed;
+
+        let due = (self.accumulator.as_nanos() / self.tick_duration.as_nanos()) as u32;
+        self.accumulator -= self.tick_duration * due;
+
+        if due > self.max_catch_up_ticks {
+            (self.max_catch_up_ticks, due - self.max_catch_up_ticks)
+        } else {
+            (due, 0)
+        }
+    }
+}
+
+/// Tick timing statistics
+#[derive(Debug, Clone, Default)]
+pub struct TickMetrics {
+    /// Ticks simulated so far
+    pub ticks: u64,
+    /// Ticks whose processing took longer than the tick duration
+    pub overruns: u64,
+    /// Ticks skipped because the catch-up limit was reached
+    pub dropped_ticks: u64,
+    pub last_tick_duration: Duration,
+    pub max_tick_duration: Duration,
+    /// Players disconnected for sending invalid inputs
+    pub kicked_players: u64,
+}
+
+/// A hitscan spell that reached something
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpellHit {
+    pub spell_id: SpellId,
+    pub hit: HitscanHit,
+}
+
+/// A spell projectile that hit something
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProjectileImpact {
+    pub projectile: EntityId,
+    pub spell: SpellProjectile,
+    /// Player hit, if the projectile struck a character
+    pub target: Option<u32>,
+    pub entity: EntityId,
+    pub position: Vec3,
+    /// Whether the impact stopped the projectile
+    pub stopped: bool,
+}
+
+/// Authoritative simulation state advanced once per tick
+pub struct Simulation {
+    tick: u32,
+    delta_time: f32,
+    physics: AuthoritativePhysics,
+    game_logic: GameLogic,
+    inputs: InputBuffer,
+    /// Furthest back a shot rewinds the other players
+    max_rewind: Duration,
+    /// Latest round-trip time of each player, for lag compensation
+    player_rtts: HashMap<u32, Duration>,
+    /// Latest interpolation delay each player reported, for lag compensation
+    interpolation_delays: HashMap<u32, u16>,
+    hits: Vec<SpellHit>,
+    impacts: Vec<ProjectileImpact>,
+}
+
+impl Simulation {
+    pub fn new(delta_time: f32, movement: MovementConfig) -> Self {
+        Self {
+            tick: 0,
+            delta_time,
+            physics: AuthoritativePhysics::with_config(movement, delta_time),
+            game_logic: GameLogic::new(),
+            inputs: InputBuffer::new(InputLimits::default()),
+            max_rewind: Duration::from_millis(ServerConfig::default().max_rewind_ms as u64),
+            player_rtts: HashMap::new(),
+            interpolation_delays: HashMap::new(),
+            hits: Vec::new(),
+            impacts: Vec::new(),
+        }
+    }
+
+    /// Let players cast the spells of `spells`
+    pub fn with_spells(mut self, spells: Arc<SpellRegistry>) -> Self {
+        self.game_logic.set_spells(spells);
+        self
+    }
+
+    /// Validate and rate limit player inputs with `limits`
+    pub fn with_input_limits(mut self, limits: InputLimits) -> Self {
+        self.inputs = InputBuffer::new(limits);
+        self
+    }
+
+    /// Limit how far back lag compensation rewinds targets
+    pub fn with_max_rewind(mut self, max_rewind: Duration) -> Self {
+        self.max_rewind = max_rewind;
+        self.physics.set_max_rewind(max_rewind);
+        self
+    }
+
+    /// Record a player's round-trip time, used to rewind their shots
+    pub fn set_player_rtt(&mut self, player_id: u32, rtt: Duration) {
+        self.player_rtts.insert(player_id, rtt);
+    }
+
+    /// Queued inputs and what was rejected, per player
+    pub fn inputs(&self) -> &InputBuffer {
+        &self.inputs
+    }
+
+    /// Players who sent enough invalid inputs to be kicked
+    pub fn players_to_kick(&self) -> Vec<u32> {
+        self.inputs.players_to_kick()
+    }
+
+    /// Hitscan results of spells that went off during the last tick
+    pub fn spell_hits(&self) -> &[SpellHit] {
+        &self.hits
+    }
+
+    /// Spell projectiles that hit something during the last tick
+    pub fn projectile_impacts(&self) -> &[ProjectileImpact] {
+        &self.impacts
+    }
+
+    /// Casts that started, completed or failed during the last tick
+    pub fn cast_events(&self) -> &[CastEvent] {
+        self.game_logic.cast_events()
+    }
+
+    /// Number of the last completed tick
+    pub fn current_tick(&self) -> u32 {
+        self.tick
+    }
+
+    pub fn physics(&self) -> &AuthoritativePhysics {
+        &self.physics
+    }
+
+    pub fn game_logic(&self) -> &GameLogic {
+        &self.game_logic
+    }
+
+    /// Buffer a player event until the next tick
+    pub fn handle_event(&mut self, event: InboundEvent) {
+        match event {
+            InboundEvent::PlayerJoined { player_id } => {
+                debug!("Player {} entered the simulation", player_id);
+                self.physics.spawn_player(player_id, SPAWN_POINT);
+                self.game_logic.spawn_player(player_id);
+                self.inputs.add_player(player_id);
+            }
+            InboundEvent::PlayerResumed { player_id } => {
+                // The character stayed put; the reconnected client numbers its
+                // inputs afresh
+                debug!("Player {} resumed control", player_id);
+                self.inputs.add_player(player_id);
+                self.physics.reset_input_sequence(player_id);
+            }
+            InboundEvent::Input { player_id, input } => {
+                let sequence = input.sequence;
+                if let Err(rejection) = self.inputs.push(player_id, input) {
+                    debug!(
+                        "Rejected input {} from player {}: {:?}",
+                        sequence, player_id, rejection
+                    );
+                }
+            }
+            // Replication state lives in the tick loop
+            InboundEvent::SnapshotAck { .. } => {}
+            InboundEvent::PlayerLeft { player_id } => {
+                debug!("Player {} left the simulation", player_id);
+                self.inputs.remove_player(player_id);
+                self.player_rtts.remove(&player_id);
+                self.interpolation_delays.remove(&player_id);
+                self.physics.remove_player(player_id);
+                self.game_logic.remove_player(player_id);
+            }
+        }
+    }
+
+    /// Advance the simulation by one tick and return the state to replicate
+    pub fn tick(&mut self) -> StateSnapshot {
+        for (player_id, mut input) in self.inputs.drain_tick() {
+            // The dead only keep acknowledging inputs, so their client stops
+            // replaying them
+            if self.game_logic.is_dead(player_id) {
+                input = PlayerInput {
+                    sequence: input.sequence,
+                    interpolation_delay_ms: input.interpolation_delay_ms,
+                    ..Default::default()
+                };
+            }
+            if self.physics.process_input(player_id, &input) {
+                self.interpolation_delays
+                    .insert(player_id, input.interpolation_delay_ms);
+                self.game_logic.queue_input(player_id, &input);
+            }
+        }
+
+        self.physics.step(self.delta_time);
+        self.tick = self.tick.wrapping_add(1);
+        self.physics.record_history(self.tick);
+        self.game_logic
+            .queue_physics_events(self.physics.drain_events());
+        self.game_logic.update(self.tick, self.delta_time);
+        self.resolve_spells();
+        self.game_logic
+            .update_surfaces(self.physics.world_mut(), self.tick, self.delta_time);
+        for (player_id, slow) in self.game_logic.slows() {
+            self.physics.set_slow(player_id, slow);
+        }
+
+        let mut snapshot = StateSnapshot::new(self.tick);
+        snapshot.entities = self.physics.entity_states();
+        snapshot.players = self.physics.player_updates();
+        snapshot.surfaces = self.game_logic.surface_updates();
+        for (player_id, active) in self.game_logic.active_spells(self.tick) {
+            if let Some(entity) = snapshot
+                .entities
+                .iter_mut()
+                .find(|entity| entity.kind == EntityKind::Player { player_id })
+            {
+                entity.active_spells.push(active);
+            }
+        }
+        for entity in &mut snapshot.entities {
+            if let EntityKind::Player { player_id } = entity.kind {
+                self.game_logic.write_vitals(player_id, entity);
+            }
+        }
+        snapshot
+    }
+
+    /// Apply the spells that went off this tick
+    ///
+    /// Hitscan spells are aimed at the other players as the caster saw them.
+    /// Projectile spells launch a body that hits whatever it reaches in later
+    /// ticks. Area spells reach everything around the point they are centered
+    /// on except the caster, and push players away from that point.
+    /// Self-cast spells affect only their caster. Whatever a spell reaches
+    /// also feels its element, see `game_logic::elements`. Damage they deal
+    /// interrupts the target's cast on the next tick.
+    fn resolve_spells(&mut self) {
+        self.hits.clear();
+        self.impacts.clear();
+        let spells = self.game_logic.spells().clone();
+        self.resolve_projectile_hits(&spells);
+
+        for (player_id, spell_id) in self.game_logic.released_spells().to_vec() {
+            let Some(spell) = spells.get(spell_id) else {
+                continue;
+            };
+            match spell.delivery {
+                Delivery::Hitscan { range } => self.cast_hitscan(player_id, spell_id, spell, range),
+                Delivery::Projectile { .. } => {
+                    let cast_sequence = self
+                        .game_logic
+                        .caster(player_id)
+                        .map_or(0, |caster| caster.cast_sequence());
+                    let projectile =
+                        self.physics
+                            .launch_projectile(player_id, spell_id, cast_sequence, spell);
+                    debug!(
+                        "Player {} cast {} as projectile {:?}",
+                        player_id, spell.name, projectile
+                    );
+                }
+                Delivery::Area { radius, range } => {
+                    let Some((center, entities)) = self.physics.area(player_id, radius, range)
+                    else {
+                        continue;
+                    };
+                    for entity in entities {
+                        if let Some(target) = self.physics.player_for_entity(entity) {
+                            let away = self
+                                .physics
+                                .eye_position(target)
+                                .map_or(Vec3::ZERO, |eyes| eyes - center);
+                            self.apply_effects(target, spell, away);
+                        }
+                        self.hit_surface(entity, spell);
+                    }
+                }
+                Delivery::SelfCast => self.apply_effects(player_id, spell, Vec3::ZERO),
+            }
+        }
+    }
+
+    /// Fire a hitscan spell with the other players rewound by the caster's lag
+    fn cast_hitscan(&mut self, player_id: u32, spell_id: SpellId, spell: &SpellDef, range: f32) {
+        let rtt = self
+            .player_rtts
+            .get(&player_id)
+            .copied()
+            .unwrap_or_default();
+        let interpolation_delay_ms = self
+            .interpolation_delays
+            .get(&player_id)
+            .copied()
+            .unwrap_or_default();
+        let rewind = rewind_ticks(
+            rtt,
+            Duration::from_millis(interpolation_delay_ms as u64),
+            Duration::from_secs_f32(self.delta_time),
+            self.max_rewind,
+        );
+        let target_tick = self.tick.wrapping_sub(rewind);
+        let Some(hit) = self.physics.hitscan(player_id, target_tick, range) else {
+            return;
+        };
+        debug!(
+            "Player {} cast {} at tick {} hit {:?} at {:?}",
+            player_id, spell.name, hit.tick, hit.target, hit.point
+        );
+        if let Some(target) = hit.target {
+            let aim = self
+                .physics
+                .player_state(player_id)
+                .map_or(Vec3::ZERO, |state| state.aim());
+            self.apply_effects(target, spell, aim);
+        }
+        if let Some(entity) = hit.entity {
+            self.hit_surface(entity, spell);
+        }
+        self.hits.push(SpellHit { spell_id, hit });
+    }
+
+    /// Apply the spell of every projectile that hit a player during this step
+    ///
+    /// Projectiles are not lag compensated: they hit wherever the targets
+    /// are on the server.
+    fn resolve_projectile_hits(&mut self, spells: &SpellRegistry) {
+        let hits: Vec<(EntityId, EntityId, Vec3, Vec3, bool)> = self
+            .game_logic
+            .physics_events()
+            .filter_map(|event| match *event {
+                PhysicsEvent::ProjectileHit {
+                    projectile,
+                    target,
+                    position,
+                    velocity,
+                    stopped,
+                } => Some((projectile, target, position, velocity, stopped)),
+                _ => None,
+            })
+            .collect();
+        for (projectile, entity, position, velocity, stopped) in hits {
+            let Some(&spell_projectile) = self.physics.spell_projectile(projectile) else {
+                continue;
+            };
+            let target = self.physics.player_for_entity(entity);
+            if let Some(spell) = spells.get(spell_projectile.spell_id) {
+                if let Some(target) = target {
+                    self.apply_effects(target, spell, velocity);
+                }
+                self.hit_surface(entity, spell);
+            }
+            self.impacts.push(ProjectileImpact {
+                projectile,
+                spell: spell_projectile,
+                target,
+                entity,
+                position,
+                stopped,
+            });
+        }
+    }
+
+    /// Apply a spell's element to what it reached, soaking or drying the
+    /// player it is; players a lightning strike jumps to take its damage too
+    fn hit_surface(&mut self, entity: EntityId, spell: &SpellDef) {
+        if let Some(player_id) = self.physics.player_for_entity(entity) {
+            self.game_logic
+                .hit_player(player_id, spell.element, self.tick, self.delta_time);
+        }
+        let soaked: BTreeSet<EntityId> = self
+            .game_logic
+            .wet_players()
+            .into_iter()
+            .filter_map(|player_id| self.physics.entity_for_player(player_id))
+            .collect();
+        let chain = self.game_logic.hit_surface(
+            self.physics.world_mut(),
+            entity,
+            spell.element,
+            &soaked,
+            self.tick,
+            self.delta_time,
+        );
+        for entity in chain {
+            if let Some(player_id) = self.physics.player_for_entity(entity) {
+                self.apply_effects(player_id, spell, Vec3::ZERO);
+            }
+        }
+    }
+
+    /// Apply a spell's effects to a player it reached
+    ///
+    /// Knockback pushes the player along `push`; players a spell reaches
+    /// without a direction, e.g. through a lightning chain, are not pushed.
+    fn apply_effects(&mut self, player_id: u32, spell: &SpellDef, push: Vec3) {
+        for effect in &spell.effects {
+            match *effect {
+                Effect::Damage { amount } => {
+                    self.game_logic
+                        .damage_player(player_id, amount, spell.element);
+                }
+                Effect::Heal { amount } => {
+                    self.game_logic.heal_player(player_id, amount);
+                }
+                Effect::Shield {
+                    amount,
+                    duration_ms,
+                } => self.game_logic.shield_player(
+                    player_id,
+                    amount,
+                    duration_ms,
+                    self.tick,
+                    self.delta_time,
+                ),
+                Effect::ApplyStatus {
+                    status,
+                    duration_ms,
+                    stacks,
+                } => {
+                    self.game_logic.apply_status(
+                        player_id,
+                        status,
+                        stacks,
+                        duration_ms,
+                        self.tick,
+                        self.delta_time,
+                    );
+                }
+                Effect::Knockback { impulse } => self
+                    .physics
+                    .knock_back(player_id, push.normalize_or_zero() * impulse),
+            }
+        }
+    }
+}
+
+/// Drives a `Simulation` at a fixed rate and broadcasts its state
+pub struct TickLoop {
+    simulation: Simulation,
+    timestep: FixedTimestep,
+    metrics: TickMetrics,
+    inbound: UnboundedReceiver<InboundEvent>,
+    sessions: SharedSessions,
+    replication: Replication,
+}
+
+impl TickLoop {
+    pub fn new(
+        config: &Config,
+        inbound: UnboundedReceiver<InboundEvent>,
+        sessions: SharedSessions,
+    ) -> Self {
+        let timestep =
+            FixedTimestep::new(config.server.tick_rate, config.server.max_catch_up_ticks);
+        Self {
+            simulation: Simulation::new(timestep.delta_time(), config.movement.clone())
+                .with_max_rewind(Duration::from_millis(config.server.max_rewind_ms as u64))
+                .with_input_limits(config.server.input.clone()),
+            timestep,
+            metrics: TickMetrics::default(),
+            inbound,
+            sessions,
+            replication: Replication::new(),
+        }
+    }
+
+    pub fn simulation(&self) -> &Simulation {
+        &self.simulation
+    }
+
+    pub fn metrics(&self) -> &TickMetrics {
+        &self.metrics
+    }
+
+    pub fn replication_stats(&self) -> ReplicationStats {
+        self.replication.stats()
+    }
+
+    /// Let players cast the spells of `spells`
+    pub fn with_spells(mut self, spells: Arc<SpellRegistry>) -> Self {
+        self.simulation = self.simulation.with_spells(spells);
+        self
+    }
+
+    /// Run ticks forever at the configured rate
+    pub async fn run(&mut self) {
+        info!(
+            "Tick loop running at {:.1} Hz",
+            1.0 / self.timestep.delta_time()
+        );
+
+        let mut interval = tokio::time::interval(self.timestep.tick_duration());
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut last = Instant::now();
+
+        loop {
+            interval.tick().await;
+            let now = Instant::now();
+            let (ticks, dropped) = self.timestep.advance(now - last);
+            last = now;
+
+            if dropped > 0 {
+                warn!("Server fell behind, dropped {} ticks", dropped);
+                self.metrics.dropped_ticks += dropped as u64;
+            }
+            for _ in 0..ticks {
+                self.run_tick();
+            }
+        }
+    }
+
+    /// Run a single tick immediately
+    pub fn run_tick(&mut self) {
+        let started = Instant::now();
+
+        for session in self.sessions.lock().unwrap().iter() {
+            if let Some(rtt) = session.rtt {
+                self.simulation.set_player_rtt(session.player_id, rtt);
+            }
+        }
+        while let Ok(event) = self.inbound.try_recv() {
+            match event {
+                InboundEvent::SnapshotAck { player_id, tick } => {
+                    self.replication.acknowledge(player_id, tick);
+                }
+                // A resumed client lost its baselines along with the connection
+                InboundEvent::PlayerLeft { player_id }
+                | InboundEvent::PlayerResumed { player_id } => {
+                    self.replication.remove_client(player_id);
+                    self.simulation.handle_event(event);
+                }
+                event => self.simulation.handle_event(event),
+            }
+        }
+        let snapshot = Arc::new(self.simulation.tick());
+        let full_size = bincode::serialized_size(snapshot.as_ref()).unwrap_or(0);
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.set_current_tick(snapshot.tick);
+        for player_id in self.simulation.players_to_kick() {
+            if sessions.kick(
+                player_id,
+                DisconnectReason::Kicked("too many invalid inputs".to_string()),
+            ) {
+                warn!(
+                    "Kicked player {}: {:?}",
+                    player_id,
+                    self.simulation.inputs().counters(player_id)
+                );
+                self.metrics.kicked_players += 1;
+            }
+        }
+        for session in sessions.iter() {
+            let message = self
+                .replication
+                .encode_for(session.player_id, &snapshot, full_size);
+            // A closed queue means the connection is being torn down
+            let _ = session.sender.send(message);
+        }
+        let cast_events = self.simulation.cast_events();
+        if !cast_events.is_empty() {
+            let message = ServerMessage::CastEvents(cast_events.to_vec());
+            for session in sessions.iter() {
+                let _ = session.sender.send(message.clone());
+            }
+        }
+        drop(sessions);
+
+        let elapsed = started.elapsed();
+        self.metrics.ticks += 1;
+        self.metrics.last_tick_duration = elapsed;
+        self.metrics.max_tick_duration = self.metrics.max_tick_duration.max(elapsed);
+        if elapsed > self.timestep.tick_duration() {
+            self.metrics.overruns += 1;
+            warn!(
+                "Tick {} overran: {:?} > {:?}",
+                self.simulation.current_tick(),
+                elapsed,
+                self.timestep.tick_duration()
+            );
+        }
+    }
+}
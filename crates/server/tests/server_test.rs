@@ -2,8 +2,1301 @@
 
 #[cfg(test)]
 mod tests {
+    use engine::config::{InputLimits, MovementConfig};
+    use engine::glam::Vec3;
+    use engine::net_proto::{ActiveSpell, EntityKind, HealthState, StateSnapshot, StatusState};
+    use engine::net_proto::{CastEventKind, CastRejection, InterruptReason};
+    use engine::net_proto::{PlayerInput, ServerMessage, BUILD_HASH, PROTOCOL_VERSION};
+    use engine::physics_core::{
+        BodyDesc, BodyKind, CharacterController, ColliderDesc, ColliderShape, EntityId, Material,
+        PhysicsEvent, PhysicsWorld, SurfaceState,
+    };
+    use engine::spells::{Element, SpellRegistry, StatusKind};
+    use server::game_logic::elements::{react, BURN_MS, FROZEN_MS, MAX_CHAIN};
+    use server::game_logic::vitals::deal_damage;
+    use server::game_logic::{
+        CastState, Caster, GameLogic, Health, Immunities, Mana, Shields, StatusEffects, Surfaces,
+        SPELL_SLOTS,
+    };
+    use server::net::{
+        validate_handshake, InboundEvent, Replication, SessionTable, SNAPSHOT_HISTORY_LEN,
+    };
+    use server::physics::{rewind_ticks, AuthoritativePhysics, SPAWN_POINT};
+    use server::tick::{FixedTimestep, InputBuffer, InputRejection, Simulation};
+    use std::collections::BTreeSet;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
     #[test]
     fn test_server_compiles() {
         assert!(true);
     }
+
+    #[test]
+    fn test_session_table_assigns_unique_ids() {
+        let mut sessions = SessionTable::new();
+        let addr = "127.0.0.1:5000".parse().unwrap();
+
+        let (tx, _rx) = mpsc::unbounded_channel();
+
+        let first = sessions.register("Alice", addr, tx.clone());
+        let second = sessions.register("Bob", addr, tx.clone());
+        assert_ne!(first, second);
+        assert_eq!(sessions.len(), 2);
+
+        // IDs are not reused after a player leaves
+        sessions.remove(first);
+        let third = sessions.register("Carol", addr, tx);
+        assert_ne!(third, first);
+        assert_eq!(sessions.get(third).unwrap().player_name, "Carol");
+    }
+
+    #[test]
+    fn test_session_table_suspends_and_resumes_sessions() {
+        let mut sessions = SessionTable::new();
+        let addr = "127.0.0.1:5000".parse().unwrap();
+        let (tx, _rx) = mpsc::unbounded_channel();
+
+        let player_id = sessions.register("Alice", addr, tx.clone());
+        let session = sessions.get(player_id).unwrap().clone();
+        assert!(sessions.is_current(player_id, session.connection));
+
+        // Only the connection serving the session can suspend it
+        assert!(!sessions.suspend(player_id, session.connection + 1));
+        assert!(sessions.suspend(player_id, session.connection));
+        assert!(!sessions.contains(player_id));
+        assert!(sessions.suspended(player_id).is_some());
+
+        assert_eq!(
+            sessions.resume(session.token.wrapping_add(1), addr, tx.clone()),
+            None
+        );
+        assert_eq!(
+            sessions.resume(session.token, addr, tx.clone()),
+            Some(player_id)
+        );
+        let resumed = sessions.get(player_id).unwrap().clone();
+        assert_ne!(resumed.token, session.token);
+        assert!(!sessions.is_current(player_id, session.connection));
+        assert!(sessions.is_current(player_id, resumed.connection));
+
+        // The old token is spent, and the old connection's expiry is void
+        assert_eq!(sessions.resume(session.token, addr, tx.clone()), None);
+        assert!(sessions.suspend(player_id, resumed.connection));
+        assert!(!sessions.expire(player_id, session.connection));
+        assert!(sessions.expire(player_id, resumed.connection));
+        assert!(sessions.suspended(player_id).is_none());
+        assert_eq!(sessions.resume(resumed.token, addr, tx), None);
+    }
+
+    #[test]
+    fn test_handshake_validation() {
+        assert!(validate_handshake(PROTOCOL_VERSION, BUILD_HASH).is_ok());
+        assert!(validate_handshake(PROTOCOL_VERSION + 1, BUILD_HASH).is_err());
+        assert!(validate_handshake(PROTOCOL_VERSION, "some-other-build").is_err());
+    }
+
+    #[test]
+    fn test_fixed_timestep_accumulates_partial_ticks() {
+        let mut timestep = FixedTimestep::new(60, 5);
+        let tick = timestep.tick_duration();
+
+        assert_eq!(timestep.advance(tick / 2), (0, 0));
+        assert_eq!(timestep.advance(tick / 2), (1, 0));
+        assert_eq!(timestep.advance(tick * 3), (3, 0));
+    }
+
+    #[test]
+    fn test_fixed_timestep_limits_catch_up() {
+        let mut timestep = FixedTimestep::new(60, 5);
+
+        // A one second stall would be 60 ticks; only 5 are run
+        assert_eq!(timestep.advance(Duration::from_secs(1)), (5, 55));
+        assert_eq!(timestep.advance(Duration::ZERO), (0, 0));
+    }
+
+    #[test]
+    fn test_simulation_tick_emits_state_snapshot() {
+        let mut simulation = Simulation::new(1.0 / 60.0, MovementConfig::default());
+
+        for expected in 1..=3 {
+            assert_eq!(simulation.tick().tick, expected);
+        }
+        assert_eq!(simulation.current_tick(), 3);
+    }
+
+    #[test]
+    fn test_game_logic_keeps_only_current_tick_physics_events() {
+        let mut game_logic = GameLogic::new();
+        let hit = PhysicsEvent::ContactStarted {
+            a: EntityId(1),
+            b: EntityId(2),
+        };
+        let exit = PhysicsEvent::TriggerExited {
+            sensor: EntityId(3),
+            other: EntityId(1),
+        };
+
+        game_logic.queue_physics_events(vec![hit, exit]);
+        assert_eq!(
+            game_logic.physics_events().copied().collect::<Vec<_>>(),
+            vec![hit, exit]
+        );
+
+        game_logic.queue_physics_events(Vec::new());
+        assert_eq!(game_logic.physics_events().count(), 0);
+    }
+
+    #[test]
+    fn test_physics_acknowledges_latest_input_only() {
+        let mut physics = AuthoritativePhysics::new();
+        physics.spawn_player(7, SPAWN_POINT);
+        assert_eq!(physics.player_updates()[0].last_input_sequence, None);
+
+        let input = |sequence| PlayerInput {
+            sequence,
+            move_forward: true,
+            ..Default::default()
+        };
+        assert!(physics.process_input(7, &input(1)));
+        assert!(physics.process_input(7, &input(3)));
+        // Stale and duplicate inputs are not applied again
+        assert!(!physics.process_input(7, &input(2)));
+        assert!(!physics.process_input(7, &input(3)));
+        assert!(!physics.process_input(8, &input(4)));
+
+        let updates = physics.player_updates();
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].player_id, 7);
+        assert_eq!(updates[0].last_input_sequence, Some(3));
+        assert_eq!(&updates[0].state, physics.player_state(7).unwrap());
+
+        // A reconnected client numbers its inputs from scratch
+        physics.reset_input_sequence(7);
+        assert!(physics.process_input(7, &input(1)));
+        assert_eq!(physics.player_updates()[0].last_input_sequence, Some(1));
+    }
+
+    #[test]
+    fn test_hitscan_rewinds_targets_to_the_tick_the_shooter_saw() {
+        let mut physics = AuthoritativePhysics::new();
+        physics.set_max_rewind(Duration::from_millis(500));
+        // The shooter faces +Z, towards the target
+        physics.spawn_player(1, SPAWN_POINT);
+        physics.spawn_player(2, SPAWN_POINT + Vec3::Z * 5.0);
+        physics.record_history(0);
+
+        // The target strafes out of the line of fire
+        for tick in 1..=30 {
+            let strafe = PlayerInput {
+                sequence: tick,
+                move_right: true,
+                ..Default::default()
+            };
+            physics.process_input(2, &strafe);
+            physics.step(1.0 / 60.0);
+            physics.record_history(tick);
+        }
+        let moved = *physics.player_state(2).unwrap();
+        assert!(moved.position.x > 1.0, "{:?}", moved.position);
+
+        assert_eq!(physics.hitscan(1, 30, 100.0), None);
+        let hit = physics.hitscan(1, 0, 100.0).expect("rewound target is hit");
+        assert_eq!((hit.shooter, hit.target, hit.tick), (1, Some(2), 0));
+        assert!((hit.distance - 5.0).abs() < 1.0, "{}", hit.distance);
+
+        // Ticks before the history reach clamp to the oldest one kept
+        let hit = physics.hitscan(1, u32::MAX - 10, 100.0).unwrap();
+        assert_eq!(hit.tick, 0);
+
+        // The target is back where it is now
+        assert_eq!(*physics.player_state(2).unwrap(), moved);
+        assert_eq!(physics.hitscan(1, 30, 100.0), None);
+
+        // The rewind covers the round trip plus interpolation, up to the cap
+        let tick = Duration::from_millis(20);
+        let ms = Duration::from_millis;
+        assert_eq!(rewind_ticks(ms(100), ms(100), tick, ms(500)), 10);
+        assert_eq!(rewind_ticks(ms(900), ms(100), tick, ms(500)), 25);
+    }
+
+    #[test]
+    fn test_input_buffer_orders_validates_and_rate_limits() {
+        let mut inputs = InputBuffer::new(InputLimits {
+            max_per_tick: 2,
+            max_buffered: 4,
+            sequence_window: 10,
+            max_look_delta: 100.0,
+            kick_threshold: 3,
+        });
+        let input = |sequence| PlayerInput {
+            sequence,
+            ..Default::default()
+        };
+        assert_eq!(inputs.push(1, input(1)), Err(InputRejection::UnknownPlayer));
+        inputs.add_player(1);
+
+        // Queued in sequence order whatever the arrival order
+        inputs.push(1, input(2)).unwrap();
+        inputs.push(1, input(1)).unwrap();
+        assert_eq!(inputs.push(1, input(2)), Err(InputRejection::Duplicate));
+        let sequences = |applied: Vec<(u32, PlayerInput)>| {
+            applied
+                .iter()
+                .map(|(_, input)| input.sequence)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sequences(inputs.drain_tick()), vec![1, 2]);
+        assert_eq!(inputs.push(1, input(2)), Err(InputRejection::Stale));
+        assert_eq!(inputs.push(1, input(13)), Err(InputRejection::OutOfWindow));
+
+        // Bursts are capped per tick, and on average one input runs per tick
+        for sequence in 3..=6 {
+            inputs.push(1, input(sequence)).unwrap();
+        }
+        assert_eq!(sequences(inputs.drain_tick()), vec![3]);
+        assert_eq!(sequences(inputs.drain_tick()), vec![4]);
+        assert_eq!(inputs.queued(1), 2);
+        assert_eq!(sequences(inputs.drain_tick()), vec![5]);
+        assert_eq!(sequences(inputs.drain_tick()), vec![6]);
+        assert!(inputs.drain_tick().is_empty());
+        assert_eq!(sequences(inputs.drain_tick()), Vec::<u32>::new());
+
+        // A full queue drops its oldest input
+        for sequence in 7..=11 {
+            inputs.push(1, input(sequence)).unwrap();
+        }
+        assert_eq!(inputs.queued(1), 4);
+        assert_eq!(sequences(inputs.drain_tick()), vec![8, 9]);
+        assert_eq!(sequences(inputs.drain_tick()), vec![10]);
+        assert_eq!(sequences(inputs.drain_tick()), vec![11]);
+
+        // Absurd look deltas are clamped
+        inputs
+            .push(
+                1,
+                PlayerInput {
+                    look_delta_x: f32::NAN,
+                    look_delta_y: -1e9,
+                    ..input(12)
+                },
+            )
+            .unwrap();
+        let applied = inputs.drain_tick();
+        assert_eq!(applied[0].1.sequence, 12);
+        assert_eq!(
+            (applied[0].1.look_delta_x, applied[0].1.look_delta_y),
+            (0.0, -100.0)
+        );
+
+        let counters = inputs.counters(1).unwrap();
+        assert_eq!(counters.duplicate, 1);
+        assert_eq!(counters.stale, 1);
+        assert_eq!(counters.out_of_window, 1);
+        assert_eq!(counters.overflow, 1);
+        assert_eq!(counters.clamped, 1);
+        assert_eq!(counters.rejected(), 4);
+        assert_eq!(counters.applied, 11);
+
+        // Out of window, overflow and clamping add strikes towards a kick
+        assert_eq!(inputs.players_to_kick(), Vec::<u32>::new());
+        for _ in 0..3 {
+            let _ = inputs.push(1, input(1000));
+        }
+        assert_eq!(inputs.players_to_kick(), vec![1]);
+    }
+
+    #[test]
+    fn test_entity_states_cover_players_and_moving_bodies() {
+        let mut physics = AuthoritativePhysics::new();
+        physics.spawn_player(4, SPAWN_POINT);
+        let prop = physics
+            .world_mut()
+            .spawn(
+                &BodyDesc::new(BodyKind::Dynamic, Vec3::new(3.0, 1.0, 0.0)).with_collider(
+                    ColliderDesc::new(ColliderShape::Box {
+                        half_extents: Vec3::splat(0.5),
+                    }),
+                ),
+            )
+            .unwrap();
+
+        // The ground plane is level geometry and is not replicated
+        let states = physics.entity_states();
+        assert_eq!(states.len(), 2);
+        assert_eq!(states[0].kind, EntityKind::Player { player_id: 4 });
+        assert_eq!(states[0].position(), SPAWN_POINT);
+        assert_eq!(states[1].id, prop);
+        assert_eq!(states[1].kind, EntityKind::Prop);
+        assert_eq!(states[1].position(), Vec3::new(3.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn test_replication_acks_survive_tick_wraparound() {
+        let mut replication = Replication::new();
+        for tick in [u32::MAX - 1, u32::MAX, 0] {
+            replication.encode_for(1, &Arc::new(StateSnapshot::new(tick)), 0);
+        }
+
+        // Tick 0 comes after u32::MAX, and a late ack does not go back
+        replication.acknowledge(1, u32::MAX);
+        replication.acknowledge(1, 0);
+        replication.acknowledge(1, u32::MAX - 1);
+        match replication.encode_for(1, &Arc::new(StateSnapshot::new(1)), 0) {
+            ServerMessage::StateDelta(delta) => assert_eq!(delta.baseline_tick, 0),
+            other => panic!("Expected StateDelta, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_replication_sends_deltas_against_acked_snapshots() {
+        let mut physics = AuthoritativePhysics::new();
+        for player_id in 1..=8 {
+            physics.spawn_player(player_id, SPAWN_POINT + Vec3::X * player_id as f32);
+        }
+        let snapshot_at = |tick, physics: &AuthoritativePhysics| {
+            let mut snapshot = StateSnapshot::new(tick);
+            snapshot.entities = physics.entity_states();
+            snapshot.players = physics.player_updates();
+            let size = bincode::serialized_size(&snapshot).unwrap();
+            (Arc::new(snapshot), size)
+        };
+        let mut replication = Replication::new();
+
+        // Nothing acknowledged yet: full snapshots
+        let (first, size) = snapshot_at(1, &physics);
+        assert!(matches!(
+            replication.encode_for(1, &first, size),
+            ServerMessage::StateUpdate(_)
+        ));
+        let (second, size) = snapshot_at(2, &physics);
+        assert!(matches!(
+            replication.encode_for(1, &second, size),
+            ServerMessage::StateUpdate(_)
+        ));
+
+        // Acks for unknown ticks or other players change nothing
+        replication.acknowledge(1, 99);
+        replication.acknowledge(2, 1);
+        let (third, size) = snapshot_at(3, &physics);
+        assert!(matches!(
+            replication.encode_for(1, &third, size),
+            ServerMessage::StateUpdate(_)
+        ));
+
+        replication.acknowledge(1, 2);
+        physics.process_input(
+            3,
+            &PlayerInput {
+                sequence: 1,
+                move_forward: true,
+                ..Default::default()
+            },
+        );
+        physics.step(1.0 / 60.0);
+        let (fourth, size) = snapshot_at(4, &physics);
+        match replication.encode_for(1, &fourth, size) {
+            ServerMessage::StateDelta(delta) => {
+                assert_eq!(delta.baseline_tick, 2);
+                assert_eq!(delta.apply(&second).unwrap(), *fourth);
+            }
+            other => panic!("Expected StateDelta, got {:?}", other),
+        }
+        let stats = replication.stats();
+        assert_eq!((stats.full_snapshots, stats.delta_snapshots), (3, 1));
+        assert!(stats.bytes_saved() > 0);
+
+        // Once the acked snapshot falls out of the history, fall back to full
+        for tick in 5..5 + SNAPSHOT_HISTORY_LEN as u32 {
+            let (snapshot, size) = snapshot_at(tick, &physics);
+            replication.encode_for(1, &snapshot, size);
+        }
+        let (late, size) = snapshot_at(100, &physics);
+        assert!(matches!(
+            replication.encode_for(1, &late, size),
+            ServerMessage::StateUpdate(_)
+        ));
+    }
+
+    /// A 300 ms hitscan bolt (ID 0) and a channeled heal (ID 1)
+    fn test_spells() -> SpellRegistry {
+        let bolt = "name = \"Bolt\"\nelement = \"lightning\"\nmana_cost = 40.0\n\
+                    cast_time_ms = 300\ncooldown_ms = 1000\n\
+                    [delivery]\ntype = \"hitscan\"\nrange = 50.0\n\
+                    [[effects]]\ntype = \"damage\"\namount = 10.0\n";
+        let heal = "name = \"Heal\"\nelement = \"water\"\nmana_cost = 20.0\n\
+                    cast_time_ms = 0\ncooldown_ms = 0\n\
+                    [channel]\nduration_ms = 500\ninterval_ms = 200\n\
+                    [delivery]\ntype = \"self\"\n\
+                    [[effects]]\ntype = \"heal\"\namount = 5.0\n";
+        let orb = "name = \"Orb\"\nelement = \"arcane\"\nmana_cost = 10.0\n\
+                   cast_time_ms = 0\ncooldown_ms = 0\ncast_while_moving = true\n\
+                   [delivery]\ntype = \"projectile\"\nspeed = 20.0\nradius = 0.2\n\
+                   lifetime_ms = 2000\n\
+                   [[effects]]\ntype = \"damage\"\namount = 10.0\n\
+                   [[effects]]\ntype = \"apply_status\"\nstatus = \"slowed\"\n\
+                   duration_ms = 1000\n";
+        let splash = "name = \"Splash\"\nelement = \"water\"\nmana_cost = 10.0\n\
+                      cast_time_ms = 0\ncooldown_ms = 0\n\
+                      [delivery]\ntype = \"projectile\"\nspeed = 20.0\nradius = 0.2\n\
+                      lifetime_ms = 2000\n\
+                      [[effects]]\ntype = \"damage\"\namount = 5.0\n";
+        let wave = "name = \"Wave\"\nelement = \"ice\"\nmana_cost = 10.0\n\
+                    cast_time_ms = 0\ncooldown_ms = 0\n\
+                    [delivery]\ntype = \"area\"\nradius = 3.0\n\
+                    [[effects]]\ntype = \"damage\"\namount = 10.0\n\
+                    [[effects]]\ntype = \"apply_status\"\nstatus = \"chilled\"\n\
+                    duration_ms = 1000\n\
+                    [[effects]]\ntype = \"knockback\"\nimpulse = 160.0\n";
+        let blast = "name = \"Blast\"\nelement = \"arcane\"\nmana_cost = 10.0\n\
+                     cast_time_ms = 0\ncooldown_ms = 0\n\
+                     [delivery]\ntype = \"area\"\nradius = 3.0\nrange = 4.0\n\
+                     [[effects]]\ntype = \"knockback\"\nimpulse = 160.0\n";
+        SpellRegistry::from_sources([
+            (Path::new("bolt.toml"), bolt),
+            (Path::new("heal.toml"), heal),
+            (Path::new("orb.toml"), orb),
+            (Path::new("splash.toml"), splash),
+            (Path::new("wave.toml"), wave),
+            (Path::new("zone.toml"), blast),
+        ])
+        .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn cast_input(held: bool, spell_slot: u8) -> PlayerInput {
+        PlayerInput {
+            cast_spell: held,
+            spell_slot,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_casts_wind_up_pay_cool_down_and_channel() {
+        let mut logic = GameLogic::new();
+        logic.set_spells(Arc::new(test_spells()));
+        logic.spawn_player(1);
+        let dt = 0.1;
+        let events = |logic: &GameLogic| {
+            logic
+                .cast_events()
+                .iter()
+                .map(|event| (event.player_id, event.spell_id, event.kind))
+                .collect::<Vec<_>>()
+        };
+
+        logic.queue_input(1, &cast_input(true, 0));
+        logic.update(1, dt);
+        assert_eq!(events(&logic), vec![(1, 0, CastEventKind::Started)]);
+        for tick in 2..=3 {
+            logic.update(tick, dt);
+            assert!(logic.cast_events().is_empty() && logic.released_spells().is_empty());
+        }
+        assert_eq!(
+            logic.active_spells(3),
+            vec![(
+                1,
+                ActiveSpell {
+                    spell_id: 0,
+                    elapsed_ticks: 2
+                }
+            )]
+        );
+
+        // Three ticks of wind-up, then the mana is paid and the cooldown starts
+        logic.update(4, dt);
+        assert_eq!(events(&logic), vec![(1, 0, CastEventKind::Completed)]);
+        assert_eq!(logic.released_spells(), &[(1, 0)]);
+        assert_eq!(logic.mana(1).unwrap().current, 60.0);
+        assert!(logic.active_spells(4).is_empty());
+
+        // Holding the button does not recast; pressing again is too soon
+        logic.update(5, dt);
+        assert!(logic.cast_events().is_empty());
+        logic.queue_input(1, &cast_input(false, 0));
+        logic.queue_input(1, &cast_input(true, 0));
+        logic.update(6, dt);
+        assert_eq!(
+            events(&logic),
+            vec![(1, 0, CastEventKind::Rejected(CastRejection::OnCooldown))]
+        );
+        assert!(logic.caster(1).unwrap().is_ready(0, 14));
+
+        // The channel goes off at once and every other tick while held
+        logic.queue_input(1, &cast_input(false, 1));
+        logic.queue_input(1, &cast_input(true, 1));
+        logic.update(7, dt);
+        assert_eq!(events(&logic), vec![(1, 1, CastEventKind::Started)]);
+        let mut pulses = vec![7];
+        for tick in 8..=12 {
+            logic.update(tick, dt);
+            if !logic.released_spells().is_empty() {
+                pulses.push(tick);
+            }
+        }
+        assert_eq!(pulses, vec![7, 9, 11]);
+        assert_eq!(events(&logic), vec![(1, 1, CastEventKind::Completed)]);
+
+        // Letting go ends a channel early
+        logic.queue_input(1, &cast_input(false, 1));
+        logic.queue_input(1, &cast_input(true, 1));
+        logic.update(13, dt);
+        logic.queue_input(1, &cast_input(false, 1));
+        logic.update(14, dt);
+        assert_eq!(events(&logic), vec![(1, 1, CastEventKind::Completed)]);
+        assert!(logic.released_spells().is_empty());
+
+        logic.remove_player(1);
+        assert!(logic.caster(1).is_none());
+    }
+
+    #[test]
+    fn test_casts_are_interrupted_and_rejected() {
+        let spells = test_spells();
+        let mut slots = [None; SPELL_SLOTS];
+        slots[0] = Some(0);
+        slots[1] = Some(1);
+        let mut caster = Caster::new(1, slots);
+        let mut mana = Mana {
+            current: 50.0,
+            max: 100.0,
+            regen: 0.0,
+        };
+        let dt = 0.1;
+        let step = |caster: &mut Caster, tick, damaged, mana: &mut Mana| {
+            caster.step(tick, dt, damaged, false, mana, &spells).events
+        };
+        let walk = PlayerInput {
+            move_forward: true,
+            cast_spell: true,
+            ..Default::default()
+        };
+
+        // Moving, taking damage and switching slots all stop the wind-up
+        caster.queue_input(&cast_input(true, 0));
+        assert_eq!(
+            step(&mut caster, 1, false, &mut mana),
+            vec![(0, CastEventKind::Started)]
+        );
+        caster.queue_input(&walk);
+        assert_eq!(
+            step(&mut caster, 2, false, &mut mana),
+            vec![(0, CastEventKind::Interrupted(InterruptReason::Moved))]
+        );
+        caster.queue_input(&cast_input(false, 0));
+        caster.queue_input(&cast_input(true, 0));
+        step(&mut caster, 3, false, &mut mana);
+        assert_eq!(
+            step(&mut caster, 4, true, &mut mana),
+            vec![(0, CastEventKind::Interrupted(InterruptReason::Damaged))]
+        );
+        caster.queue_input(&cast_input(false, 0));
+        caster.queue_input(&cast_input(true, 0));
+        step(&mut caster, 5, false, &mut mana);
+        caster.queue_input(&cast_input(true, 1));
+        assert_eq!(
+            step(&mut caster, 6, false, &mut mana),
+            vec![(0, CastEventKind::Interrupted(InterruptReason::Cancelled))]
+        );
+        assert_eq!((caster.selected(), caster.state()), (1, CastState::Idle));
+        assert_eq!(mana.current, 50.0, "interrupted casts cost nothing");
+
+        // Starting needs a standing caster and enough mana
+        caster.queue_input(&cast_input(false, 0));
+        caster.queue_input(&walk);
+        assert_eq!(
+            step(&mut caster, 7, false, &mut mana),
+            vec![(0, CastEventKind::Rejected(CastRejection::Moving))]
+        );
+        mana.current = 30.0;
+        caster.queue_input(&cast_input(false, 0));
+        caster.queue_input(&cast_input(true, 0));
+        assert_eq!(
+            step(&mut caster, 8, false, &mut mana),
+            vec![(0, CastEventKind::Rejected(CastRejection::NotEnoughMana))]
+        );
+
+        // Mana spent elsewhere during the wind-up fails the cast when it goes off
+        mana.current = 45.0;
+        caster.queue_input(&cast_input(false, 0));
+        caster.queue_input(&cast_input(true, 0));
+        step(&mut caster, 9, false, &mut mana);
+        mana.current = 10.0;
+        step(&mut caster, 10, false, &mut mana);
+        step(&mut caster, 11, false, &mut mana);
+        assert_eq!(
+            step(&mut caster, 12, false, &mut mana),
+            vec![(0, CastEventKind::Interrupted(InterruptReason::OutOfMana))]
+        );
+        assert!(caster.is_ready(0, 12));
+
+        // An empty slot casts nothing
+        caster.queue_input(&cast_input(false, 2));
+        caster.queue_input(&cast_input(true, 2));
+        assert!(step(&mut caster, 13, false, &mut mana).is_empty());
+        assert_eq!(caster.state(), CastState::Idle);
+    }
+
+    #[test]
+    fn test_simulation_replicates_casts() {
+        let mut simulation = Simulation::new(1.0 / 60.0, MovementConfig::default())
+            .with_spells(Arc::new(test_spells()));
+        simulation.handle_event(InboundEvent::PlayerJoined { player_id: 1 });
+        simulation.handle_event(InboundEvent::Input {
+            player_id: 1,
+            input: PlayerInput {
+                sequence: 1,
+                ..cast_input(true, 0)
+            },
+        });
+
+        let snapshot = simulation.tick();
+        assert_eq!(simulation.cast_events()[0].kind, CastEventKind::Started);
+        let player = snapshot
+            .entities
+            .iter()
+            .find(|entity| entity.kind == EntityKind::Player { player_id: 1 })
+            .unwrap();
+        assert_eq!(
+            player.active_spells,
+            vec![ActiveSpell {
+                spell_id: 0,
+                elapsed_ticks: 0
+            }]
+        );
+
+        // 300 ms at 60 Hz
+        for _ in 0..18 {
+            simulation.tick();
+        }
+        assert_eq!(simulation.cast_events()[0].kind, CastEventKind::Completed);
+        assert_eq!(simulation.cast_events()[0].tick, 19);
+        assert!(simulation.spell_hits().is_empty(), "nobody to hit");
+    }
+
+    #[test]
+    fn test_simulation_launches_and_resolves_projectiles() {
+        let mut simulation = Simulation::new(1.0 / 60.0, MovementConfig::default())
+            .with_spells(Arc::new(test_spells()));
+        simulation.handle_event(InboundEvent::PlayerJoined { player_id: 1 });
+        simulation.handle_event(InboundEvent::PlayerJoined { player_id: 2 });
+        // Player 2 walks out in front of player 1
+        for sequence in 1..=60 {
+            simulation.handle_event(InboundEvent::Input {
+                player_id: 2,
+                input: PlayerInput {
+                    sequence,
+                    move_forward: true,
+                    ..Default::default()
+                },
+            });
+            simulation.tick();
+        }
+        let target = simulation.physics().player_state(2).unwrap().position;
+        assert!(target.distance(SPAWN_POINT) > 2.0);
+
+        // The orb in slot 2 goes off at once and carries the casting input
+        simulation.handle_event(InboundEvent::Input {
+            player_id: 1,
+            input: PlayerInput {
+                sequence: 7,
+                ..cast_input(true, 2)
+            },
+        });
+        let snapshot = simulation.tick();
+        let projectile = snapshot
+            .entities
+            .iter()
+            .find(|entity| matches!(entity.kind, EntityKind::Projectile { .. }))
+            .unwrap();
+        assert_eq!(
+            projectile.kind,
+            EntityKind::Projectile {
+                owner: 1,
+                spell_id: 2,
+                cast_sequence: 7
+            }
+        );
+        let id = projectile.id;
+
+        let mut impacts = Vec::new();
+        for _ in 0..60 {
+            let snapshot = simulation.tick();
+            impacts.extend_from_slice(simulation.projectile_impacts());
+            if !impacts.is_empty() {
+                assert!(snapshot.entity(id).is_none(), "stopped by the hit");
+                break;
+            }
+        }
+        assert_eq!(impacts.len(), 1);
+        assert_eq!(impacts[0].projectile, id);
+        assert_eq!(impacts[0].target, Some(2));
+        assert_eq!(impacts[0].spell.cast_sequence, 7);
+        assert!(impacts[0].stopped);
+
+        // The hit is replicated as lost health and a status, and slows the target
+        let snapshot = simulation.tick();
+        let hit = snapshot
+            .entities
+            .iter()
+            .find(|entity| entity.kind == EntityKind::Player { player_id: 2 })
+            .unwrap();
+        assert_eq!(
+            hit.health,
+            Some(HealthState {
+                current: 90,
+                max: 100,
+                shield: 0
+            })
+        );
+        assert_eq!(hit.mana.map(|mana| mana.max), Some(100));
+        assert_eq!(hit.statuses.len(), 1);
+        assert_eq!(hit.statuses[0].kind, StatusKind::Slowed);
+        assert_eq!(simulation.physics().player_state(2).unwrap().slow, 0.4);
+
+        // Player 2 heals themselves back up with the self-cast channel in slot 1
+        simulation.handle_event(InboundEvent::Input {
+            player_id: 2,
+            input: PlayerInput {
+                sequence: 61,
+                ..cast_input(true, 1)
+            },
+        });
+        for _ in 0..60 {
+            simulation.tick();
+        }
+        let health = simulation.game_logic().health(2).unwrap();
+        assert_eq!(health.current, health.max);
+        assert_eq!(simulation.physics().player_state(2).unwrap().slow, 0.0);
+    }
+
+    #[test]
+    fn test_status_stacking_rules() {
+        use StatusKind::*;
+        let dt = 0.1;
+        let none = Immunities::default();
+        let mut statuses = StatusEffects::default();
+
+        // Stacks add up to the maximum; a shorter application never shortens
+        assert!(statuses.apply(Burning, 2, 2000, &none, 0, dt));
+        assert!(statuses.apply(Burning, 2, 500, &none, 5, dt));
+        let burning = *statuses.get(Burning).unwrap();
+        assert_eq!((burning.stacks, burning.until), (3, 20));
+        assert_eq!(burning.next_pulse, 5, "restacking keeps the pulse rhythm");
+
+        // Refresh keeps one stack and runs to the later end
+        statuses.apply(Slowed, 3, 1000, &none, 0, dt);
+        statuses.apply(Slowed, 1, 3000, &none, 5, dt);
+        assert_eq!(
+            statuses.get(Slowed).map(|s| (s.stacks, s.until)),
+            Some((1, 35))
+        );
+        statuses.apply(Slowed, 1, 100, &none, 6, dt);
+        assert_eq!(statuses.get(Slowed).unwrap().until, 35);
+
+        // Override replaces stacks and timer, even with a shorter one
+        statuses.apply(Shocked, 2, 3000, &none, 0, dt);
+        statuses.apply(Shocked, 1, 500, &none, 10, dt);
+        assert_eq!(
+            statuses.get(Shocked).map(|s| (s.stacks, s.until)),
+            Some((1, 15))
+        );
+
+        // Water puts the fire out and soaks; fire on a wet target only dries it
+        assert!(statuses.apply(Wet, 1, 5000, &none, 11, dt));
+        assert!(!statuses.has(Burning));
+        assert!(!statuses.apply(Burning, 1, 5000, &none, 12, dt));
+        assert!(!statuses.has(Burning) && !statuses.has(Wet));
+
+        // Chill turns into ice at five stacks, which also dries the target
+        statuses.apply(Wet, 1, 5000, &none, 13, dt);
+        statuses.apply(Chilled, 3, 2000, &none, 13, dt);
+        assert_eq!(statuses.stacks(Chilled), 3);
+        assert!(
+            (statuses.slow() - 0.4).abs() < 1e-6,
+            "the strongest slow wins"
+        );
+        assert!(!statuses.silenced());
+        statuses.apply(Chilled, 3, 2000, &none, 14, dt);
+        assert!(statuses.has(Frozen) && !statuses.has(Chilled) && !statuses.has(Wet));
+        assert_eq!(statuses.slow(), 1.0);
+        assert!(statuses.silenced());
+        // Fire thaws the ice and is spent doing it
+        assert!(!statuses.apply(Burning, 1, 1000, &none, 15, dt));
+        assert!(!statuses.has(Frozen) && !statuses.has(Burning));
+        assert!(
+            !statuses.apply(Silenced, 0, 1000, &none, 15, dt),
+            "zero stacks"
+        );
+
+        // Immunities ignore a status; chill that cannot freeze stays at the cap
+        let immune = Immunities {
+            statuses: [Frozen, Silenced].into(),
+            ..Default::default()
+        };
+        let mut statuses = StatusEffects::default();
+        assert!(!statuses.apply(Silenced, 1, 1000, &immune, 0, dt));
+        statuses.apply(Chilled, 9, 1000, &immune, 0, dt);
+        assert_eq!(statuses.stacks(Chilled), 5);
+        assert!(!statuses.has(Frozen));
+        assert_eq!(
+            statuses.states(),
+            vec![StatusState {
+                kind: Chilled,
+                stacks: 5,
+                until_tick: 10
+            }]
+        );
+    }
+
+    #[test]
+    fn test_damage_over_time_shields_and_immunities() {
+        let dt = 0.1;
+        let none = Immunities::default();
+
+        // Two stacks of fire for a second pulse twice, the second as it ends
+        let mut statuses = StatusEffects::default();
+        statuses.apply(StatusKind::Burning, 2, 1000, &none, 0, dt);
+        let mut pulses = Vec::new();
+        for tick in 1..=20 {
+            for pulse in statuses.update(tick, dt) {
+                pulses.push((tick, pulse));
+            }
+        }
+        assert_eq!(
+            pulses,
+            vec![(5, (Element::Fire, 4.0)), (10, (Element::Fire, 4.0))]
+        );
+        assert!(!statuses.has(StatusKind::Burning));
+
+        // Shields soak damage, the one running out first going first
+        let mut health = Health::default();
+        let mut shields = Shields::default();
+        shields.add(10.0, 20);
+        shields.add(30.0, 10);
+        shields.add(0.0, 30);
+        assert_eq!(
+            shields
+                .iter()
+                .map(|shield| shield.until)
+                .collect::<Vec<_>>(),
+            vec![10, 20]
+        );
+        let dealt = deal_damage(&mut health, &mut shields, &none, 35.0, Element::Arcane);
+        assert_eq!((dealt, health.current), (0.0, 100.0));
+        assert_eq!(shields.total(), 5.0);
+        let dealt = deal_damage(&mut health, &mut shields, &none, 15.0, Element::Arcane);
+        assert_eq!((dealt, health.current, shields.total()), (10.0, 90.0, 0.0));
+        shields.add(10.0, 20);
+        shields.expire(20);
+        assert_eq!(shields.total(), 0.0);
+
+        // Immune elements do nothing; health stays between zero and its maximum
+        let fireproof = Immunities {
+            elements: [Element::Fire].into(),
+            ..Default::default()
+        };
+        let burn = deal_damage(&mut health, &mut shields, &fireproof, 50.0, Element::Fire);
+        assert_eq!((burn, health.current), (0.0, 90.0));
+        assert_eq!(health.heal(25.0), 10.0);
+        let dealt = deal_damage(&mut health, &mut shields, &none, 250.0, Element::Ice);
+        assert_eq!((dealt, health.current), (100.0, 0.0));
+        assert!(health.is_dead());
+
+        // In the game world, burning hurts without interrupting, and silence
+        // keeps a player from casting
+        let mut logic = GameLogic::new();
+        logic.set_spells(Arc::new(test_spells()));
+        logic.spawn_player(1);
+        logic.shield_player(1, 5.0, 10_000, 0, dt);
+        logic.apply_status(1, StatusKind::Burning, 2, 1000, 0, dt);
+        logic.queue_input(1, &cast_input(true, 0));
+        logic.update(1, dt);
+        for tick in 2..=10 {
+            logic.update(tick, dt);
+        }
+        assert!(logic
+            .cast_events()
+            .iter()
+            .all(|event| !matches!(event.kind, CastEventKind::Interrupted(_))));
+        assert_eq!(logic.health(1).unwrap().current, 97.0, "5 of 8 shielded");
+        assert_eq!(logic.shields(1).unwrap().total(), 0.0);
+
+        logic.apply_status(1, StatusKind::Silenced, 1, 1000, 10, dt);
+        logic.queue_input(1, &cast_input(false, 0));
+        logic.queue_input(1, &cast_input(true, 0));
+        logic.update(11, dt);
+        assert_eq!(
+            logic.cast_events()[0].kind,
+            CastEventKind::Rejected(CastRejection::Silenced)
+        );
+        logic.set_immunities(
+            1,
+            Immunities {
+                statuses: [StatusKind::Stunned].into(),
+                ..Default::default()
+            },
+        );
+        assert!(!logic.apply_status(1, StatusKind::Stunned, 1, 1000, 11, dt));
+    }
+
+    /// Block of `material` with its bottom on the floor
+    fn block(world: &mut PhysicsWorld, x: f32, material: Material) -> EntityId {
+        let desc =
+            BodyDesc::new(BodyKind::Fixed, Vec3::new(x, 0.5, 0.0)).with_collider(ColliderDesc {
+                material,
+                ..ColliderDesc::new(ColliderShape::Box {
+                    half_extents: Vec3::splat(0.5),
+                })
+            });
+        world.spawn(&desc).unwrap()
+    }
+
+    #[test]
+    fn test_elements_react_with_surfaces() {
+        use SurfaceState::*;
+        assert_eq!(react(Normal, Material::Wood, Element::Fire), Some(Burning));
+        assert_eq!(react(Normal, Material::Stone, Element::Fire), None);
+        assert_eq!(react(Charred, Material::Wood, Element::Fire), None);
+        assert_eq!(react(Burning, Material::Wood, Element::Water), Some(Wet));
+        assert_eq!(react(Normal, Material::Stone, Element::Ice), None);
+        assert_eq!(react(Wet, Material::Stone, Element::Ice), Some(Frozen));
+        assert_eq!(react(Frozen, Material::Stone, Element::Fire), Some(Wet));
+        assert_eq!(react(Wet, Material::Stone, Element::Fire), Some(Normal));
+        assert_eq!(react(Wet, Material::Stone, Element::Lightning), None);
+        assert_eq!(react(Normal, Material::Flesh, Element::Water), None);
+
+        // Water then cold turns the floor to ice, which thaws back into water
+        let dt = 0.1;
+        let dry = BTreeSet::new();
+        let mut world = PhysicsWorld::new();
+        let ground = world.add_ground_plane(0.0);
+        let mut surfaces = Surfaces::default();
+        surfaces.hit(&mut world, ground, Element::Ice, &dry, 1, dt);
+        assert_eq!(surfaces.state(ground), Normal);
+        surfaces.hit(&mut world, ground, Element::Water, &dry, 1, dt);
+        surfaces.hit(&mut world, ground, Element::Ice, &dry, 2, dt);
+        assert_eq!(surfaces.state(ground), Frozen);
+        let thaw = 2 + FROZEN_MS / 100;
+        for tick in 3..thaw {
+            surfaces.update(&mut world, tick, dt);
+        }
+        assert_eq!(surfaces.state(ground), Frozen);
+        surfaces.update(&mut world, thaw, dt);
+        assert_eq!(surfaces.state(ground), Wet);
+        assert_eq!(surfaces.updates()[0].entity, ground);
+
+        // Despawned bodies are forgotten
+        let plank = block(&mut world, 0.0, Material::Wood);
+        surfaces.hit(&mut world, plank, Element::Fire, &dry, thaw, dt);
+        assert_eq!(surfaces.state(plank), Burning);
+        world.despawn(plank);
+        surfaces.update(&mut world, thaw + 1, dt);
+        assert!(surfaces.get(plank).is_none());
+    }
+
+    #[test]
+    fn test_fire_spreads_deterministically_and_leaves_char() {
+        let dt = 0.1;
+        let dry = BTreeSet::new();
+        // Three planks in a row, then a stone block and a soaked plank
+        let run = || {
+            let mut world = PhysicsWorld::new();
+            world.add_ground_plane(0.0);
+            let row: Vec<EntityId> = [0.0, 1.1, 2.2]
+                .into_iter()
+                .map(|x| block(&mut world, x, Material::Wood))
+                .collect();
+            let stone = block(&mut world, 3.3, Material::Stone);
+            let soaked = block(&mut world, -1.1, Material::Wood);
+            let mut surfaces = Surfaces::default();
+            surfaces.hit(&mut world, soaked, Element::Water, &dry, 0, dt);
+            surfaces.hit(&mut world, row[0], Element::Fire, &dry, 1, dt);
+
+            let mut history = Vec::new();
+            for tick in 2..=80 {
+                surfaces.update(&mut world, tick, dt);
+                history.push(surfaces.updates());
+            }
+            (row, stone, soaked, surfaces, history)
+        };
+        let (row, stone, soaked, surfaces, history) = run();
+        assert_eq!(run().4, history, "same hits, same fire");
+
+        // One plank further every second; history[i] is tick i + 2
+        let state_at = |tick: u32, entity: EntityId| {
+            history[tick as usize - 2]
+                .iter()
+                .find(|surface| surface.entity == entity)
+                .map_or(SurfaceState::Normal, |surface| surface.state)
+        };
+        assert_eq!(state_at(10, row[1]), SurfaceState::Normal);
+        assert_eq!(state_at(11, row[1]), SurfaceState::Burning);
+        assert_eq!(state_at(20, row[2]), SurfaceState::Normal);
+        assert_eq!(state_at(21, row[2]), SurfaceState::Burning);
+
+        // Burnt planks stay charred and are never lit again by their neighbours
+        let burnt_out = 1 + BURN_MS / 100;
+        assert_eq!(state_at(burnt_out - 1, row[0]), SurfaceState::Burning);
+        assert_eq!(state_at(burnt_out, row[0]), SurfaceState::Charred);
+        for plank in &row {
+            assert_eq!(surfaces.state(*plank), SurfaceState::Charred);
+        }
+        assert_eq!(surfaces.state(stone), SurfaceState::Normal);
+        assert_eq!(surfaces.state(soaked), SurfaceState::Wet);
+    }
+
+    #[test]
+    fn test_lightning_chains_through_wet_and_metal_bodies() {
+        let dt = 0.1;
+        let dry = BTreeSet::new();
+        let mut world = PhysicsWorld::new();
+        let ground = world.add_ground_plane(0.0);
+        let mut surfaces = Surfaces::default();
+
+        // Striking wet ground reaches the wet bodies on it in ID order, up to the limit
+        let wet: Vec<EntityId> = (0..6)
+            .map(|i| block(&mut world, i as f32 * 2.0, Material::Stone))
+            .collect();
+        for entity in wet.iter().chain([&ground]) {
+            surfaces.hit(&mut world, *entity, Element::Water, &dry, 1, dt);
+        }
+        assert_eq!(
+            surfaces.hit(&mut world, ground, Element::Lightning, &dry, 2, dt),
+            wet[..MAX_CHAIN].to_vec()
+        );
+        assert_eq!(
+            surfaces.state(ground),
+            SurfaceState::Wet,
+            "lightning leaves surfaces be"
+        );
+
+        // Dry stone stops lightning, metal conducts it
+        let mut world = PhysicsWorld::new();
+        world.add_ground_plane(0.0);
+        let dry_stone = block(&mut world, 0.0, Material::Stone);
+        let pipe = block(&mut world, 5.0, Material::Metal);
+        let touching = block(&mut world, 6.1, Material::Metal);
+        let surfaces = Surfaces::default();
+        assert!(surfaces.strike(&world, dry_stone, &dry).is_empty());
+        assert_eq!(surfaces.strike(&world, pipe, &dry), vec![touching]);
+
+        // Players conduct while they have the `Wet` status, which water gives
+        // them instead of a wet surface
+        let controller = CharacterController::default();
+        let player = controller
+            .spawn(&mut world, Vec3::new(5.0, 0.0, 1.0))
+            .entity;
+        let mut logic = GameLogic::new();
+        logic.spawn_player(1);
+        let mut soaked = BTreeSet::new();
+        assert_eq!(surfaces.strike(&world, pipe, &soaked), vec![touching]);
+        logic.hit_player(1, Element::Water, 1, dt);
+        assert_eq!(logic.wet_players(), vec![1]);
+        logic.hit_surface(&mut world, player, Element::Water, &soaked, 1, dt);
+        assert_eq!(logic.surfaces().state(player), SurfaceState::Normal);
+        soaked.insert(player);
+        assert_eq!(
+            surfaces.strike(&world, pipe, &soaked),
+            vec![touching, player]
+        );
+        logic.hit_player(1, Element::Fire, 2, dt);
+        assert!(logic.wet_players().is_empty());
+    }
+
+    #[test]
+    fn test_simulation_replicates_surface_states() {
+        let mut simulation = Simulation::new(1.0 / 60.0, MovementConfig::default())
+            .with_spells(Arc::new(test_spells()));
+        simulation.handle_event(InboundEvent::PlayerJoined { player_id: 1 });
+        // Look straight down and splash the floor
+        simulation.handle_event(InboundEvent::Input {
+            player_id: 1,
+            input: PlayerInput {
+                sequence: 1,
+                look_delta_y: 600.0,
+                ..cast_input(true, 3)
+            },
+        });
+        let mut snapshot = simulation.tick();
+        let mut impacts = Vec::new();
+        for _ in 0..30 {
+            snapshot = simulation.tick();
+            impacts.extend_from_slice(simulation.projectile_impacts());
+            if !impacts.is_empty() {
+                break;
+            }
+        }
+        let floor = impacts[0].entity;
+        assert_eq!(impacts[0].target, None);
+        assert_eq!(snapshot.surface(floor), SurfaceState::Wet);
+        assert_eq!(
+            simulation.game_logic().surfaces().state(floor),
+            SurfaceState::Wet
+        );
+    }
+
+    #[test]
+    fn test_simulation_resolves_area_spells_around_the_caster() {
+        let mut simulation = Simulation::new(1.0 / 60.0, MovementConfig::default())
+            .with_spells(Arc::new(test_spells()));
+        for player_id in 1..=3 {
+            simulation.handle_event(InboundEvent::PlayerJoined { player_id });
+        }
+        // Player 3 walks out of reach, then player 2 takes a step away
+        for (player_id, steps) in [(3, 60), (2, 15)] {
+            for sequence in 1..=steps {
+                simulation.handle_event(InboundEvent::Input {
+                    player_id,
+                    input: PlayerInput {
+                        sequence,
+                        move_forward: true,
+                        ..Default::default()
+                    },
+                });
+                simulation.tick();
+            }
+        }
+        let distance = |player_id| {
+            simulation
+                .physics()
+                .player_state(player_id)
+                .unwrap()
+                .position
+                .distance(SPAWN_POINT)
+        };
+        assert!(distance(2) < 3.0 && distance(3) > 3.0);
+
+        // The wave in slot 4 goes off at once around player 1 and pushes
+        // player 2 away at 2 m/s
+        let state = |simulation: &Simulation| *simulation.physics().player_state(2).unwrap();
+        let before = state(&simulation);
+        simulation.handle_event(InboundEvent::Input {
+            player_id: 1,
+            input: cast_input(true, 4),
+        });
+        simulation.tick();
+        let after = state(&simulation);
+        let away = (after.position - SPAWN_POINT).normalize();
+        let pushed = after.velocity - before.velocity;
+        assert!((pushed.dot(away) - 2.0).abs() < 0.1, "{pushed:?}");
+        let logic = simulation.game_logic();
+        assert_eq!(logic.health(2).unwrap().current, 90.0);
+        assert!(logic.statuses(2).unwrap().has(StatusKind::Chilled));
+        for untouched in [1, 3] {
+            assert_eq!(logic.health(untouched).unwrap().current, 100.0);
+            assert!(!logic.statuses(untouched).unwrap().has(StatusKind::Chilled));
+        }
+
+        // The blast in slot 5 is centered 4 m ahead of player 1's eyes, so it
+        // pushes player 2 back towards them and player 3 further on
+        let states = |simulation: &Simulation| {
+            [2, 3].map(|player_id| *simulation.physics().player_state(player_id).unwrap())
+        };
+        simulation.handle_event(InboundEvent::Input {
+            player_id: 1,
+            input: PlayerInput {
+                sequence: 1,
+                ..cast_input(false, 5)
+            },
+        });
+        for _ in 0..60 {
+            simulation.tick();
+        }
+        let before = states(&simulation);
+        simulation.handle_event(InboundEvent::Input {
+            player_id: 1,
+            input: PlayerInput {
+                sequence: 2,
+                ..cast_input(true, 5)
+            },
+        });
+        simulation.tick();
+        let after = states(&simulation);
+        let forward = (before[1].position - SPAWN_POINT).normalize();
+        let pushed = [0, 1].map(|i| (after[i].velocity - before[i].velocity).dot(forward));
+        assert!(pushed[0] < -1.0 && pushed[1] > 1.0, "{pushed:?}");
+    }
+
+    #[test]
+    fn test_dead_players_stop_casting_and_moving() {
+        // A player killed while winding up loses the cast, once, and cannot
+        // be healed or cast again
+        let dt = 0.1;
+        let mut logic = GameLogic::new();
+        logic.set_spells(Arc::new(test_spells()));
+        logic.spawn_player(1);
+        logic.queue_input(1, &cast_input(true, 0));
+        logic.update(1, dt);
+        assert_eq!(logic.cast_events()[0].kind, CastEventKind::Started);
+        assert_eq!(logic.damage_player(1, 250.0, Element::Arcane), 100.0);
+        assert!(logic.is_dead(1));
+        logic.update(2, dt);
+        assert_eq!(logic.deaths(), &[1]);
+        assert_eq!(
+            logic.cast_events()[0].kind,
+            CastEventKind::Interrupted(InterruptReason::Died)
+        );
+        assert_eq!(logic.heal_player(1, 50.0), 0.0);
+        logic.queue_input(1, &cast_input(false, 1));
+        logic.queue_input(1, &cast_input(true, 1));
+        logic.update(3, dt);
+        assert!(logic.deaths().is_empty());
+        assert!(logic.cast_events().is_empty());
+        assert_eq!(logic.caster(1).unwrap().state(), CastState::Idle);
+
+        // In the simulation, an area spell kills player 2, whose inputs are
+        // then acknowledged without moving them
+        let doom = "name = \"Doom\"\nelement = \"arcane\"\nmana_cost = 10.0\n\
+                    cast_time_ms = 0\ncooldown_ms = 0\n\
+                    [delivery]\ntype = \"area\"\nradius = 3.0\n\
+                    [[effects]]\ntype = \"damage\"\namount = 500.0\n";
+        let spells = SpellRegistry::from_sources([(Path::new("doom.toml"), doom)]).unwrap();
+        let mut simulation =
+            Simulation::new(1.0 / 60.0, MovementConfig::default()).with_spells(Arc::new(spells));
+        for player_id in 1..=2 {
+            simulation.handle_event(InboundEvent::PlayerJoined { player_id });
+        }
+        simulation.handle_event(InboundEvent::Input {
+            player_id: 1,
+            input: cast_input(true, 0),
+        });
+        simulation.tick();
+        assert!(simulation.game_logic().is_dead(2));
+        assert!(!simulation.game_logic().is_dead(1));
+        let start = simulation.physics().player_state(2).unwrap().position;
+        let mut snapshot = simulation.tick();
+        assert_eq!(simulation.game_logic().deaths(), &[2]);
+        for sequence in 1..=30 {
+            simulation.handle_event(InboundEvent::Input {
+                player_id: 2,
+                input: PlayerInput {
+                    sequence,
+                    move_forward: true,
+                    cast_spell: sequence % 2 == 1,
+                    ..Default::default()
+                },
+            });
+            snapshot = simulation.tick();
+            assert!(simulation
+                .cast_events()
+                .iter()
+                .all(|event| event.player_id != 2));
+        }
+        let moved = simulation.physics().player_state(2).unwrap().position - start;
+        assert!(
+            Vec3::new(moved.x, 0.0, moved.z).length() < 1e-3,
+            "{moved:?}"
+        );
+        let update = snapshot.players.iter().find(|p| p.player_id == 2).unwrap();
+        assert_eq!(update.last_input_sequence, Some(30));
+        let entity = snapshot
+            .entities
+            .iter()
+            .find(|entity| entity.kind == EntityKind::Player { player_id: 2 })
+            .unwrap();
+        assert!(entity.health.unwrap().is_dead());
+    }
 }
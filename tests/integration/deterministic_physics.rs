@@ -1,17 +1,77 @@
+use client::physics::ClientPhysics;
+use engine::config::MovementConfig;
+use engine::glam::Vec3;
+use engine::net_proto::{
+    decode_message, encode_message, EntityKind, PlayerInput, ServerMessage, StateSnapshot,
+};
 /// Tests for deterministic physics simulation
-use engine::physics_core::PhysicsWorld;
+use engine::physics_core::{CharacterController, PhysicsWorld, SurfaceState, WorldSnapshot};
+use engine::spells::SpellRegistry;
+use server::game_logic::elements::WET_MS;
+use server::net::InboundEvent;
+use server::physics::AuthoritativePhysics;
+use server::tick::Simulation;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Build a world with level geometry and a character, then drive it with a
+/// scripted input sequence
+fn simulate_scripted_world(ticks: u32) -> PhysicsWorld {
+    let dt = 1.0 / 60.0;
+    let mut world = PhysicsWorld::new();
+    world.add_ground_plane(0.0);
+    world.add_static_box(Vec3::new(0.0, 0.1, 4.0), Vec3::new(2.0, 0.1, 2.0));
+
+    let controller = CharacterController::default();
+    let mut character = controller.spawn(&mut world, Vec3::ZERO);
+    for i in 0..ticks {
+        let input = PlayerInput {
+            sequence: i,
+            move_forward: true,
+            jump: i % 45 == 0,
+            look_delta_x: (i % 11) as f32 - 5.0,
+            ..Default::default()
+        };
+        controller.apply_input(&mut world, &mut character, &input, dt);
+        world.step(dt);
+    }
+    world
+}
 
 #[test]
 fn test_physics_determinism() {
-    let mut world1 = PhysicsWorld::new();
-    let mut world2 = PhysicsWorld::new();
+    let world1 = simulate_scripted_world(180);
+    let world2 = simulate_scripted_world(180);
 
     // Both worlds should produce identical results with same inputs
-    world1.step(1.0 / 60.0);
-    world2.step(1.0 / 60.0);
+    let snapshot1 = world1.snapshot();
+    let snapshot2 = world2.snapshot();
+    let diff = snapshot1.diff(&snapshot2);
+    assert!(diff.is_empty(), "{}", diff);
+    assert_eq!(world1.state_hash(), world2.state_hash());
+}
 
-    // TODO: Compare world states
-    assert!(true, "Physics determinism test placeholder");
+#[test]
+fn test_physics_divergence_is_detected() {
+    let world1 = simulate_scripted_world(180);
+    let world2 = simulate_scripted_world(181);
+
+    let diff = world1.snapshot().diff(&world2.snapshot());
+    assert!(!diff.is_empty());
+    assert!(diff.to_string().contains("translation"), "{}", diff);
+    assert_ne!(world1.state_hash(), world2.state_hash());
+}
+
+#[test]
+fn test_snapshot_serialization_round_trip() {
+    let world = simulate_scripted_world(30);
+    let snapshot = world.snapshot();
+
+    let bytes = encode_message(&snapshot).unwrap();
+    let decoded: WorldSnapshot = decode_message(&bytes).unwrap();
+    assert_eq!(decoded, snapshot);
+    assert_eq!(decoded.state_hash(), world.state_hash());
 }
 
 #[test]
@@ -26,3 +86,260 @@ fn test_physics_consistency() {
     // TODO: Verify consistency
     assert!(true, "Physics consistency test placeholder");
 }
+
+#[test]
+fn test_client_prediction_matches_server_movement() {
+    let dt = 1.0 / 60.0;
+    let movement = MovementConfig {
+        max_speed: 7.5,
+        ..Default::default()
+    };
+    let mut server = AuthoritativePhysics::with_config(movement.clone(), dt);
+    let mut client = ClientPhysics::with_config(movement, dt);
+
+    server.spawn_player(1, Vec3::ZERO);
+    client.spawn_local_player(Vec3::ZERO);
+
+    for i in 0..240u32 {
+        let input = PlayerInput {
+            sequence: i,
+            move_forward: i % 50 < 30,
+            move_left: i % 70 > 40,
+            jump: i % 90 == 0,
+            crouch: (100..130).contains(&i),
+            look_delta_x: (i % 7) as f32,
+            look_delta_y: -((i % 5) as f32),
+            ..Default::default()
+        };
+
+        assert!(server.process_input(1, &input));
+        client.apply_local_input(&input);
+        server.step(dt);
+        client.predict(dt);
+
+        assert_eq!(
+            server.player_state(1),
+            client.local_player_state(),
+            "diverged at input {}",
+            i
+        );
+    }
+}
+
+#[test]
+fn test_prediction_converges_under_latency() {
+    const DT: f32 = 1.0 / 60.0;
+    // 100 ms each way
+    const LATENCY_TICKS: u32 = 6;
+    const INPUTS: u32 = 360;
+
+    let movement = MovementConfig::default();
+    let mut server = Simulation::new(DT, movement.clone());
+    server.handle_event(InboundEvent::PlayerJoined { player_id: 1 });
+    let mut client = ClientPhysics::with_config(movement, DT);
+    // The client starts out wrong, as it would after a missed teleport
+    client.spawn_local_player(Vec3::new(0.5, 0.0, 0.0));
+
+    // (arrival tick, encoded message)
+    let mut uplink: VecDeque<(u32, Vec<u8>)> = VecDeque::new();
+    let mut downlink: VecDeque<(u32, Vec<u8>)> = VecDeque::new();
+    let mut largest_error: f32 = 0.0;
+
+    // Play for INPUTS ticks, then keep ticking until everything in flight has landed
+    for tick in 0..INPUTS + 2 * LATENCY_TICKS + 1 {
+        // Client: predict with this tick's input and send it
+        if tick < INPUTS {
+            let input = PlayerInput {
+                sequence: tick + 1,
+                move_forward: tick < 240 && tick % 90 < 60,
+                move_right: (60..150).contains(&tick),
+                jump: tick % 75 == 10,
+                look_delta_x: if tick < 200 { 3.0 } else { 0.0 },
+                ..Default::default()
+            };
+            client.apply_local_input(&input);
+            uplink.push_back((tick + LATENCY_TICKS, encode_message(&input).unwrap()));
+        }
+        client.predict(DT);
+
+        // Server: take the inputs that have arrived and run one tick
+        while uplink.front().is_some_and(|(arrival, _)| *arrival <= tick) {
+            let (_, bytes) = uplink.pop_front().unwrap();
+            let input: PlayerInput = decode_message(&bytes).unwrap();
+            server.handle_event(InboundEvent::Input {
+                player_id: 1,
+                input,
+            });
+        }
+        let update = ServerMessage::StateUpdate(server.tick());
+        downlink.push_back((tick + LATENCY_TICKS, encode_message(&update).unwrap()));
+
+        // Client: reconcile with the updates that have arrived
+        while downlink
+            .front()
+            .is_some_and(|(arrival, _)| *arrival <= tick)
+        {
+            let (_, bytes) = downlink.pop_front().unwrap();
+            let ServerMessage::StateUpdate(snapshot) = decode_message(&bytes).unwrap() else {
+                panic!("Expected StateUpdate");
+            };
+            let own = snapshot.player(1).unwrap();
+            let error = client.reconcile(own);
+            largest_error = largest_error.max(error.length());
+        }
+
+        // Only the inputs still in flight are waiting for acknowledgement
+        assert!(client.pending_inputs() <= 2 * LATENCY_TICKS as usize + 1);
+    }
+
+    // The initial offset was found and corrected, but not snapped on screen
+    assert!(
+        largest_error > 0.4 && largest_error < client::physics::SNAP_DISTANCE,
+        "largest error {}",
+        largest_error
+    );
+
+    // With every input acknowledged, prediction and server agree exactly
+    let server_state = *server.physics().player_state(1).unwrap();
+    let predicted = *client.local_player_state().unwrap();
+    assert!(
+        (server_state.position - predicted.position).length() < 1e-4,
+        "server {:?}, predicted {:?}",
+        server_state.position,
+        predicted.position
+    );
+    assert!(client.correction_offset().length() < 1e-3);
+    assert_eq!(client.last_acked_sequence(), Some(INPUTS));
+    assert_eq!(client.pending_inputs(), 0);
+}
+
+#[test]
+fn test_predicted_projectiles_follow_the_server() {
+    const DT: f32 = 1.0 / 60.0;
+    const LATENCY_TICKS: u32 = 6;
+
+    let orb = "name = \"Orb\"\nelement = \"arcane\"\nmana_cost = 10.0\n\
+               cast_time_ms = 0\ncooldown_ms = 0\n\
+               [delivery]\ntype = \"projectile\"\nspeed = 20.0\nradius = 0.2\n\
+               lifetime_ms = 500\n\
+               [[effects]]\ntype = \"damage\"\namount = 10.0\n";
+    let spells = Arc::new(SpellRegistry::from_sources([(Path::new("orb.toml"), orb)]).unwrap());
+    let movement = MovementConfig::default();
+    let mut server = Simulation::new(DT, movement.clone()).with_spells(spells.clone());
+    server.handle_event(InboundEvent::PlayerJoined { player_id: 1 });
+    let mut client = ClientPhysics::with_config(movement, DT);
+    client.spawn_local_player(Vec3::ZERO);
+
+    // The orb goes off at once, so the client launches it with the press
+    let press = PlayerInput {
+        sequence: 1,
+        cast_spell: true,
+        ..Default::default()
+    };
+    client.apply_local_input(&press);
+    let local = client
+        .predict_projectile(0, press.sequence, spells.get(0).unwrap())
+        .unwrap();
+
+    let mut downlink: VecDeque<(u32, StateSnapshot)> = VecDeque::new();
+    let mut server_id = None;
+    for tick in 0..60 {
+        client.predict(DT);
+        if tick == LATENCY_TICKS {
+            server.handle_event(InboundEvent::Input {
+                player_id: 1,
+                input: press.clone(),
+            });
+        }
+        downlink.push_back((tick + LATENCY_TICKS, server.tick()));
+
+        while downlink
+            .front()
+            .is_some_and(|(arrival, _)| *arrival <= tick)
+        {
+            let (_, snapshot) = downlink.pop_front().unwrap();
+            client.reconcile_projectiles(1, &snapshot.entities);
+            let Some(server_projectile) = snapshot
+                .entities
+                .iter()
+                .find(|entity| matches!(entity.kind, EntityKind::Projectile { .. }))
+            else {
+                continue;
+            };
+            // Linked as soon as it shows up, and a round trip ahead of it
+            server_id = Some(server_projectile.id);
+            assert_eq!(client.local_projectile(server_projectile.id), Some(local));
+            if let Some((predicted, _)) = client.predicted_world().transform(local) {
+                let behind = server_projectile.position.get();
+                assert!(predicted.distance(Vec3::ZERO) > behind.distance(Vec3::ZERO));
+            }
+        }
+    }
+    assert!(server_id.is_some(), "the server launched the orb");
+    // The server's orb expired and the prediction went with it
+    assert!(client.predicted_projectiles().is_empty());
+
+    // A prediction the server never confirms is dropped after a while
+    let stray = client
+        .predict_projectile(0, 2, spells.get(0).unwrap())
+        .unwrap();
+    for _ in 0..(client::physics::PROJECTILE_CONFIRM_TIMEOUT / DT) as u32 + 2 {
+        client.predict(DT);
+        client.reconcile_projectiles(1, &[]);
+    }
+    assert!(client.predicted_projectiles().is_empty());
+    assert!(client.predicted_world().projectile(stray).is_none());
+
+    // As is one whose cast the server interrupted
+    client.predict_projectile(0, 3, spells.get(0).unwrap());
+    client.cancel_projectiles(0);
+    assert!(client.predicted_projectiles().is_empty());
+}
+
+#[test]
+fn test_client_takes_over_replicated_surfaces() {
+    const DT: f32 = 1.0 / 60.0;
+
+    let splash = "name = \"Splash\"\nelement = \"water\"\nmana_cost = 10.0\n\
+                  cast_time_ms = 0\ncooldown_ms = 0\n\
+                  [delivery]\ntype = \"projectile\"\nspeed = 20.0\nradius = 0.2\n\
+                  lifetime_ms = 2000\n\
+                  [[effects]]\ntype = \"damage\"\namount = 5.0\n";
+    let spells =
+        Arc::new(SpellRegistry::from_sources([(Path::new("splash.toml"), splash)]).unwrap());
+    let movement = MovementConfig::default();
+    let mut server = Simulation::new(DT, movement.clone()).with_spells(spells);
+    server.handle_event(InboundEvent::PlayerJoined { player_id: 1 });
+    let mut client = ClientPhysics::with_config(movement, DT);
+
+    // Splash the floor at the player's feet
+    server.handle_event(InboundEvent::Input {
+        player_id: 1,
+        input: PlayerInput {
+            sequence: 1,
+            cast_spell: true,
+            look_delta_y: 600.0,
+            ..Default::default()
+        },
+    });
+    let mut wetted = None;
+    for _ in 0..(WET_MS as f32 / 1000.0 / DT) as u32 + 60 {
+        let snapshot = server.tick();
+        client.apply_surfaces(&snapshot.surfaces);
+        if let Some(floor) = server
+            .projectile_impacts()
+            .first()
+            .map(|impact| impact.entity)
+        {
+            wetted = Some(floor);
+            assert_eq!(client.surface(floor), SurfaceState::Wet);
+        }
+    }
+    // Level geometry has the same IDs on both sides, and the floor dried again
+    let floor = wetted.expect("the splash hit the floor");
+    assert_eq!(client.surface(floor), SurfaceState::Normal);
+    assert_eq!(
+        server.game_logic().surfaces().state(floor),
+        SurfaceState::Normal
+    );
+}
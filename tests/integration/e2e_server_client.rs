@@ -1,3 +1,9 @@
+use client::net::ConnectionEvent;
+use engine::config::Transport;
+use engine::net_proto::{
+    CastEventKind, ClientMessage, DisconnectReason, PlayerInput, ServerMessage, BUILD_HASH,
+    PROTOCOL_VERSION,
+};
 use tokio::task;
 /// End-to-end test for server-client communication
 use tokio::time::{sleep, Duration};
@@ -18,22 +24,26 @@ async fn test_server_client_hello_world() {
     let result = client.connect("127.0.0.1", 7778).await;
     assert!(result.is_ok(), "Client should connect to server");
 
-    // Send HELLO message
-    let response = client.send_message("HELLO\n").await;
-    assert!(response.is_ok(), "Client should send message");
-    assert_eq!(
-        response.unwrap().trim(),
-        "WORLD",
-        "Server should respond with WORLD"
-    );
+    // Perform the handshake
+    let player_id = client.join("Alice").await;
+    assert!(player_id.is_ok(), "Server should welcome the client");
+    assert_eq!(client.player_id(), player_id.ok());
 
-    // Send PING message
-    let response = client.send_message("PING\n").await;
-    assert!(response.is_ok(), "Client should send message");
-    assert_eq!(
-        response.unwrap().trim(),
-        "PONG",
-        "Server should respond with PONG"
+    // Send Ping message
+    assert!(
+        client
+            .send_message(&ClientMessage::Ping {
+                id: 42,
+                timestamp: 0
+            })
+            .await
+            .is_ok(),
+        "Client should send message"
+    );
+    let response = client.receive_message().await;
+    assert!(
+        matches!(response, Ok(ServerMessage::Pong { id: 42, .. })),
+        "Server should respond with Pong"
     );
 
     // Clean up
@@ -60,15 +70,675 @@ async fn test_multiple_clients() {
     assert!(client1.connect("127.0.0.1", 7779).await.is_ok());
     assert!(client2.connect("127.0.0.1", 7779).await.is_ok());
 
+    // Both clients get their own player ID
+    let id1 = client1.join("Alice").await.unwrap();
+    let id2 = client2.join("Bob").await.unwrap();
+    assert_ne!(id1, id2, "Player IDs must be unique");
+
     // Both clients send messages
-    let resp1 = client1.send_message("HELLO\n").await.unwrap();
-    let resp2 = client2.send_message("PING\n").await.unwrap();
+    client1
+        .send_message(&ClientMessage::Ping {
+            id: 1,
+            timestamp: 0,
+        })
+        .await
+        .unwrap();
+    client2
+        .send_message(&ClientMessage::Ping {
+            id: 2,
+            timestamp: 0,
+        })
+        .await
+        .unwrap();
+
+    let resp1 = client1.receive_message().await.unwrap();
+    let resp2 = client2.receive_message().await.unwrap();
 
-    assert_eq!(resp1.trim(), "WORLD");
-    assert_eq!(resp2.trim(), "PONG");
+    assert!(matches!(resp1, ServerMessage::Pong { id: 1, .. }));
+    assert!(matches!(resp2, ServerMessage::Pong { id: 2, .. }));
 
     // Clean up
     client1.disconnect().await.unwrap();
     client2.disconnect().await.unwrap();
     server_task.abort();
 }
+
+#[tokio::test]
+async fn test_large_message_round_trip() {
+    let server_task = task::spawn(async {
+        let mut server = server::net::NetworkServer::new("127.0.0.1", 7780);
+        let _ = server.start().await;
+    });
+
+    sleep(Duration::from_millis(100)).await;
+
+    let mut client = client::net::NetworkClient::new();
+    client.connect("127.0.0.1", 7780).await.unwrap();
+
+    // Well over the old 1 KiB read buffer
+    client.join(&"x".repeat(8 * 1024)).await.unwrap();
+
+    // The stream must still be in sync afterwards
+    client
+        .send_message(&ClientMessage::Ping {
+            id: 1,
+            timestamp: 0,
+        })
+        .await
+        .unwrap();
+    let response = client.receive_message().await.unwrap();
+    assert!(matches!(response, ServerMessage::Pong { id: 1, .. }));
+
+    client.disconnect().await.unwrap();
+    server_task.abort();
+}
+
+#[tokio::test]
+async fn test_protocol_mismatch_is_refused() {
+    let server_task = task::spawn(async {
+        let mut server = server::net::NetworkServer::new("127.0.0.1", 7781);
+        let _ = server.start().await;
+    });
+
+    sleep(Duration::from_millis(100)).await;
+
+    let mut client = client::net::NetworkClient::new();
+    client.connect("127.0.0.1", 7781).await.unwrap();
+
+    client
+        .send_message(&ClientMessage::Connect {
+            player_name: "Mallory".to_string(),
+            protocol_version: PROTOCOL_VERSION + 1,
+            build_hash: BUILD_HASH.to_string(),
+            session_token: None,
+        })
+        .await
+        .unwrap();
+
+    match client.receive_message().await.unwrap() {
+        ServerMessage::Disconnect {
+            reason: DisconnectReason::Refused(reason),
+        } => {
+            assert!(reason.contains("Protocol version mismatch"), "{}", reason)
+        }
+        other => panic!("Expected Disconnect, got {:?}", other),
+    }
+
+    client.disconnect().await.unwrap();
+    server_task.abort();
+}
+
+#[tokio::test]
+async fn test_connect_must_be_first_message() {
+    let server_task = task::spawn(async {
+        let mut server = server::net::NetworkServer::new("127.0.0.1", 7782);
+        let _ = server.start().await;
+    });
+
+    sleep(Duration::from_millis(100)).await;
+
+    let mut client = client::net::NetworkClient::new();
+    client.connect("127.0.0.1", 7782).await.unwrap();
+
+    client
+        .send_message(&ClientMessage::Ping {
+            id: 1,
+            timestamp: 0,
+        })
+        .await
+        .unwrap();
+    let response = client.receive_message().await.unwrap();
+    assert!(matches!(response, ServerMessage::Disconnect { .. }));
+
+    client.disconnect().await.unwrap();
+    server_task.abort();
+}
+
+#[tokio::test]
+async fn test_tick_loop_broadcasts_state_updates() {
+    let server_task = task::spawn(async {
+        let mut config = engine::config::Config::default();
+        config.server.port = 7783;
+        let mut server = server::net::NetworkServer::from_config(&config);
+        let mut tick_loop =
+            server::TickLoop::new(&config, server.inbound_events(), server.sessions());
+        task::spawn(async move { tick_loop.run().await });
+        let _ = server.start().await;
+    });
+
+    sleep(Duration::from_millis(100)).await;
+
+    let mut client = client::net::NetworkClient::new();
+    client.connect("127.0.0.1", 7783).await.unwrap();
+    client.join("Alice").await.unwrap();
+
+    let mut last_tick = None;
+    for _ in 0..3 {
+        match client.receive_message().await.unwrap() {
+            ServerMessage::StateUpdate(snapshot) => {
+                snapshot.check_schema().unwrap();
+                if let Some(last) = last_tick {
+                    assert!(snapshot.tick > last, "Ticks must increase");
+                }
+                last_tick = Some(snapshot.tick);
+            }
+            other => panic!("Expected StateUpdate, got {:?}", other),
+        }
+    }
+
+    client.disconnect().await.unwrap();
+    server_task.abort();
+}
+
+#[tokio::test]
+async fn test_acknowledged_snapshots_are_delta_compressed() {
+    let server_task = task::spawn(async {
+        let mut config = engine::config::Config::default();
+        config.server.port = 7784;
+        let mut server = server::net::NetworkServer::from_config(&config);
+        let mut tick_loop =
+            server::TickLoop::new(&config, server.inbound_events(), server.sessions());
+        task::spawn(async move { tick_loop.run().await });
+        let _ = server.start().await;
+    });
+
+    sleep(Duration::from_millis(100)).await;
+
+    let mut client = client::net::NetworkClient::new();
+    client.connect("127.0.0.1", 7784).await.unwrap();
+    let player_id = client.join("Alice").await.unwrap();
+
+    let mut decoder = client::net::snapshots::SnapshotDecoder::new();
+    let mut deltas = 0;
+    for _ in 0..30 {
+        let message = client.receive_message().await.unwrap();
+        if matches!(message, ServerMessage::StateDelta(_)) {
+            deltas += 1;
+        }
+        let snapshot = decoder.decode(&message).unwrap().expect("state message");
+        assert!(snapshot.player(player_id).is_some());
+        client
+            .send_message(&ClientMessage::SnapshotAck {
+                tick: snapshot.tick,
+            })
+            .await
+            .unwrap();
+    }
+    assert!(deltas > 20, "only {} of 30 updates were deltas", deltas);
+
+    client.disconnect().await.unwrap();
+    server_task.abort();
+}
+
+#[tokio::test]
+async fn test_udp_transport_handshake_ping_and_leave() {
+    let mut server =
+        server::net::NetworkServer::new("127.0.0.1", 7785).with_transport(Transport::Udp);
+    let sessions = server.sessions();
+    let server_task = task::spawn(async move {
+        let _ = server.start().await;
+    });
+
+    sleep(Duration::from_millis(100)).await;
+
+    let mut client = client::net::NetworkClient::new().with_transport(Transport::Udp);
+    client.connect("127.0.0.1", 7785).await.unwrap();
+    // Big enough to need a datagram of its own
+    let player_id = client.join(&"x".repeat(4 * 1024)).await.unwrap();
+    assert_eq!(sessions.lock().unwrap().len(), 1);
+
+    client
+        .send_message(&ClientMessage::Ping {
+            id: 9,
+            timestamp: 0,
+        })
+        .await
+        .unwrap();
+    let response = client.receive_message().await.unwrap();
+    assert!(matches!(response, ServerMessage::Pong { id: 9, .. }));
+
+    // Disconnect is delivered reliably and ends the session
+    client.disconnect().await.unwrap();
+    sleep(Duration::from_millis(100)).await;
+    assert!(sessions.lock().unwrap().get(player_id).is_none());
+
+    // A refusal reaches the client over the reliable channel too
+    let mut mallory = client::net::NetworkClient::new().with_transport(Transport::Udp);
+    mallory.connect("127.0.0.1", 7785).await.unwrap();
+    mallory
+        .send_message(&ClientMessage::Connect {
+            player_name: "Mallory".to_string(),
+            protocol_version: PROTOCOL_VERSION + 1,
+            build_hash: BUILD_HASH.to_string(),
+            session_token: None,
+        })
+        .await
+        .unwrap();
+    let response = mallory.receive_message().await.unwrap();
+    assert!(matches!(response, ServerMessage::Disconnect { .. }));
+
+    mallory.disconnect().await.unwrap();
+    server_task.abort();
+}
+
+#[tokio::test]
+async fn test_udp_transport_replicates_state() {
+    let server_task = task::spawn(async {
+        let mut config = engine::config::Config::default();
+        config.server.port = 7786;
+        config.network.transport = Transport::Udp;
+        let mut server = server::net::NetworkServer::from_config(&config);
+        let mut tick_loop =
+            server::TickLoop::new(&config, server.inbound_events(), server.sessions());
+        task::spawn(async move { tick_loop.run().await });
+        let _ = server.start().await;
+    });
+
+    sleep(Duration::from_millis(100)).await;
+
+    let mut client = client::net::NetworkClient::new().with_transport(Transport::Udp);
+    client.connect("127.0.0.1", 7786).await.unwrap();
+    let player_id = client.join("Alice").await.unwrap();
+
+    let mut decoder = client::net::snapshots::SnapshotDecoder::new();
+    let mut last_tick = 0;
+    for _ in 0..20 {
+        let message = client.receive_message().await.unwrap();
+        if let Some(snapshot) = decoder.decode(&message).unwrap() {
+            assert!(snapshot.tick > last_tick);
+            assert!(snapshot.player(player_id).is_some());
+            last_tick = snapshot.tick;
+            client
+                .send_message(&ClientMessage::SnapshotAck {
+                    tick: snapshot.tick,
+                })
+                .await
+                .unwrap();
+        }
+    }
+    assert!(last_tick > 0);
+
+    client.disconnect().await.unwrap();
+    server_task.abort();
+}
+
+#[tokio::test]
+async fn test_cheating_inputs_get_player_kicked() {
+    let mut config = engine::config::Config::default();
+    config.server.port = 7787;
+    config.server.input.kick_threshold = 5;
+    let mut server = server::net::NetworkServer::from_config(&config);
+    let sessions = server.sessions();
+    let mut tick_loop = server::TickLoop::new(&config, server.inbound_events(), server.sessions());
+    let server_task = task::spawn(async move {
+        task::spawn(async move { tick_loop.run().await });
+        let _ = server.start().await;
+    });
+
+    sleep(Duration::from_millis(100)).await;
+
+    let mut client = client::net::NetworkClient::new();
+    client.connect("127.0.0.1", 7787).await.unwrap();
+    let player_id = client.join("Mallory").await.unwrap();
+
+    // Spinning faster than any mouse can
+    for sequence in 1..=10 {
+        client
+            .send_message(&ClientMessage::Input(PlayerInput {
+                sequence,
+                look_delta_x: f32::INFINITY,
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+    }
+
+    loop {
+        match client.receive_message().await {
+            Ok(ServerMessage::Disconnect {
+                reason: DisconnectReason::Kicked(reason),
+            }) => {
+                assert!(reason.contains("invalid inputs"), "{}", reason);
+                break;
+            }
+            Ok(_) => {}
+            Err(e) => panic!("Connection closed before the kick: {}", e),
+        }
+    }
+    sleep(Duration::from_millis(100)).await;
+    assert!(!sessions.lock().unwrap().contains(player_id));
+
+    client.disconnect().await.unwrap();
+    server_task.abort();
+}
+
+#[tokio::test]
+async fn test_silent_client_times_out() {
+    let mut config = engine::config::Config::default();
+    config.server.port = 7788;
+    config.network.timeout_ms = 1000;
+    let mut server = server::net::NetworkServer::from_config(&config);
+    let sessions = server.sessions();
+    let server_task = task::spawn(async move {
+        let _ = server.start().await;
+    });
+
+    sleep(Duration::from_millis(100)).await;
+
+    let mut client = client::net::NetworkClient::new();
+    client.connect("127.0.0.1", 7788).await.unwrap();
+    let player_id = client.join("Sleepy").await.unwrap();
+    assert_eq!(
+        client.drain_events(),
+        vec![ConnectionEvent::Connected { player_id }]
+    );
+
+    // Not receiving means not answering the server's heartbeats
+    sleep(Duration::from_millis(1500)).await;
+    assert!(!sessions.lock().unwrap().contains(player_id));
+    assert!(sessions.lock().unwrap().suspended(player_id).is_some());
+
+    loop {
+        match client.receive_message().await.unwrap() {
+            ServerMessage::Disconnect { reason } => {
+                assert_eq!(reason, DisconnectReason::TimedOut);
+                break;
+            }
+            ServerMessage::Ping { .. } | ServerMessage::Pong { .. } => {}
+            other => panic!("Expected Disconnect, got {:?}", other),
+        }
+    }
+    assert_eq!(
+        client.drain_events(),
+        vec![ConnectionEvent::Disconnected {
+            reason: DisconnectReason::TimedOut
+        }]
+    );
+    assert!(!client.is_connected());
+
+    server_task.abort();
+}
+
+#[tokio::test]
+async fn test_client_resumes_session_after_timing_out() {
+    let mut config = engine::config::Config::default();
+    config.server.port = 7798;
+    config.network.timeout_ms = 1000;
+    let mut server = server::net::NetworkServer::from_config(&config);
+    let mut tick_loop = server::TickLoop::new(&config, server.inbound_events(), server.sessions());
+    let server_task = task::spawn(async move {
+        task::spawn(async move { tick_loop.run().await });
+        let _ = server.start().await;
+    });
+
+    sleep(Duration::from_millis(100)).await;
+
+    let mut client = client::net::NetworkClient::new();
+    client.connect("127.0.0.1", 7798).await.unwrap();
+    let player_id = client.join("Sleepy").await.unwrap();
+
+    // Fall behind on heartbeats until the server times us out
+    sleep(Duration::from_millis(1500)).await;
+    loop {
+        if let ServerMessage::Disconnect { reason } = client.receive_message().await.unwrap() {
+            assert_eq!(reason, DisconnectReason::TimedOut);
+            break;
+        }
+    }
+    assert!(!client.is_connected());
+    client.drain_events();
+
+    // The session was left to resume, so the next receive takes it back
+    client.receive_message().await.unwrap();
+    assert!(client.is_connected());
+    let events = client.drain_events();
+    assert_eq!(
+        events.last(),
+        Some(&ConnectionEvent::Reconnected { player_id })
+    );
+
+    client.disconnect().await.unwrap();
+    server_task.abort();
+}
+
+#[tokio::test]
+async fn test_client_detects_silent_server() {
+    // Accepts connections and never says a word
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:7789")
+        .await
+        .unwrap();
+    let server_task = task::spawn(async move {
+        let mut sockets = Vec::new();
+        while let Ok((socket, _)) = listener.accept().await {
+            sockets.push(socket);
+        }
+    });
+
+    let mut client = client::net::NetworkClient::new().with_timeout(Duration::from_millis(300));
+    client.connect("127.0.0.1", 7789).await.unwrap();
+    assert!(client.join("Alice").await.is_err());
+    assert_eq!(client.drain_events(), vec![ConnectionEvent::ConnectionLost]);
+    assert!(!client.is_connected());
+
+    server_task.abort();
+}
+
+/// Handshake by hand to get at the session token
+async fn join_with_token(
+    client: &mut client::net::NetworkClient,
+    session_token: Option<u64>,
+) -> (u32, u64) {
+    client
+        .send_message(&ClientMessage::Connect {
+            player_name: "Alice".to_string(),
+            protocol_version: PROTOCOL_VERSION,
+            build_hash: BUILD_HASH.to_string(),
+            session_token,
+        })
+        .await
+        .unwrap();
+    loop {
+        if let ServerMessage::Welcome {
+            player_id,
+            session_token,
+            ..
+        } = client.receive_message().await.unwrap()
+        {
+            return (player_id, session_token);
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_dropped_session_resumes_within_grace_period() {
+    let mut config = engine::config::Config::default();
+    config.server.port = 7794;
+    config.server.session_grace_ms = 500;
+    let mut server = server::net::NetworkServer::from_config(&config);
+    let sessions = server.sessions();
+    let server_task = task::spawn(async move {
+        let _ = server.start().await;
+    });
+
+    sleep(Duration::from_millis(100)).await;
+
+    let mut client = client::net::NetworkClient::new();
+    client.connect("127.0.0.1", 7794).await.unwrap();
+    let (player_id, token) = join_with_token(&mut client, None).await;
+
+    // Dropping the client closes the socket without a Disconnect
+    drop(client);
+    sleep(Duration::from_millis(100)).await;
+    assert!(sessions.lock().unwrap().suspended(player_id).is_some());
+
+    // A wrong token joins as a new player
+    let mut other = client::net::NetworkClient::new();
+    other.connect("127.0.0.1", 7794).await.unwrap();
+    let (other_id, _) = join_with_token(&mut other, Some(token.wrapping_add(1))).await;
+    assert_ne!(other_id, player_id);
+    other.disconnect().await.unwrap();
+
+    let mut client = client::net::NetworkClient::new();
+    client.connect("127.0.0.1", 7794).await.unwrap();
+    let (resumed_id, new_token) = join_with_token(&mut client, Some(token)).await;
+    assert_eq!(resumed_id, player_id);
+    assert_ne!(new_token, token);
+    assert!(sessions.lock().unwrap().contains(player_id));
+
+    // Once the grace period is over the session is gone for good
+    drop(client);
+    sleep(Duration::from_millis(700)).await;
+    assert!(sessions.lock().unwrap().suspended(player_id).is_none());
+
+    let mut client = client::net::NetworkClient::new();
+    client.connect("127.0.0.1", 7794).await.unwrap();
+    let (late_id, _) = join_with_token(&mut client, Some(new_token)).await;
+    assert_ne!(late_id, player_id);
+
+    client.disconnect().await.unwrap();
+    server_task.abort();
+}
+
+/// Forward TCP connections from `listen` to `target` until `cut` is called
+struct FlakyProxy {
+    connections: std::sync::Arc<std::sync::Mutex<Vec<task::JoinHandle<()>>>>,
+    accept: task::JoinHandle<()>,
+}
+
+impl FlakyProxy {
+    async fn start(listen: u16, target: u16) -> Self {
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", listen))
+            .await
+            .unwrap();
+        let connections = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let accepted = connections.clone();
+        let accept = task::spawn(async move {
+            while let Ok((mut inbound, _)) = listener.accept().await {
+                let Ok(mut outbound) = tokio::net::TcpStream::connect(("127.0.0.1", target)).await
+                else {
+                    continue;
+                };
+                accepted.lock().unwrap().push(task::spawn(async move {
+                    let _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await;
+                }));
+            }
+        });
+        Self {
+            connections,
+            accept,
+        }
+    }
+
+    /// Drop every connection open so far, as a flaky network would
+    fn cut(&self) {
+        for connection in self.connections.lock().unwrap().drain(..) {
+            connection.abort();
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_client_reconnects_to_same_player_after_drop() {
+    let mut config = engine::config::Config::default();
+    config.server.port = 7795;
+    let mut server = server::net::NetworkServer::from_config(&config);
+    let mut tick_loop = server::TickLoop::new(&config, server.inbound_events(), server.sessions());
+    let server_task = task::spawn(async move {
+        task::spawn(async move { tick_loop.run().await });
+        let _ = server.start().await;
+    });
+    let proxy = FlakyProxy::start(7796, 7795).await;
+
+    sleep(Duration::from_millis(100)).await;
+
+    let mut client = client::net::NetworkClient::new();
+    client.connect("127.0.0.1", 7796).await.unwrap();
+    let player_id = client.join("Alice").await.unwrap();
+    client.receive_message().await.unwrap();
+
+    proxy.cut();
+
+    // The client notices the closed connection, reconnects and carries on
+    let mut events = Vec::new();
+    loop {
+        let message = client.receive_message().await.unwrap();
+        events.extend(client.drain_events());
+        if !events.contains(&ConnectionEvent::Reconnected { player_id }) {
+            continue;
+        }
+        // The character was kept and the first snapshot after resuming is full
+        if let ServerMessage::StateUpdate(snapshot) = message {
+            assert!(snapshot.player(player_id).is_some());
+            break;
+        }
+    }
+    assert_eq!(
+        events.first(),
+        Some(&ConnectionEvent::Connected { player_id })
+    );
+    assert!(events.contains(&ConnectionEvent::Reconnecting { attempt: 1 }));
+    assert_eq!(
+        events.last(),
+        Some(&ConnectionEvent::Reconnected { player_id })
+    );
+
+    client.disconnect().await.unwrap();
+    proxy.accept.abort();
+    server_task.abort();
+}
+
+#[tokio::test]
+async fn test_cast_events_reach_every_client() {
+    let mut config = engine::config::Config::default();
+    config.server.port = 7797;
+    let spells =
+        engine::spells::SpellRegistry::load_dir(std::path::Path::new(&config.server.spells_dir))
+            .unwrap();
+    let bolt = spells.id("lightning_bolt").unwrap();
+    let mut server = server::net::NetworkServer::from_config(&config);
+    let mut tick_loop = server::TickLoop::new(&config, server.inbound_events(), server.sessions())
+        .with_spells(std::sync::Arc::new(spells));
+    let server_task = task::spawn(async move {
+        task::spawn(async move { tick_loop.run().await });
+        let _ = server.start().await;
+    });
+
+    sleep(Duration::from_millis(100)).await;
+
+    let mut alice = client::net::NetworkClient::new();
+    let mut bob = client::net::NetworkClient::new();
+    alice.connect("127.0.0.1", 7797).await.unwrap();
+    bob.connect("127.0.0.1", 7797).await.unwrap();
+    let caster = alice.join("Alice").await.unwrap();
+    bob.join("Bob").await.unwrap();
+
+    // New players get the spells in ID order, one per slot
+    alice
+        .send_message(&ClientMessage::Input(PlayerInput {
+            sequence: 1,
+            cast_spell: true,
+            spell_slot: bolt as u8,
+            ..Default::default()
+        }))
+        .await
+        .unwrap();
+
+    let mut seen = Vec::new();
+    let received = tokio::time::timeout(Duration::from_secs(5), async {
+        while !seen.contains(&CastEventKind::Completed) {
+            if let ServerMessage::CastEvents(events) = bob.receive_message().await.unwrap() {
+                for event in events {
+                    assert_eq!((event.player_id, event.spell_id), (caster, bolt));
+                    seen.push(event.kind);
+                }
+            }
+        }
+    })
+    .await;
+    assert!(received.is_ok(), "Bob saw only {:?}", seen);
+    assert_eq!(seen, vec![CastEventKind::Started, CastEventKind::Completed]);
+
+    alice.disconnect().await.unwrap();
+    bob.disconnect().await.unwrap();
+    server_task.abort();
+}
@@ -0,0 +1,65 @@
+use client::lan::LanHost;
+use client::net::{ConnectionEvent, NetworkClient};
+use engine::config::Config;
+/// Tests of the LAN server embedded in the client, run headless
+use engine::net_proto::{DisconnectReason, ServerMessage};
+use tokio::time::{sleep, Duration};
+
+fn lan_config(port: u16) -> Config {
+    let mut config = Config::default();
+    config.server.port = port;
+    config
+}
+
+#[tokio::test]
+async fn test_lan_host_serves_players_and_shuts_down_cleanly() {
+    // Port 0 lets the OS pick, so the host reports where it listens
+    let host = LanHost::start(&lan_config(0)).await.unwrap();
+    let port = host.addr().port();
+    assert_ne!(port, 0);
+
+    let mut first = NetworkClient::new();
+    let first_id = host.join(&mut first, "Host").await.unwrap();
+    let mut second = NetworkClient::new();
+    let second_id = host.join(&mut second, "Guest").await.unwrap();
+    assert_ne!(first_id, second_id);
+    assert_eq!(host.player_count(), 2);
+
+    // The tick loop runs and replicates both players
+    loop {
+        if let ServerMessage::StateUpdate(snapshot) = first.receive_message().await.unwrap() {
+            if snapshot.player(second_id).is_some() {
+                assert!(snapshot.player(first_id).is_some());
+                break;
+            }
+        }
+    }
+
+    host.shutdown().await;
+
+    for client in [&mut first, &mut second] {
+        loop {
+            if let ServerMessage::Disconnect { reason } = client.receive_message().await.unwrap() {
+                assert_eq!(reason, DisconnectReason::ServerShutdown);
+                break;
+            }
+        }
+        assert!(client
+            .drain_events()
+            .contains(&ConnectionEvent::Disconnected {
+                reason: DisconnectReason::ServerShutdown
+            }));
+    }
+
+    // The port is free again
+    sleep(Duration::from_millis(100)).await;
+    let host = LanHost::start(&lan_config(port)).await.unwrap();
+    host.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_lan_host_fails_when_port_is_taken() {
+    let taken = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = taken.local_addr().unwrap().port();
+    assert!(LanHost::start(&lan_config(port)).await.is_err());
+}
@@ -0,0 +1,212 @@
+use client::net::snapshots::SnapshotDecoder;
+use client::net::NetworkClient;
+use engine::config::{Config, NetworkConditions, Transport};
+use engine::net_proto::{ClientMessage, ServerMessage};
+/// Tests running client and server over loopback with simulated network conditions
+use std::time::Instant;
+use tokio::task;
+use tokio::time::{sleep, timeout, Duration};
+
+fn spawn_server(config: Config) -> task::JoinHandle<()> {
+    task::spawn(async move {
+        let mut server = server::net::NetworkServer::from_config(&config);
+        let mut tick_loop =
+            server::TickLoop::new(&config, server.inbound_events(), server.sessions());
+        task::spawn(async move { tick_loop.run().await });
+        let _ = server.start().await;
+    })
+}
+
+/// Send a ping and wait for its pong, skipping state updates
+async fn round_trip(client: &mut NetworkClient, id: u32) -> Duration {
+    let sent = Instant::now();
+    client
+        .send_message(&ClientMessage::Ping { id, timestamp: 0 })
+        .await
+        .unwrap();
+    loop {
+        if let ServerMessage::Pong { id: pong, .. } = client.receive_message().await.unwrap() {
+            if pong == id {
+                return sent.elapsed();
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_udp_replication_survives_bad_network() {
+    // Configured on both ends, as both would read it from config.toml
+    let mut config = Config::default();
+    config.server.port = 7790;
+    config.client.server_port = 7790;
+    config.network.transport = Transport::Udp;
+    config.network.conditions = NetworkConditions {
+        latency_ms: 20,
+        jitter_ms: 10,
+        loss: 0.1,
+        duplication: 0.05,
+        reordering: 0.05,
+        seed: 1,
+        ..Default::default()
+    };
+    let server_task = spawn_server(config.clone());
+    sleep(Duration::from_millis(100)).await;
+
+    let mut client = NetworkClient::from_config(&config);
+    client
+        .connect(&config.client.server_host, config.client.server_port)
+        .await
+        .unwrap();
+    // The handshake is reliable, so lost packets only slow it down
+    let player_id = timeout(Duration::from_secs(5), client.join("Alice"))
+        .await
+        .expect("join timed out")
+        .unwrap();
+
+    let mut decoder = SnapshotDecoder::new();
+    let mut snapshots = 0;
+    let mut deltas = 0;
+    let mut last_tick = 0;
+    while snapshots < 60 {
+        let message = timeout(Duration::from_secs(5), client.receive_message())
+            .await
+            .expect("replication stalled")
+            .unwrap();
+        if matches!(message, ServerMessage::StateDelta(_)) {
+            deltas += 1;
+        }
+        // Late, duplicated and reordered snapshots decode to None
+        let Some(snapshot) = decoder.decode(&message).unwrap() else {
+            continue;
+        };
+        assert!(snapshot.tick > last_tick);
+        assert!(snapshot.player(player_id).is_some());
+        last_tick = snapshot.tick;
+        snapshots += 1;
+        client
+            .send_message(&ClientMessage::SnapshotAck {
+                tick: snapshot.tick,
+            })
+            .await
+            .unwrap();
+    }
+    assert!(deltas > 0);
+
+    client.disconnect().await.unwrap();
+    server_task.abort();
+}
+
+#[tokio::test]
+async fn test_client_side_latency_over_tcp() {
+    let mut config = Config::default();
+    config.server.port = 7791;
+    let server_task = spawn_server(config);
+    sleep(Duration::from_millis(100)).await;
+
+    // Only the client simulates: 40 ms out and 40 ms back
+    let mut client = NetworkClient::new().with_conditions(NetworkConditions {
+        latency_ms: 40,
+        jitter_ms: 20,
+        ..Default::default()
+    });
+    client.connect("127.0.0.1", 7791).await.unwrap();
+    client.join("Alice").await.unwrap();
+
+    let rtt = round_trip(&mut client, 1).await;
+    assert!(rtt >= Duration::from_millis(80), "rtt {:?}", rtt);
+
+    // Jitter never reorders a stream
+    let mut last_tick = 0;
+    for _ in 0..20 {
+        if let ServerMessage::StateUpdate(snapshot) = client.receive_message().await.unwrap() {
+            assert!(snapshot.tick > last_tick);
+            last_tick = snapshot.tick;
+        }
+    }
+
+    client.disconnect().await.unwrap();
+    server_task.abort();
+}
+
+#[tokio::test]
+async fn test_server_side_latency_over_udp() {
+    let mut server = server::net::NetworkServer::new("127.0.0.1", 7792)
+        .with_transport(Transport::Udp)
+        .with_conditions(NetworkConditions {
+            latency_ms: 30,
+            ..Default::default()
+        });
+    let server_task = task::spawn(async move {
+        let _ = server.start().await;
+    });
+    sleep(Duration::from_millis(100)).await;
+
+    let mut client = NetworkClient::new().with_transport(Transport::Udp);
+    client.connect("127.0.0.1", 7792).await.unwrap();
+    client.join("Alice").await.unwrap();
+
+    let rtt = round_trip(&mut client, 1).await;
+    assert!(rtt >= Duration::from_millis(60), "rtt {:?}", rtt);
+
+    client.disconnect().await.unwrap();
+    server_task.abort();
+}
+
+#[tokio::test]
+async fn test_clock_sync_measures_rtt_and_server_tick() {
+    let mut config = Config::default();
+    config.server.port = 7793;
+    config.network.conditions = NetworkConditions {
+        latency_ms: 40,
+        ..Default::default()
+    };
+    let mut server = server::net::NetworkServer::from_config(&config);
+    let sessions = server.sessions();
+    let mut tick_loop = server::TickLoop::new(&config, server.inbound_events(), server.sessions());
+    let server_task = task::spawn(async move {
+        task::spawn(async move { tick_loop.run().await });
+        let _ = server.start().await;
+    });
+    sleep(Duration::from_millis(100)).await;
+
+    let mut client = NetworkClient::new();
+    client.connect("127.0.0.1", 7793).await.unwrap();
+    let player_id = client.join("Alice").await.unwrap();
+
+    // Long enough for a few pings each way
+    let started = Instant::now();
+    let mut latest_tick = 0;
+    while started.elapsed() < Duration::from_millis(1500) {
+        if let ServerMessage::StateUpdate(snapshot) = client.receive_message().await.unwrap() {
+            latest_tick = snapshot.tick;
+        }
+    }
+
+    let rtt = client
+        .rtt()
+        .expect("client should have measured the round trip");
+    assert!(rtt >= Duration::from_millis(80), "client rtt {:?}", rtt);
+    assert!(rtt < Duration::from_millis(300), "client rtt {:?}", rtt);
+    let server_rtt = sessions
+        .lock()
+        .unwrap()
+        .rtt(player_id)
+        .expect("server should have measured the round trip");
+    assert!(
+        server_rtt >= Duration::from_millis(80),
+        "server rtt {:?}",
+        server_rtt
+    );
+
+    // Snapshots arrive 40ms (2.4 ticks) after the server ran them
+    let ahead = client.server_tick().unwrap() - latest_tick as f64;
+    assert!(
+        (0.5..8.0).contains(&ahead),
+        "estimate {} ticks ahead",
+        ahead
+    );
+    assert!(client.recommended_interpolation_delay().unwrap() >= Duration::from_millis(33));
+
+    client.disconnect().await.unwrap();
+    server_task.abort();
+}